@@ -0,0 +1,24 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use csvtest::bench::{self, Workload};
+
+/// Rows of each generated input.
+const ROWS: u64 = 100_000;
+
+/// Reading and applying each workload with the engine alone, in rows per second.
+fn engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+    group.throughput(Throughput::Elements(ROWS));
+    group.sample_size(20);
+    for workload in Workload::ALL {
+        let input = workload.input(ROWS).expect("generate the input");
+        group.bench_with_input(
+            BenchmarkId::from_parameter(workload.name()),
+            &input,
+            |b, input| b.iter(|| bench::apply(input).expect("apply the input")),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, engine);
+criterion_main!(benches);
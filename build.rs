@@ -0,0 +1,10 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // only the gRPC service is generated, the protobuf input format has hand written
+    // messages that the service reuses
+    #[cfg(feature = "grpc")]
+    tonic_build::configure()
+        .build_client(false)
+        .extern_path(".tx_processing.Transaction", "crate::protobuf::Transaction")
+        .compile(&["proto/ledger.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,86 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use csvtest::{policy::Policy, Engine, Record, TxType};
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+
+/// A transaction on a few clients and tx ids, so that disputes find their deposits.
+#[derive(Debug, Arbitrary)]
+struct Op {
+    kind: Kind,
+    client: u8,
+    tx: u8,
+    /// in units of 0.0001
+    amount: Option<i32>,
+    fee: Option<u16>,
+}
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Void,
+    Other,
+}
+
+impl From<Op> for Record {
+    fn from(op: Op) -> Self {
+        let tx_type = match op.kind {
+            Kind::Deposit => TxType::Deposit,
+            Kind::Withdrawal => TxType::Withdrawal,
+            Kind::Dispute => TxType::Dispute,
+            Kind::Resolve => TxType::Resolve,
+            Kind::Chargeback => TxType::Chargeback,
+            Kind::Void => TxType::Void,
+            Kind::Other => TxType::from("transfer"),
+        };
+        Record {
+            tx_type,
+            client: Some(u16::from(op.client % 8)),
+            tx: u32::from(op.tx % 32),
+            amount: op.amount.map(|amount| Decimal::new(i64::from(amount), 4)),
+            fee: op.fee.map(|fee| Decimal::new(i64::from(fee), 4)),
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+// Applies any sequence of transactions. After every one, each account's total is its
+// available and held funds, the held funds are not negative, and an account that got
+// locked stays locked.
+fuzz_target!(|ops: Vec<Op>| {
+    let mut engine = Engine::new(Policy::default());
+    let mut locked = HashSet::new();
+    for op in ops {
+        let _ = engine.apply(Record::from(op));
+        for balance in engine.balances() {
+            assert_eq!(
+                balance.total,
+                balance.available + balance.held,
+                "client {}",
+                balance.client
+            );
+            assert!(balance.held >= Decimal::ZERO, "client {}", balance.client);
+            if balance.locked {
+                locked.insert(balance.client);
+            } else {
+                assert!(
+                    !locked.contains(&balance.client),
+                    "client {} got unlocked",
+                    balance.client
+                );
+            }
+        }
+    }
+});
@@ -0,0 +1,39 @@
+#![no_main]
+use csv::{ByteRecord, Position};
+use csvtest::{csv_row::Columns, policy::Policy, Engine};
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+// Reads any bytes as a CSV input with a header row, as the file run does, and applies the
+// records that could be read. The reader and the engine may refuse the input, not panic,
+// and after every record each account's total is its available and held funds, with the
+// held funds not negative.
+fuzz_target!(|input: &[u8]| {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input);
+    let columns = match reader.byte_headers() {
+        Ok(headers) => Columns::new(Some(headers)),
+        Err(_) => return,
+    };
+    let mut engine = Engine::new(Policy::default());
+    let mut row = ByteRecord::new();
+    while let Ok(true) = reader.read_byte_record(&mut row) {
+        let line = row.position().map_or(0, Position::line);
+        let record = match columns.record(&row, line) {
+            Ok(record) => record,
+            Err(_) => continue,
+        };
+        let _ = engine.apply(record);
+        for balance in engine.balances() {
+            assert_eq!(
+                balance.total,
+                balance.available + balance.held,
+                "client {}",
+                balance.client
+            );
+            assert!(balance.held >= Decimal::ZERO, "client {}", balance.client);
+        }
+    }
+});
@@ -0,0 +1,171 @@
+use crate::{
+    amount::Amount,
+    apply_record,
+    id_allocator::{IdAllocator, IdStrategy},
+    policy::{MalformedRow, Policy, Refused},
+    transforms::{check_amount, check_client, check_duplicate},
+    Balance, ClientInfo, Outcome, Record,
+};
+use log::error;
+use rust_decimal::Decimal;
+use std::{collections::HashMap, error::Error, io};
+
+/// Client accounts kept in any [`Amount`], for code embedding the crate that keeps balances
+/// in `f32` or in `i64` minor units rather than the [`Decimal`](rust_decimal::Decimal) of
+/// the binary. Records go through the policy and the handlers of the binary; the rules read
+/// from files on the command line (client remap, denylist, tagging, funding links,
+/// withdrawal limits, suspense account and dispute SLA) are not applied.
+///
+/// A record that would overflow a balance is logged and not applied, as an overdraft is.
+pub struct Accounts<A = Decimal> {
+    client_map: HashMap<u16, ClientInfo<A>>,
+    /// position of the first deposit or withdrawal with each tx id, for the duplicate tx
+    /// policy
+    seen_tx: HashMap<u32, u64>,
+    policy: Policy,
+    /// picks the ids of the `generate` missing client policy, random ids seeded with 0
+    ids: Box<dyn IdAllocator + Send>,
+    /// records read so far, skipped ones included
+    processed: u64,
+}
+
+impl<A: Amount> Accounts<A> {
+    pub fn new(policy: Policy) -> Self {
+        Accounts {
+            client_map: HashMap::new(),
+            seen_tx: HashMap::new(),
+            policy,
+            ids: IdStrategy::default().allocator(0),
+            processed: 0,
+        }
+    }
+
+    /// Applies the records of `input`, CSV with a header as the binary reads it, the amounts
+    /// written in `A`: `i64` amounts are whole minor units. Fails on the rows and records
+    /// the policy aborts on.
+    pub fn apply_csv<R: io::Read>(&mut self, input: R) -> Result<(), Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input);
+        for result in reader.deserialize() {
+            let mut record: Record<A> = match (result, self.policy.malformed_row) {
+                (Ok(record), _) => record,
+                (Err(err), MalformedRow::Skip) => {
+                    error!("malformed row, skipping: {}", err);
+                    continue;
+                }
+                (Err(err), MalformedRow::Abort) => return Err(From::from(err)),
+            };
+            self.processed += 1;
+            record.position = self.processed;
+            let policy = &self.policy;
+            let client_map = &self.client_map;
+            if check_client(
+                &mut record,
+                |id| client_map.contains_key(&id),
+                policy.missing_client,
+                self.ids.as_mut(),
+            )? && check_amount(&record, policy.missing_amount)?
+                && check_duplicate(&record, &mut self.seen_tx, policy.duplicate_tx)?
+            {
+                let (position, tx) = (record.position, record.tx);
+                let outcome = apply_record(&mut self.client_map, record, &self.policy)?;
+                if let (Outcome::Refused(reason), Refused::Abort) = (outcome, policy.refused) {
+                    return Err(From::from(format!(
+                        "tx {} at position {} refused: {}",
+                        tx,
+                        position,
+                        reason.as_str()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Balances of the account, `None` if it does not exist.
+    pub fn balance(&self, client: u16) -> Option<Balance<A>> {
+        let info = self.client_map.get(&client)?;
+        Some(Balance::of(client, info))
+    }
+
+    /// Balances of every account, sorted by client.
+    pub fn balances(&self) -> Vec<Balance<A>> {
+        let mut balances: Vec<Balance<A>> = self
+            .client_map
+            .iter()
+            .map(|(client, info)| Balance::of(*client, info))
+            .collect();
+        balances.sort_by_key(|balance| balance.client);
+        balances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn funds<A: Amount>(accounts: &Accounts<A>, client: u16) -> (A, A, A) {
+        let balance = accounts.balance(client).unwrap();
+        (balance.available, balance.held, balance.total)
+    }
+
+    #[test]
+    fn decimal_accounts_are_exact() {
+        let mut accounts: Accounts<Decimal> = Accounts::new(Policy::default());
+        accounts
+            .apply_csv(
+                "type,client,tx,amount,fee
+                deposit,1,1,0.1,
+                deposit,1,2,0.2,
+                withdrawal,1,3,0.0003,0.0001
+                deposit,1,4,1.5,0.5
+                dispute,1,4,,"
+                    .as_bytes(),
+            )
+            .unwrap();
+        let decimal = |value| Decimal::from_str(value).unwrap();
+        assert_eq!(
+            funds(&accounts, 1),
+            (decimal("0.2996"), decimal("1.0"), decimal("1.2996"))
+        );
+    }
+
+    #[test]
+    fn minor_unit_accounts_refuse_overflow() {
+        let mut accounts: Accounts<i64> = Accounts::new(Policy::default());
+        let input = format!(
+            "type,client,tx,amount,fee
+            deposit,1,1,1000,25
+            withdrawal,1,2,300,
+            deposit,1,3,{max},
+            withdrawal,1,4,{max},1
+            deposit,2,5,{max},
+            deposit,2,6,1,
+            dispute,1,3,,",
+            max = i64::MAX
+        );
+        accounts.apply_csv(input.as_bytes()).unwrap();
+        // tx 3 and 4 were refused, so tx 3 can not be disputed
+        assert_eq!(funds(&accounts, 1), (675, 0, 675));
+        assert_eq!(funds(&accounts, 2), (i64::MAX, 0, i64::MAX));
+    }
+
+    #[test]
+    fn balances_are_sorted_by_client() {
+        let mut accounts: Accounts<i64> = Accounts::new(Policy::default());
+        accounts
+            .apply_csv(
+                "type,client,tx,amount
+                deposit,3,1,5
+                deposit,1,2,7
+                withdrawal,2,3,1"
+                    .as_bytes(),
+            )
+            .unwrap();
+        let clients: Vec<u16> = accounts.balances().iter().map(|b| b.client).collect();
+        assert_eq!(clients, vec![1, 2, 3]);
+        assert_eq!(accounts.balance(1).unwrap().available, 7);
+    }
+}
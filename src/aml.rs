@@ -0,0 +1,233 @@
+use crate::{
+    transactions::{TxEntry, TxKind},
+    ClientInfo, TxError,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::HashMap, path::Path};
+
+/// Thresholds of the structuring detection rules. Windows are counted in input records,
+/// as the timestamp of a transaction is optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmlRules {
+    /// reporting threshold deposits are kept just under
+    pub threshold: Decimal,
+    /// deposits from `threshold * (1 - margin)` up to the threshold count as just under it
+    pub margin: Decimal,
+    /// how many just-under deposits inside one window are reported
+    pub min_count: usize,
+    pub window: u64,
+    /// share of a deposit withdrawn again inside the window that counts as a rapid cycle
+    pub cycle_ratio: Decimal,
+}
+
+impl Default for AmlRules {
+    fn default() -> Self {
+        AmlRules {
+            threshold: Decimal::from(10000),
+            margin: Decimal::new(1, 1),
+            min_count: 3,
+            window: 1000,
+            cycle_ratio: Decimal::new(9, 1),
+        }
+    }
+}
+
+/// One suspicious activity finding.
+#[derive(Serialize, Debug, PartialEq)]
+struct Finding {
+    client: u16,
+    pattern: &'static str,
+    /// `;` separated tx ids involved
+    txs: String,
+    amount: Decimal,
+}
+
+/// Runs the rules over the applied transactions of every client and writes the findings as CSV.
+pub fn write_report(
+    path: &Path,
+    rules: &AmlRules,
+    client_map: &HashMap<u16, ClientInfo>,
+) -> Result<(), TxError> {
+    let mut clients: Vec<&u16> = client_map.keys().collect();
+    clients.sort();
+
+    let mut wtr = csv::Writer::from_path(path)?;
+    for client in clients {
+        for finding in findings(*client, client_map[client].transactions(), rules) {
+            wtr.serialize(finding)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn findings(client: u16, transactions: &[TxEntry], rules: &AmlRules) -> Vec<Finding> {
+    let mut findings = structuring(client, transactions, rules);
+    findings.extend(rapid_cycles(client, transactions, rules));
+    findings
+}
+
+/// Deposits and withdrawals that still stand, voided ones left out.
+fn applied(transactions: &[TxEntry], kind: TxKind) -> impl Iterator<Item = &TxEntry> {
+    transactions
+        .iter()
+        .filter(move |r| r.kind == kind && !r.voided)
+}
+
+/// Many deposits just under the threshold inside one window. Each deposit is reported at most once.
+fn structuring(client: u16, transactions: &[TxEntry], rules: &AmlRules) -> Vec<Finding> {
+    let floor = rules.threshold * (Decimal::ONE - rules.margin);
+    let under: Vec<&TxEntry> = applied(transactions, TxKind::Deposit)
+        .filter(|r| r.amount.is_some_and(|a| a >= floor && a < rules.threshold))
+        .collect();
+
+    let mut findings = Vec::new();
+    let mut start = 0;
+    while start < under.len() {
+        let end = under[start..]
+            .iter()
+            .take_while(|r| r.position - under[start].position < rules.window)
+            .count()
+            + start;
+        if end - start >= rules.min_count {
+            let involved = &under[start..end];
+            findings.push(Finding {
+                client,
+                pattern: "structuring",
+                txs: join_txs(involved),
+                amount: involved.iter().filter_map(|r| r.amount).sum(),
+            });
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+    findings
+}
+
+/// A deposit mostly withdrawn again inside the window.
+fn rapid_cycles(client: u16, transactions: &[TxEntry], rules: &AmlRules) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for deposit in applied(transactions, TxKind::Deposit) {
+        let amount = match deposit.amount {
+            Some(amount) if amount > Decimal::ZERO => amount,
+            _ => continue,
+        };
+        let withdrawal = applied(transactions, TxKind::Withdrawal).find(|r| {
+            r.position > deposit.position
+                && r.position - deposit.position < rules.window
+                && r.amount.is_some_and(|w| w >= amount * rules.cycle_ratio)
+        });
+        if let Some(withdrawal) = withdrawal {
+            findings.push(Finding {
+                client,
+                pattern: "rapid_cycle",
+                txs: join_txs(&[deposit, withdrawal]),
+                amount,
+            });
+        }
+    }
+    findings
+}
+
+fn join_txs(records: &[&TxEntry]) -> String {
+    let txs: Vec<String> = records.iter().map(|r| r.tx.to_string()).collect();
+    txs.join(";")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Record;
+
+    /// Transactions one client keeps of `input`, positions numbered from 1 as the run does.
+    fn transactions(input: &str) -> Vec<TxEntry> {
+        let mut info = ClientInfo::default();
+        let records = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize::<Record>()
+            .zip(1..);
+        for (record, position) in records {
+            info.keep(Record {
+                position,
+                ..record.unwrap()
+            });
+        }
+        info.transactions().to_vec()
+    }
+
+    fn rules() -> AmlRules {
+        AmlRules {
+            window: 10,
+            ..AmlRules::default()
+        }
+    }
+
+    #[test]
+    fn finds_deposits_kept_just_under_the_threshold() {
+        let transactions = transactions(
+            "type,client,tx,amount
+            deposit,1,1,9500
+            deposit,1,2,9900
+            deposit,1,3,500
+            deposit,1,4,9999",
+        );
+        assert_eq!(
+            findings(1, &transactions, &rules()),
+            vec![Finding {
+                client: 1,
+                pattern: "structuring",
+                txs: "1;2;4".to_string(),
+                amount: Decimal::from(29399),
+            }]
+        );
+    }
+
+    #[test]
+    fn deposits_further_apart_than_the_window_are_not_structuring() {
+        let mut transactions = transactions(
+            "type,client,tx,amount
+            deposit,1,1,9500
+            deposit,1,2,9900
+            deposit,1,3,9999",
+        );
+        transactions[2].position = 11;
+        assert!(findings(1, &transactions, &rules()).is_empty());
+    }
+
+    #[test]
+    fn finds_deposits_withdrawn_again() {
+        let transactions = transactions(
+            "type,client,tx,amount
+            deposit,1,1,100
+            withdrawal,1,2,95
+            deposit,1,3,50
+            withdrawal,1,4,10",
+        );
+        assert_eq!(
+            findings(1, &transactions, &rules()),
+            vec![Finding {
+                client: 1,
+                pattern: "rapid_cycle",
+                txs: "1;2".to_string(),
+                amount: Decimal::from(100),
+            }]
+        );
+    }
+
+    #[test]
+    fn voided_transactions_are_left_out() {
+        let transactions = transactions(
+            "type,client,tx,amount
+            deposit,1,1,9500
+            deposit,1,2,9900
+            deposit,1,3,9999
+            withdrawal,1,4,9999
+            void,1,3,
+            void,1,4,",
+        );
+        assert!(findings(1, &transactions, &rules()).is_empty());
+    }
+}
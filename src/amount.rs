@@ -0,0 +1,80 @@
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+/// The number type balances are kept in. The binary keeps them in [`Decimal`]; code
+/// embedding the crate can apply records with [`Accounts`](crate::accounts::Accounts) in
+/// `f32`, or in `i64` counting minor units such as cents.
+///
+/// A record whose amounts would overflow a balance is refused rather than applied: `i64`
+/// does not wrap, [`Decimal`] does not go past its 28 digits and `f32` does not become
+/// infinite.
+pub trait Amount:
+    Copy
+    + PartialOrd
+    + Default
+    + fmt::Debug
+    + fmt::Display
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+    + 'static
+{
+    const ZERO: Self;
+
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    fn checked_sub(self, other: Self) -> Option<Self>;
+
+    /// For report figures that should not refuse the records adding up to them.
+    fn saturating_add(self, other: Self) -> Self;
+}
+
+impl Amount for f32 {
+    const ZERO: Self = 0.0;
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Some(self + other).filter(|sum| sum.is_finite())
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Some(self - other).filter(|difference| difference.is_finite())
+    }
+
+    fn saturating_add(self, other: Self) -> Self {
+        (self + other).clamp(f32::MIN, f32::MAX)
+    }
+}
+
+impl Amount for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Decimal::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Decimal::checked_sub(self, other)
+    }
+
+    fn saturating_add(self, other: Self) -> Self {
+        Decimal::saturating_add(self, other)
+    }
+}
+
+impl Amount for i64 {
+    const ZERO: Self = 0;
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        i64::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        i64::checked_sub(self, other)
+    }
+
+    fn saturating_add(self, other: Self) -> Self {
+        i64::saturating_add(self, other)
+    }
+}
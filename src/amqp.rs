@@ -0,0 +1,158 @@
+use crate::{
+    events::{EngineEvent, RejectReason},
+    messages::{self, Encoding},
+    Record, TxError,
+};
+use amiquip::{
+    AmqpProperties, AmqpValue, Connection, ConsumerMessage, ConsumerOptions, FieldTable, Publish,
+};
+use log::{error, info};
+use std::{
+    error::Error,
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread,
+};
+
+/// Header of a dead lettered message holding the reject reason code.
+const REASON_HEADER: &str = "x-reject-reason";
+
+/// Where to consume from.
+#[derive(Debug, Clone)]
+pub struct AmqpOptions {
+    pub url: String,
+    pub queue: String,
+    /// unacked messages the broker sends ahead
+    pub prefetch: u16,
+    /// queue rejected and malformed messages are published to, with the reason code in the
+    /// `x-reject-reason` header. Without one they are nacked, so the broker dead letters
+    /// them if the queue has a dead letter exchange
+    pub dead_letter_queue: Option<String>,
+}
+
+/// What to do with a delivery once the pipeline is done with it.
+enum Verdict {
+    Ack,
+    DeadLetter(RejectReason),
+}
+
+/// Consumes JSON or MessagePack transactions from an AMQP queue. A message is acked once it
+/// went through the engine and every sink, or dead lettered with its reason code when it
+/// could not be read or was rejected; a message the run stopped on is left unacked and
+/// redelivered. The connection lives on its own thread, handing over one message at a time.
+pub struct AmqpSource {
+    deliveries: Receiver<Vec<u8>>,
+    verdicts: Sender<Verdict>,
+    events: Receiver<EngineEvent>,
+    /// whether a message was handed out and waits for its verdict
+    pending: bool,
+}
+
+impl AmqpSource {
+    /// Connects and starts consuming. `events` must be subscribed to the pipeline the
+    /// source is used in, they tell which messages were rejected.
+    pub fn new(
+        options: AmqpOptions,
+        events: Receiver<EngineEvent>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (ready, started) = mpsc::channel();
+        let (deliver, deliveries) = mpsc::sync_channel(0);
+        let (verdicts, verdict) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(err) = consume(&options, &ready, deliver, verdict) {
+                // before the start this goes to `new`
+                if ready.send(Err(err.to_string())).is_err() {
+                    error!("amqp consumer of {} stopped: {}", options.queue, err);
+                }
+            }
+        });
+        started
+            .recv()
+            .map_err(|_| "amqp consumer did not start")?
+            .map_err(|err| format!("amqp: {}", err))?;
+        Ok(AmqpSource {
+            deliveries,
+            verdicts,
+            events,
+            pending: false,
+        })
+    }
+}
+
+impl Iterator for AmqpSource {
+    type Item = Result<Record, TxError>;
+
+    /// Ends when the consumer is cancelled or the connection is lost.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending {
+            let mut verdict = Verdict::Ack;
+            for event in self.events.try_iter() {
+                match event {
+                    EngineEvent::Rejected { reason, .. } => verdict = Verdict::DeadLetter(reason),
+                    EngineEvent::Malformed { .. } => {
+                        verdict = Verdict::DeadLetter(RejectReason::Malformed)
+                    }
+                    _ => {}
+                }
+            }
+            if self.verdicts.send(verdict).is_err() {
+                return None;
+            }
+        }
+        let body = self.deliveries.recv().ok()?;
+        self.pending = true;
+        Some(messages::decode(Encoding::sniff(&body), &body).map_err(TxError::malformed))
+    }
+}
+
+/// Runs the connection: hands each delivery to the source and acks, dead letters or stops
+/// on its verdict. Only returns errors, once started the stop is logged.
+fn consume(
+    options: &AmqpOptions,
+    ready: &Sender<Result<(), String>>,
+    deliver: SyncSender<Vec<u8>>,
+    verdicts: Receiver<Verdict>,
+) -> Result<(), amiquip::Error> {
+    let mut connection = Connection::open(&options.url)?;
+    let channel = connection.open_channel(None)?;
+    channel.qos(0, options.prefetch, false)?;
+    let consumer = channel.basic_consume(&options.queue, ConsumerOptions::default())?;
+    let _ = ready.send(Ok(()));
+    info!("consuming amqp queue {}", options.queue);
+    for message in consumer.receiver().iter() {
+        let delivery = match message {
+            ConsumerMessage::Delivery(delivery) => delivery,
+            other => {
+                info!("amqp consumer of {} ended: {:?}", options.queue, other);
+                break;
+            }
+        };
+        if deliver.send(delivery.body.clone()).is_err() {
+            break;
+        }
+        match verdicts.recv() {
+            Ok(Verdict::Ack) => consumer.ack(delivery)?,
+            Ok(Verdict::DeadLetter(reason)) => match &options.dead_letter_queue {
+                Some(queue) => {
+                    let mut headers = FieldTable::default();
+                    headers.insert(
+                        REASON_HEADER.into(),
+                        AmqpValue::LongString(reason.as_str().into()),
+                    );
+                    channel.basic_publish(
+                        "",
+                        Publish::with_properties(
+                            &delivery.body,
+                            queue.as_str(),
+                            AmqpProperties::default().with_headers(headers),
+                        ),
+                    )?;
+                    consumer.ack(delivery)?;
+                }
+                None => consumer.nack(delivery, false)?,
+            },
+            // the run stopped on this message, it stays unacked
+            Err(_) => break,
+        }
+    }
+    connection.close()
+}
@@ -0,0 +1,70 @@
+use crate::{policy::MalformedRow, Balance, Engine, Record, Report, TxError};
+use futures::{Stream, StreamExt};
+use log::error;
+use tokio::sync::Mutex;
+
+/// An [`Engine`] shared by tasks of a tokio runtime. Each record is applied under an async
+/// lock, in the order the tasks get it, on the task that applies it: applying is quick and
+/// never blocks, so no blocking threads are needed. Like [`Engine::apply`] it is the engine
+/// alone, the checks of a pipeline do not run.
+pub struct AsyncEngine {
+    engine: Mutex<Engine>,
+}
+
+impl AsyncEngine {
+    pub fn new(engine: Engine) -> Self {
+        AsyncEngine {
+            engine: Mutex::new(engine),
+        }
+    }
+
+    /// Applies one record, and returns the balances of its account afterwards.
+    pub async fn apply(&self, record: Record) -> Result<Option<Balance>, TxError> {
+        let client = record.client;
+        let mut engine = self.engine.lock().await;
+        engine.apply(record)?;
+        Ok(client.and_then(|client| {
+            let info = engine.state().client_map.get(&client)?;
+            Some(Balance::of(client, info))
+        }))
+    }
+
+    /// Applies the records of `records` as they arrive, until it ends. Records that can not
+    /// be read follow the malformed row policy, other errors end the run.
+    pub async fn run<S>(&self, records: S) -> Result<(), TxError>
+    where
+        S: Stream<Item = Result<Record, TxError>>,
+    {
+        let abort = self.engine.lock().await.policy().malformed_row == MalformedRow::Abort;
+        futures::pin_mut!(records);
+        while let Some(result) = records.next().await {
+            match result {
+                Ok(record) => {
+                    self.apply(record).await?;
+                }
+                Err(err @ TxError::ParseError { .. }) if abort => return Err(err),
+                Err(err @ TxError::ParseError { .. }) => {
+                    error!("malformed record, skipping: {}", err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Balances of `client`, `None` if it has no account.
+    pub async fn balance(&self, client: u16) -> Option<Balance> {
+        let engine = self.engine.lock().await;
+        let info = engine.state().client_map.get(&client)?;
+        Some(Balance::of(client, info))
+    }
+
+    /// Current balances of every account, sorted by client.
+    pub async fn balances(&self) -> Vec<Balance> {
+        self.engine.lock().await.balances()
+    }
+
+    pub fn into_report(self) -> Report {
+        self.engine.into_inner().into_report()
+    }
+}
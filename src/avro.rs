@@ -0,0 +1,327 @@
+use crate::{decimal_from_be_bytes, timestamp, Record, TxError, TxType};
+use apache_avro::{schema::Schema, types::Value, Reader};
+use chrono::{DateTime, Utc};
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// What a transaction field may be in the Avro schema, besides a union with `null`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    /// `string`
+    Text,
+    /// `int` or `long`
+    Integer,
+    /// a decimal `string`, `int`, `long`, `float`, `double` or the `decimal` logical type
+    Amount,
+    /// an RFC 3339 `string`, epoch millis as `long`, or the `timestamp-millis` or
+    /// `timestamp-micros` logical type
+    Timestamp,
+}
+
+impl Kind {
+    fn expected(self) -> &'static str {
+        match self {
+            Kind::Text => "string",
+            Kind::Integer => "int or long",
+            Kind::Amount => "string, int, long, float, double or decimal",
+            Kind::Timestamp => "string, long, timestamp-millis or timestamp-micros",
+        }
+    }
+
+    fn accepts(self, schema: &Schema) -> bool {
+        match (self, schema) {
+            (_, Schema::Union(union)) => union
+                .variants()
+                .iter()
+                .all(|variant| *variant == Schema::Null || self.accepts(variant)),
+            (Kind::Text, Schema::String) => true,
+            (Kind::Integer, Schema::Int | Schema::Long) => true,
+            (
+                Kind::Amount,
+                Schema::String
+                | Schema::Int
+                | Schema::Long
+                | Schema::Float
+                | Schema::Double
+                | Schema::Decimal { .. },
+            ) => true,
+            (
+                Kind::Timestamp,
+                Schema::String | Schema::Long | Schema::TimestampMillis | Schema::TimestampMicros,
+            ) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The expected transaction schema: field name, kind and whether the field is required.
+/// Field names are the CSV headers.
+const FIELDS: [(&str, Kind, bool); 8] = [
+    ("type", Kind::Text, true),
+    ("client", Kind::Integer, false),
+    ("tx", Kind::Integer, true),
+    ("amount", Kind::Amount, false),
+    ("fee", Kind::Amount, false),
+    ("memo", Kind::Text, false),
+    ("evidence", Kind::Text, false),
+    ("timestamp", Kind::Timestamp, false),
+];
+
+/// Checks the schema embedded in a container file against the transaction schema, naming
+/// every missing or mistyped field. Returns the scale of decimal amount fields by name.
+fn validate(schema: &Schema) -> Result<Vec<(&'static str, usize)>, String> {
+    let fields = match schema {
+        Schema::Record { fields, .. } => fields,
+        other => {
+            return Err(format!(
+                "avro schema is {:?}, expected a record of transactions",
+                other
+            ))
+        }
+    };
+    let mut problems = Vec::new();
+    let mut scales = Vec::new();
+    for (name, kind, required) in FIELDS {
+        match fields.iter().find(|field| field.name == name) {
+            None if required => problems.push(format!("field `{}` is missing", name)),
+            None => {}
+            Some(field) if !kind.accepts(&field.schema) => problems.push(format!(
+                "field `{}` is {}, expected {}",
+                name,
+                field.schema.canonical_form(),
+                kind.expected()
+            )),
+            Some(field) => {
+                if let Some(scale) = decimal_scale(&field.schema) {
+                    scales.push((name, scale));
+                }
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(scales)
+    } else {
+        Err(format!(
+            "avro schema does not match the transaction schema: {}",
+            problems.join("; ")
+        ))
+    }
+}
+
+fn decimal_scale(schema: &Schema) -> Option<usize> {
+    match schema {
+        Schema::Decimal { scale, .. } => Some(*scale),
+        Schema::Union(union) => union.variants().iter().find_map(decimal_scale),
+        _ => None,
+    }
+}
+
+/// Streams the records of an Avro object container file. The schema embedded in the file
+/// is validated up front, so a file with missing or mistyped fields fails before any
+/// record is applied.
+pub struct AvroReader<R: Read> {
+    rows: Reader<'static, R>,
+    /// scale of the decimal amount fields, which only the schema knows
+    scales: Vec<(&'static str, usize)>,
+}
+
+impl AvroReader<BufReader<File>> {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        AvroReader::new(BufReader::new(File::open(path)?))
+            .map_err(|err| From::from(format!("{}: {}", path.display(), err)))
+    }
+}
+
+impl<R: Read> AvroReader<R> {
+    pub fn new(reader: R) -> Result<Self, Box<dyn Error>> {
+        let rows = Reader::new(reader)?;
+        let scales = validate(rows.writer_schema())?;
+        Ok(AvroReader { rows, scales })
+    }
+
+    fn to_record(&self, row: Value) -> Result<Record, TxError> {
+        let mut fields = match row {
+            Value::Record(fields) => fields,
+            other => {
+                return Err(TxError::malformed(format!(
+                    "avro value is not a record: {:?}",
+                    other
+                )))
+            }
+        };
+        let mut take = |name: &str| {
+            fields
+                .iter_mut()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| std::mem::replace(value, Value::Null))
+                .map(unwrap_union)
+                .filter(|value| *value != Value::Null)
+        };
+        let tx_type = take("type")
+            .and_then(text)
+            .ok_or_else(|| TxError::malformed("avro record without a transaction type"))?;
+        let tx = take("tx")
+            .and_then(integer)
+            .and_then(|tx| u32::try_from(tx).ok())
+            .ok_or_else(|| TxError::malformed("avro record with a missing or invalid tx id"))?;
+        let client = take("client").and_then(integer);
+        let amount = take("amount");
+        let fee = take("fee");
+        let memo = take("memo").and_then(text);
+        let evidence = take("evidence").and_then(text);
+        let timestamp = take("timestamp").and_then(datetime);
+        Ok(Record {
+            tx_type: TxType::from(tx_type),
+            // like the CSV reader, invalid optional values are treated as missing
+            client: client.and_then(|client| u16::try_from(client).ok()),
+            tx,
+            amount: amount.and_then(|value| self.decimal("amount", value)),
+            fee: fee.and_then(|value| self.decimal("fee", value)),
+            memo,
+            evidence,
+            timestamp,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        })
+    }
+
+    fn decimal(&self, field: &str, value: Value) -> Option<Decimal> {
+        match value {
+            Value::String(s) => s.trim().parse().ok(),
+            Value::Int(i) => Some(Decimal::from(i)),
+            Value::Long(i) => Some(Decimal::from(i)),
+            Value::Float(f) => Decimal::from_f32(f),
+            Value::Double(f) => Decimal::from_f64(f),
+            Value::Decimal(decimal) => {
+                let scale = self
+                    .scales
+                    .iter()
+                    .find(|(name, _)| *name == field)
+                    .map(|(_, scale)| *scale)?;
+                let bytes = <Vec<u8>>::try_from(&decimal).ok()?;
+                decimal_from_be_bytes(&bytes, u32::try_from(scale).ok()?)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<R: Read> Iterator for AvroReader<R> {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.rows.next()?;
+        Some(
+            row.map_err(TxError::malformed)
+                .and_then(|row| self.to_record(row)),
+        )
+    }
+}
+
+fn unwrap_union(value: Value) -> Value {
+    match value {
+        Value::Union(_, value) => *value,
+        value => value,
+    }
+}
+
+fn text(value: Value) -> Option<String> {
+    match value {
+        Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        _ => None,
+    }
+}
+
+fn datetime(value: Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(s) => timestamp::parse(&s),
+        Value::Long(millis) | Value::TimestampMillis(millis) => timestamp::from_millis(millis),
+        Value::TimestampMicros(micros) => timestamp::from_millis(micros / 1000),
+        _ => None,
+    }
+}
+
+fn integer(value: Value) -> Option<i64> {
+    match value {
+        Value::Int(i) => Some(i64::from(i)),
+        Value::Long(i) => Some(i),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apache_avro::{types::Record as AvroRecord, Writer};
+
+    fn schema(fields: &str) -> Schema {
+        Schema::parse_str(&format!(
+            r#"{{"type": "record", "name": "Transaction", "fields": [{}]}}"#,
+            fields
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn the_schema_errors_name_every_offending_field() {
+        let err = validate(&schema(
+            r#"{"name": "tx", "type": "string"},
+            {"name": "amount", "type": ["null", "boolean"]}"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "avro schema does not match the transaction schema: field `type` is missing; \
+             field `tx` is \"string\", expected int or long; field `amount` is \
+             [\"null\",\"boolean\"], expected string, int, long, float, double or decimal"
+        );
+    }
+
+    #[test]
+    fn records_are_read_with_decimal_amounts_and_timestamps() {
+        let schema = schema(
+            r#"{"name": "type", "type": "string"},
+            {"name": "client", "type": ["null", "int"]},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": ["null", {"type": "bytes", "logicalType": "decimal",
+                "precision": 10, "scale": 4}]},
+            {"name": "timestamp", "type": ["null", "string"]}"#,
+        );
+        let mut writer = Writer::new(&schema, Vec::new());
+        let mut row = AvroRecord::new(writer.schema()).unwrap();
+        row.put("type", "deposit");
+        row.put("client", Value::Union(1, Box::new(Value::Int(3))));
+        row.put("tx", 9_i64);
+        row.put(
+            "amount",
+            Value::Union(1, Box::new(Value::Decimal(vec![0x3a, 0x98].into()))),
+        );
+        row.put(
+            "timestamp",
+            Value::Union(1, Box::new(Value::String("2022-03-01T09:00:00Z".into()))),
+        );
+        writer.append(row).unwrap();
+        let bytes = writer.into_inner().unwrap();
+        let records: Vec<Record> = AvroReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(
+            (record.tx_type.as_str(), record.client, record.tx),
+            ("deposit", Some(3), 9)
+        );
+        // 0x3a98 is 15000 at scale 4
+        assert_eq!(record.amount, Some("1.5".parse().unwrap()));
+        assert_eq!(record.timestamp, timestamp::parse("2022-03-01T09:00:00Z"));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    csv_row::Columns,
+    generate::{self, GenerateOptions},
+    policy::Policy,
+    Engine,
+};
+use csv::{ByteRecord, Position};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fs,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// Seed of the benchmark inputs, so every run measures the same rows.
+const SEED: u64 = 42;
+
+/// Generated inputs the engine is measured on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Workload {
+    /// deposits and withdrawals of a thousand clients, no disputes
+    DepositHeavy,
+    /// a tenth of the rows dispute a deposit, as many resolve or charge one back
+    DisputeHeavy,
+    /// every client id in use, a dispute now and then
+    ManyClients,
+}
+
+impl Workload {
+    pub const ALL: [Workload; 3] = [
+        Workload::DepositHeavy,
+        Workload::DisputeHeavy,
+        Workload::ManyClients,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Workload::DepositHeavy => "deposit-heavy",
+            Workload::DisputeHeavy => "dispute-heavy",
+            Workload::ManyClients => "many-clients",
+        }
+    }
+
+    /// Generator settings for `rows` rows of the workload.
+    fn options(&self, rows: u64) -> GenerateOptions {
+        let (clients, dispute_rate) = match self {
+            Workload::DepositHeavy => (1_000, 0.0),
+            Workload::DisputeHeavy => (1_000, 0.1),
+            Workload::ManyClients => (u16::MAX, 0.01),
+        };
+        GenerateOptions {
+            clients,
+            rows,
+            dispute_rate,
+            malformed_rate: 0.0,
+            seed: Some(SEED),
+        }
+    }
+
+    /// The CSV input of `rows` rows of the workload, in memory.
+    pub fn input(&self, rows: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut input = Vec::new();
+        generate::write(&mut input, &self.options(rows))?;
+        Ok(input)
+    }
+}
+
+/// Reads the CSV `input` and applies it to a fresh engine with the default policy, as the
+/// file run does without any checks or outputs.
+pub fn apply(input: &[u8]) -> Result<Engine, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    let columns = Columns::new(Some(reader.byte_headers()?));
+    let mut engine = Engine::new(Policy::default());
+    let mut row = ByteRecord::new();
+    while reader.read_byte_record(&mut row)? {
+        let line = row.position().map_or(0, Position::line);
+        engine.apply(columns.record(&row, line)?)?;
+    }
+    Ok(engine)
+}
+
+/// How a timed run went.
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub rows: u64,
+    pub elapsed: Duration,
+    /// peak resident memory of the process so far, in bytes
+    pub peak_rss: Option<u64>,
+}
+
+impl Measurement {
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Times applying `input`, see [`apply`].
+pub fn measure(input: &[u8]) -> Result<Measurement, Box<dyn Error>> {
+    let start = Instant::now();
+    let engine = apply(input)?;
+    let elapsed = start.elapsed();
+    Ok(Measurement {
+        rows: engine.state().processed,
+        elapsed,
+        peak_rss: peak_rss(),
+    })
+}
+
+/// Peak resident set size of the process in bytes, from `/proc`; `None` where there is none.
+pub fn peak_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Measures each of `workloads` on `rows` generated rows and writes a CSV line per workload:
+/// rows, seconds, rows per second and the peak memory so far, which includes the input.
+pub fn run<W: Write>(out: W, workloads: &[Workload], rows: u64) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record([
+        "workload",
+        "rows",
+        "seconds",
+        "rows_per_sec",
+        "peak_rss_bytes",
+    ])?;
+    for workload in workloads {
+        let input = workload.input(rows)?;
+        let measurement = measure(&input)?;
+        writer.write_record(&[
+            workload.name().to_string(),
+            measurement.rows.to_string(),
+            format!("{:.3}", measurement.elapsed.as_secs_f64()),
+            format!("{:.0}", measurement.rows_per_sec()),
+            measurement
+                .peak_rss
+                .map_or(String::new(), |bytes| bytes.to_string()),
+        ])?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_line_per_workload_with_every_row_applied() {
+        let mut out = Vec::new();
+        run(&mut out, &Workload::ALL, 500).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next(),
+            Some("workload,rows,seconds,rows_per_sec,peak_rss_bytes")
+        );
+        for workload in Workload::ALL {
+            let line = lines.next().unwrap();
+            assert!(
+                line.starts_with(&format!("{},500,", workload.name())),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(lines.next(), None);
+    }
+}
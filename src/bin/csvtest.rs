@@ -0,0 +1,10 @@
+use csvtest::TxError;
+use log::error;
+use std::process;
+
+fn main() {
+    if let Err(err) = csvtest::run() {
+        error!("{}", err);
+        process::exit(err.downcast_ref::<TxError>().map_or(1, TxError::exit_code));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    pipeline::{Sink, State},
+    state_file, TxError,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Start of every checkpoint file, the number is the layout version.
+const MAGIC: &[u8; 8] = b"TXCHECK1";
+
+/// Where in the input a checkpoint was taken: the start of the first record not applied yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputPosition {
+    pub input: PathBuf,
+    pub byte: u64,
+    pub line: u64,
+    pub record: u64,
+}
+
+impl InputPosition {
+    pub fn to_csv(&self) -> csv::Position {
+        let mut position = csv::Position::new();
+        position
+            .set_byte(self.byte)
+            .set_line(self.line)
+            .set_record(self.record);
+        position
+    }
+}
+
+/// Reads the input position of a checkpoint without its state.
+pub fn read_position(path: &Path) -> Result<InputPosition, TxError> {
+    let mut input = BufReader::new(File::open(path)?);
+    read_header(&mut input)
+}
+
+/// Reads a checkpoint, to continue from.
+pub fn read(path: &Path) -> Result<(InputPosition, State), TxError> {
+    let mut input = BufReader::new(File::open(path)?);
+    let position = read_header(&mut input)?;
+    Ok((position, state_file::read(input)?))
+}
+
+fn read_header<R: Read>(input: &mut R) -> Result<InputPosition, TxError> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(TxError::other(
+            "not a checkpoint, or one written by another version",
+        ));
+    }
+    state_file::decode(input)
+}
+
+/// Checkpoints the state and the position in `input` every `every` records read, through a
+/// temporary file renamed over `path`. The checkpoint is removed once the run is done, as
+/// there is nothing left to resume.
+pub struct Checkpoints {
+    pub path: PathBuf,
+    pub input: PathBuf,
+    pub every: u64,
+    /// records read when the last checkpoint was taken
+    pub taken: u64,
+}
+
+impl Checkpoints {
+    fn write(&self, state: &State) -> Result<(), TxError> {
+        let next = match &state.next_position {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        let position = InputPosition {
+            input: self.input.clone(),
+            byte: next.byte(),
+            line: next.line(),
+            record: next.record(),
+        };
+        let mut partial = self.path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let mut out = BufWriter::new(File::create(&partial)?);
+        out.write_all(MAGIC)?;
+        state_file::encode(&mut out, &position)?;
+        state_file::write(state, &mut out)?;
+        out.into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        fs::rename(&partial, &self.path)?;
+        info!(
+            "checkpoint after {} records, at line {} of {}",
+            state.processed,
+            position.line,
+            self.input.display()
+        );
+        Ok(())
+    }
+}
+
+impl Sink for Checkpoints {
+    fn applied(&mut self, _client: u16, state: &State) -> Result<(), TxError> {
+        if state.processed >= self.taken + self.every {
+            self.write(state)?;
+            self.taken = state.processed;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _state: &State) -> Result<(), TxError> {
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "msgpack"))]
+mod tests {
+    use super::*;
+    use crate::{pipeline::PipelineBuilder, Balance, Record};
+    use std::{env, io::Cursor, iter, process};
+
+    const INPUT: &str = "type,client,tx,amount
+        deposit,1,1,10
+        deposit,2,2,5
+        withdrawal,1,3,4
+        dispute,2,2,
+        deposit,1,4,7
+        resolve,2,2,
+        withdrawal,2,5,3
+        dispute,1,4,
+        chargeback,1,4,
+        deposit,2,6,1";
+
+    /// The records of `INPUT` from `from`, or from the start, knowing where the next one
+    /// starts like the records of an input file do.
+    fn records(from: Option<&InputPosition>) -> Vec<Record> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(Cursor::new(INPUT));
+        let headers = reader.headers().unwrap().clone();
+        if let Some(position) = from {
+            reader.seek(position.to_csv()).unwrap();
+        }
+        let mut records = Vec::new();
+        let mut row = csv::StringRecord::new();
+        while reader.read_record(&mut row).unwrap() {
+            let mut record: Record = row.deserialize(Some(&headers)).unwrap();
+            record.next_position = Some(reader.position().clone());
+            records.push(record);
+        }
+        records
+    }
+
+    fn balances(state: &State) -> Vec<Balance> {
+        let mut balances: Vec<Balance> = state
+            .client_map
+            .iter()
+            .map(|(client, info)| Balance::of(*client, info))
+            .collect();
+        balances.sort_by_key(|balance| balance.client);
+        balances
+    }
+
+    fn checkpoints(path: &Path) -> Checkpoints {
+        Checkpoints {
+            path: path.to_path_buf(),
+            input: PathBuf::from("input.csv"),
+            every: 2,
+            taken: 0,
+        }
+    }
+
+    #[test]
+    fn resume_ends_as_a_run_in_one_go() {
+        let path = env::temp_dir().join(format!("csvtest-checkpoint-{}", process::id()));
+        let whole = PipelineBuilder::new()
+            .source(records(None).into_iter().map(Ok))
+            .build()
+            .run()
+            .unwrap();
+
+        // stops after the 7th record, past the checkpoint of the 6th
+        let crashed = records(None)
+            .into_iter()
+            .take(7)
+            .map(Ok)
+            .chain(iter::once_with(|| Err(TxError::other("crashed"))));
+        let run = PipelineBuilder::new()
+            .source(crashed)
+            .sink(checkpoints(&path))
+            .build()
+            .run();
+        assert!(run.is_err());
+        let (position, state) = read(&path).unwrap();
+        assert_eq!(state.processed, 6);
+
+        let resumed = PipelineBuilder::new()
+            .source(records(Some(&position)).into_iter().map(Ok))
+            .state(state)
+            .sink(checkpoints(&path))
+            .build()
+            .run()
+            .unwrap();
+        assert_eq!(balances(&resumed), balances(&whole));
+        assert_eq!(resumed.processed, whole.processed);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,98 @@
+use flate2::read::MultiGzDecoder;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Whether the file is gzip or zstd compressed, told by its magic bytes so a misnamed file
+/// is still read right.
+pub fn is_compressed(path: &Path) -> io::Result<bool> {
+    let mut start = Vec::with_capacity(ZSTD_MAGIC.len());
+    File::open(path)?
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut start)?;
+    Ok(start.starts_with(&GZIP_MAGIC) || start.starts_with(&ZSTD_MAGIC))
+}
+
+/// The path without a `.gz` or `.zst` extension, to pick the format of the content from,
+/// e.g. `transactions.pb` for `transactions.pb.gz`.
+pub fn content_path(path: &Path) -> PathBuf {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") | Some("zst") => path.with_extension(""),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Decompresses gzip (including concatenated members, as written by `pigz` and log
+/// rotation) and zstd input on the fly, passing anything else through as is. zstd input
+/// needs the `zstd` feature.
+pub fn decompress<R: Read + Send + 'static>(input: R) -> io::Result<Box<dyn Read + Send>> {
+    let mut input = BufReader::new(input);
+    let start = input.fill_buf()?;
+    if start.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(input)))
+    } else if start.starts_with(&ZSTD_MAGIC) {
+        zstd_decoder(input)
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decoder<R: Read + Send + 'static>(input: BufReader<R>) -> io::Result<Box<dyn Read + Send>> {
+    Ok(Box::new(zstd::Decoder::with_buffer(input)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decoder<R: Read + Send + 'static>(
+    _input: BufReader<R>,
+) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "zstd input needs the zstd feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read_all(input: Vec<u8>) -> String {
+        let mut read = String::new();
+        decompress(io::Cursor::new(input))
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        read
+    }
+
+    #[test]
+    fn reads_concatenated_gzip_members_as_one_stream() {
+        let mut input = gzip(b"type,client,tx,amount\n");
+        input.extend(gzip(b"deposit,1,1,1.5\n"));
+        assert_eq!(read_all(input), "type,client,tx,amount\ndeposit,1,1,1.5\n");
+        assert_eq!(read_all(b"type,client\n".to_vec()), "type,client\n");
+    }
+
+    #[test]
+    fn picks_the_format_from_the_extension_before_the_compression() {
+        assert_eq!(
+            content_path(Path::new("day.pb.gz")),
+            PathBuf::from("day.pb")
+        );
+        assert_eq!(content_path(Path::new("day.zst")), PathBuf::from("day"));
+        assert_eq!(content_path(Path::new("day.csv")), PathBuf::from("day.csv"));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{timestamp, Record, TxError, TxType};
+use csv::ByteRecord;
+use rust_decimal::Decimal;
+use std::{error::Error, fmt, str};
+
+/// Fields of a [`Record`], in the order of the columns of a CSV input without a header row.
+const FIELDS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "fee",
+    "memo",
+    "evidence",
+    "timestamp",
+];
+const TYPE: usize = 0;
+const CLIENT: usize = 1;
+const TX: usize = 2;
+const AMOUNT: usize = 3;
+const FEE: usize = 4;
+const MEMO: usize = 5;
+const EVIDENCE: usize = 6;
+const TIMESTAMP: usize = 7;
+
+/// Where each field of a [`Record`] is in the rows of a CSV input, from its header row or in
+/// the default order. Records are read straight from byte rows rather than through serde:
+/// the type and numbers are parsed in place, only a memo or evidence is copied out.
+#[derive(Debug, Clone)]
+pub struct Columns {
+    /// column of each of `FIELDS`, `None` when the header row has no such column
+    at: [Option<usize>; 8],
+}
+
+impl Columns {
+    /// Columns named by `headers`, the first of a name wins. Other columns are ignored.
+    pub fn new(headers: Option<&ByteRecord>) -> Self {
+        let mut at = [None; 8];
+        match headers {
+            Some(headers) => {
+                for (column, name) in headers.iter().enumerate() {
+                    if let Some(field) = FIELDS.iter().position(|field| field.as_bytes() == name) {
+                        at[field].get_or_insert(column);
+                    }
+                }
+            }
+            None => {
+                for (field, at) in at.iter_mut().enumerate() {
+                    *at = Some(field);
+                }
+            }
+        }
+        Columns { at }
+    }
+
+    fn field<'r>(&self, row: &'r ByteRecord, field: usize) -> Option<&'r [u8]> {
+        self.at[field].and_then(|column| row.get(column))
+    }
+
+    /// Reads the record in `row`, at `line` of the input. As with the serde reader the type
+    /// and tx id are required, and an invalid or empty optional value is treated as missing.
+    pub fn record(&self, row: &ByteRecord, line: u64) -> Result<Record, RowError> {
+        let error = |message: String| RowError { line, message };
+        let tx_type = self
+            .field(row, TYPE)
+            .ok_or_else(|| error("missing field `type`".to_string()))?;
+        let tx_type = TxType::from_bytes(tx_type)
+            .ok_or_else(|| error("transaction type is not UTF-8".to_string()))?;
+        let tx = self
+            .field(row, TX)
+            .ok_or_else(|| error("missing field `tx`".to_string()))?;
+        let tx = text(tx)
+            .and_then(|tx| tx.parse::<u32>().ok())
+            .ok_or_else(|| error(format!("invalid tx id {:?}", String::from_utf8_lossy(tx))))?;
+        Ok(Record {
+            tx_type,
+            client: self
+                .field(row, CLIENT)
+                .and_then(text)
+                .and_then(|client| client.parse().ok()),
+            tx,
+            amount: self.field(row, AMOUNT).and_then(decimal),
+            fee: self.field(row, FEE).and_then(decimal),
+            memo: self.field(row, MEMO).and_then(text).map(str::to_string),
+            evidence: self.field(row, EVIDENCE).and_then(text).map(str::to_string),
+            timestamp: self
+                .field(row, TIMESTAMP)
+                .and_then(text)
+                .and_then(timestamp::parse),
+            position: 0,
+            line,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        })
+    }
+}
+
+/// `None` for an empty or non UTF-8 field.
+fn text(field: &[u8]) -> Option<&str> {
+    str::from_utf8(field).ok().filter(|text| !text.is_empty())
+}
+
+/// Plain or scientific notation, like the serde reader.
+fn decimal(field: &[u8]) -> Option<Decimal> {
+    let text = text(field)?;
+    text.parse()
+        .or_else(|_| Decimal::from_scientific(text))
+        .ok()
+}
+
+/// A CSV row that is not a record. Text only, so it can cross threads, unlike a `TxError`.
+#[derive(Debug, Clone)]
+pub struct RowError {
+    pub line: u64,
+    message: String,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for RowError {}
+
+impl From<RowError> for TxError {
+    fn from(err: RowError) -> Self {
+        TxError::ParseError {
+            line: Some(err.line),
+            message: err.message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(fields: &str) -> ByteRecord {
+        ByteRecord::from(fields.split(',').collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn picks_the_columns_by_the_header_row() {
+        let columns = Columns::new(Some(&row("tx,amount,extra,type,client")));
+        let record = columns.record(&row("7,1.5e1,x,withdrawal,3"), 2).unwrap();
+        assert_eq!(record.tx_type, TxType::Withdrawal);
+        assert_eq!(
+            (record.client, record.tx, record.amount, record.line),
+            (Some(3), 7, Some(Decimal::from(15)), 2)
+        );
+        assert_eq!(record.memo, None);
+
+        let record = columns.record(&row("8,,,refund,abc"), 3).unwrap();
+        assert_eq!(record.tx_type, TxType::Other("refund".to_string()));
+        assert_eq!((record.client, record.amount), (None, None));
+    }
+
+    #[test]
+    fn a_row_without_a_type_or_tx_id_is_malformed() {
+        let columns = Columns::new(None);
+        assert!(columns.record(&row("deposit,1,2,3.5"), 1).is_ok());
+        let err = TxError::from(columns.record(&row("deposit,1,x,3.5"), 4).unwrap_err());
+        assert!(
+            matches!(err, TxError::ParseError { line: Some(4), ref message } if message.contains("tx id")),
+            "{:?}",
+            err
+        );
+        let columns = Columns::new(Some(&row("client,tx,amount")));
+        assert!(columns.record(&row("1,2,3.5"), 2).is_err());
+    }
+}
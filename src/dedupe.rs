@@ -0,0 +1,176 @@
+use crate::TxError;
+#[cfg(feature = "sled")]
+use log::info;
+use std::collections::HashMap;
+#[cfg(feature = "sled")]
+use std::{error::Error, path::Path};
+
+/// False positive rate the bloom filter is sized for.
+#[cfg(feature = "sled")]
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+/// Cache of the exact store, the rest of it stays on disk.
+#[cfg(feature = "sled")]
+const EXACT_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The tx ids the duplicate check has seen, with the position of the first deposit or
+/// withdrawal of each.
+pub trait SeenTx {
+    /// Position of the first record with `tx`. `position` when `tx` is new, which makes that
+    /// record the first.
+    fn first_position(&mut self, tx: u32, position: u64) -> Result<u64, TxError>;
+}
+
+/// Every id in memory, the default.
+impl SeenTx for HashMap<u32, u64> {
+    fn first_position(&mut self, tx: u32, position: u64) -> Result<u64, TxError> {
+        Ok(*self.entry(tx).or_insert(position))
+    }
+}
+
+/// A bloom filter of tx ids: an id it has seen is always reported as seen, one it has not
+/// at about the rate it was sized for.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Sized for `expected` ids at a false positive rate of `rate`.
+    pub fn new(expected: u64, rate: f64) -> Self {
+        let expected = expected.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-expected * rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hashes = (bits / expected * ln2).round().clamp(1.0, 16.0) as u32;
+        BloomFilter {
+            bits: vec![0; (bits as usize).div_ceil(64)],
+            hashes,
+        }
+    }
+
+    /// Adds `tx`, returns whether it may have been added before.
+    pub fn insert(&mut self, tx: u32) -> bool {
+        let mut seen = true;
+        for index in indexes(tx, self.bits.len() as u64 * 64, self.hashes) {
+            let (word, bit) = (index / 64, 1u64 << (index % 64));
+            seen &= self.bits[word] & bit != 0;
+            self.bits[word] |= bit;
+        }
+        seen
+    }
+
+    /// Bytes the filter takes.
+    pub fn size(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+/// The bits of `tx`, by double hashing.
+fn indexes(tx: u32, bits: u64, hashes: u32) -> impl Iterator<Item = usize> {
+    let first = mix(u64::from(tx));
+    let step = mix(u64::from(tx) ^ 0x9e37_79b9_7f4a_7c15) | 1;
+    (0..u64::from(hashes)).map(move |i| (first.wrapping_add(i.wrapping_mul(step)) % bits) as usize)
+}
+
+/// The splitmix64 finalizer.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Memory-bounded ids for feeds too large for a map: a bloom filter in memory answers for
+/// new ids, and every id with its position is also kept in a sled database on disk, only
+/// read when the filter reports an id as seen.
+#[cfg(feature = "sled")]
+pub struct Tiered {
+    bloom: BloomFilter,
+    exact: sled::Tree,
+    // keeps the database open
+    _db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl Tiered {
+    /// A filter sized for `expected` ids, the exact ids in a database at `path`. It is only
+    /// good for one run, so it starts empty and is removed when dropped.
+    pub fn open(path: &Path, expected: u64) -> Result<Self, Box<dyn Error>> {
+        let db = sled::Config::new()
+            .path(path)
+            .cache_capacity(EXACT_CACHE_BYTES)
+            .temporary(true)
+            .open()?;
+        let exact = db.open_tree("seen_tx")?;
+        // left behind by a run that did not end
+        exact.clear()?;
+        let bloom = BloomFilter::new(expected, FALSE_POSITIVE_RATE);
+        info!(
+            "duplicate tx ids: a {} byte bloom filter, exact ids in {}",
+            bloom.size(),
+            path.display()
+        );
+        Ok(Tiered {
+            bloom,
+            exact,
+            _db: db,
+        })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl SeenTx for Tiered {
+    fn first_position(&mut self, tx: u32, position: u64) -> Result<u64, TxError> {
+        if self.bloom.insert(tx) {
+            if let Some(first) = self.exact.get(tx.to_be_bytes()).map_err(TxError::other)? {
+                let first = first.as_ref().try_into().map_err(TxError::other)?;
+                return Ok(u64::from_be_bytes(first));
+            }
+        }
+        self.exact
+            .insert(tx.to_be_bytes(), &position.to_be_bytes())
+            .map_err(TxError::other)?;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_filter_knows_every_id_added() {
+        let mut bloom = BloomFilter::new(10_000, 0.01);
+        for tx in 0..10_000 {
+            bloom.insert(tx * 7);
+        }
+        assert!((0..10_000).all(|tx| bloom.insert(tx * 7)));
+        // about 1% of new ids come out as seen, few are tried as each is added as well
+        let false_positives = (0..1000).filter(|tx| bloom.insert(tx * 7 + 1)).count();
+        assert!(false_positives < 30, "{} false positives", false_positives);
+    }
+
+    /// The first positions `seen` reports for a feed where every third record repeats the
+    /// tx id of the one before.
+    #[cfg(feature = "sled")]
+    fn first_positions(seen: &mut dyn SeenTx) -> Vec<u64> {
+        (0..3000u64)
+            .map(|position| {
+                let tx = if position % 3 == 2 {
+                    position - 1
+                } else {
+                    position
+                } as u32;
+                seen.first_position(tx, position).unwrap()
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn tiered_store_reports_the_first_positions_of_a_map() {
+        let path = std::env::temp_dir().join(format!("csvtest-dedupe-{}", std::process::id()));
+        let expected = first_positions(&mut HashMap::new());
+        // a filter far too small, so most new ids are looked up on disk
+        let mut tiered = Tiered::open(&path, 10).unwrap();
+        assert_eq!(first_positions(&mut tiered), expected);
+    }
+}
@@ -0,0 +1,527 @@
+use crate::{ClientInfo, Record};
+use ::parquet::{arrow::ArrowWriter, file::writer::InMemoryWriteableCursor};
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, DecimalBuilder, Int32Array, Int64Array, StringArray,
+        TimestampMicrosecondArray,
+    },
+    datatypes::{DataType, Field as Column, Schema, TimeUnit},
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, Utc};
+use log::info;
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Rows of the events table written to one data file.
+const EVENTS_PER_FILE: usize = 1_000_000;
+
+/// A Delta Lake table in a local directory: Parquet data files, and the JSON commits of
+/// `_delta_log` telling which of them make up each version. A run commits one version, so
+/// the tables of earlier runs stay readable by time travel.
+struct DeltaTable {
+    path: PathBuf,
+    /// names the data files of this writer apart from those of other runs
+    run: i64,
+    /// data files written, not committed yet: name and size
+    written: Vec<(String, u64)>,
+}
+
+impl DeltaTable {
+    fn new(path: &Path) -> Self {
+        DeltaTable {
+            path: path.to_path_buf(),
+            run: Utc::now().timestamp_millis(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Writes a data file for the next commit. Until then it is not part of the table.
+    fn write(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.path)?;
+        let name = format!("part-{}-{:05}.parquet", self.run, self.written.len());
+        fs::write(self.path.join(&name), data)?;
+        self.written.push((name, data.len() as u64));
+        Ok(())
+    }
+
+    fn log(&self) -> PathBuf {
+        self.path.join("_delta_log")
+    }
+
+    /// The version the next commit gets, 0 for a new table, and the data files of the
+    /// current version.
+    fn current(&self) -> Result<(u64, BTreeSet<String>), Box<dyn Error>> {
+        let mut files = BTreeSet::new();
+        let mut version = 0;
+        loop {
+            let commit = match File::open(self.commit_path(version)) {
+                Ok(commit) => commit,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => break,
+                Err(err) => return Err(err.into()),
+            };
+            for line in BufReader::new(commit).lines() {
+                let action: Value = serde_json::from_str(&line?)?;
+                if let Some(path) = action["add"]["path"].as_str() {
+                    files.insert(path.to_string());
+                }
+                if let Some(path) = action["remove"]["path"].as_str() {
+                    files.remove(path);
+                }
+            }
+            version += 1;
+        }
+        Ok((version, files))
+    }
+
+    fn commit_path(&self, version: u64) -> PathBuf {
+        self.log().join(format!("{:020}.json", version))
+    }
+
+    /// Commits the data files written as the next version, in place of the files of the
+    /// current version with `overwrite`. A commit is created only if no other writer took
+    /// its version first, as the Delta protocol wants.
+    fn commit(&mut self, schema: &Schema, overwrite: bool) -> Result<u64, Box<dyn Error>> {
+        fs::create_dir_all(self.log())?;
+        let (version, current) = self.current()?;
+        let now = Utc::now().timestamp_millis();
+        let mut actions = Vec::new();
+        if version == 0 {
+            actions.push(json!({
+                "protocol": {"minReaderVersion": 1, "minWriterVersion": 2}
+            }));
+            actions.push(json!({
+                "metaData": {
+                    "id": format!("tx-processing-{}", now),
+                    "format": {"provider": "parquet", "options": {}},
+                    "schemaString": delta_schema(schema).to_string(),
+                    "partitionColumns": [],
+                    "configuration": {},
+                    "createdTime": now,
+                }
+            }));
+        }
+        if overwrite {
+            for path in current {
+                actions.push(json!({
+                    "remove": {"path": path, "deletionTimestamp": now, "dataChange": true}
+                }));
+            }
+        }
+        for (name, size) in self.written.drain(..) {
+            actions.push(json!({
+                "add": {
+                    "path": name,
+                    "partitionValues": {},
+                    "size": size,
+                    "modificationTime": now,
+                    "dataChange": true,
+                }
+            }));
+        }
+        let operation = if overwrite { "Overwrite" } else { "Append" };
+        actions.push(json!({
+            "commitInfo": {
+                "timestamp": now,
+                "operation": "WRITE",
+                "operationParameters": {"mode": operation},
+                "engineInfo": concat!("tx-processing/", env!("CARGO_PKG_VERSION")),
+            }
+        }));
+
+        let mut commit = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.commit_path(version))
+            .map_err(|err| {
+                format!(
+                    "committing version {} of the table {}: {}",
+                    version,
+                    self.path.display(),
+                    err
+                )
+            })?;
+        for action in actions {
+            serde_json::to_writer(&mut commit, &action)?;
+            commit.write_all(b"\n")?;
+        }
+        commit.sync_all()?;
+        Ok(version)
+    }
+}
+
+/// The schema of `schema` as the JSON a Delta `metaData` action holds.
+fn delta_schema(schema: &Schema) -> Value {
+    let fields: Vec<Value> = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let data_type = match field.data_type() {
+                DataType::Int32 => "integer".to_string(),
+                DataType::Int64 => "long".to_string(),
+                DataType::Decimal(precision, scale) => format!("decimal({},{})", precision, scale),
+                DataType::Boolean => "boolean".to_string(),
+                DataType::Utf8 => "string".to_string(),
+                DataType::Timestamp(..) => "timestamp".to_string(),
+                other => format!("{:?}", other).to_lowercase(),
+            };
+            json!({
+                "name": field.name(),
+                "type": data_type,
+                "nullable": field.is_nullable(),
+                "metadata": {},
+            })
+        })
+        .collect();
+    json!({"type": "struct", "fields": fields})
+}
+
+/// Amounts are `decimal(38,4)` columns, exact up to four decimal places; amounts with more
+/// are rounded to four.
+const AMOUNT_PRECISION: usize = 38;
+const AMOUNT_SCALE: u32 = 4;
+
+fn amount_type() -> DataType {
+    DataType::Decimal(AMOUNT_PRECISION, AMOUNT_SCALE as usize)
+}
+
+fn amounts(amounts: impl Iterator<Item = Option<Decimal>>) -> Result<ArrayRef, Box<dyn Error>> {
+    let mut builder = DecimalBuilder::new(0, AMOUNT_PRECISION, AMOUNT_SCALE as usize);
+    for amount in amounts {
+        match amount {
+            Some(mut amount) => {
+                amount.rescale(AMOUNT_SCALE);
+                builder.append_value(amount.mantissa())?;
+            }
+            None => builder.append_null()?,
+        }
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// The columns `arrays` as the bytes of a Parquet file.
+fn parquet_file(schema: Arc<Schema>, arrays: Vec<ArrayRef>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+    let cursor = InMemoryWriteableCursor::default();
+    let mut writer = ArrowWriter::try_new(cursor.clone(), schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(cursor.data())
+}
+
+/// Commits the final balances to a Delta table at the end of the run, replacing those of
+/// the run before. The columns are those of the CSV snapshot without `aliases`; Delta has
+/// no unsigned integers, so the client id is an `integer`, and amounts are `decimal(38,4)`.
+pub struct DeltaBalances {
+    table: DeltaTable,
+}
+
+impl DeltaBalances {
+    pub fn new(path: &Path) -> Self {
+        DeltaBalances {
+            table: DeltaTable::new(path),
+        }
+    }
+
+    pub fn commit(&mut self, client_map: &HashMap<u16, ClientInfo>) -> Result<u64, Box<dyn Error>> {
+        let mut accounts: Vec<_> = client_map.iter().collect();
+        accounts.sort_unstable_by_key(|(client, _)| **client);
+
+        let schema = Arc::new(Schema::new(vec![
+            Column::new("client", DataType::Int32, false),
+            Column::new("available", amount_type(), false),
+            Column::new("held", amount_type(), false),
+            Column::new("total", amount_type(), false),
+            Column::new("locked", DataType::Boolean, false),
+        ]));
+        let balance = |amount: fn(&ClientInfo) -> Decimal| {
+            amounts(accounts.iter().map(|(_, info)| Some(amount(info))))
+        };
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from_iter_values(
+                accounts.iter().map(|(client, _)| i32::from(**client)),
+            )),
+            balance(|info| info.available_funds)?,
+            balance(|info| info.held_funds)?,
+            balance(|info| info.total_funds)?,
+            Arc::new(BooleanArray::from(
+                accounts
+                    .iter()
+                    .map(|(_, info)| info.locked)
+                    .collect::<Vec<_>>(),
+            )),
+        ];
+        self.table.write(&parquet_file(schema.clone(), arrays)?)?;
+        let version = self.table.commit(&schema, true)?;
+        info!(
+            "balances committed as version {} of {}",
+            version,
+            self.table.path.display()
+        );
+        Ok(version)
+    }
+}
+
+/// A record the account kept, with the balances it left the account with.
+struct EventRow {
+    position: u64,
+    tx_type: String,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    timestamp: Option<DateTime<Utc>>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Appends every record an account kept, with the balances it left the
+/// account with, to a Delta table as one commit at the end of the run. Data files are
+/// written as the rows come in and only become part of the table with the commit, so a
+/// failed run adds nothing.
+pub struct DeltaEvents {
+    table: DeltaTable,
+    rows: Vec<EventRow>,
+    /// the last record read and the records its account kept before it was applied
+    pending: Option<(EventRow, u64)>,
+}
+
+impl DeltaEvents {
+    pub fn new(path: &Path) -> Self {
+        DeltaEvents {
+            table: DeltaTable::new(path),
+            rows: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Notes `record` before it is handed to the handlers.
+    pub fn read(&mut self, record: &Record, client_map: &HashMap<u16, ClientInfo>) {
+        self.pending = record.client.map(|client| {
+            let kept = client_map.get(&client).map_or(0, |info| info.kept);
+            let row = EventRow {
+                position: record.position,
+                tx_type: record.tx_type.to_string(),
+                client,
+                tx: record.tx,
+                amount: record.amount,
+                timestamp: record.timestamp,
+                available: Decimal::ZERO,
+                held: Decimal::ZERO,
+                total: Decimal::ZERO,
+                locked: false,
+            };
+            (row, kept)
+        });
+    }
+
+    /// Keeps the row of the last record read if its account kept it, as handlers do with
+    /// every record they take.
+    pub fn applied(&mut self, client_map: &HashMap<u16, ClientInfo>) -> Result<(), Box<dyn Error>> {
+        let (mut row, kept) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let info = match client_map.get(&row.client) {
+            Some(info) if info.kept > kept => info,
+            _ => return Ok(()),
+        };
+        row.available = info.available_funds;
+        row.held = info.held_funds;
+        row.total = info.total_funds;
+        row.locked = info.locked;
+        self.rows.push(row);
+        if self.rows.len() >= EVENTS_PER_FILE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining rows and commits them as the next version of the table.
+    pub fn commit(&mut self) -> Result<u64, Box<dyn Error>> {
+        self.flush()?;
+        let version = self.table.commit(&schema_of_events(), false)?;
+        info!(
+            "events committed as version {} of {}",
+            version,
+            self.table.path.display()
+        );
+        Ok(version)
+    }
+
+    /// Writes the rows collected so far as one data file.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let balance =
+            |amount: fn(&EventRow) -> Decimal| amounts(rows.iter().map(|row| Some(amount(row))));
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from_iter_values(
+                rows.iter().map(|row| row.position as i64),
+            )),
+            Arc::new(
+                rows.iter()
+                    .map(|row| Some(row.tx_type.as_str()))
+                    .collect::<StringArray>(),
+            ),
+            Arc::new(Int32Array::from_iter_values(
+                rows.iter().map(|row| i32::from(row.client)),
+            )),
+            Arc::new(Int64Array::from_iter_values(
+                rows.iter().map(|row| i64::from(row.tx)),
+            )),
+            amounts(rows.iter().map(|row| row.amount))?,
+            Arc::new(
+                rows.iter()
+                    .map(|row| row.timestamp.map(|at| at.timestamp_micros()))
+                    .collect::<TimestampMicrosecondArray>(),
+            ),
+            balance(|row| row.available)?,
+            balance(|row| row.held)?,
+            balance(|row| row.total)?,
+            Arc::new(BooleanArray::from(
+                rows.iter().map(|row| row.locked).collect::<Vec<_>>(),
+            )),
+        ];
+        let data = parquet_file(schema_of_events(), arrays)?;
+        self.table.write(&data)
+    }
+}
+
+fn schema_of_events() -> Arc<Schema> {
+    Arc::new(Schema::new(vec![
+        Column::new("position", DataType::Int64, false),
+        Column::new("type", DataType::Utf8, false),
+        Column::new("client", DataType::Int32, false),
+        Column::new("tx", DataType::Int64, false),
+        Column::new("amount", amount_type(), true),
+        Column::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            true,
+        ),
+        Column::new("available", amount_type(), false),
+        Column::new("held", amount_type(), false),
+        Column::new("total", amount_type(), false),
+        Column::new("locked", DataType::Boolean, false),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_record, policy::Policy};
+    use csv::Trim;
+
+    fn table_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("delta-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    /// The actions of a commit, one JSON value per line.
+    fn actions(table: &Path, version: u64) -> Vec<Value> {
+        let commit = table.join(format!("_delta_log/{:020}.json", version));
+        fs::read_to_string(commit)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    /// Applies the records of `input` with the events table hooked in as the run loop does.
+    fn run(input: &str, events: &mut DeltaEvents) -> HashMap<u16, ClientInfo> {
+        let mut client_map = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        for (i, record) in reader.deserialize().enumerate() {
+            let mut record: Record = record.unwrap();
+            record.position = i as u64 + 1;
+            events.read(&record, &client_map);
+            apply_record(&mut client_map, record, &Policy::default()).unwrap();
+            events.applied(&client_map).unwrap();
+        }
+        client_map
+    }
+
+    const INPUT: &str = "type,client,tx,amount
+        deposit,1,1,10
+        dispute,1,1,
+        chargeback,1,1,
+        deposit,1,2,5
+        deposit,2,3,1.5";
+
+    #[test]
+    fn each_run_replaces_the_balances_in_a_new_version() {
+        let path = table_dir("balances");
+        let mut events = DeltaEvents::new(&path.join("unused"));
+        let client_map = run(INPUT, &mut events);
+        assert_eq!(DeltaBalances::new(&path).commit(&client_map).unwrap(), 0);
+        assert_eq!(DeltaBalances::new(&path).commit(&client_map).unwrap(), 1);
+
+        let first = actions(&path, 0);
+        assert!(first[0]["protocol"].is_object());
+        let schema: Value =
+            serde_json::from_str(first[1]["metaData"]["schemaString"].as_str().unwrap()).unwrap();
+        assert_eq!(schema["fields"][0]["type"], "integer");
+        assert_eq!(schema["fields"][1]["type"], "decimal(38,4)");
+        let added = first[2]["add"]["path"].as_str().unwrap().to_string();
+        assert!(path.join(&added).exists());
+
+        // the second version drops the files of the first
+        let second = actions(&path, 1);
+        assert_eq!(second[0]["remove"]["path"], added.as_str());
+        assert!(second[1]["add"]["path"].is_string());
+        assert_eq!(
+            second[2]["commitInfo"]["operationParameters"]["mode"],
+            "Overwrite"
+        );
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn events_append_the_records_accounts_kept() {
+        let path = table_dir("events");
+        let mut events = DeltaEvents::new(&path);
+        run(INPUT, &mut events);
+        // the deposit on the locked account was refused
+        assert_eq!(
+            events
+                .rows
+                .iter()
+                .map(|row| row.position)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3, 5]
+        );
+        let chargeback = &events.rows[2];
+        assert_eq!(
+            (chargeback.available, chargeback.held, chargeback.total),
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO)
+        );
+        assert!(chargeback.locked);
+        assert_eq!(events.commit().unwrap(), 0);
+
+        let mut second = DeltaEvents::new(&path);
+        run("type,client,tx,amount\ndeposit,3,4,2", &mut second);
+        assert_eq!(second.commit().unwrap(), 1);
+        let actions = actions(&path, 1);
+        assert!(actions.iter().all(|action| action["remove"].is_null()));
+        assert_eq!(
+            actions.last().unwrap()["commitInfo"]["operationParameters"]["mode"],
+            "Append"
+        );
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    fs::File,
+    io::{self, Cursor, Read},
+    path::Path,
+};
+
+/// How many bytes from the start of the input are inspected.
+const SAMPLE_SIZE: u64 = 8 * 1024;
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+const QUOTES: [u8; 2] = [b'"', b'\''];
+
+/// CSV layout of an input file, either sniffed or forced from the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub has_headers: bool,
+    /// escape character inside quoted fields, replaces doubled quotes when set
+    pub escape: Option<u8>,
+    /// lines starting with this character are skipped
+    pub comment: Option<u8>,
+    /// when false quote characters are read as plain text
+    pub quoting: bool,
+    /// allow rows with fewer or more columns than the header
+    pub flexible: bool,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect {
+            delimiter: b',',
+            quote: b'"',
+            has_headers: true,
+            escape: None,
+            comment: None,
+            quoting: true,
+            flexible: false,
+        }
+    }
+}
+
+impl Dialect {
+    pub fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .trim(csv::Trim::All)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .has_headers(self.has_headers)
+            .escape(self.escape)
+            .double_quote(self.escape.is_none())
+            .comment(self.comment)
+            .quoting(self.quoting)
+            .flexible(self.flexible);
+        builder
+    }
+}
+
+/// Parts of the dialect given on the command line, these win over sniffing.
+/// Escaping, comments, quoting and flexible rows are never sniffed.
+#[derive(Debug, Clone, Copy)]
+pub struct DialectOverrides {
+    pub delimiter: Option<u8>,
+    pub quote: Option<u8>,
+    pub has_headers: Option<bool>,
+    pub escape: Option<u8>,
+    pub comment: Option<u8>,
+    pub quoting: bool,
+    pub flexible: bool,
+}
+
+impl Default for DialectOverrides {
+    fn default() -> Self {
+        DialectOverrides {
+            delimiter: None,
+            quote: None,
+            has_headers: None,
+            escape: None,
+            comment: None,
+            quoting: true,
+            flexible: false,
+        }
+    }
+}
+
+impl DialectOverrides {
+    fn is_complete(&self) -> bool {
+        self.delimiter.is_some() && self.quote.is_some() && self.has_headers.is_some()
+    }
+}
+
+/// Works out the dialect of `path` from its first few KB, anything in `overrides` is used as is.
+pub fn detect(path: &Path, overrides: DialectOverrides) -> io::Result<Dialect> {
+    if overrides.is_complete() {
+        return Ok(apply(Dialect::default(), overrides));
+    }
+    let mut sample = Vec::new();
+    File::open(path)?
+        .take(SAMPLE_SIZE)
+        .read_to_end(&mut sample)?;
+    Ok(apply(sniff(&sample, overrides.comment), overrides))
+}
+
+/// A stream read for its sample, with the sample put back in front of the rest.
+pub type Rewound<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+/// Works out the dialect of a stream that can only be read once, like stdin. The sample is
+/// put back in front of the rest of the stream, which is returned for reading the records.
+pub fn detect_stream<R: Read>(
+    mut input: R,
+    overrides: DialectOverrides,
+) -> io::Result<(Dialect, Rewound<R>)> {
+    if overrides.is_complete() {
+        let dialect = apply(Dialect::default(), overrides);
+        return Ok((dialect, Cursor::new(Vec::new()).chain(input)));
+    }
+    let mut sample = Vec::new();
+    (&mut input).take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+    let dialect = apply(sniff(&sample, overrides.comment), overrides);
+    Ok((dialect, Cursor::new(sample).chain(input)))
+}
+
+fn apply(dialect: Dialect, overrides: DialectOverrides) -> Dialect {
+    Dialect {
+        delimiter: overrides.delimiter.unwrap_or(dialect.delimiter),
+        quote: overrides.quote.unwrap_or(dialect.quote),
+        has_headers: overrides.has_headers.unwrap_or(dialect.has_headers),
+        escape: overrides.escape,
+        comment: overrides.comment,
+        quoting: overrides.quoting,
+        flexible: overrides.flexible,
+    }
+}
+
+/// Sniffs the dialect of `sample`, leaving out the lines that start with `comment`.
+fn sniff(sample: &[u8], comment: Option<u8>) -> Dialect {
+    let text = String::from_utf8_lossy(sample);
+    let mut lines: Vec<&str> = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter(|l| comment.is_none_or(|c| l.as_bytes().first() != Some(&c)))
+        .collect();
+    // the sample most likely cut the last line in half
+    if sample.len() as u64 == SAMPLE_SIZE && lines.len() > 1 {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return Dialect::default();
+    }
+
+    let delimiter = sniff_delimiter(&lines);
+    let quote = QUOTES
+        .iter()
+        .copied()
+        .max_by_key(|q| text.bytes().filter(|b| b == q).count())
+        .filter(|q| text.bytes().any(|b| b == *q))
+        .unwrap_or(b'"');
+    let has_headers = looks_like_header(lines[0], delimiter, quote);
+    Dialect {
+        delimiter,
+        quote,
+        has_headers,
+        ..Dialect::default()
+    }
+}
+
+/// Picks the delimiter that splits the most lines into the same number of fields (more than one).
+fn sniff_delimiter(lines: &[&str]) -> u8 {
+    let mut best = (b',', 0);
+    for delimiter in DELIMITERS {
+        let counts: Vec<usize> = lines
+            .iter()
+            .map(|l| l.bytes().filter(|b| *b == delimiter).count())
+            .collect();
+        if counts[0] == 0 {
+            continue;
+        }
+        let consistent = counts.iter().filter(|c| **c == counts[0]).count();
+        if consistent > best.1 {
+            best = (delimiter, consistent);
+        }
+    }
+    best.0
+}
+
+/// Data rows carry a numeric tx id in the third column, a header row does not.
+fn looks_like_header(line: &str, delimiter: u8, quote: u8) -> bool {
+    let quote = quote as char;
+    let tx = line
+        .split(delimiter as char)
+        .nth(2)
+        .map(|field| field.trim().trim_matches(quote));
+    tx.is_none_or(|field| field.parse::<u32>().is_err())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROWS: [[&str; 4]; 3] = [
+        ["deposit", "1", "1", "1.5"],
+        ["withdrawal", "2", "2", "0.25"],
+        ["dispute", "1", "1", ""],
+    ];
+
+    /// `ROWS` written in `dialect`, with every field quoted so the quote can be sniffed.
+    fn written(dialect: &Dialect) -> Vec<u8> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(dialect.delimiter)
+            .quote(dialect.quote)
+            .quote_style(csv::QuoteStyle::NonNumeric)
+            .from_writer(Vec::new());
+        if dialect.has_headers {
+            writer
+                .write_record(["type", "client", "tx", "amount"])
+                .unwrap();
+        }
+        for row in ROWS {
+            writer.write_record(row).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn sniffed_dialect_reads_back_what_was_written() {
+        for (delimiter, quote, has_headers) in [
+            (b',', b'"', true),
+            (b';', b'\'', true),
+            (b'\t', b'"', false),
+            (b'|', b'\'', false),
+        ] {
+            let dialect = Dialect {
+                delimiter,
+                quote,
+                has_headers,
+                ..Dialect::default()
+            };
+            let input = written(&dialect);
+            let (sniffed, rest) = detect_stream(input.as_slice(), Default::default()).unwrap();
+            assert_eq!(sniffed, dialect);
+
+            let mut reader = sniffed.reader_builder().from_reader(rest);
+            let rows: Vec<Vec<String>> = reader
+                .records()
+                .map(|row| row.unwrap().iter().map(String::from).collect())
+                .collect();
+            assert_eq!(rows, ROWS.map(|row| row.map(String::from).to_vec()));
+        }
+    }
+
+    #[test]
+    fn overrides_win_over_sniffing() {
+        let overrides = DialectOverrides {
+            quote: Some(b'\''),
+            has_headers: Some(true),
+            ..Default::default()
+        };
+        let input = "deposit;1;1;1.5\nwithdrawal;1;2;0.5\n";
+        let dialect = apply(sniff(input.as_bytes(), None), overrides);
+        assert_eq!(dialect.delimiter, b';');
+        assert_eq!(dialect.quote, b'\'');
+        assert!(dialect.has_headers);
+    }
+
+    fn read(dialect: Dialect, input: &str) -> Vec<Vec<String>> {
+        dialect
+            .reader_builder()
+            .from_reader(input.as_bytes())
+            .records()
+            .map(|row| row.unwrap().iter().map(String::from).collect())
+            .collect()
+    }
+
+    #[test]
+    fn backslash_escaped_quotes_stay_in_the_memo() {
+        let dialect = Dialect {
+            escape: Some(b'\\'),
+            ..Dialect::default()
+        };
+        let rows = read(
+            dialect,
+            "type,client,tx,amount,memo\ndeposit,1,1,1.5,\"rent \\\"march\\\", late\"\n",
+        );
+        assert_eq!(rows[0][4], "rent \"march\", late");
+    }
+
+    #[test]
+    fn comment_lines_are_skipped_and_not_sniffed() {
+        let input =
+            "# exported 2022-03-01\ntype;client;tx;amount\n# first batch\ndeposit;1;1;1.5\n";
+        let sniffed = sniff(input.as_bytes(), Some(b'#'));
+        assert_eq!(sniffed.delimiter, b';');
+        assert!(sniffed.has_headers);
+        let overrides = DialectOverrides {
+            comment: Some(b'#'),
+            ..Default::default()
+        };
+        let rows = read(apply(sniffed, overrides), input);
+        assert_eq!(rows, vec![vec!["deposit", "1", "1", "1.5"]]);
+    }
+
+    #[test]
+    fn unquoted_and_flexible_rows() {
+        let dialect = Dialect {
+            quoting: false,
+            flexible: true,
+            ..Dialect::default()
+        };
+        let rows = read(
+            dialect,
+            "type,client,tx,amount,memo\ndeposit,1,1,1.5,\"quoted\"\ndispute,1,1\n",
+        );
+        assert_eq!(rows[0][4], "\"quoted\"");
+        assert_eq!(rows[1], vec!["dispute", "1", "1"]);
+    }
+}
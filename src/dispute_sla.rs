@@ -0,0 +1,206 @@
+use crate::{
+    can_settle_dispute, handle_resolve, has_open_dispute, ClientInfo, Policy, Record, TxType,
+};
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use std::collections::{BTreeSet, HashMap};
+
+/// Resolves the disputes that were not settled within `sla` of the dispute record. Time is
+/// told by the timestamps of the records: a dispute is due once a record with a timestamp
+/// past its deadline is read, and a dispute without a timestamp never is.
+pub struct DisputeSla {
+    sla: Duration,
+    /// deadline, client and tx id of the disputes opened with a timestamp, oldest first.
+    /// Disputes settled since are dropped when they come due
+    deadlines: BTreeSet<(DateTime<Utc>, u16, u32)>,
+}
+
+impl DisputeSla {
+    pub fn new(sla: Duration) -> Self {
+        DisputeSla {
+            sla,
+            deadlines: BTreeSet::new(),
+        }
+    }
+
+    /// Starts the clock of the dispute `record` opened, if it did and has a timestamp.
+    pub fn applied(&mut self, client_map: &HashMap<u16, ClientInfo>, record: &Record) {
+        if record.tx_type != TxType::Dispute {
+            return;
+        }
+        if let (Some(client), Some(at)) = (record.client, record.timestamp) {
+            let opened = client_map
+                .get(&client)
+                .is_some_and(|info| has_open_dispute(info, record.tx));
+            if let Some(deadline) = at.checked_add_signed(self.sla).filter(|_| opened) {
+                self.deadlines.insert((deadline, client, record.tx));
+            }
+        }
+    }
+
+    /// The clients of the disputes due at `now`, settled or not.
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<u16> {
+        self.deadlines
+            .iter()
+            .take_while(|(deadline, _, _)| *deadline <= now)
+            .map(|(_, client, _)| *client)
+            .collect()
+    }
+
+    /// Resolves the disputes due at `now` through the normal resolve handler, oldest first,
+    /// and logs each under the `audit` target. `position` is the position of the record
+    /// that told the time; each resolve takes the next number of `sequence`. Returns the
+    /// resolve records applied.
+    pub fn expire(
+        &mut self,
+        now: DateTime<Utc>,
+        client_map: &mut HashMap<u16, ClientInfo>,
+        policy: &Policy,
+        position: u64,
+        sequence: &mut u64,
+    ) -> Vec<Record> {
+        let mut resolved = Vec::new();
+        while let Some(&(deadline, client, tx)) = self.deadlines.first() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop_first();
+            // settled in time, or on an account that can no longer settle it
+            let due = client_map.get(&client).is_some_and(|info| {
+                has_open_dispute(info, tx) && can_settle_dispute(info, tx, policy)
+            });
+            if !due {
+                continue;
+            }
+            *sequence += 1;
+            let mut record = resolve(client, tx, deadline, position);
+            record.sequence = *sequence;
+            handle_resolve(client_map, record.clone(), policy);
+            info!(
+                target: "audit",
+                "seq {} client {} tx {} auto-resolved, dispute open past {}",
+                sequence,
+                client,
+                tx,
+                deadline.to_rfc3339()
+            );
+            resolved.push(record);
+        }
+        resolved
+    }
+}
+
+/// The resolve record settling a dispute that ran past its deadline, stamped with the
+/// deadline and marked in its memo.
+fn resolve(client: u16, tx: u32, deadline: DateTime<Utc>, position: u64) -> Record {
+    Record {
+        tx_type: TxType::Resolve,
+        client: Some(client),
+        tx,
+        amount: None,
+        fee: None,
+        memo: Some("auto-resolved".to_string()),
+        evidence: None,
+        timestamp: Some(deadline),
+        position,
+        line: 0,
+        sequence: 0,
+        next_position: None,
+        tags: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_record, timestamp, transactions::TxState};
+    use csv::Trim;
+    use rust_decimal::Decimal;
+
+    /// Applies the records of `input` the way the binary does with `--dispute-sla-days`,
+    /// returns the accounts and the resolves applied.
+    fn run(input: &str, days: i64) -> (HashMap<u16, ClientInfo>, Vec<Record>) {
+        let policy = Policy::default();
+        let mut sla = DisputeSla::new(Duration::days(days));
+        let mut client_map = HashMap::new();
+        let mut sequence = 0;
+        let mut resolved = Vec::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        for (position, record) in reader.deserialize().enumerate() {
+            let mut record: Record = record.unwrap();
+            record.position = position as u64 + 1;
+            if let Some(now) = record.timestamp {
+                resolved.extend(sla.expire(
+                    now,
+                    &mut client_map,
+                    &policy,
+                    record.position,
+                    &mut sequence,
+                ));
+            }
+            let applied = record.clone();
+            apply_record(&mut client_map, record, &policy).unwrap();
+            sla.applied(&client_map, &applied);
+        }
+        (client_map, resolved)
+    }
+
+    fn balances(client_map: &HashMap<u16, ClientInfo>, client: u16) -> (Decimal, Decimal) {
+        let info = &client_map[&client];
+        (info.available_funds, info.held_funds)
+    }
+
+    #[test]
+    fn resolves_a_dispute_past_its_deadline() {
+        let (client_map, resolved) = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,2022-03-01T00:00:00Z
+            dispute,1,1,,2022-03-02T00:00:00Z
+            deposit,2,2,1,2022-04-16T00:00:00Z",
+            45,
+        );
+        assert_eq!(balances(&client_map, 1), (Decimal::from(10), Decimal::ZERO));
+        assert_eq!(
+            client_map[&1].transaction(1).unwrap().state,
+            TxState::Resolved
+        );
+        let resolve = &resolved[0];
+        assert_eq!(resolve.tx_type, TxType::Resolve);
+        assert_eq!(resolve.memo.as_deref(), Some("auto-resolved"));
+        assert_eq!(resolve.timestamp, timestamp::parse("2022-04-16T00:00:00Z"));
+    }
+
+    #[test]
+    fn keeps_a_dispute_open_until_its_deadline() {
+        let (client_map, _) = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,2022-03-01T00:00:00Z
+            dispute,1,1,,2022-03-02T00:00:00Z
+            deposit,2,2,1,2022-04-15T23:59:59Z",
+            45,
+        );
+        assert_eq!(balances(&client_map, 1), (Decimal::ZERO, Decimal::from(10)));
+    }
+
+    #[test]
+    fn only_resolves_open_disputes_with_a_timestamp() {
+        // the dispute of tx 2 has no timestamp and never expires
+        let (client_map, _) = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,1646092800000
+            deposit,1,2,5,1646092800000
+            dispute,1,1,,1646179200000
+            resolve,1,1,,1646222400000
+            dispute,1,2,,
+            deposit,2,3,1,1672531200000",
+            1,
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(10), Decimal::from(5))
+        );
+        assert_eq!(client_map[&1].kept, 5);
+    }
+}
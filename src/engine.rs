@@ -0,0 +1,392 @@
+use crate::{
+    apply_record, charged_back, credit_suspense,
+    dispute_sla::DisputeSla,
+    funding::FundingLinks,
+    pipeline::State,
+    policy::{Policy, Refused},
+    Balance, Outcome, Record, TxError, TxType,
+};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Applies records to client accounts under a policy, with the funding links, suspense
+/// account and dispute SLA of the command line. Records are applied as given; the checks
+/// of the command line (missing clients, duplicates, remapping, screening, ...) are
+/// transforms of a pipeline built around an engine.
+pub struct Engine {
+    state: State,
+    policy: Policy,
+    funding_links: FundingLinks,
+    suspense_account: Option<u16>,
+    dispute_sla: Option<DisputeSla>,
+}
+
+/// Outcome of a run: the final balances sorted by client and the record counts.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    pub balances: Vec<Balance>,
+    /// records given to the engine
+    pub records: u64,
+    /// records that reached their handler, the last sequence number handed out
+    pub accepted: u64,
+}
+
+impl Engine {
+    pub fn new(policy: Policy) -> Self {
+        Engine {
+            state: State::default(),
+            policy,
+            funding_links: FundingLinks::default(),
+            suspense_account: None,
+            dispute_sla: None,
+        }
+    }
+
+    /// Sweeps the shortfall of a withdrawal from the funding client before it is applied.
+    pub(crate) fn with_funding_links(mut self, funding_links: FundingLinks) -> Self {
+        self.funding_links = funding_links;
+        self
+    }
+
+    /// Internal account chargeback funds are booked to.
+    pub fn with_suspense_account(mut self, suspense_account: Option<u16>) -> Self {
+        self.suspense_account = suspense_account;
+        self.open_suspense_account();
+        self
+    }
+
+    /// Disputes still open `sla` after they were opened are resolved.
+    pub fn with_dispute_sla(mut self, sla: Option<Duration>) -> Self {
+        self.dispute_sla = sla.map(DisputeSla::new);
+        self
+    }
+
+    /// Continues from `state` instead of an empty one, e.g. the one an earlier run left.
+    pub(crate) fn with_state(mut self, state: State) -> Self {
+        self.state = state;
+        self.open_suspense_account();
+        self
+    }
+
+    fn open_suspense_account(&mut self) {
+        if let Some(suspense) = self.suspense_account {
+            // opened up front so it is always in the output and never handed out as a
+            // generated client id
+            self.state.client_map.entry(suspense).or_default();
+        }
+    }
+
+    /// The accounts applying `record` can change.
+    pub(crate) fn accounts_of(&self, record: &Record) -> Vec<u16> {
+        let mut accounts: Vec<u16> = record.client.into_iter().collect();
+        match record.tx_type {
+            TxType::Withdrawal => accounts.extend(
+                record
+                    .client
+                    .and_then(|client| self.funding_links.funding_account(client)),
+            ),
+            TxType::Chargeback => accounts.extend(self.suspense_account),
+            _ => {}
+        }
+        accounts
+    }
+
+    /// The clients of the disputes a record at `now` resolves through the dispute SLA.
+    pub(crate) fn due_disputes(&self, now: DateTime<Utc>) -> Vec<u16> {
+        self.dispute_sla
+            .as_ref()
+            .map_or_else(Vec::new, |sla| sla.due(now))
+    }
+
+    pub(crate) fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    pub(crate) fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub(crate) fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub(crate) fn into_state(self) -> State {
+        self.state
+    }
+
+    /// Applies one record and tells what its handler did with it. Fails on an unknown
+    /// transaction type or a refused record if the policy says to abort.
+    pub fn apply(&mut self, mut record: Record) -> Result<Outcome, TxError> {
+        self.read(&mut record);
+        self.accept(record)
+    }
+
+    /// Counts a record read from the input and stamps its position, before any check runs,
+    /// unless the source numbered it already.
+    /// A record with a timestamp moves the clock, resolving the disputes open past the
+    /// dispute SLA; returns those resolves.
+    pub(crate) fn read(&mut self, record: &mut Record) -> Vec<Record> {
+        let state = &mut self.state;
+        state.processed += 1;
+        state.fees_seen |= record.fee.is_some();
+        // a record without a timestamp counts as at the time of the one before
+        state.clock = record.timestamp.or(state.clock);
+        // the shard router numbers records over all shards
+        if record.position == 0 {
+            record.position = state.processed;
+        }
+        if record.next_position.is_some() {
+            state.next_position = record.next_position.clone();
+        }
+        let resolved = match (self.dispute_sla.as_mut(), record.timestamp) {
+            (Some(sla), Some(now)) => sla.expire(
+                now,
+                &mut state.client_map,
+                &self.policy,
+                state.processed,
+                &mut state.sequence,
+            ),
+            _ => Vec::new(),
+        };
+        state
+            .touched
+            .extend(resolved.iter().filter_map(|resolve| resolve.client));
+        resolved
+    }
+
+    /// Numbers a record that passed every check and hands it to its handler, sweeping the
+    /// shortfall of a withdrawal from the funding client first and booking a chargeback to
+    /// the suspense account after.
+    pub(crate) fn accept(&mut self, mut record: Record) -> Result<Outcome, TxError> {
+        let state = &mut self.state;
+        // numbered here, after every check, so accepted records are numbered without gaps
+        // no matter how the source produced them; the shard router numbers them before
+        if record.sequence == 0 {
+            state.sequence += 1;
+            record.sequence = state.sequence;
+        } else {
+            state.sequence = record.sequence;
+        }
+        info!(
+            target: "audit",
+            "seq {} {} client {} tx {} tags {}",
+            record.sequence,
+            record.tx_type,
+            record.client.map_or("-".to_string(), |client| client.to_string()),
+            record.tx,
+            if record.tags.is_empty() {
+                "-".to_string()
+            } else {
+                record.tags.join(",")
+            }
+        );
+        let client = record.client;
+        state.touched.extend(client);
+        // kept to start the clock of a dispute once it is applied
+        let sla_record = self.dispute_sla.is_some().then(|| record.clone());
+        #[cfg(feature = "sql")]
+        if let Some(audit) = state.audit.as_mut() {
+            audit.read(&record, &state.client_map);
+        }
+        let mut sweep = None;
+        if let (TxType::Withdrawal, Some(client), Some(amount)) =
+            (&record.tx_type, record.client, record.amount)
+        {
+            // an amount that overflows with its fee is refused by the handler, nothing to sweep
+            if let Some(amount) = amount.checked_add(record.fee.unwrap_or(Decimal::ZERO)) {
+                sweep = self.funding_links.sweep_shortfall(
+                    &mut state.client_map,
+                    client,
+                    amount,
+                    record.tx,
+                );
+                state.touched.extend(sweep.map(|sweep| sweep.funding_id));
+            }
+        }
+        let chargeback = (record.tx_type == TxType::Chargeback).then_some(record.tx);
+        // kept for the error when a refusal aborts the run
+        let aborts = (self.policy.refused == Refused::Abort).then(|| record.clone());
+        let outcome = apply_record(&mut state.client_map, record, &self.policy)?;
+        if let (Outcome::Refused(_), Some(sweep)) = (outcome, sweep) {
+            sweep.undo(&mut state.client_map);
+        }
+        if let (Outcome::Refused(reason), Some(record)) = (outcome, aborts) {
+            error!(
+                "record at position {} refused: {:?}",
+                record.position, record
+            );
+            return Err(TxError::refused(&record, reason));
+        }
+        if let (Outcome::Applied, Some(tx), Some(suspense)) =
+            (outcome, chargeback, self.suspense_account)
+        {
+            if let Some(amount) = state
+                .account(client)
+                .and_then(|info| charged_back(info, tx))
+            {
+                credit_suspense(&mut state.client_map, suspense, amount);
+                state.touched.push(suspense);
+            }
+        }
+        #[cfg(feature = "sql")]
+        if let Some(audit) = state.audit.as_mut() {
+            audit.applied(&state.client_map);
+        }
+        if let (Some(sla), Some(record)) = (self.dispute_sla.as_mut(), sla_record) {
+            sla.applied(&state.client_map, &record);
+        }
+        Ok(outcome)
+    }
+
+    /// Current balances of every account, sorted by client.
+    pub fn balances(&self) -> Vec<Balance> {
+        let mut balances: Vec<Balance> = self
+            .state
+            .client_map
+            .iter()
+            .map(|(client, info)| Balance::of(*client, info))
+            .collect();
+        balances.sort_by_key(|balance| balance.client);
+        balances
+    }
+
+    pub fn into_report(self) -> Report {
+        Report {
+            balances: self.balances(),
+            records: self.state.processed,
+            accepted: self.state.sequence,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{events::RejectReason, funding::FundingLinks};
+    use std::fs;
+
+    fn record(tx_type: &str, client: u16, tx: u32, amount: Option<&str>) -> Record {
+        Record {
+            tx_type: TxType::from(tx_type),
+            client: Some(client),
+            tx,
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn applies_records_and_reports_the_balances() {
+        let links = std::env::temp_dir().join(format!("engine-links-{}.csv", std::process::id()));
+        fs::write(&links, "client,funding_client\n1,2\n").unwrap();
+        let funding_links = FundingLinks::from_path(&links).unwrap();
+        fs::remove_file(&links).unwrap();
+        let mut engine = Engine::new(Policy::default())
+            .with_funding_links(funding_links)
+            .with_suspense_account(Some(9));
+        let outcomes: Vec<Outcome> = [
+            record("deposit", 1, 1, Some("5")),
+            record("deposit", 2, 2, Some("10")),
+            record("withdrawal", 1, 3, Some("8")),
+            record("withdrawal", 1, 4, Some("20")),
+            record("dispute", 2, 2, None),
+            record("chargeback", 2, 2, None),
+        ]
+        .into_iter()
+        .map(|record| engine.apply(record).unwrap())
+        .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Applied,
+                Outcome::Applied,
+                Outcome::Applied,
+                Outcome::Refused(RejectReason::InsufficientFunds),
+                Outcome::Applied,
+                Outcome::Applied,
+            ]
+        );
+        let balance = |client, available: &str, total: &str, locked| Balance {
+            client,
+            available: available.parse().unwrap(),
+            held: Decimal::ZERO,
+            total: total.parse().unwrap(),
+            locked,
+        };
+        // the shortfall of tx 3 came from client 2, whose deposit went to the suspense account
+        assert_eq!(
+            engine.into_report(),
+            Report {
+                balances: vec![
+                    balance(1, "0", "0", false),
+                    balance(2, "-3", "-3", true),
+                    balance(9, "10", "10", false),
+                ],
+                records: 6,
+                accepted: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn overflowing_records_are_refused() {
+        let mut engine = Engine::new(Policy::default());
+        let max = Decimal::MAX.to_string();
+        let outcomes: Vec<Outcome> = [
+            record("deposit", 1, 1, Some(&max)),
+            record("deposit", 1, 2, Some("1")),
+            record("withdrawal", 1, 3, Some(&max)),
+            record("deposit", 1, 4, Some("1")),
+        ]
+        .into_iter()
+        .map(|record| engine.apply(record).unwrap())
+        .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Applied,
+                Outcome::Refused(RejectReason::Overflow),
+                Outcome::Applied,
+                Outcome::Applied,
+            ]
+        );
+        assert_eq!(engine.balances()[0].total, Decimal::ONE);
+    }
+
+    #[test]
+    fn negative_amounts_are_refused() {
+        let mut engine = Engine::new(Policy::default());
+        let outcomes: Vec<Outcome> = [
+            record("deposit", 1, 1, Some("10")),
+            record("withdrawal", 1, 2, Some("-5")),
+            record("dispute", 1, 2, None),
+            record("deposit", 1, 3, Some("-1")),
+        ]
+        .into_iter()
+        .map(|record| engine.apply(record).unwrap())
+        .collect();
+        assert_eq!(
+            outcomes[1..],
+            [
+                Outcome::Refused(RejectReason::InvalidAmount),
+                Outcome::Refused(RejectReason::TxNotFound),
+                Outcome::Refused(RejectReason::InvalidAmount),
+            ]
+        );
+        let balance = &engine.balances()[0];
+        assert_eq!(
+            (balance.available, balance.held),
+            (Decimal::TEN, Decimal::ZERO)
+        );
+    }
+}
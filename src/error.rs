@@ -0,0 +1,289 @@
+use crate::{events::RejectReason, minor_units::AmountError, transactions::TxState, Record};
+use std::{error::Error, fmt, io};
+
+/// Why a run or an applied record failed, the error of [`Engine::apply`](crate::Engine::apply),
+/// [`Pipeline::run`](crate::pipeline::Pipeline::run) and every stage of a pipeline. Sources
+/// report rows they can not read as `ParseError`.
+#[derive(Debug)]
+pub enum TxError {
+    /// an input row could not be read as a record
+    ParseError {
+        line: Option<u64>,
+        message: String,
+    },
+    UnknownTxType {
+        tx: u32,
+        tx_type: String,
+    },
+    MissingClient {
+        tx: u32,
+    },
+    MissingAmount {
+        tx: u32,
+    },
+    DuplicateTx {
+        tx: u32,
+    },
+    InsufficientFunds {
+        client: Option<u16>,
+        tx: u32,
+    },
+    AccountLocked {
+        client: Option<u16>,
+        tx: u32,
+    },
+    TxNotFound {
+        client: Option<u16>,
+        tx: u32,
+    },
+    /// a dispute, resolve or chargeback the referenced tx can not take from its state
+    InvalidTransition {
+        client: Option<u16>,
+        tx: u32,
+        state: Option<TxState>,
+        tx_type: String,
+    },
+    /// a record refused for a reason without a variant of its own
+    Refused {
+        tx: u32,
+        reason: RejectReason,
+    },
+    /// an account broke a balance invariant under `--check-invariants`
+    InvariantViolated {
+        client: u16,
+        tx: u32,
+        violation: String,
+    },
+    Amount(AmountError),
+    Io(io::Error),
+    Other(Box<dyn Error>),
+}
+
+impl TxError {
+    /// An input row that could not be read as a record, when the line is not known.
+    pub fn malformed(err: impl fmt::Display) -> Self {
+        TxError::ParseError {
+            line: None,
+            message: err.to_string(),
+        }
+    }
+
+    /// Any other error, e.g. of an output file format.
+    pub fn other(err: impl Into<Box<dyn Error>>) -> Self {
+        TxError::Other(err.into())
+    }
+
+    /// The error for a record refused under the `abort` policy.
+    pub fn refused(record: &Record, reason: RejectReason) -> Self {
+        let (client, tx) = (record.client, record.tx);
+        match reason {
+            RejectReason::MissingClient => TxError::MissingClient { tx },
+            RejectReason::MissingAmount => TxError::MissingAmount { tx },
+            RejectReason::DuplicateTx => TxError::DuplicateTx { tx },
+            RejectReason::UnknownType => TxError::UnknownTxType {
+                tx,
+                tx_type: record.tx_type.to_string(),
+            },
+            RejectReason::AccountLocked => TxError::AccountLocked { client, tx },
+            RejectReason::InsufficientFunds => TxError::InsufficientFunds { client, tx },
+            RejectReason::TxNotFound => TxError::TxNotFound { client, tx },
+            RejectReason::InvalidTransition => TxError::InvalidTransition {
+                client,
+                tx,
+                state: None,
+                tx_type: record.tx_type.to_string(),
+            },
+            reason => TxError::Refused { tx, reason },
+        }
+    }
+
+    /// Process exit code of the command line for the error: 2 for unreadable input, 3 for a
+    /// record the policy aborts on, 4 for I/O errors and 1 for anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TxError::ParseError { .. } => 2,
+            TxError::Io(_) => 4,
+            TxError::Other(_) | TxError::InvariantViolated { .. } => 1,
+            _ => 3,
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let client = |client: &Option<u16>| client.map_or("-".to_string(), |c| c.to_string());
+        match self {
+            TxError::ParseError {
+                line: Some(line),
+                message,
+            } => write!(f, "malformed row at line {}: {}", line, message),
+            TxError::ParseError {
+                line: None,
+                message,
+            } => write!(f, "malformed row: {}", message),
+            TxError::UnknownTxType { tx, tx_type } => {
+                write!(f, "unknown transaction type {:?} in tx {}", tx_type, tx)
+            }
+            TxError::MissingClient { tx } => write!(f, "client id missing in tx {}", tx),
+            TxError::MissingAmount { tx } => write!(f, "amount missing or malformed in tx {}", tx),
+            TxError::DuplicateTx { tx } => write!(f, "duplicate tx id {}", tx),
+            TxError::InsufficientFunds { client: c, tx } => write!(
+                f,
+                "insufficient funds of client {} for tx {}",
+                client(c),
+                tx
+            ),
+            TxError::AccountLocked { client: c, tx } => {
+                write!(f, "account of client {} is locked, tx {}", client(c), tx)
+            }
+            TxError::TxNotFound { client: c, tx } => {
+                write!(f, "tx {} not found for client {}", tx, client(c))
+            }
+            TxError::InvalidTransition {
+                client: c,
+                tx,
+                state: Some(state),
+                tx_type,
+            } => write!(
+                f,
+                "tx {} of client {} is {} and can not take a {}",
+                tx,
+                client(c),
+                state,
+                tx_type
+            ),
+            TxError::InvalidTransition {
+                client: c,
+                tx,
+                state: None,
+                tx_type,
+            } => write!(
+                f,
+                "tx {} of client {} can not take a {}",
+                tx,
+                client(c),
+                tx_type
+            ),
+            TxError::Refused { tx, reason } => write!(f, "tx {} refused: {}", tx, reason.as_str()),
+            TxError::InvariantViolated {
+                client,
+                tx,
+                violation,
+            } => write!(
+                f,
+                "invariant violated on the account of client {} by tx {}: {}",
+                client, tx, violation
+            ),
+            TxError::Amount(err) => err.fmt(f),
+            TxError::Io(err) => err.fmt(f),
+            TxError::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for TxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TxError::Amount(err) => Some(err),
+            TxError::Io(err) => Some(err),
+            TxError::Other(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TxError {
+    fn from(err: io::Error) -> Self {
+        TxError::Io(err)
+    }
+}
+
+/// Formatting into a `String` only fails with a broken `Display` implementation.
+impl From<fmt::Error> for TxError {
+    fn from(err: fmt::Error) -> Self {
+        TxError::Other(Box::new(err))
+    }
+}
+
+impl From<AmountError> for TxError {
+    fn from(err: AmountError) -> Self {
+        TxError::Amount(err)
+    }
+}
+
+/// Rows that can not be read are a `ParseError` with their line, failing reads and writes
+/// an `Io` error.
+impl From<csv::Error> for TxError {
+    fn from(err: csv::Error) -> Self {
+        let line = err.position().map(csv::Position::line);
+        let message = err.to_string();
+        match err.into_kind() {
+            csv::ErrorKind::Io(err) => TxError::Io(err),
+            csv::ErrorKind::Serialize(message) => TxError::Other(message.into()),
+            _ => TxError::ParseError { line, message },
+        }
+    }
+}
+
+/// Sources tell unreadable JSON apart themselves, see [`TxError::malformed`].
+impl From<serde_json::Error> for TxError {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            TxError::Io(err.into())
+        } else {
+            TxError::Other(Box::new(err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TxType;
+
+    #[test]
+    fn unreadable_rows_keep_their_line() {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader("type,client,tx,amount\ndeposit,1,x,3\n".as_bytes());
+        let err = TxError::from(reader.deserialize::<Record>().next().unwrap().unwrap_err());
+        assert!(
+            matches!(err, TxError::ParseError { line: Some(2), .. }),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[test]
+    fn refusals_map_to_their_variant() {
+        let record: Record = Record {
+            tx_type: TxType::Withdrawal,
+            client: Some(4),
+            tx: 9,
+            amount: None,
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        };
+        let err = TxError::refused(&record, RejectReason::InsufficientFunds);
+        assert!(matches!(
+            err,
+            TxError::InsufficientFunds {
+                client: Some(4),
+                tx: 9
+            }
+        ));
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(
+            TxError::refused(&record, RejectReason::Overflow).to_string(),
+            "tx 9 refused: overflow"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    events::EngineEvent,
+    pipeline::{Sink, State},
+    TxError,
+};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::mpsc::Receiver,
+};
+
+/// An applied record and the balances it left the account with.
+#[derive(Serialize, Debug)]
+struct EventRow {
+    sequence: u64,
+    #[serde(rename = "type")]
+    tx_type: String,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Utc>>,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// JSON lines of every applied record with the resulting balances of its account, so the
+/// effect of each transaction can be audited and not just the end state.
+pub struct EventLog {
+    out: BufWriter<File>,
+    events: Receiver<EngineEvent>,
+}
+
+impl EventLog {
+    pub fn new(path: &Path, events: Receiver<EngineEvent>) -> Result<Self, TxError> {
+        Ok(EventLog {
+            out: BufWriter::new(File::create(path)?),
+            events,
+        })
+    }
+
+    /// Writes the records applied since the last call. Called right after each record, so
+    /// the account in `state` holds the balances that record left.
+    fn drain(&mut self, state: &State) -> Result<(), TxError> {
+        for event in self.events.try_iter() {
+            let (sequence, client, tx, tx_type, amount, tags, timestamp) = match event {
+                EngineEvent::Applied {
+                    sequence,
+                    client,
+                    tx,
+                    tx_type,
+                    amount,
+                    tags,
+                    timestamp,
+                } => (sequence, client, tx, tx_type, amount, tags, timestamp),
+                _ => continue,
+            };
+            let info = match state.client_map.get(&client) {
+                Some(info) => info,
+                None => continue,
+            };
+            let row = EventRow {
+                sequence,
+                tx_type,
+                client,
+                tx,
+                amount,
+                tags,
+                timestamp,
+                available: info.available_funds,
+                held: info.held_funds,
+                total: info.total_funds,
+                locked: info.locked,
+            };
+            serde_json::to_writer(&mut self.out, &row)?;
+            self.out.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for EventLog {
+    fn applied(&mut self, _client: u16, state: &State) -> Result<(), TxError> {
+        self.drain(state)
+    }
+
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        self.drain(state)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{csv_records, pipeline::PipelineBuilder};
+    use std::fs;
+
+    #[test]
+    fn every_applied_record_is_written_with_the_balances_it_left() {
+        let path = std::env::temp_dir().join(format!("events-{}.jsonl", std::process::id()));
+        let reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,
+            withdrawal,1,2,15,
+            withdrawal,1,3,4,
+            dispute,1,1,,1646136000000"
+                .as_bytes(),
+        );
+        let mut pipeline = PipelineBuilder::new().source(csv_records(reader, 1).unwrap());
+        let events = pipeline.subscribe();
+        pipeline
+            .sink(EventLog::new(&path, events).unwrap())
+            .build()
+            .run()
+            .unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let rows: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // the refused withdrawal is not listed, the dispute tells the amount it holds and has
+        // its timestamp
+        assert_eq!(
+            rows,
+            vec![
+                serde_json::json!({"sequence": 1, "type": "deposit", "client": 1, "tx": 1,
+                    "amount": "10", "available": "10", "held": "0", "total": "10",
+                    "locked": false}),
+                serde_json::json!({"sequence": 3, "type": "withdrawal", "client": 1, "tx": 3,
+                    "amount": "4", "available": "6", "held": "0", "total": "6",
+                    "locked": false}),
+                serde_json::json!({"sequence": 4, "type": "dispute", "client": 1, "tx": 1,
+                    "amount": "10", "timestamp": "2022-03-01T12:00:00Z", "available": "-4", "held": "10", "total": "6",
+                    "locked": false}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use crate::{locks::LockReason, transactions::TxState, ClientInfo, Outcome, Record, TxType};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// What the engine did with a record, for subscribers inside the process.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    /// the record was applied to the client's account
+    Applied {
+        sequence: u64,
+        client: u16,
+        tx: u32,
+        tx_type: String,
+        /// amount of the record, or of the transaction a dispute, resolve, chargeback or
+        /// void refers to
+        amount: Option<Decimal>,
+        /// tags the rules file gave the record
+        tags: Vec<String>,
+        timestamp: Option<DateTime<Utc>>,
+    },
+    /// the record was dropped by a check or refused by its handler, the details are logged
+    Rejected {
+        position: u64,
+        /// line of the record in CSV input, 0 for other inputs
+        line: u64,
+        client: Option<u16>,
+        tx: u32,
+        tx_type: String,
+        amount: Option<Decimal>,
+        fee: Option<Decimal>,
+        timestamp: Option<DateTime<Utc>>,
+        reason: RejectReason,
+    },
+    /// an input row could not be read as a record and was skipped
+    Malformed {
+        /// line of the row in CSV input, when known
+        line: Option<u64>,
+        error: String,
+    },
+    AccountLocked {
+        client: u16,
+        reason: LockReason,
+    },
+    /// a dispute, resolve or chargeback was refused because the transaction it refers to
+    /// can not take that step from its dispute state, sent after the `Rejected` event
+    InvalidTransition {
+        client: u16,
+        tx: u32,
+        state: TxState,
+        tx_type: String,
+    },
+    DisputeOpened {
+        client: u16,
+        tx: u32,
+    },
+    DisputeClosed {
+        client: u16,
+        tx: u32,
+        outcome: DisputeOutcome,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeOutcome {
+    Resolved,
+    ChargedBack,
+}
+
+/// Why a record was rejected, as a stable machine readable code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    MissingClient,
+    MissingAmount,
+    DuplicateTx,
+    /// the client is on the denylist
+    Denylisted,
+    UnknownType,
+    AccountLocked,
+    /// a withdrawal over the available funds
+    InsufficientFunds,
+    /// the tx a dispute, resolve or chargeback refers to is not on the account
+    TxNotFound,
+    /// the tx the record refers to can not take this step, e.g. a void of a disputed tx
+    InvalidTransition,
+    /// applying the record would overflow a balance
+    Overflow,
+    /// the amount or fee of a deposit or withdrawal is negative, or the fee of a deposit
+    /// exceeds its amount
+    InvalidAmount,
+    /// the withdrawal would take the client past a rolling 24 hour withdrawal limit
+    WithdrawalLimit,
+    /// the client posted more records than `--throttle-rate` lets through
+    Throttled,
+    /// dropped by a check without a more specific reason
+    Filtered,
+    /// the input row could not be read as a record
+    Malformed,
+    /// the record is outside the `--from`/`--to` window
+    OutsideWindow,
+}
+
+impl RejectReason {
+    pub const ALL: [RejectReason; 16] = [
+        RejectReason::MissingClient,
+        RejectReason::MissingAmount,
+        RejectReason::DuplicateTx,
+        RejectReason::Denylisted,
+        RejectReason::UnknownType,
+        RejectReason::AccountLocked,
+        RejectReason::InsufficientFunds,
+        RejectReason::TxNotFound,
+        RejectReason::InvalidTransition,
+        RejectReason::Overflow,
+        RejectReason::InvalidAmount,
+        RejectReason::WithdrawalLimit,
+        RejectReason::Throttled,
+        RejectReason::Filtered,
+        RejectReason::Malformed,
+        RejectReason::OutsideWindow,
+    ];
+
+    /// The code as serialized, e.g. `insufficient_funds`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RejectReason::MissingClient => "missing_client",
+            RejectReason::MissingAmount => "missing_amount",
+            RejectReason::DuplicateTx => "duplicate_tx",
+            RejectReason::Denylisted => "denylisted",
+            RejectReason::UnknownType => "unknown_type",
+            RejectReason::AccountLocked => "account_locked",
+            RejectReason::InsufficientFunds => "insufficient_funds",
+            RejectReason::TxNotFound => "tx_not_found",
+            RejectReason::InvalidTransition => "invalid_transition",
+            RejectReason::Overflow => "overflow",
+            RejectReason::InvalidAmount => "invalid_amount",
+            RejectReason::WithdrawalLimit => "withdrawal_limit",
+            RejectReason::Throttled => "throttled",
+            RejectReason::Filtered => "filtered",
+            RejectReason::Malformed => "malformed",
+            RejectReason::OutsideWindow => "outside_window",
+        }
+    }
+
+    /// The reason of a code as serialized, `None` for a code that is not one.
+    pub fn from_code(code: &str) -> Option<RejectReason> {
+        RejectReason::ALL
+            .into_iter()
+            .find(|reason| reason.as_str() == code)
+    }
+
+    /// What the code means and how to fix the input, as printed by `explain`.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            RejectReason::MissingClient => {
+                "The record has no client id. Fill in the client column, or run with \
+                 `--missing-client generate` to give such records a new client id of their own."
+            }
+            RejectReason::MissingAmount => {
+                "A deposit or withdrawal has no amount, or one that can not be read as a \
+                 number. Fix the amount column, e.g. `1.5` with a `.` for the decimal point, or \
+                 run with `--missing-amount record` to keep such records without moving funds."
+            }
+            RejectReason::DuplicateTx => {
+                "A deposit or withdrawal reuses the tx id of an earlier one. Give every deposit \
+                 and withdrawal its own tx id, or run with `--duplicate-tx allow` if the ids \
+                 are only unique per client."
+            }
+            RejectReason::Denylisted => {
+                "The client is on the `--denylist`, so its records are blocked and its account \
+                 frozen. Take the client off the denylist if it was listed by mistake."
+            }
+            RejectReason::UnknownType => {
+                "The type column is not one of deposit, withdrawal, dispute, resolve, \
+                 chargeback or void, written in lower case. Fix the type of the row."
+            }
+            RejectReason::AccountLocked => {
+                "The account was locked by a chargeback and takes no more records. Accounts \
+                 stay locked; `--locked-account settle_open_disputes` still lets disputes that \
+                 were open at the time be resolved or charged back."
+            }
+            RejectReason::InsufficientFunds => {
+                "A withdrawal is more than the available funds of the account. Funds held by \
+                 open disputes are not available. Check the amounts and that the deposits come \
+                 first."
+            }
+            RejectReason::TxNotFound => {
+                "A dispute, resolve or chargeback refers to a tx id that is not a deposit of \
+                 the same client. Check the tx and client ids of the record; the referenced \
+                 deposit has to come before it."
+            }
+            RejectReason::InvalidTransition => {
+                "The referenced transaction can not take this step: a second dispute, a \
+                 resolve or chargeback of a transaction not under dispute, a dispute or void of \
+                 a voided transaction, a void of a disputed one, or a void after the day of the \
+                 transaction, once it is settled. Check the order of the records of the \
+                 transaction."
+            }
+            RejectReason::Overflow => {
+                "Applying the record would take a balance past the largest amount that can be \
+                 held. Check the amount for a misplaced decimal point."
+            }
+            RejectReason::InvalidAmount => {
+                "The amount or fee of a deposit or withdrawal is negative, or the fee of a deposit \
+                 is more than its amount, so it would move funds the wrong way. Check the amount \
+                 and fee columns of the row."
+            }
+            RejectReason::WithdrawalLimit => {
+                "The withdrawal would take the client past `--withdrawal-limit-count` \
+                 withdrawals or `--withdrawal-limit-amount` withdrawn in the 24 hours up to it. \
+                 It can be sent again once older withdrawals have left the window."
+            }
+            RejectReason::Throttled => {
+                "The client posted records faster than `--throttle-rate` and `--throttle-burst` \
+                 let through, and the record was not applied. Send it again later, or slow \
+                 down the records of the client."
+            }
+            RejectReason::Filtered => {
+                "A check of the run dropped the record without a more specific reason. The log \
+                 of the run tells which check and why."
+            }
+            RejectReason::Malformed => {
+                "The input row could not be read as a record, e.g. a tx id that is not a \
+                 number or a missing column. The `detail` column holds the parse error; fix \
+                 the row, or run with `--malformed-row abort` to stop at the first one."
+            }
+            RejectReason::OutsideWindow => {
+                "The record is before `--from` or not before `--to`, so it was skipped on \
+                 purpose. A record without a timestamp counts as at the time of the record \
+                 before it. Widen the window if the record should be applied."
+            }
+        }
+    }
+}
+
+/// Fans events out to every subscriber. Subscribers whose receiver is gone are dropped.
+#[derive(Default)]
+pub struct Subscribers(Vec<Sender<EngineEvent>>);
+
+impl Subscribers {
+    pub fn subscribe(&mut self) -> Receiver<EngineEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.0.push(sender);
+        receiver
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn publish(&mut self, event: EngineEvent) {
+        self.0.retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+/// The locks an account had, to tell which ones a record added.
+pub struct AccountMark {
+    locks: usize,
+}
+
+impl AccountMark {
+    pub fn of(info: Option<&ClientInfo>) -> Self {
+        AccountMark {
+            locks: info.map_or(0, |info| info.lock_history.len()),
+        }
+    }
+}
+
+/// Lock events for every lock `info` got since `mark` was taken.
+pub fn locks_since(client: u16, mark: &AccountMark, info: &ClientInfo) -> Vec<EngineEvent> {
+    info.lock_history
+        .iter()
+        .skip(mark.locks)
+        .map(|event| EngineEvent::AccountLocked {
+            client,
+            reason: event.reason.clone(),
+        })
+        .collect()
+}
+
+/// Events for a record the engine handled, as its handler's `outcome` tells.
+pub fn handled(
+    record: &Record,
+    outcome: Outcome,
+    mark: &AccountMark,
+    info: Option<&ClientInfo>,
+) -> Vec<EngineEvent> {
+    let (client, info) = match (record.client, info, outcome) {
+        (Some(client), Some(info), _) => (client, info),
+        (_, _, Outcome::Refused(reason)) => return vec![rejected(record, reason)],
+        // an applied record without an account leaves nothing to tell
+        (_, _, Outcome::Applied) => return Vec::new(),
+    };
+    if let Outcome::Refused(reason) = outcome {
+        let mut events = vec![rejected(record, reason)];
+        let state = info.transaction(record.tx).map(|entry| entry.state);
+        if let (RejectReason::InvalidTransition, Some(state)) = (reason, state) {
+            if state.next(&record.tx_type).is_none()
+                && matches!(
+                    record.tx_type,
+                    TxType::Dispute | TxType::Resolve | TxType::Chargeback
+                )
+            {
+                events.push(EngineEvent::InvalidTransition {
+                    client,
+                    tx: record.tx,
+                    state,
+                    tx_type: record.tx_type.to_string(),
+                });
+            }
+        }
+        events.extend(locks_since(client, mark, info));
+        return events;
+    }
+
+    let amount = record.amount.or_else(|| {
+        info.transaction(record.tx)
+            .and_then(|entry| entry.net_amount())
+    });
+    let mut events = vec![EngineEvent::Applied {
+        sequence: record.sequence,
+        client,
+        tx: record.tx,
+        tx_type: record.tx_type.to_string(),
+        amount,
+        tags: record.tags.clone(),
+        timestamp: record.timestamp,
+    }];
+    let tx = record.tx;
+    match record.tx_type {
+        TxType::Dispute => events.push(EngineEvent::DisputeOpened { client, tx }),
+        TxType::Resolve => events.push(EngineEvent::DisputeClosed {
+            client,
+            tx,
+            outcome: DisputeOutcome::Resolved,
+        }),
+        TxType::Chargeback => events.push(EngineEvent::DisputeClosed {
+            client,
+            tx,
+            outcome: DisputeOutcome::ChargedBack,
+        }),
+        _ => {}
+    }
+    events.extend(locks_since(client, mark, info));
+    events
+}
+
+pub fn rejected(record: &Record, reason: RejectReason) -> EngineEvent {
+    EngineEvent::Rejected {
+        position: record.position,
+        line: record.line,
+        client: record.client,
+        tx: record.tx,
+        tx_type: record.tx_type.to_string(),
+        amount: record.amount,
+        fee: record.fee,
+        timestamp: record.timestamp,
+        reason,
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{process_input, Config};
+use glob::Pattern;
+use log::{error, info, warn};
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Flags that tune the shared drop folder mode.
+#[derive(Debug, Clone)]
+pub struct FileQueueOptions {
+    /// glob the file names have to match
+    pub pattern: String,
+    pub interval_secs: u64,
+    /// a lock older than this is assumed to belong to a crashed instance and is taken over
+    pub stale_after_secs: u64,
+}
+
+impl Default for FileQueueOptions {
+    fn default() -> Self {
+        FileQueueOptions {
+            pattern: "*.csv".to_string(),
+            interval_secs: 10,
+            stale_after_secs: 600,
+        }
+    }
+}
+
+/// Suffixes of the files written next to the queued ones, never queued themselves.
+const MARKER_SUFFIXES: [&str; 5] = [".out.csv", ".lock", ".done", ".failed", ".partial"];
+
+/// Claims made by this process, to tell its own locks apart.
+static CLAIMS: AtomicUsize = AtomicUsize::new(0);
+
+/// Marker files kept next to each queued file.
+struct Markers {
+    lock: PathBuf,
+    done: PathBuf,
+    failed: PathBuf,
+    output: PathBuf,
+}
+
+impl Markers {
+    fn new(path: &Path) -> Self {
+        let with = |suffix: &str| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        };
+        Markers {
+            lock: with(".lock"),
+            done: with(".done"),
+            failed: with(".failed"),
+            output: with(".out.csv"),
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.done.exists() || self.failed.exists()
+    }
+}
+
+/// Consumes the drop folder forever, alongside any number of other instances.
+///
+/// An instance claims a file by creating `<name>.lock` exclusively, so only one of them
+/// processes it. The lock names its owner and is touched while the file is processed, so
+/// only the locks of crashed instances go stale. The output goes to `<name>.out.csv` and a
+/// `<name>.done` marker is written on success, `<name>.failed` holding the error otherwise.
+/// Files with either marker are never picked up again, and the outputs and markers
+/// themselves are never queued.
+pub fn consume(
+    dir: &Path,
+    options: &FileQueueOptions,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let pattern = Pattern::new(&options.pattern)?;
+    loop {
+        if let Err(err) = consume_once(dir, &pattern, options, config) {
+            error!("scanning queue directory {} failed: {}", dir.display(), err);
+        }
+        thread::sleep(Duration::from_secs(options.interval_secs));
+    }
+}
+
+fn consume_once(
+    dir: &Path,
+    pattern: &Pattern,
+    options: &FileQueueOptions,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    for path in queued(dir, pattern)? {
+        take(&path, options, |path, markers| {
+            process_claimed(path, markers, config)
+        })?;
+    }
+    Ok(())
+}
+
+/// The files of `dir` matching `pattern`, sorted, leaving out the ones the queue writes.
+fn queued(dir: &Path, pattern: &Pattern) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let matches = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| pattern.matches(name) && !is_marker(name));
+        if matches && entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn is_marker(name: &str) -> bool {
+    MARKER_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) || name.contains(".lock.stale.")
+}
+
+/// Processes `path` with `process` if it is not finished and its lock can be claimed. The
+/// lock is kept fresh meanwhile and released afterwards, also when processing failed.
+fn take(
+    path: &Path,
+    options: &FileQueueOptions,
+    process: impl FnOnce(&Path, &Markers) -> io::Result<()>,
+) -> io::Result<()> {
+    let markers = Markers::new(path);
+    if markers.finished() {
+        return Ok(());
+    }
+    let owner = match claim(&markers, options)? {
+        Some(owner) => owner,
+        None => return Ok(()),
+    };
+    let heartbeat = Heartbeat::start(&markers.lock, &owner, options.stale_after_secs);
+    // another instance may have finished the file between the check and the claim
+    let result = if markers.finished() {
+        Ok(())
+    } else {
+        process(path, &markers)
+    };
+    heartbeat.stop();
+    let released = release(&markers.lock, &owner);
+    result.and(released)
+}
+
+/// Tries to take the lock for a file, taking over locks that went stale. Returns the
+/// owner written into the lock when it was taken.
+fn claim(markers: &Markers, options: &FileQueueOptions) -> io::Result<Option<String>> {
+    match create_lock(&markers.lock) {
+        Ok(owner) => return Ok(Some(owner)),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(err),
+    }
+    if !is_stale(&markers.lock, options.stale_after_secs)? {
+        return Ok(None);
+    }
+    // renaming is atomic, so only one instance gets to remove a stale lock
+    let taken = markers
+        .lock
+        .with_extension(format!("lock.stale.{}", process::id()));
+    match fs::rename(&markers.lock, &taken) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    // another instance may have replaced the stale lock with its own since we looked
+    if !is_stale(&taken, options.stale_after_secs)? {
+        let restored = match fs::hard_link(&taken, &markers.lock) {
+            // unless yet another instance claimed the file meanwhile
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            restored => restored,
+        };
+        fs::remove_file(&taken)?;
+        return restored.map(|()| None);
+    }
+    warn!("took over stale lock {}", markers.lock.display());
+    fs::remove_file(&taken)?;
+    match create_lock(&markers.lock) {
+        Ok(owner) => Ok(Some(owner)),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn create_lock(lock: &Path) -> io::Result<String> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(lock)?;
+    let owner = format!(
+        "pid {} claim {}",
+        process::id(),
+        CLAIMS.fetch_add(1, Ordering::Relaxed)
+    );
+    writeln!(file, "{}", owner)?;
+    Ok(owner)
+}
+
+fn owns(lock: &Path, owner: &str) -> io::Result<bool> {
+    match fs::read_to_string(lock) {
+        Ok(content) => Ok(content.trim_end() == owner),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the lock if it is still `owner`'s.
+fn release(lock: &Path, owner: &str) -> io::Result<()> {
+    if owns(lock, owner)? {
+        fs::remove_file(lock)?;
+    }
+    Ok(())
+}
+
+/// Touches the lock so it does not go stale, failing if it is no longer `owner`'s.
+fn refresh(lock: &Path, owner: &str) -> io::Result<()> {
+    if !owns(lock, owner)? {
+        return Err(io::Error::other("taken over by another instance"));
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(lock)?
+        .set_modified(SystemTime::now())
+}
+
+/// Refreshes a lock from another thread while its file is processed, four times per
+/// stale period.
+struct Heartbeat {
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Heartbeat {
+    fn start(lock: &Path, owner: &str, stale_after_secs: u64) -> Self {
+        let every = (Duration::from_secs(stale_after_secs) / 4).max(Duration::from_secs(1));
+        let (stop, stopped) = mpsc::channel();
+        let lock = lock.to_path_buf();
+        let owner = owner.to_string();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(every) {
+                if let Err(err) = refresh(&lock, &owner) {
+                    warn!("refreshing lock {} failed: {}", lock.display(), err);
+                    break;
+                }
+            }
+        });
+        Heartbeat { stop, thread }
+    }
+
+    fn stop(self) {
+        drop(self.stop);
+        let _ = self.thread.join();
+    }
+}
+
+fn is_stale(lock: &Path, stale_after_secs: u64) -> io::Result<bool> {
+    let modified = match fs::metadata(lock) {
+        Ok(metadata) => metadata.modified()?,
+        // released while we were looking, not stale but not ours either
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    Ok(age > Duration::from_secs(stale_after_secs))
+}
+
+fn process_claimed(path: &Path, markers: &Markers, config: &Config) -> io::Result<()> {
+    // write the output under a temporary name so readers never see a partial file
+    let partial = markers.output.with_extension("csv.partial");
+    let result = File::create(&partial)
+        .map_err(From::from)
+        .and_then(|file| process_input(config, &[path.to_path_buf()], file));
+    match result {
+        Ok(()) => {
+            fs::rename(&partial, &markers.output)?;
+            File::create(&markers.done)?;
+            info!("processed queued file {}", path.display());
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&partial);
+            fs::write(&markers.failed, format!("{}\n", err))?;
+            error!("processing queued file {} failed: {}", path.display(), err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn queue_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("csvtest-queue-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn age(path: &Path, secs: u64) {
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let dir = queue_dir("stale");
+        let markers = Markers::new(&dir.join("a.csv"));
+        fs::write(&markers.lock, "pid 1 claim 0\n").unwrap();
+        age(&markers.lock, 3600);
+
+        let owner = claim(&markers, &FileQueueOptions::default()).unwrap();
+        assert!(owner.is_some());
+        assert!(owns(&markers.lock, &owner.unwrap()).unwrap());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn live_lock_is_kept() {
+        let dir = queue_dir("live");
+        let markers = Markers::new(&dir.join("a.csv"));
+        fs::write(&markers.lock, "pid 1 claim 0\n").unwrap();
+
+        assert_eq!(claim(&markers, &FileQueueOptions::default()).unwrap(), None);
+        assert_eq!(
+            fs::read_to_string(&markers.lock).unwrap(),
+            "pid 1 claim 0\n"
+        );
+    }
+
+    #[test]
+    fn refreshed_lock_does_not_go_stale() {
+        let dir = queue_dir("refresh");
+        let markers = Markers::new(&dir.join("a.csv"));
+        let owner = create_lock(&markers.lock).unwrap();
+        age(&markers.lock, 3600);
+        assert!(is_stale(&markers.lock, 600).unwrap());
+
+        refresh(&markers.lock, &owner).unwrap();
+        assert!(!is_stale(&markers.lock, 600).unwrap());
+        assert!(refresh(&markers.lock, "pid 1 claim 0").is_err());
+    }
+
+    #[test]
+    fn outputs_and_markers_are_not_queued() {
+        let dir = queue_dir("scan");
+        for name in [
+            "a.csv",
+            "a.csv.out.csv",
+            "a.csv.lock",
+            "a.csv.done",
+            "b.csv",
+            "b.csv.failed",
+            "c.csv.out.csv.partial",
+            "d.csv.lock.stale.1",
+            "e.txt",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let pattern = Pattern::new("*.csv*").unwrap();
+        assert_eq!(
+            queued(&dir, &pattern).unwrap(),
+            vec![dir.join("a.csv"), dir.join("b.csv")]
+        );
+    }
+
+    #[test]
+    fn lock_is_released_when_processing_fails() {
+        let dir = queue_dir("failing");
+        let path = dir.join("a.csv");
+        fs::write(&path, "").unwrap();
+
+        let result = take(&path, &FileQueueOptions::default(), |_, _| {
+            Err(io::Error::other("disk full"))
+        });
+        assert!(result.is_err());
+        assert!(!Markers::new(&path).lock.exists());
+    }
+
+    #[test]
+    fn finished_file_is_not_processed() {
+        let dir = queue_dir("finished");
+        let path = dir.join("a.csv");
+        fs::write(&path, "").unwrap();
+        File::create(Markers::new(&path).done).unwrap();
+
+        take(&path, &FileQueueOptions::default(), |_, _| {
+            panic!("processed a finished file")
+        })
+        .unwrap();
+        assert!(!Markers::new(&path).lock.exists());
+    }
+}
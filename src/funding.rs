@@ -0,0 +1,222 @@
+use crate::ClientInfo;
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, path::Path};
+
+#[derive(Debug, Deserialize)]
+struct LinkRow {
+    client: u16,
+    funding_client: u16,
+}
+
+/// Accounts that cover withdrawals exceeding the available funds of a linked client.
+#[derive(Debug, Default)]
+pub struct FundingLinks {
+    links: HashMap<u16, u16>,
+}
+
+impl FundingLinks {
+    /// Loads a `client,funding_client` CSV.
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let mut links = HashMap::new();
+        for result in reader.deserialize() {
+            let row: LinkRow = result?;
+            if row.client == row.funding_client {
+                return Err(From::from(format!(
+                    "client {} cannot fund itself",
+                    row.client
+                )));
+            }
+            links.insert(row.client, row.funding_client);
+        }
+        Ok(FundingLinks { links })
+    }
+
+    /// The account covering overdrafts of `client`, if any.
+    pub fn funding_account(&self, client: u16) -> Option<u16> {
+        self.links.get(&client).copied()
+    }
+
+    /// Before a withdrawal of `amount` is applied, moves the shortfall from the linked funding
+    /// account into the client's available funds, as long as the funding account is unlocked
+    /// and can cover all of it. Nothing moves otherwise and the withdrawal is rejected as usual.
+    /// Returns what was moved, to [`undo`](Sweep::undo) if the withdrawal is refused anyway.
+    pub fn sweep_shortfall(
+        &self,
+        client_map: &mut HashMap<u16, ClientInfo>,
+        client_id: u16,
+        amount: Decimal,
+        tx: u32,
+    ) -> Option<Sweep> {
+        let funding_id = *self.links.get(&client_id)?;
+        let shortfall = match client_map.get(&client_id) {
+            Some(info) if !info.locked && amount > info.available_funds => {
+                amount.checked_sub(info.available_funds)?
+            }
+            _ => return None,
+        };
+
+        match client_map.get_mut(&funding_id) {
+            Some(funding) if !funding.locked && funding.available_funds >= shortfall => {
+                // cannot overflow, the funding account holds at least the shortfall
+                funding.available_funds -= shortfall;
+                funding.total_funds -= shortfall;
+            }
+            _ => {
+                error!(
+                    "OVERDRAFT: funding client {} cannot cover {} for client {} in tx {}",
+                    funding_id, shortfall, client_id, tx
+                );
+                return None;
+            }
+        }
+        let info = client_map.get_mut(&client_id)?;
+        match (
+            info.available_funds.checked_add(shortfall),
+            info.total_funds.checked_add(shortfall),
+        ) {
+            (Some(available), Some(total)) => {
+                info.available_funds = available;
+                info.total_funds = total;
+            }
+            _ => {
+                // the funding account gets it back, taking it from there did not overflow
+                if let Some(funding) = client_map.get_mut(&funding_id) {
+                    funding.available_funds += shortfall;
+                    funding.total_funds += shortfall;
+                }
+                error!(
+                    "shortfall {} overflows client {} in tx {}, not swept",
+                    shortfall, client_id, tx
+                );
+                return None;
+            }
+        }
+        info!(
+            target: "audit",
+            "internal transfer of {} from funding client {} to client {} covering withdrawal tx {}",
+            shortfall, funding_id, client_id, tx
+        );
+        Some(Sweep {
+            funding_id,
+            client_id,
+            shortfall,
+            tx,
+        })
+    }
+}
+
+/// A shortfall moved by [`FundingLinks::sweep_shortfall`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sweep {
+    pub funding_id: u16,
+    client_id: u16,
+    shortfall: Decimal,
+    /// the withdrawal it covered
+    tx: u32,
+}
+
+impl Sweep {
+    /// Moves the shortfall back to the funding account when the withdrawal it covered was
+    /// refused after all, e.g. for a negative fee, so refused records move no money.
+    pub fn undo(self, client_map: &mut HashMap<u16, ClientInfo>) {
+        // the refused withdrawal left both accounts as the sweep did, so neither overflows
+        if let Some(info) = client_map.get_mut(&self.client_id) {
+            info.available_funds -= self.shortfall;
+            info.total_funds -= self.shortfall;
+        }
+        if let Some(funding) = client_map.get_mut(&self.funding_id) {
+            funding.available_funds += self.shortfall;
+            funding.total_funds += self.shortfall;
+        }
+        info!(
+            target: "audit",
+            "internal transfer of {} from client {} back to funding client {}, withdrawal tx {} was refused",
+            self.shortfall, self.client_id, self.funding_id, self.tx
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline::{PipelineBuilder, State},
+        Record,
+    };
+
+    /// Runs `input` with client 1 funded by client 2, returns the clients each record touched.
+    fn run(input: &str) -> (State, Vec<Vec<u16>>) {
+        let mut pipeline = PipelineBuilder::new()
+            .funding_links(FundingLinks {
+                links: HashMap::from([(1, 2)]),
+            })
+            .build();
+        let touched = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .deserialize::<Record>()
+            .map(|record| pipeline.process(record.unwrap()).unwrap())
+            .collect();
+        (pipeline.into_state(), touched)
+    }
+
+    #[test]
+    fn sweeps_the_shortfall_from_the_funding_client() {
+        let (state, touched) = run("type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,100
+            withdrawal,1,3,50");
+        let client_map = &state.client_map;
+        assert_eq!(client_map[&1].available_funds, Decimal::ZERO);
+        assert_eq!(client_map[&1].total_funds, Decimal::ZERO);
+        assert_eq!(client_map[&2].available_funds, Decimal::from(60));
+        assert_eq!(client_map[&2].total_funds, Decimal::from(60));
+        assert_eq!(touched[2], vec![1, 2]);
+    }
+
+    #[test]
+    fn nothing_moves_when_the_funding_client_cannot_cover_all_of_it() {
+        let (state, touched) = run("type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,30
+            withdrawal,1,3,50
+            dispute,2,2,
+            chargeback,2,2,
+            withdrawal,1,4,20");
+        let client_map = &state.client_map;
+        assert_eq!(client_map[&1].available_funds, Decimal::from(10));
+        assert_eq!(client_map[&2].total_funds, Decimal::ZERO);
+        assert_eq!(touched[2], vec![1]);
+        assert_eq!(touched[5], vec![1]);
+    }
+
+    #[test]
+    fn the_sweep_for_a_refused_withdrawal_is_given_back() {
+        let (state, _) = run("type,client,tx,amount,fee
+            deposit,1,1,10,
+            deposit,2,2,100,
+            withdrawal,1,3,50,-1");
+        let client_map = &state.client_map;
+        assert_eq!(client_map[&1].available_funds, Decimal::from(10));
+        assert_eq!(client_map[&1].total_funds, Decimal::from(10));
+        assert_eq!(client_map[&2].available_funds, Decimal::from(100));
+        assert_eq!(client_map[&2].total_funds, Decimal::from(100));
+    }
+
+    #[test]
+    fn a_client_cannot_fund_itself() {
+        let path = std::env::temp_dir().join(format!("funding-links-{}.csv", std::process::id()));
+        std::fs::write(&path, "client,funding_client\n1,2\n3,3\n").unwrap();
+        let links = FundingLinks::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(links
+            .unwrap_err()
+            .to_string()
+            .contains("cannot fund itself"));
+    }
+}
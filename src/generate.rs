@@ -0,0 +1,224 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{collections::VecDeque, error::Error, io::Write};
+
+/// Deposits kept as candidates for a dispute, the most recent ones.
+const RECENT_DEPOSITS: usize = 100_000;
+/// Amounts are drawn in units of 0.0001.
+const SCALE: i64 = 10_000;
+
+/// What `generate` writes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerateOptions {
+    pub clients: u16,
+    pub rows: u64,
+    /// share of rows that dispute an earlier deposit; about as many settle an open dispute
+    pub dispute_rate: f64,
+    /// share of rows that are malformed
+    pub malformed_rate: f64,
+    /// seed of the random generator, a random one when `None`
+    pub seed: Option<u64>,
+}
+
+/// A deposit that can still be disputed, or is.
+#[derive(Clone, Copy)]
+struct Deposit {
+    client: u16,
+    tx: u32,
+    amount: i64,
+}
+
+/// Writes `rows` random transactions as CSV with a header row, for load tests and the
+/// benchmarks. Mostly deposits and withdrawals within the balance of the client, some
+/// withdrawals beyond it, disputes of recent deposits that are later resolved or charged
+/// back, and malformed rows at `malformed_rate`. Accounts that got charged back are mostly
+/// left alone afterwards, as they are locked. The same seed gives the same rows.
+pub fn write<W: Write>(out: W, options: &GenerateOptions) -> Result<(), Box<dyn Error>> {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let clients = usize::from(options.clients.max(1));
+    // available funds of every client, index 0 is client 1
+    let mut available = vec![0i64; clients];
+    let mut locked = vec![false; clients];
+    let mut deposits: VecDeque<Deposit> = VecDeque::new();
+    let mut disputed: Vec<Deposit> = Vec::new();
+    let mut next_tx: u32 = 1;
+
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(out);
+    writer.write_record(["type", "client", "tx", "amount"])?;
+    for _ in 0..options.rows {
+        if rng.gen_bool(options.malformed_rate) {
+            let tx = next_tx.to_string();
+            let client = rng.gen_range(1..=clients).to_string();
+            let (client, tx) = (client.as_str(), tx.as_str());
+            let row = match rng.gen_range(0..4) {
+                0 => vec!["deposit", client, tx, "1,5"],
+                1 => vec!["transfer", client, tx, "10.0"],
+                2 => vec!["withdrawal", client, "", "1.0"],
+                _ => vec!["deposit", client],
+            };
+            writer.write_record(&row)?;
+            continue;
+        }
+        if !disputed.is_empty() && rng.gen_bool(options.dispute_rate) {
+            let deposit = disputed.swap_remove(rng.gen_range(0..disputed.len()));
+            let index = usize::from(deposit.client) - 1;
+            // most disputes are settled in the client's favour
+            let kind = if rng.gen_bool(0.7) {
+                available[index] += deposit.amount;
+                "resolve"
+            } else {
+                locked[index] = true;
+                "chargeback"
+            };
+            write_row(&mut writer, kind, deposit.client, deposit.tx, None)?;
+            continue;
+        }
+        if !deposits.is_empty() && rng.gen_bool(options.dispute_rate) {
+            let at = rng.gen_range(0..deposits.len());
+            let deposit = deposits.swap_remove_back(at).expect("index is in range");
+            let index = usize::from(deposit.client) - 1;
+            if !locked[index] {
+                available[index] -= deposit.amount;
+                disputed.push(deposit);
+                write_row(&mut writer, "dispute", deposit.client, deposit.tx, None)?;
+                continue;
+            }
+        }
+
+        let client = (0..4)
+            .map(|_| rng.gen_range(0..clients))
+            .find(|index| !locked[*index])
+            .unwrap_or_else(|| rng.gen_range(0..clients));
+        let tx = next_tx;
+        next_tx = next_tx.wrapping_add(1);
+        if available[client] > 0 && rng.gen_bool(0.4) {
+            // now and then more than the client has
+            let most = if rng.gen_bool(0.05) {
+                available[client] * 2
+            } else {
+                available[client]
+            };
+            let amount = rng.gen_range(1..=most);
+            if amount <= available[client] {
+                available[client] -= amount;
+            }
+            write_row(
+                &mut writer,
+                "withdrawal",
+                client as u16 + 1,
+                tx,
+                Some(amount),
+            )?;
+        } else {
+            let amount = *[SCALE, 10 * SCALE, 100 * SCALE, 1000 * SCALE]
+                .choose(&mut rng)
+                .expect("not empty");
+            let amount = rng.gen_range(1..=amount);
+            available[client] += amount;
+            let deposit = Deposit {
+                client: client as u16 + 1,
+                tx,
+                amount,
+            };
+            if deposits.len() == RECENT_DEPOSITS {
+                deposits.pop_front();
+            }
+            deposits.push_back(deposit);
+            write_row(&mut writer, "deposit", deposit.client, tx, Some(amount))?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_row<W: Write>(
+    writer: &mut csv::Writer<W>,
+    kind: &str,
+    client: u16,
+    tx: u32,
+    amount: Option<i64>,
+) -> Result<(), Box<dyn Error>> {
+    let amount = amount.map_or(String::new(), |amount| {
+        format!("{}.{:04}", amount / SCALE, amount % SCALE)
+    });
+    let (client, tx) = (client.to_string(), tx.to_string());
+    writer.write_record([kind, client.as_str(), tx.as_str(), amount.as_str()])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{csv_records, pipeline::PipelineBuilder, TxType};
+    use std::{collections::HashMap, io::Cursor};
+
+    fn generated(rows: u64, malformed_rate: f64, seed: u64) -> String {
+        let options = GenerateOptions {
+            clients: 50,
+            rows,
+            dispute_rate: 0.05,
+            malformed_rate,
+            seed: Some(seed),
+        };
+        let mut out = Vec::new();
+        write(&mut out, &options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn the_same_seed_writes_the_same_rows() {
+        let rows = generated(1000, 0.1, 7);
+        assert_eq!(rows.lines().count(), 1001);
+        assert_eq!(rows.lines().next(), Some("type,client,tx,amount"));
+        assert_eq!(generated(1000, 0.1, 7), rows);
+        assert_ne!(generated(1000, 0.1, 8), rows);
+    }
+
+    #[test]
+    fn disputes_settle_earlier_deposits_of_their_client() {
+        let rows = generated(20_000, 0.0, 1);
+        let reader = csv::Reader::from_reader(Cursor::new(rows));
+        let mut deposits = HashMap::new();
+        let mut counts: HashMap<TxType, usize> = HashMap::new();
+        for record in csv_records(reader, 1).unwrap() {
+            let record = record.unwrap();
+            match record.tx_type {
+                TxType::Deposit => {
+                    assert!(deposits.insert(record.tx, record.client).is_none());
+                }
+                TxType::Withdrawal => {}
+                TxType::Dispute | TxType::Resolve | TxType::Chargeback => {
+                    assert_eq!(deposits.get(&record.tx), Some(&record.client));
+                }
+                _ => panic!("unexpected row {:?}", record),
+            }
+            *counts.entry(record.tx_type).or_default() += 1;
+        }
+        for tx_type in [TxType::Dispute, TxType::Resolve, TxType::Chargeback] {
+            assert!(
+                counts.get(&tx_type).is_some_and(|count| *count > 0),
+                "{:?}",
+                tx_type
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_rows_change_no_account() {
+        let rows = generated(500, 1.0, 3);
+        let reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(Cursor::new(rows));
+        let state = PipelineBuilder::new()
+            .source(csv_records(reader, 1).unwrap())
+            .build()
+            .run()
+            .unwrap();
+        assert!(state
+            .client_map
+            .values()
+            .all(|info| info.total_funds.is_zero()));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    bind_address,
+    ledger::{Ledger, Outcome, SubmitError},
+    protobuf::Transaction,
+    Balance, Record,
+};
+use log::info;
+use std::{error::Error, net::SocketAddr, sync::Arc};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Server, Request, Response, Status};
+
+/// Generated from `proto/ledger.proto`.
+pub mod proto {
+    tonic::include_proto!("tx_processing");
+}
+
+use proto::{
+    ledger_server::{Ledger as LedgerRpc, LedgerServer},
+    Account, GetAccountRequest, StreamAccountsRequest, SubmitResponse,
+};
+
+/// Accounts buffered per `StreamAccounts` call before the stream waits for the client.
+const STREAM_BUFFER: usize = 64;
+
+impl From<Balance> for Account {
+    fn from(balance: Balance) -> Self {
+        Account {
+            client: u32::from(balance.client),
+            available: balance.available.to_string(),
+            held: balance.held.to_string(),
+            total: balance.total.to_string(),
+            locked: balance.locked,
+        }
+    }
+}
+
+struct LedgerService {
+    ledger: Arc<Ledger>,
+}
+
+#[tonic::async_trait]
+impl LedgerRpc for LedgerService {
+    async fn submit_transaction(
+        &self,
+        request: Request<Transaction>,
+    ) -> Result<Response<SubmitResponse>, Status> {
+        let record = Record::from(request.into_inner());
+        let ledger = Arc::clone(&self.ledger);
+        let outcome = tokio::task::spawn_blocking(move || ledger.submit(record))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?
+            .map_err(|err| match err {
+                SubmitError::Busy => Status::resource_exhausted(err.to_string()),
+                SubmitError::Stopped(_) => Status::unavailable(err.to_string()),
+            })?;
+        let (applied, account) = match outcome {
+            Outcome::Applied { account } => (true, account),
+            Outcome::Rejected { account } => (false, account),
+            Outcome::Throttled => {
+                return Err(Status::resource_exhausted(
+                    "client over its rate limit, the transaction was not applied",
+                ))
+            }
+        };
+        Ok(Response::new(SubmitResponse {
+            applied,
+            account: account.map(Account::from),
+        }))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> Result<Response<Account>, Status> {
+        let client = request.into_inner().client;
+        u16::try_from(client)
+            .ok()
+            .and_then(|client| self.ledger.account(client))
+            .map(|balance| Response::new(Account::from(balance)))
+            .ok_or_else(|| Status::not_found(format!("no account for client {}", client)))
+    }
+
+    type StreamAccountsStream = ReceiverStream<Result<Account, Status>>;
+
+    async fn stream_accounts(
+        &self,
+        _request: Request<StreamAccountsRequest>,
+    ) -> Result<Response<Self::StreamAccountsStream>, Status> {
+        // watching first, so no change between the two is missed; it may be sent twice
+        let changes = self.ledger.watch();
+        let current = self.ledger.accounts();
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            for balance in current.into_iter().chain(changes.iter()) {
+                if sender.blocking_send(Ok(Account::from(balance))).is_err() {
+                    // the client went away
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Serves the ledger service on `addr` until the process is stopped.
+pub fn serve(ledger: Ledger, addr: &str) -> Result<(), Box<dyn Error>> {
+    let addr: SocketAddr = bind_address(addr).parse()?;
+    let service = LedgerService {
+        ledger: Arc::new(ledger),
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    info!("serving the ledger over gRPC on {}", addr);
+    runtime.block_on(
+        Server::builder()
+            .add_service(LedgerServer::new(service))
+            .serve(addr),
+    )?;
+    Ok(())
+}
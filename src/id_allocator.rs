@@ -0,0 +1,169 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "uuid")]
+use uuid::Uuid;
+
+/// Ids tried at random before the random allocator searches for a free one in order.
+const RANDOM_ATTEMPTS: u32 = 64;
+
+/// Picks the client id of a record without one, under the `generate` missing client policy.
+pub trait IdAllocator {
+    /// A client id for the record with id `tx` at `position` in the input, one `taken` says
+    /// no account has. `None` when every id is taken.
+    fn allocate(&mut self, tx: u32, position: u64, taken: &dyn Fn(u16) -> bool) -> Option<u16>;
+}
+
+/// Which [`IdAllocator`] the command line uses, see `--client-ids`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// [`Sequential`]
+    Sequential,
+    /// [`SeededRandom`], the default
+    #[default]
+    Random,
+    /// [`UuidDerived`], needs the `uuid` feature
+    #[cfg(feature = "uuid")]
+    Uuid,
+}
+
+impl IdStrategy {
+    /// The allocator of the strategy. `seed` only matters to the random one.
+    pub fn allocator(self, seed: u64) -> Box<dyn IdAllocator + Send> {
+        match self {
+            IdStrategy::Sequential => Box::new(Sequential::default()),
+            IdStrategy::Random => Box::new(SeededRandom::new(seed)),
+            #[cfg(feature = "uuid")]
+            IdStrategy::Uuid => Box::new(UuidDerived),
+        }
+    }
+}
+
+/// The highest free id, counting down from 65535, so generated accounts stay clear of the
+/// low ids partners hand out.
+pub struct Sequential {
+    /// where the search for the next id starts
+    next: u16,
+}
+
+impl Default for Sequential {
+    fn default() -> Self {
+        Sequential { next: u16::MAX }
+    }
+}
+
+impl IdAllocator for Sequential {
+    fn allocate(&mut self, _tx: u32, _position: u64, taken: &dyn Fn(u16) -> bool) -> Option<u16> {
+        let start = self.next;
+        let id = first_free(taken, (0..=u16::MAX).map(|i| start.wrapping_sub(i)))?;
+        self.next = id.wrapping_sub(1);
+        Some(id)
+    }
+}
+
+/// Random ids from a generator seeded by `seed` and the position of the record, so a run
+/// gives the same ids however it was resumed.
+pub struct SeededRandom {
+    seed: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        SeededRandom { seed }
+    }
+}
+
+impl IdAllocator for SeededRandom {
+    fn allocate(&mut self, _tx: u32, position: u64, taken: &dyn Fn(u16) -> bool) -> Option<u16> {
+        let mut rng = StdRng::seed_from_u64(self.seed ^ position);
+        let start: u16 = rng.gen();
+        let drawn = (0..RANDOM_ATTEMPTS).map(|_| rng.gen::<u16>());
+        first_free(
+            taken,
+            drawn.chain((0..=u16::MAX).map(|i| start.wrapping_add(i))),
+        )
+    }
+}
+
+/// The first two bytes of a name based (v5) UUID of the record's tx id and position, or the
+/// next free id after them. The same record gets the same id in any run.
+#[cfg(feature = "uuid")]
+pub struct UuidDerived;
+
+#[cfg(feature = "uuid")]
+impl IdAllocator for UuidDerived {
+    fn allocate(&mut self, tx: u32, position: u64, taken: &dyn Fn(u16) -> bool) -> Option<u16> {
+        let name = format!("{}:{}", tx, position);
+        let uuid = Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes());
+        let bytes = uuid.as_bytes();
+        let start = u16::from_be_bytes([bytes[0], bytes[1]]);
+        first_free(taken, (0..=u16::MAX).map(|i| start.wrapping_add(i)))
+    }
+}
+
+/// The first of `candidates` no account has.
+fn first_free(
+    taken: &dyn Fn(u16) -> bool,
+    mut candidates: impl Iterator<Item = u16>,
+) -> Option<u16> {
+    candidates.find(|id| !taken(*id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The ids `ids` gives the records at positions 1 to `n`, each id taken once given.
+    fn allocated(ids: &mut dyn IdAllocator, taken: &[u16], n: u64) -> Vec<u16> {
+        let mut taken = taken.to_vec();
+        (1..=n)
+            .map(|position| {
+                let id = ids
+                    .allocate(position as u32, position, &|id| taken.contains(&id))
+                    .unwrap();
+                taken.push(id);
+                id
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sequential_ids_count_down_past_the_taken_ones() {
+        let ids = allocated(&mut Sequential::default(), &[65534], 3);
+        assert_eq!(ids, [65535, 65533, 65532]);
+    }
+
+    #[test]
+    fn random_ids_depend_on_the_seed_and_position_only() {
+        let first = allocated(&mut SeededRandom::new(7), &[], 20);
+        assert_eq!(first, allocated(&mut SeededRandom::new(7), &[], 20));
+        assert_ne!(first, allocated(&mut SeededRandom::new(8), &[], 20));
+
+        // a resumed run starts at a later position with the accounts of the first part
+        let mut resumed = SeededRandom::new(7);
+        let later = resumed.allocate(20, 20, &|id| first[..19].contains(&id));
+        assert_eq!(later, Some(first[19]));
+    }
+
+    #[test]
+    fn random_ids_skip_the_taken_ones_and_run_out() {
+        let mut ids = SeededRandom::new(0);
+        let drawn = ids.allocate(1, 1, &|_| false).unwrap();
+        let next = ids.allocate(1, 1, &|id| id == drawn).unwrap();
+        assert_ne!(next, drawn);
+        assert_eq!(ids.allocate(1, 1, &|id| id != 42), Some(42));
+        assert_eq!(ids.allocate(1, 1, &|_| true), None);
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn uuid_ids_are_the_same_for_the_same_record() {
+        let mut ids = UuidDerived;
+        let id = ids.allocate(5, 3, &|_| false).unwrap();
+        assert_eq!(ids.allocate(5, 3, &|_| false), Some(id));
+        assert_eq!(
+            ids.allocate(5, 3, &|taken| taken == id),
+            Some(id.wrapping_add(1))
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{pipeline::State, policy::LockedAccount, Balance, Record, TxError, TxType};
+use log::error;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// What `--check-invariants` does with an account that breaks one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnViolation {
+    /// stop the run with the record and the balances before and after it
+    Abort,
+    /// log the same and keep going
+    Log,
+}
+
+/// Checks the accounts a record touched after the engine applied it: the total is the
+/// available plus the held funds, the held funds are not negative, and an account that was
+/// locked before the record still has the same balances. The balances are fields of their
+/// own, updated one by one by the handlers, so this catches a handler that misses one.
+pub struct InvariantCheck {
+    on_violation: OnViolation,
+    /// resolves and chargebacks still change locked accounts under this policy
+    settles_locked: bool,
+    /// balances of the accounts of the record being applied, before it
+    before: Vec<Balance>,
+}
+
+impl InvariantCheck {
+    pub fn new(on_violation: OnViolation, locked_account: LockedAccount) -> Self {
+        InvariantCheck {
+            on_violation,
+            settles_locked: locked_account == LockedAccount::SettleOpenDisputes,
+            before: Vec::new(),
+        }
+    }
+
+    /// Notes the balances of `accounts` before a record is applied to them.
+    pub(crate) fn before(&mut self, state: &State, accounts: &[u16]) {
+        self.before = balances(state, accounts);
+    }
+
+    /// Checks `accounts` once `record` was applied to them.
+    pub(crate) fn after(
+        &self,
+        state: &State,
+        record: &Record,
+        accounts: &[u16],
+    ) -> Result<(), TxError> {
+        let settling =
+            self.settles_locked && matches!(record.tx_type, TxType::Resolve | TxType::Chargeback);
+        for after in balances(state, accounts) {
+            let before = self
+                .before
+                .iter()
+                .find(|before| before.client == after.client);
+            let violation = match violation(before, &after, settling) {
+                Some(violation) => violation,
+                None => continue,
+            };
+            error!(
+                "invariant violated by record {} at position {}: {}, before {:?}, after {:?}, record {:?}",
+                state.sequence, record.position, violation, before, after, record
+            );
+            if self.on_violation == OnViolation::Abort {
+                return Err(TxError::InvariantViolated {
+                    client: after.client,
+                    tx: record.tx,
+                    violation: violation.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The first invariant `after` breaks. `settling` allows a locked account to change.
+fn violation(before: Option<&Balance>, after: &Balance, settling: bool) -> Option<&'static str> {
+    if after.total != after.available + after.held {
+        Some("total is not available plus held")
+    } else if after.held < Decimal::ZERO {
+        Some("held is negative")
+    } else if before
+        .is_some_and(|before| before.locked && !settling && !same_balances(before, after))
+    {
+        Some("balances of a locked account changed")
+    } else if before.is_some_and(|before| before.locked && !after.locked) {
+        Some("a locked account got unlocked")
+    } else {
+        None
+    }
+}
+
+fn same_balances(before: &Balance, after: &Balance) -> bool {
+    before.available == after.available && before.held == after.held && before.total == after.total
+}
+
+fn balances(state: &State, accounts: &[u16]) -> Vec<Balance> {
+    accounts
+        .iter()
+        .filter_map(|client| {
+            let info = state.client_map.get(client)?;
+            Some(Balance::of(*client, info))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        csv_records,
+        pipeline::{PipelineBuilder, Transform},
+    };
+    use std::io::Cursor;
+
+    fn balance(available: i64, held: i64, total: i64, locked: bool) -> Balance {
+        Balance {
+            client: 1,
+            available: Decimal::from(available),
+            held: Decimal::from(held),
+            total: Decimal::from(total),
+            locked,
+        }
+    }
+
+    #[test]
+    fn names_the_first_invariant_broken() {
+        let locked = balance(5, 0, 5, true);
+        assert_eq!(
+            violation(None, &balance(5, 1, 5, false), false),
+            Some("total is not available plus held")
+        );
+        assert_eq!(
+            violation(None, &balance(6, -1, 5, false), false),
+            Some("held is negative")
+        );
+        assert_eq!(
+            violation(Some(&locked), &balance(4, 0, 4, true), false),
+            Some("balances of a locked account changed")
+        );
+        assert_eq!(
+            violation(Some(&locked), &balance(4, 0, 4, true), true),
+            None
+        );
+        assert_eq!(
+            violation(Some(&locked), &balance(5, 0, 5, false), true),
+            Some("a locked account got unlocked")
+        );
+        assert_eq!(
+            violation(
+                Some(&balance(5, 0, 5, false)),
+                &balance(2, 3, 5, false),
+                false
+            ),
+            None
+        );
+    }
+
+    /// Holds funds on the account of client 2 without counting them in its total, as a
+    /// handler that misses a field would.
+    struct Drift;
+
+    impl Transform for Drift {
+        fn apply(&mut self, record: Record, state: &mut State) -> Result<Option<Record>, TxError> {
+            if let Some(info) = state.client_map.get_mut(&2) {
+                info.held_funds += Decimal::ONE;
+            }
+            Ok(Some(record))
+        }
+    }
+
+    const INPUT: &str = "type,client,tx,amount
+        deposit,1,1,10
+        dispute,1,1,
+        chargeback,1,1,
+        deposit,1,2,5
+        deposit,2,3,10
+        withdrawal,2,4,3";
+
+    fn run(transform: Option<Drift>, on_violation: OnViolation) -> Result<State, TxError> {
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(Cursor::new(INPUT));
+        let mut pipeline = PipelineBuilder::new()
+            .source(csv_records(reader, 1).unwrap())
+            .check_invariants(Some(on_violation));
+        if let Some(transform) = transform {
+            pipeline = pipeline.transform(transform);
+        }
+        pipeline.build().run()
+    }
+
+    #[test]
+    fn a_run_that_keeps_the_invariants_goes_through() {
+        let state = run(None, OnViolation::Abort).unwrap();
+        assert_eq!(state.processed, 6);
+    }
+
+    #[test]
+    fn a_broken_invariant_aborts_the_run_unless_it_is_only_logged() {
+        let err = match run(Some(Drift), OnViolation::Abort) {
+            Err(err) => err,
+            Ok(_) => panic!("the run went through"),
+        };
+        assert!(
+            matches!(
+                &err,
+                TxError::InvariantViolated { client: 2, tx: 4, violation }
+                    if violation == "total is not available plus held"
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.exit_code(), 1);
+        let state = run(Some(Drift), OnViolation::Log).unwrap();
+        assert_eq!(state.processed, 6);
+    }
+}
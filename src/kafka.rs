@@ -0,0 +1,82 @@
+use crate::{
+    messages::{self, Encoding},
+    Record, TxError,
+};
+use log::error;
+use rdkafka::{
+    consumer::{BaseConsumer, Consumer},
+    ClientConfig, Message,
+};
+use std::{error::Error, time::Duration};
+
+/// How long a poll waits for a message before polling again.
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Consumes JSON or MessagePack transactions from a Kafka topic, forever. The offset of a
+/// message is only stored for the periodic commit once the pipeline asks for the next
+/// record, that is after the message went through the engine and every sink, so a crash
+/// replays what was not applied yet. A malformed message is handled like a malformed row,
+/// and is committed when the policy skips it.
+pub struct KafkaSource {
+    consumer: BaseConsumer,
+    /// topic and partition of the message handed out last, and the offset to commit once
+    /// it is applied: the offset after it
+    pending: Option<(String, i32, i64)>,
+}
+
+impl KafkaSource {
+    /// Joins the consumer group `group`, starting at the earliest offset when the group
+    /// has not committed any.
+    pub fn new(brokers: &str, group: &str, topic: &str) -> Result<Self, Box<dyn Error>> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group)
+            .set("enable.auto.commit", "true")
+            // offsets are stored by hand, after the message is applied
+            .set("enable.auto.offset.store", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+        consumer.subscribe(&[topic])?;
+        Ok(KafkaSource {
+            consumer,
+            pending: None,
+        })
+    }
+}
+
+impl Iterator for KafkaSource {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((topic, partition, offset)) = self.pending.take() {
+            if let Err(err) = self.consumer.store_offset(&topic, partition, offset) {
+                error!(
+                    "failed to store offset {} of {}/{}: {}",
+                    offset, topic, partition, err
+                );
+            }
+        }
+        loop {
+            match self.consumer.poll(POLL_TIMEOUT) {
+                None => continue,
+                Some(Err(err)) => {
+                    // the client reconnects by itself
+                    error!("kafka consumer error: {}", err);
+                    continue;
+                }
+                Some(Ok(message)) => {
+                    self.pending = Some((
+                        message.topic().to_string(),
+                        message.partition(),
+                        message.offset() + 1,
+                    ));
+                    let payload = message.payload().unwrap_or_default();
+                    return Some(
+                        messages::decode(Encoding::sniff(payload), payload)
+                            .map_err(TxError::malformed),
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use crate::{
+    pipeline::Pipeline,
+    throttle::{Throttle, ThrottleLimits},
+    Balance, Record,
+};
+use log::error;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// Records that may wait for the engine, and balance changes a watcher may fall behind,
+/// unless [`Ledger::bounded`] sets another capacity.
+const CAPACITY: usize = 10_000;
+
+/// What happened to a submitted record, with the balances of its account afterwards.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Applied {
+        account: Option<Balance>,
+    },
+    Rejected {
+        account: Option<Balance>,
+    },
+    /// the client sent more records than `--throttle-rate` lets through, nothing was applied
+    Throttled,
+}
+
+/// Why a record was not taken by the ledger.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmitError {
+    /// as many records as the capacity wait for the engine, the record can be sent again later
+    Busy,
+    /// the ledger stopped on a record an `abort` policy refused, later submissions fail too
+    Stopped(String),
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubmitError::Busy => write!(f, "the ledger is busy, try again later"),
+            SubmitError::Stopped(err) => write!(f, "the ledger has stopped: {}", err),
+        }
+    }
+}
+
+impl Error for SubmitError {}
+
+/// Accounts kept in memory and fed one record at a time, for the server. Records go through
+/// the same checks as a file run, in the order they are submitted.
+///
+/// At most `capacity` records wait for the engine, submissions beyond that are refused as
+/// busy rather than queued, and each watcher is at most `capacity` changes behind.
+///
+/// Abort policies stop the ledger on the first refused record, later submissions fail.
+pub struct Ledger {
+    engine: Mutex<LedgerEngine>,
+    /// kept apart from the engine, so a throttled client does not wait for it
+    throttle: Option<Mutex<Throttle>>,
+    watchers: Mutex<Vec<SyncSender<Balance>>>,
+    capacity: usize,
+    /// submissions applied or waiting for the engine
+    waiting: AtomicUsize,
+}
+
+struct LedgerEngine {
+    pipeline: Pipeline<'static>,
+    /// balances last sent to the watchers, to tell which accounts a record changed
+    accounts: BTreeMap<u16, Balance>,
+    stopped: Option<SubmitError>,
+}
+
+impl Ledger {
+    pub fn new(pipeline: Pipeline<'static>) -> Ledger {
+        let accounts = pipeline
+            .state()
+            .client_map
+            .iter()
+            .map(|(client, info)| (*client, Balance::of(*client, info)))
+            .collect();
+        Ledger {
+            engine: Mutex::new(LedgerEngine {
+                pipeline,
+                accounts,
+                stopped: None,
+            }),
+            throttle: None,
+            watchers: Mutex::new(Vec::new()),
+            capacity: CAPACITY,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets how many records may wait for the engine and how far a watcher may fall behind.
+    pub fn bounded(mut self, capacity: usize) -> Ledger {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Caps the records each client can submit, the ones past it are not applied.
+    pub fn throttled(mut self, limits: ThrottleLimits) -> Ledger {
+        self.throttle = Some(Mutex::new(Throttle::new(limits)));
+        self
+    }
+
+    /// Applies `record`. It is rejected when the policy skips it or it leaves its account as
+    /// it was, as a withdrawal over the available funds does. Records of a client over its
+    /// throttle are not applied at all; records without a client are not throttled. Fails at
+    /// once with [`SubmitError::Busy`] when the capacity of records already wait.
+    pub fn submit(&self, record: Record) -> Result<Outcome, SubmitError> {
+        if let (Some(throttle), Some(client)) = (&self.throttle, record.client) {
+            let mut throttle = throttle
+                .lock()
+                .map_err(|err| SubmitError::Stopped(err.to_string()))?;
+            if !throttle.take(client, Instant::now()) {
+                error!("client over its rate limit, skipping tx {:?}", record);
+                return Ok(Outcome::Throttled);
+            }
+        }
+        if self.waiting.fetch_add(1, Ordering::SeqCst) >= self.capacity {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(SubmitError::Busy);
+        }
+        let outcome = self.apply(record);
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        outcome
+    }
+
+    fn apply(&self, record: Record) -> Result<Outcome, SubmitError> {
+        let mut engine = self
+            .engine
+            .lock()
+            .map_err(|err| SubmitError::Stopped(err.to_string()))?;
+        if let Some(stopped) = &engine.stopped {
+            return Err(stopped.clone());
+        }
+        let client = record.client;
+        let before = engine.funds(client);
+        let touched = match engine.pipeline.process(record) {
+            Ok(touched) => touched,
+            Err(err) => {
+                let stopped = SubmitError::Stopped(err.to_string());
+                engine.stopped = Some(stopped.clone());
+                return Err(stopped);
+            }
+        };
+        for client in touched {
+            let Some(info) = engine.pipeline.state().client_map.get(&client) else {
+                continue;
+            };
+            let balance = Balance::of(client, info);
+            if engine.accounts.get(&client) != Some(&balance) {
+                engine.accounts.insert(client, balance.clone());
+                self.notify(balance);
+            }
+        }
+        let account = client.and_then(|client| engine.accounts.get(&client).cloned());
+        Ok(if engine.funds(client) != before {
+            Outcome::Applied { account }
+        } else {
+            Outcome::Rejected { account }
+        })
+    }
+
+    pub fn account(&self, client: u16) -> Option<Balance> {
+        self.engine.lock().ok()?.accounts.get(&client).cloned()
+    }
+
+    /// Every account, sorted by client.
+    pub fn accounts(&self) -> Vec<Balance> {
+        self.engine.lock().map_or_else(
+            |_| Vec::new(),
+            |engine| engine.accounts.values().cloned().collect(),
+        )
+    }
+
+    /// Returns a receiver of the balances of every account a record changes, including the
+    /// ones it locks, from now on. A receiver that falls more than the capacity behind is
+    /// dropped, ending it.
+    pub fn watch(&self) -> Receiver<Balance> {
+        let (sender, receiver) = mpsc::sync_channel(self.capacity);
+        if let Ok(mut watchers) = self.watchers.lock() {
+            watchers.push(sender);
+        }
+        receiver
+    }
+
+    fn notify(&self, balance: Balance) {
+        if let Ok(mut watchers) = self.watchers.lock() {
+            watchers.retain(|watcher| match watcher.try_send(balance.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+            });
+        }
+    }
+}
+
+impl LedgerEngine {
+    /// Available, held and total funds and lock of the account of `client`, a missing
+    /// account has none and is not locked.
+    fn funds(&self, client: Option<u16>) -> (Decimal, Decimal, Decimal, bool) {
+        client
+            .and_then(|client| self.pipeline.state().client_map.get(&client))
+            .map_or(
+                (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, false),
+                |info| {
+                    (
+                        info.available_funds,
+                        info.held_funds,
+                        info.total_funds,
+                        info.locked,
+                    )
+                },
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline::PipelineBuilder,
+        policy::{Policy, Profile},
+        TxType,
+    };
+
+    fn record(tx_type: &str, client: u16, tx: u32, amount: Option<&str>) -> Record {
+        Record {
+            tx_type: TxType::from(tx_type),
+            client: Some(client),
+            tx,
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        }
+    }
+
+    fn balance(client: u16, available: &str, held: &str, locked: bool) -> Balance {
+        let (available, held): (Decimal, Decimal) =
+            (available.parse().unwrap(), held.parse().unwrap());
+        Balance {
+            client,
+            available,
+            held,
+            total: available + held,
+            locked,
+        }
+    }
+
+    #[test]
+    fn applies_records_and_rejects_the_ones_the_account_refuses() {
+        let ledger = Ledger::new(PipelineBuilder::new().build());
+        assert_eq!(
+            ledger.submit(record("deposit", 1, 1, Some("5"))),
+            Ok(Outcome::Applied {
+                account: Some(balance(1, "5", "0", false))
+            })
+        );
+        assert_eq!(
+            ledger.submit(record("withdrawal", 1, 2, Some("8"))),
+            Ok(Outcome::Rejected {
+                account: Some(balance(1, "5", "0", false))
+            })
+        );
+        assert_eq!(ledger.account(1), Some(balance(1, "5", "0", false)));
+        assert_eq!(ledger.account(2), None);
+        assert_eq!(ledger.accounts(), vec![balance(1, "5", "0", false)]);
+    }
+
+    #[test]
+    fn watchers_get_the_accounts_a_record_changes() {
+        let ledger = Ledger::new(PipelineBuilder::new().build());
+        ledger.submit(record("deposit", 1, 1, Some("5"))).unwrap();
+        let changes = ledger.watch();
+        ledger.submit(record("deposit", 2, 2, Some("3"))).unwrap();
+        // refused, nothing changed
+        ledger
+            .submit(record("withdrawal", 1, 3, Some("8")))
+            .unwrap();
+        ledger.submit(record("dispute", 1, 1, None)).unwrap();
+        ledger.submit(record("chargeback", 1, 1, None)).unwrap();
+        let sent: Vec<Balance> = changes.try_iter().collect();
+        assert_eq!(
+            sent,
+            vec![
+                balance(2, "3", "0", false),
+                balance(1, "0", "5", false),
+                balance(1, "0", "0", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_watcher_that_went_away_is_dropped() {
+        let ledger = Ledger::new(PipelineBuilder::new().build());
+        drop(ledger.watch());
+        ledger.submit(record("deposit", 1, 1, Some("5"))).unwrap();
+        assert!(ledger.watchers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn submissions_past_the_capacity_are_refused_as_busy() {
+        let ledger = Ledger::new(PipelineBuilder::new().build()).bounded(1);
+        // one submission is being applied
+        ledger.waiting.store(1, Ordering::SeqCst);
+        assert_eq!(
+            ledger.submit(record("deposit", 1, 1, Some("5"))),
+            Err(SubmitError::Busy)
+        );
+        assert_eq!(ledger.account(1), None);
+        ledger.waiting.store(0, Ordering::SeqCst);
+        assert!(matches!(
+            ledger.submit(record("deposit", 1, 1, Some("5"))),
+            Ok(Outcome::Applied { .. })
+        ));
+        assert_eq!(ledger.waiting.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn a_watcher_that_falls_behind_is_dropped() {
+        let ledger = Ledger::new(PipelineBuilder::new().build()).bounded(1);
+        let changes = ledger.watch();
+        ledger.submit(record("deposit", 1, 1, Some("5"))).unwrap();
+        ledger.submit(record("deposit", 2, 2, Some("5"))).unwrap();
+        assert!(ledger.watchers.lock().unwrap().is_empty());
+        assert_eq!(changes.iter().count(), 1);
+    }
+
+    #[test]
+    fn throttled_clients_are_not_applied() {
+        let limits = ThrottleLimits {
+            rate: 0.001,
+            burst: 2,
+        };
+        let ledger = Ledger::new(PipelineBuilder::new().build()).throttled(limits);
+        ledger.submit(record("deposit", 1, 1, Some("5"))).unwrap();
+        ledger.submit(record("deposit", 1, 2, Some("5"))).unwrap();
+        assert_eq!(
+            ledger.submit(record("deposit", 1, 3, Some("5"))),
+            Ok(Outcome::Throttled)
+        );
+        assert_eq!(ledger.account(1), Some(balance(1, "10", "0", false)));
+        assert!(matches!(
+            ledger.submit(record("deposit", 2, 4, Some("5"))),
+            Ok(Outcome::Applied { .. })
+        ));
+    }
+
+    #[test]
+    fn an_abort_policy_stops_the_ledger() {
+        let pipeline = PipelineBuilder::new()
+            .policy(Policy::from_profile(Profile::Strict))
+            .build();
+        let ledger = Ledger::new(pipeline);
+        ledger.submit(record("deposit", 1, 1, Some("5"))).unwrap();
+        let err = ledger
+            .submit(record("deposit", 1, 1, Some("5")))
+            .unwrap_err();
+        assert!(err.to_string().contains("duplicate tx id"));
+        assert_eq!(ledger.submit(record("deposit", 1, 2, Some("1"))), Err(err));
+    }
+}
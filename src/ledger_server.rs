@@ -0,0 +1,401 @@
+use crate::{
+    bind_address,
+    ledger::{Ledger, Outcome, SubmitError},
+    messages::{self, Encoding},
+    Balance,
+};
+use axum::{
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, Path,
+    },
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use log::{error, info};
+use serde_json::{json, Value};
+use std::{
+    error::Error,
+    net::SocketAddr,
+    sync::{mpsc::RecvTimeoutError, Arc},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+/// Account updates buffered per event stream or websocket before waiting for the client.
+const UPDATE_BUFFER: usize = 64;
+/// How often a thread forwarding account updates checks that its client is still there.
+const CLOSED_POLL: Duration = Duration::from_millis(500);
+
+type Failure = (StatusCode, Json<Value>);
+
+fn failure(status: StatusCode, error: impl ToString) -> Failure {
+    (status, Json(json!({ "error": error.to_string() })))
+}
+
+/// Serves the ledger over HTTP until the process is stopped:
+/// - `POST /transactions`, applies the JSON or MessagePack transaction in the body
+/// - `GET /accounts`, every account
+/// - `GET /accounts/{client}`, one account
+/// - `GET /updates`, a websocket of every account, then each account again as it changes
+/// - `GET /accounts/{client}/stream`, server-sent events of one account as it changes
+pub fn serve(ledger: Ledger, addr: &str) -> Result<(), Box<dyn Error>> {
+    let addr: SocketAddr = bind_address(addr).parse()?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    info!("serving the ledger over HTTP on {}", addr);
+    runtime.block_on(axum::Server::bind(&addr).serve(router(ledger).into_make_service()))?;
+    Ok(())
+}
+
+fn router(ledger: Ledger) -> Router {
+    Router::new()
+        .route("/transactions", post(submit))
+        .route("/accounts", get(accounts))
+        .route("/accounts/:client", get(account))
+        .route("/updates", get(updates))
+        .route("/accounts/:client/stream", get(account_stream))
+        .layer(Extension(Arc::new(ledger)))
+}
+
+/// `200` with the outcome when the transaction is applied, `422` when it is rejected, `429`
+/// when the client is over its `--throttle-rate` or `--queue-capacity` submissions wait
+/// already, `503` once an `abort` policy stopped the ledger. The body is decoded as its `Content-Type`
+/// says, JSON when it has none: `415` for one that is neither JSON nor MessagePack, `400`
+/// for a body that does not decode.
+async fn submit(
+    Extension(ledger): Extension<Arc<Ledger>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Outcome>), Failure> {
+    let encoding = match headers.get(CONTENT_TYPE) {
+        None => Encoding::Json,
+        Some(content_type) => content_type
+            .to_str()
+            .ok()
+            .and_then(Encoding::from_content_type)
+            .ok_or_else(|| {
+                failure(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "expected application/json or application/msgpack",
+                )
+            })?,
+    };
+    let record =
+        messages::decode(encoding, &body).map_err(|err| failure(StatusCode::BAD_REQUEST, err))?;
+    let outcome = tokio::task::spawn_blocking(move || ledger.submit(record))
+        .await
+        .map_err(|err| failure(StatusCode::INTERNAL_SERVER_ERROR, err))?
+        .map_err(|err| match err {
+            SubmitError::Busy => failure(StatusCode::TOO_MANY_REQUESTS, err),
+            SubmitError::Stopped(_) => failure(StatusCode::SERVICE_UNAVAILABLE, err),
+        })?;
+    let status = match outcome {
+        Outcome::Applied { .. } => StatusCode::OK,
+        Outcome::Rejected { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        Outcome::Throttled => StatusCode::TOO_MANY_REQUESTS,
+    };
+    Ok((status, Json(outcome)))
+}
+
+async fn accounts(Extension(ledger): Extension<Arc<Ledger>>) -> Json<Vec<Balance>> {
+    Json(ledger.accounts())
+}
+
+/// `404` for a client without an account or a client id that is not one.
+async fn account(
+    Extension(ledger): Extension<Arc<Ledger>>,
+    Path(client): Path<String>,
+) -> Result<Json<Balance>, Failure> {
+    client
+        .parse::<u16>()
+        .ok()
+        .and_then(|client| ledger.account(client))
+        .map(Json)
+        .ok_or_else(|| failure(StatusCode::NOT_FOUND, "not found"))
+}
+
+/// Server-sent events of the account, a `balance` event with the JSON account now if it
+/// exists and again whenever its balances change or it gets locked, until the client goes
+/// away. `404` for a client id that is not one.
+async fn account_stream(
+    Extension(ledger): Extension<Arc<Ledger>>,
+    Path(client): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, Failure> {
+    let client = client
+        .parse::<u16>()
+        .map_err(|_| failure(StatusCode::NOT_FOUND, "not found"))?;
+    // watching first, so no change between the two is missed; it may be sent twice
+    let changes = ledger.watch();
+    let current = ledger.account(client);
+    let (sender, receiver) = mpsc::channel(UPDATE_BUFFER);
+    forward(
+        current.into_iter().collect(),
+        changes,
+        move |balance| balance.client == client,
+        sender,
+    );
+    let events = ReceiverStream::new(receiver).map(|balance| {
+        Event::default()
+            .event("balance")
+            .json_data(balance)
+            .map_err(axum::Error::new)
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+async fn updates(Extension(ledger): Extension<Arc<Ledger>>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| push_updates(socket, ledger))
+}
+
+/// Sends every account as a JSON text message, then each account whose balances change or
+/// that gets locked, until the client goes away.
+async fn push_updates(mut socket: WebSocket, ledger: Arc<Ledger>) {
+    // watching first, so no change between the two is missed; it may be sent twice
+    let changes = ledger.watch();
+    let current = ledger.accounts();
+    let (sender, mut receiver) = mpsc::channel(UPDATE_BUFFER);
+    forward(current, changes, |_| true, sender);
+    while let Some(balance) = receiver.recv().await {
+        let text = match serde_json::to_string(&balance) {
+            Ok(text) => text,
+            Err(err) => {
+                error!("failed to encode account update: {}", err);
+                continue;
+            }
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            // the client went away, dropping the receiver ends the watch
+            break;
+        }
+    }
+}
+
+/// Sends `current`, then the balances of `changes` that `keep` passes, to `sender` from a
+/// blocking thread. The thread checks every [`CLOSED_POLL`] whether the client went away,
+/// so it ends even while none of its accounts change; dropping `changes` ends the watch.
+fn forward(
+    current: Vec<Balance>,
+    changes: std::sync::mpsc::Receiver<Balance>,
+    keep: impl Fn(&Balance) -> bool + Send + 'static,
+    sender: mpsc::Sender<Balance>,
+) {
+    tokio::task::spawn_blocking(move || {
+        for balance in current {
+            if sender.blocking_send(balance).is_err() {
+                return;
+            }
+        }
+        while !sender.is_closed() {
+            match changes.recv_timeout(CLOSED_POLL) {
+                Ok(balance) if keep(&balance) => {
+                    if sender.blocking_send(balance).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::PipelineBuilder, throttle::ThrottleLimits};
+    use axum::{
+        body::{Body, HttpBody},
+        http::Request,
+    };
+    use tower::ServiceExt;
+
+    fn post_transaction(body: &str) -> Request<Body> {
+        post_encoded("application/json", body.as_bytes().to_vec())
+    }
+
+    fn post_encoded(content_type: &str, body: Vec<u8>) -> Request<Body> {
+        Request::post("/transactions")
+            .header("content-type", content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn status(request: Request<Body>) -> StatusCode {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let app = router(Ledger::new(PipelineBuilder::new().build()));
+            app.oneshot(request).await.unwrap().status()
+        })
+    }
+
+    #[test]
+    fn posts_transactions_and_streams_the_account() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let app = router(Ledger::new(PipelineBuilder::new().build()));
+            let response = app
+                .clone()
+                .oneshot(post_transaction(
+                    r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 5.0}"#,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = app
+                .clone()
+                .oneshot(post_transaction(
+                    r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": 8.0}"#,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+            let request = Request::get("/accounts/1/stream")
+                .body(Body::empty())
+                .unwrap();
+            let mut body = app.clone().oneshot(request).await.unwrap().into_body();
+            let first = body.data().await.unwrap().unwrap();
+            assert_eq!(
+                std::str::from_utf8(&first).unwrap(),
+                "event:balance\ndata:{\"client\":1,\"available\":\"5\",\"held\":\"0\",\"total\":\"5\",\"locked\":false}\n\n"
+            );
+            app.clone()
+                .oneshot(post_transaction(
+                    r#"{"type": "withdrawal", "client": 1, "tx": 3, "amount": 2.0}"#,
+                ))
+                .await
+                .unwrap();
+            let next = body.data().await.unwrap().unwrap();
+            assert!(std::str::from_utf8(&next)
+                .unwrap()
+                .contains("\"available\":\"3\""));
+        });
+    }
+
+    /// Body of a `GET` of `uri` after posting `deposits` (client, tx, amount).
+    fn get_after(deposits: &[(u16, u32, &str)], uri: &str) -> (StatusCode, String) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let app = router(Ledger::new(PipelineBuilder::new().build()));
+            for (client, tx, amount) in deposits {
+                let deposit = format!(
+                    r#"{{"type": "deposit", "client": {}, "tx": {}, "amount": "{}"}}"#,
+                    client, tx, amount
+                );
+                app.clone()
+                    .oneshot(post_transaction(&deposit))
+                    .await
+                    .unwrap();
+            }
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().data().await.unwrap().unwrap();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        })
+    }
+
+    #[test]
+    fn reads_every_account_or_one() {
+        let deposits = [(2, 1, "3"), (1, 2, "5.5")];
+        assert_eq!(
+            get_after(&deposits, "/accounts"),
+            (
+                StatusCode::OK,
+                concat!(
+                    r#"[{"client":1,"available":"5.5","held":"0","total":"5.5","locked":false},"#,
+                    r#"{"client":2,"available":"3","held":"0","total":"3","locked":false}]"#
+                )
+                .to_string()
+            )
+        );
+        assert_eq!(
+            get_after(&deposits, "/accounts/2"),
+            (
+                StatusCode::OK,
+                r#"{"client":2,"available":"3","held":"0","total":"3","locked":false}"#.to_string()
+            )
+        );
+        assert_eq!(get_after(&deposits, "/accounts/3").0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            get_after(&deposits, "/accounts/bob").0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn a_client_id_that_is_not_one_is_not_found() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let response = runtime.block_on(async {
+            let app = router(Ledger::new(PipelineBuilder::new().build()));
+            let request = Request::get("/accounts/bob/stream")
+                .body(Body::empty())
+                .unwrap();
+            app.oneshot(request).await.unwrap()
+        });
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn bodies_that_do_not_decode_are_bad_requests() {
+        assert_eq!(
+            status(post_transaction(r#"{"type": "deposit"}"#)),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(post_encoded("text/csv", b"deposit,1,1,1.0".to_vec())),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    #[test]
+    fn throttled_posts_are_too_many_requests() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let limits = ThrottleLimits {
+                rate: 0.001,
+                burst: 1,
+            };
+            let ledger = Ledger::new(PipelineBuilder::new().build()).throttled(limits);
+            let app = router(ledger);
+            let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 5.0}"#;
+            let response = app
+                .clone()
+                .oneshot(post_transaction(deposit))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let deposit = r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 5.0}"#;
+            let response = app
+                .clone()
+                .oneshot(post_transaction(deposit))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            let body = response.into_body().data().await.unwrap().unwrap();
+            assert_eq!(&body[..], br#"{"status":"throttled"}"#);
+        });
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn takes_msgpack_transactions() {
+        let body = rmp_serde::to_vec_named(&serde_json::json!({
+            "type": "deposit", "client": 1, "tx": 1, "amount": "5.0"
+        }))
+        .unwrap();
+        assert_eq!(
+            status(post_encoded("application/msgpack", body)),
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,3255 @@
+//! Transaction processing: applies deposits, withdrawals, disputes, resolves and chargebacks
+//! to client accounts. The `csvtest` binary runs [`run`]; other programs apply records with
+//! an [`Engine`]. With the `test-util` feature, [`test_util::TestEngine`] applies
+//! transactions in memory for integration tests.
+
+use amount::Amount;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use csv_row::Columns;
+use dedupe::SeenTx;
+use log::{error, info};
+use pipeline::PipelineBuilder;
+use policy::{LockedAccount, Policy, UnknownType};
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    iter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use transactions::{TxEntry, TxKind, TxState};
+
+pub mod accounts;
+mod aml;
+pub mod amount;
+#[cfg(feature = "amqp")]
+mod amqp;
+#[cfg(feature = "async")]
+pub mod async_engine;
+#[cfg(feature = "avro")]
+mod avro;
+pub mod bench;
+mod checkpoint;
+mod cli;
+mod compression;
+pub mod csv_row;
+// the bloom filter is only for the disk store of `--dedupe-store`
+#[cfg_attr(not(feature = "sled"), allow(dead_code))]
+mod dedupe;
+#[cfg(feature = "delta")]
+mod delta;
+mod dialect;
+mod dispute_sla;
+mod engine;
+mod error;
+mod event_log;
+mod events;
+mod file_queue;
+mod funding;
+mod generate;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod id_allocator;
+mod invariants;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(any(feature = "http", feature = "grpc"))]
+mod ledger;
+#[cfg(feature = "http")]
+mod ledger_server;
+mod locks;
+mod merge;
+// decoding by content type is only for the posts of `serve`
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+mod messages;
+mod metadata;
+mod minor_units;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+#[cfg(feature = "nats")]
+mod nats;
+mod parallel_csv;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod pipeline;
+pub mod policy;
+#[cfg(feature = "postgres")]
+mod postgres_sink;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod rebuild;
+#[cfg(feature = "redis")]
+mod redis_stream;
+mod rejects;
+mod remap;
+mod review_queue;
+mod run_report;
+#[cfg(feature = "s3")]
+mod s3;
+mod screening;
+mod sftp;
+mod shard;
+mod simulate;
+mod sinks;
+#[cfg(feature = "sled")]
+mod sled_store;
+mod snapshot;
+#[cfg(feature = "http")]
+mod snapshot_server;
+mod socket;
+#[cfg(feature = "sql")]
+mod sql;
+#[cfg(feature = "sqlite")]
+mod sqlite_store;
+mod state_file;
+mod store;
+mod stream_output;
+mod tag_report;
+mod tagging;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod throttle;
+mod timestamp;
+pub mod transactions;
+mod transforms;
+mod tx_type;
+mod wal;
+mod watch;
+mod withdrawal_limits;
+#[cfg(feature = "xlsx")]
+mod xlsx;
+
+pub use engine::{Engine, Report};
+pub use error::TxError;
+pub use events::RejectReason;
+pub use tx_type::TxType;
+
+/// One input row, amounts in exact decimals unless the accounts keep another [`Amount`].
+#[derive(Debug, Deserialize, Clone)]
+#[serde(bound(deserialize = "A: Amount"))]
+pub struct Record<A = Decimal> {
+    #[serde(rename = "type")]
+    pub tx_type: TxType,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    pub client: Option<u16>,
+    pub tx: u32,
+    // defaults let `--flexible` read rows that stop before the optional columns
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    pub amount: Option<A>,
+    /// acquirer fee, taken from deposits and added to withdrawals. The column is optional
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    pub fee: Option<A>,
+    /// free text note from the partner, the column is optional in the input
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    pub memo: Option<String>,
+    /// case reference of the card network for dispute, resolve and chargeback records.
+    /// The column is optional
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    pub evidence: Option<String>,
+    /// when the partner made the transaction, RFC 3339 or milliseconds since the unix epoch.
+    /// The column is optional
+    #[serde(default, deserialize_with = "timestamp::deserialize")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// 1-based position of the record in the input, used to age open disputes. Set when the
+    /// record is read, unless the source numbered it already
+    #[serde(skip)]
+    pub position: u64,
+    /// line of the record in CSV input, 0 for other inputs
+    #[serde(skip)]
+    pub line: u64,
+    /// global sequence number, assigned in order to every record the engine accepts, or by
+    /// the shard router before it
+    #[serde(skip)]
+    pub sequence: u64,
+    /// where the record after this one starts in CSV file input, to resume reading there
+    #[serde(skip)]
+    pub next_position: Option<csv::Position>,
+    /// tags attached by the tagging rules file
+    #[serde(skip)]
+    pub tags: Vec<String>,
+}
+
+impl<A: Amount> Record<A> {
+    /// Funds the transaction moved on the client's account. Deposits are credited net of
+    /// their fee, so that is also what a dispute on them holds. `None` as well when the fee
+    /// can not be taken from the amount without overflowing.
+    fn net_amount(&self) -> Option<A> {
+        match self.tx_type {
+            TxType::Deposit => self
+                .amount
+                .and_then(|amount| amount.checked_sub(self.fee.unwrap_or(A::ZERO))),
+            _ => self.amount,
+        }
+    }
+}
+
+/// One account: balances, lock state and the transactions kept for disputes. Serializable so
+/// a [`StateStore`](store::StateStore) can keep it outside of memory.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ClientInfo<A = Decimal> {
+    /// deposits and withdrawals applied to the account, see [`ClientInfo::keep`]
+    transactions: Vec<transactions::TxEntry<A>>,
+    /// position in `transactions` by tx id, the first entry when an id was reused
+    #[serde(serialize_with = "sorted_map")]
+    tx_index: HashMap<u32, usize>,
+    /// records applied to the account
+    kept: u64,
+    /// count and summed amount of applied records by tag, type and `YYYY-MM` month, the
+    /// month empty for records without a timestamp
+    tag_totals: BTreeMap<(String, String, String), (u64, A)>,
+    available_funds: A,
+    held_funds: A,
+    total_funds: A,
+    locked: bool,
+    /// ids of accounts folded into this one by the client remap file
+    aliases: Vec<u16>,
+    /// fees charged on applied deposits and withdrawals
+    fees: A,
+    /// why and when the account got locked
+    lock_history: Vec<locks::LockEvent>,
+    /// type and tx id of the records applied to the account, kept with `--skip-applied`
+    #[serde(default, serialize_with = "sorted_set")]
+    applied: HashSet<(TxType, u32)>,
+}
+
+/// Writes a map in key order, so the same account always serializes the same.
+fn sorted_map<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    serializer.collect_map(map.iter().collect::<BTreeMap<_, _>>())
+}
+
+/// Writes a set in order, see [`sorted_map`].
+fn sorted_set<T, S>(set: &HashSet<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Ord + Serialize,
+    S: Serializer,
+{
+    serializer.collect_seq(set.iter().collect::<BTreeSet<_>>())
+}
+
+/// What to write to stdout once the input has been processed.
+#[derive(Debug, Default, PartialEq)]
+enum Mode {
+    /// final balances per client, the default
+    #[default]
+    Balances,
+    /// `validate`, the number of rejected records and unreadable rows per reason
+    Validate,
+    /// `report by-tag [--global] [--by-month]`, counts and amounts per tag
+    ReportByTag { global: bool, by_month: bool },
+    /// `report html|markdown [--base snapshot.csv]`, run report for the daily close
+    RunReport {
+        format: run_report::ReportFormat,
+        base: Option<PathBuf>,
+    },
+    /// `migrate-snapshot --from v1 --to v3 snapshot.csv`, rewrites a snapshot instead of processing transactions
+    MigrateSnapshot { from: u32, to: u32 },
+    /// `query-sql "SELECT ..."`, result rows of a query over the balances, transactions and audit trail
+    QuerySql { sql: String },
+    /// `serve [--addr :8080] [input]`, a ledger over HTTP starting from the input, if any
+    Serve { addr: String },
+    /// `serve-grpc [--addr :50051] [input]`, the same ledger over gRPC
+    ServeGrpc { addr: String },
+    /// `serve-snapshot snapshot.csv [--addr :8080]`, read-only HTTP queries over a finished run
+    ServeSnapshot {
+        addr: String,
+        audit_log: Option<PathBuf>,
+    },
+    /// `simulate --base transactions.csv hypothetical.csv`, balance deltas and would-be
+    /// rejects of the hypothetical records on top of the base, nothing is committed
+    Simulate { base: PathBuf },
+    /// `query --as-of 1500 --wal accounts.wal`, the balances replayed from the log up to a
+    /// record or a time
+    Query { as_of: wal::AsOf },
+    /// `rebuild --wal accounts.wal --db accounts.sqlite`, the accounts that differ between
+    /// the whole log replayed onto an empty state and the stored state
+    Rebuild,
+    /// `completions bash|zsh|fish`, the completion script of the command line
+    Completions(clap_complete::Shell),
+    /// `explain insufficient_funds`, what a reject code means and how to fix the input
+    Explain(events::RejectReason),
+    /// `generate --clients 10000 --rows 5000000`, random transactions instead of processing any
+    Generate(generate::GenerateOptions),
+    /// `bench --workload dispute-heavy --rows 1000000`, rows per second and peak memory of
+    /// the engine on generated input
+    Bench {
+        workloads: Vec<bench::Workload>,
+        rows: u64,
+    },
+}
+
+/// Everything parsed from the command line.
+#[derive(Debug, Default)]
+struct Config {
+    mode: Mode,
+    /// applied in order to the same state, `-` for stdin; empty only for the servers, whose
+    /// ledger then starts empty
+    inputs: Vec<PathBuf>,
+    /// the inputs are merged in timestamp order instead of applied one after the other
+    merge_by_time: bool,
+    /// file the output is written to instead of stdout, replaced atomically once complete
+    output: Option<PathBuf>,
+    /// format of the input, picked from the file extension when not given
+    input_format: Option<InputFormat>,
+    /// csv layout flags, anything not given is sniffed from the input
+    dialect: dialect::DialectOverrides,
+    /// sheet to read from spreadsheet input, the first sheet by default
+    #[cfg(feature = "xlsx")]
+    sheet: Option<String>,
+    /// spreadsheet columns of the transaction fields, found from the header row by default
+    #[cfg(feature = "xlsx")]
+    xlsx_columns: Option<xlsx::ColumnLayout>,
+    policy: Policy,
+    /// where to write the open disputes at the end of the run, csv or json by extension
+    review_queue: Option<PathBuf>,
+    /// where to write the suspicious activity report, the rules only run when set
+    sar_report: Option<PathBuf>,
+    aml_rules: aml::AmlRules,
+    /// decimal or integer minor unit balances in the snapshot
+    amount_units: AmountUnits,
+    /// csv or json balances
+    format: OutputFormat,
+    /// add a `lock_reason` column to the snapshot
+    lock_reasons: bool,
+    /// leave the balances in map order instead of sorting them by client
+    unsorted: bool,
+    /// where to write the lock history of every account
+    lock_history: Option<PathBuf>,
+    /// internal account chargeback funds are booked to
+    suspense_account: Option<u16>,
+    /// how ids are generated for records without a client, and the seed of random ones
+    client_ids: id_allocator::IdStrategy,
+    client_id_seed: u64,
+    /// records before this time are skipped, but their transactions can still be disputed
+    from: Option<DateTime<Utc>>,
+    /// records from this time on are skipped
+    to: Option<DateTime<Utc>>,
+    /// database the accounts are kept in, and continued from
+    state_store: Option<StateStoreKind>,
+    /// accounts held in memory with a state store
+    state_cache: usize,
+    /// state file of an earlier run to continue from
+    load_state: Option<PathBuf>,
+    /// where to write the state left by the run
+    save_state: Option<PathBuf>,
+    /// periodic checkpoints of a run over a large file, and whether to resume from one
+    checkpoint: Option<CheckpointOptions>,
+    /// write-ahead log of the records applied, replayed on top of the state at startup
+    wal: Option<PathBuf>,
+    /// the WAL keeps every record instead of being emptied once the state is persisted
+    keep_wal: bool,
+    /// skip records already applied to the account, in this run or one the state is from
+    skip_applied: bool,
+    /// check the balance invariants of every account a record touched, and what to do when
+    /// one breaks
+    check_invariants: Option<invariants::OnViolation>,
+    /// disk store of the tx ids seen by the duplicate check, with the ids the bloom filter in
+    /// front of it is sized for
+    dedupe_store: Option<(PathBuf, u64)>,
+    /// threads the input is applied over, by client
+    shards: Option<usize>,
+    /// threads CSV rows are deserialized on, 1 reads them on the applying thread
+    parse_threads: usize,
+    /// read CSV files from a memory mapping
+    mmap: bool,
+    /// records the ledger or a socket source holds waiting for the engine
+    queue_capacity: usize,
+    /// client ids whose transactions are blocked and whose accounts are frozen
+    denylist: Option<PathBuf>,
+    /// where blocked records are written
+    quarantine: Option<PathBuf>,
+    /// where duplicate deposits and withdrawals are written under the `reject` policy
+    duplicate_rejects: Option<PathBuf>,
+    /// dead letter file of every rejected record and malformed row
+    rejects: Option<PathBuf>,
+    /// JSON lines of every applied record with the resulting balances
+    events: Option<PathBuf>,
+    /// disputes still open this long after the dispute record are resolved
+    dispute_sla: Option<chrono::Duration>,
+    /// rules file used to tag transactions while processing
+    tag_rules: Option<PathBuf>,
+    /// `old_id,new_id` file folding client ids into the surviving account
+    client_remap: Option<PathBuf>,
+    /// `client,funding_client` file of accounts covering overdrafts
+    funding_links: Option<PathBuf>,
+    /// most withdrawals, and funds withdrawn, per client in any 24 hours
+    withdrawal_limits: withdrawal_limits::WithdrawalLimits,
+    /// poll a remote directory instead of reading `input`, which is then the local download directory
+    sftp: Option<sftp::SftpSource>,
+    /// consume the records from a message broker instead of reading `input`
+    broker: Option<Broker>,
+    /// write an NDJSON line per balance change, at most once per interval per account,
+    /// instead of the final output
+    stream_output: Option<Duration>,
+    /// sidecar JSON recording the engine version, input checksum, policy and counts of the run
+    metadata: Option<PathBuf>,
+    /// write the same metadata as `#` comment lines above the balances
+    metadata_header: bool,
+    /// Delta table the final balances are committed to, one version per run
+    #[cfg(feature = "delta")]
+    delta_table: Option<PathBuf>,
+    /// Delta table the records kept by accounts are appended to
+    #[cfg(feature = "delta")]
+    delta_events: Option<PathBuf>,
+    /// postgres url and table the final balances are upserted into
+    sink: Option<(String, String)>,
+    /// consume `input` as a drop folder shared with other instances
+    file_queue: Option<file_queue::FileQueueOptions>,
+    /// apply the new files of a directory as they appear, on top of the state of `input`
+    watch: Option<watch::WatchOptions>,
+    /// records per client the servers and brokers take, past them records are refused
+    throttle: Option<throttle::ThrottleLimits>,
+}
+
+/// Message broker or listener the records are consumed from, see `--source`. Messages are
+/// JSON or MessagePack transactions with the CSV header names as keys, redis stream entries
+/// have them as fields.
+#[derive(Debug, PartialEq)]
+enum Broker {
+    // the settings of a broker are only kept when its feature is on
+    Kafka {
+        #[cfg(feature = "kafka")]
+        brokers: String,
+        #[cfg(feature = "kafka")]
+        group: String,
+        #[cfg(feature = "kafka")]
+        topic: String,
+    },
+    Nats {
+        #[cfg(feature = "nats")]
+        url: String,
+        #[cfg(feature = "nats")]
+        subject: String,
+        #[cfg(feature = "nats")]
+        durable: String,
+    },
+    Amqp {
+        #[cfg(feature = "amqp")]
+        url: String,
+        #[cfg(feature = "amqp")]
+        queue: String,
+        #[cfg(feature = "amqp")]
+        prefetch: u16,
+        #[cfg(feature = "amqp")]
+        dead_letter_queue: Option<String>,
+    },
+    Redis {
+        #[cfg(feature = "redis")]
+        url: String,
+        #[cfg(feature = "redis")]
+        stream: String,
+        #[cfg(feature = "redis")]
+        group: String,
+        #[cfg(feature = "redis")]
+        consumer: String,
+    },
+    /// not a broker, transactions pushed over TCP connections
+    Tcp {
+        addr: String,
+        framing: socket::Framing,
+    },
+    /// transactions pushed over unix domain socket connections
+    Unix {
+        path: PathBuf,
+        framing: socket::Framing,
+    },
+}
+
+/// Database of `--state-store` or `--db`.
+#[derive(Debug)]
+enum StateStoreKind {
+    Sled {
+        #[cfg(feature = "sled")]
+        path: PathBuf,
+    },
+    Sqlite {
+        #[cfg(feature = "sqlite")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug)]
+struct CheckpointOptions {
+    path: PathBuf,
+    /// records read between two checkpoints
+    every: u64,
+    /// continue from the checkpoint when there is one
+    resume: bool,
+}
+
+/// Input file formats, picked by `--input-format` or else the file extension.
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq)]
+enum InputFormat {
+    Csv,
+    Xlsx,
+    /// length-delimited `Transaction` messages, see `proto/transaction.proto`
+    Protobuf,
+    /// needs the `parquet` feature
+    Parquet,
+    /// object container files, needs the `avro` feature
+    Avro,
+    /// MessagePack transactions, each prefixed with its length as a 4 byte big-endian
+    /// integer, needs the `msgpack` feature
+    Msgpack,
+}
+
+impl InputFormat {
+    /// Spreadsheet, protobuf, parquet, avro and msgpack extensions, CSV for everything else.
+    fn from_path(path: &Path) -> Self {
+        if is_spreadsheet(path) {
+            return InputFormat::Xlsx;
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pb") | Some("binpb") => InputFormat::Protobuf,
+            Some("parquet") => InputFormat::Parquet,
+            Some("avro") => InputFormat::Avro,
+            Some("msgpack") => InputFormat::Msgpack,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+/// Records from whichever reader handles the input format.
+type Records = Box<dyn Iterator<Item = Result<Record, TxError>> + Send>;
+
+/// Decimal places of major unit amounts in the snapshot.
+const OUTPUT_DECIMALS: u32 = 4;
+
+/// How balances are written in the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AmountUnits {
+    /// decimal amounts with exactly four decimal places, e.g. 12.3456 or 1.5000
+    Major { rounding: Rounding },
+    /// integer count of the smallest denomination with `scale` decimal places, e.g. 123456.
+    /// Amounts are converted to minor units as they are read, see [`minor_units`]
+    Minor { scale: u32 },
+}
+
+impl Default for AmountUnits {
+    fn default() -> Self {
+        AmountUnits::Major {
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+/// How major unit amounts with more than four decimal places are rounded for output.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Rounding {
+    /// to the nearest, ties to the even neighbour
+    #[default]
+    HalfEven,
+    /// to the nearest, ties away from zero
+    HalfUp,
+    /// towards zero
+    Down,
+    /// away from zero
+    Up,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+            Rounding::Up => RoundingStrategy::AwayFromZero,
+        }
+    }
+}
+
+/// File format of the balances snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OutputFormat {
+    #[default]
+    Csv,
+    /// an array of account objects with the same fields as the csv columns
+    Json,
+    /// the csv columns as a Parquet file, needs the `parquet` feature
+    Parquet,
+}
+
+/// A snapshot amount, serialized as the bare number.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+enum OutputAmount {
+    Major(Decimal),
+    Minor(i64),
+}
+
+impl AmountUnits {
+    /// Fails for an amount that is not a whole count of minor units fitting an `i64` rather
+    /// than writing a wrong one.
+    fn convert(self, amount: Decimal) -> Result<OutputAmount, TxError> {
+        match self {
+            AmountUnits::Major { rounding } => {
+                let mut amount =
+                    amount.round_dp_with_strategy(OUTPUT_DECIMALS, rounding.strategy());
+                // pads as well, so 1.5 is written as 1.5000
+                amount.rescale(OUTPUT_DECIMALS);
+                Ok(OutputAmount::Major(amount))
+            }
+            AmountUnits::Minor { scale } => {
+                // already in minor units, converted as they were read
+                let units = Some(amount)
+                    .filter(|amount| amount.fract().is_zero())
+                    .and_then(|amount| amount.to_i64())
+                    .ok_or_else(|| {
+                        TxError::other(format!(
+                            "{} has no count of minor units with scale {}",
+                            amount, scale
+                        ))
+                    })?;
+                Ok(OutputAmount::Minor(units))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct OutputInfo {
+    client: u16,
+    available: OutputAmount,
+    held: OutputAmount,
+    total: OutputAmount,
+    locked: bool,
+    /// only written when a client remap file is used, `;` separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aliases: Option<String>,
+    /// only written when the input has a fee column
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fees: Option<OutputAmount>,
+    /// only written with withdrawal limits, withdrawals in the 24 hours up to the newest
+    /// record of the run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    withdrawals_24h: Option<u32>,
+    /// only written with withdrawal limits, funds withdrawn in the same 24 hours
+    #[serde(skip_serializing_if = "Option::is_none")]
+    withdrawn_24h: Option<OutputAmount>,
+    /// only written with `--lock-reasons`, `;` separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_reason: Option<String>,
+}
+
+/// Funds and lock of an account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Balance<A = Decimal> {
+    pub client: u16,
+    pub available: A,
+    pub held: A,
+    pub total: A,
+    pub locked: bool,
+}
+
+impl<A: Amount> Balance<A> {
+    fn of(client: u16, info: &ClientInfo<A>) -> Self {
+        Balance {
+            client,
+            available: info.available_funds,
+            held: info.held_funds,
+            total: info.total_funds,
+            locked: info.locked,
+        }
+    }
+}
+
+/// What the handler of a record did with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// the record was applied to the account
+    Applied,
+    /// the handler refused the record and left the accounts as they were
+    Refused(RejectReason),
+}
+
+impl Config {
+    /// The first input, the one file or directory of the modes that take a single input.
+    fn input(&self) -> &Path {
+        self.inputs
+            .first()
+            .map_or(Path::new(STDIN), PathBuf::as_path)
+    }
+}
+
+/// Runs the command line program: reads the arguments, processes the input and writes the
+/// output to stdout. The `csvtest` binary only calls this.
+pub fn run() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+
+    let config = cli::Cli::parse().into_config()?;
+    if let Mode::Completions(shell) = config.mode {
+        return cli::write_completions(shell, io::stdout());
+    }
+    if let Mode::Explain(reason) = config.mode {
+        println!("{}: {}", reason.as_str(), reason.explanation());
+        return Ok(());
+    }
+    if let Mode::Bench { workloads, rows } = &config.mode {
+        // a refused record would be measured as a log line
+        log::set_max_level(log::LevelFilter::Off);
+        return bench::run(io::stdout(), workloads, *rows);
+    }
+    if let Mode::Generate(options) = &config.mode {
+        return match &config.output {
+            Some(output) => write_atomic(output, |out| generate::write(out, options)),
+            None => generate::write(BufWriter::new(io::stdout()), options),
+        };
+    }
+    if let Mode::MigrateSnapshot { from, to } = config.mode {
+        let defaults = snapshot::MigrationDefaults::default();
+        return snapshot::migrate(config.input(), from, to, &defaults, io::stdout());
+    }
+    if let Mode::Serve { addr } = &config.mode {
+        return serve(&config, addr);
+    }
+    if let Mode::ServeGrpc { addr } = &config.mode {
+        return serve_grpc(&config, addr);
+    }
+    if let Mode::ServeSnapshot { addr, audit_log } = &config.mode {
+        return serve_snapshot(config.input(), audit_log.as_deref(), addr);
+    }
+    if let Mode::Query { as_of } = config.mode {
+        return match &config.output {
+            Some(output) => write_atomic(output, |out| query_as_of(&config, as_of, out)),
+            None => query_as_of(&config, as_of, io::stdout()),
+        };
+    }
+    if let Mode::Rebuild = config.mode {
+        return match &config.output {
+            Some(output) => write_atomic(output, |out| rebuild_accounts(&config, out)),
+            None => rebuild_accounts(&config, io::stdout()),
+        };
+    }
+    if let Some(source) = &config.sftp {
+        return sftp::poll(source, &config);
+    }
+    if let Some(options) = &config.file_queue {
+        return file_queue::consume(config.input(), options, &config);
+    }
+    if let Some(options) = &config.watch {
+        return watch::watch(options, &config);
+    }
+    match &config.output {
+        Some(output) => write_atomic(output, |out| process_input(&config, &config.inputs, out)),
+        None => process_input(&config, &config.inputs, io::stdout()),
+    }
+}
+
+/// Writes to a temporary file next to `path` that is renamed over it once `write` is done,
+/// so `path` only ever holds a complete output. The temporary file is removed on failure.
+fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let result: Result<(), Box<dyn Error>> =
+        File::create(&partial).map_err(From::from).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write(&mut out)?;
+            // on disk before the rename, or a crash could still leave a partial file
+            out.into_inner()
+                .map_err(io::IntoInnerError::into_error)?
+                .sync_all()?;
+            Ok(())
+        });
+    match result {
+        Ok(()) => Ok(fs::rename(&partial, path)?),
+        Err(err) => {
+            let _ = fs::remove_file(&partial);
+            Err(err)
+        }
+    }
+}
+
+#[cfg(feature = "sled")]
+fn seen_on_disk(path: &Path, expected: u64) -> Result<Box<dyn SeenTx + Send>, Box<dyn Error>> {
+    Ok(Box::new(dedupe::Tiered::open(path, expected)?))
+}
+
+#[cfg(not(feature = "sled"))]
+fn seen_on_disk(_path: &Path, _expected: u64) -> Result<Box<dyn SeenTx + Send>, Box<dyn Error>> {
+    Err(From::from("--dedupe-store needs the sled feature"))
+}
+
+/// The pipeline of a run without its source and sinks: the policy, then the client remap,
+/// the date window, the denylist, the tagging rules and the withdrawal limits read from the files named on
+/// the command line, and the throttle of a broker.
+fn pipeline_for(config: &Config) -> Result<PipelineBuilder<'static>, Box<dyn Error>> {
+    let duplicate_rejects = match (config.policy.duplicate_tx, &config.duplicate_rejects) {
+        (policy::DuplicateTx::Reject, None) => {
+            return Err(From::from(
+                "duplicate tx policy reject expects --duplicate-rejects",
+            ))
+        }
+        (policy::DuplicateTx::Reject, Some(path)) => {
+            Some(transforms::DuplicateRejects::create(path)?)
+        }
+        _ => None,
+    };
+    let mut pipeline = engine_for(config)?.duplicate_rejects(duplicate_rejects);
+    if let Some((path, expected)) = &config.dedupe_store {
+        pipeline = pipeline.seen_tx(seen_on_disk(path, *expected)?);
+    }
+    if let AmountUnits::Minor { scale } = config.amount_units {
+        // first, so every later stage sees minor units
+        pipeline = pipeline.transform(minor_units::MinorUnits::new(scale)?);
+    }
+    if let Some(path) = &config.client_remap {
+        pipeline = pipeline.transform(transforms::Remap::new(remap::ClientRemap::from_path(path)?));
+    }
+    if config.from.is_some() || config.to.is_some() {
+        pipeline = pipeline.transform(transforms::DateWindow::new(
+            config.from,
+            config.to,
+            config.policy.clone(),
+        ));
+    }
+    let denylist = match &config.denylist {
+        Some(path) => screening::Denylist::from_path(path)?,
+        None => screening::Denylist::default(),
+    };
+    pipeline = pipeline.transform(screening::Screening::new(
+        denylist,
+        config.quarantine.as_deref(),
+    )?);
+    if let Some(path) = &config.tag_rules {
+        pipeline = pipeline.transform(tagging::TagRules::from_path(path)?);
+    }
+    if config.withdrawal_limits.is_set() {
+        pipeline = pipeline.transform(config.withdrawal_limits);
+    }
+    #[cfg(feature = "delta")]
+    if let Some(path) = &config.delta_events {
+        pipeline = pipeline.transform(delta::DeltaEvents::new(path));
+    }
+    if config.skip_applied {
+        // after the transforms that change the client
+        pipeline = pipeline.transform(transforms::SkipApplied::default());
+    }
+    if let (Some(_), Some(limits)) = (&config.broker, config.throttle) {
+        // last, so a record is throttled under its final client
+        pipeline = pipeline.transform(throttle::Throttle::new(limits));
+    }
+    Ok(pipeline)
+}
+
+/// The pipeline with only the engine options of the command line: the policy, the suspense
+/// account, the client ids, the dispute deadline and the funding links.
+fn engine_for(config: &Config) -> Result<PipelineBuilder<'static>, Box<dyn Error>> {
+    let mut pipeline = PipelineBuilder::new()
+        .policy(config.policy.clone())
+        .suspense_account(config.suspense_account)
+        .client_ids(config.client_ids.allocator(config.client_id_seed))
+        .dispute_sla(config.dispute_sla)
+        .check_invariants(config.check_invariants);
+    if let Some(path) = &config.funding_links {
+        pipeline = pipeline.funding_links(funding::FundingLinks::from_path(path)?);
+    }
+    Ok(pipeline)
+}
+
+/// Keeps the accounts of `pipeline` in the state store of the command line and logs its
+/// records to the WAL, for either that is given.
+fn persisted<'a>(
+    mut pipeline: PipelineBuilder<'a>,
+    config: &Config,
+) -> Result<PipelineBuilder<'a>, Box<dyn Error>> {
+    if let Some(store) = &config.state_store {
+        pipeline = with_store(pipeline, store, config.state_cache)?;
+    }
+    if let Some(path) = &config.wal {
+        pipeline = pipeline.wal(open_wal(path, config)?);
+    }
+    Ok(pipeline)
+}
+
+/// The WAL at `path`, kept whole with `--keep-wal`.
+fn open_wal(path: &Path, config: &Config) -> Result<wal::Wal, TxError> {
+    let wal = wal::Wal::open(path)?;
+    Ok(if config.keep_wal { wal.keep() } else { wal })
+}
+
+/// Keeps the accounts of the pipeline in the configured database.
+#[cfg_attr(
+    not(any(feature = "sled", feature = "sqlite")),
+    allow(unused_variables)
+)]
+fn with_store<'a>(
+    pipeline: PipelineBuilder<'a>,
+    store: &StateStoreKind,
+    cache: usize,
+) -> Result<PipelineBuilder<'a>, Box<dyn Error>> {
+    match store {
+        #[cfg(feature = "sled")]
+        StateStoreKind::Sled { path } => {
+            Ok(pipeline.store(sled_store::SledStore::open(path)?, cache))
+        }
+        #[cfg(not(feature = "sled"))]
+        StateStoreKind::Sled {} => Err(From::from("--state-store needs the sled feature")),
+        #[cfg(feature = "sqlite")]
+        StateStoreKind::Sqlite { path } => {
+            Ok(pipeline.store(sqlite_store::SqliteStore::open(path)?, cache))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        StateStoreKind::Sqlite {} => Err(From::from("--db needs the sqlite feature")),
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn with_postgres_sink<'a>(
+    pipeline: PipelineBuilder<'a>,
+    url: &str,
+    table: &str,
+) -> Result<PipelineBuilder<'a>, Box<dyn Error>> {
+    Ok(pipeline.sink(postgres_sink::PostgresBalances::connect(url, table)?))
+}
+
+#[cfg(not(feature = "postgres"))]
+fn with_postgres_sink<'a>(
+    _pipeline: PipelineBuilder<'a>,
+    _url: &str,
+    _table: &str,
+) -> Result<PipelineBuilder<'a>, Box<dyn Error>> {
+    Err(From::from("--sink needs the postgres feature"))
+}
+
+/// The ledger of the servers, starting from the state the input leaves if one is given.
+/// Nothing is written for the input.
+#[cfg(any(feature = "http", feature = "grpc"))]
+fn start_ledger(config: &Config) -> Result<ledger::Ledger, Box<dyn Error>> {
+    let mut pipeline = pipeline_for(config)?.state(loaded_state(config)?).build();
+    for result in open_inputs(config, &config.inputs)? {
+        pipeline.process(result?)?;
+    }
+    // the input is applied at every start, the log holds what was served on top of it
+    if let Some(path) = &config.wal {
+        pipeline.log_to(open_wal(path, config)?);
+    }
+    let mut ledger = ledger::Ledger::new(pipeline).bounded(config.queue_capacity);
+    if let Some(limits) = config.throttle {
+        ledger = ledger.throttled(limits);
+    }
+    Ok(ledger)
+}
+
+/// `:8080` listens on every interface.
+fn bind_address(addr: &str) -> String {
+    if addr.starts_with(':') {
+        format!("0.0.0.0{}", addr)
+    } else {
+        addr.to_string()
+    }
+}
+
+/// Applies the input, if one is given, then serves the accounts over HTTP.
+#[cfg(feature = "http")]
+fn serve(config: &Config, addr: &str) -> Result<(), Box<dyn Error>> {
+    ledger_server::serve(start_ledger(config)?, addr)
+}
+
+#[cfg(not(feature = "http"))]
+fn serve(_config: &Config, _addr: &str) -> Result<(), Box<dyn Error>> {
+    Err(From::from("serve needs the http feature"))
+}
+
+/// Applies the input, if one is given, then serves the ledger service over gRPC.
+#[cfg(feature = "grpc")]
+fn serve_grpc(config: &Config, addr: &str) -> Result<(), Box<dyn Error>> {
+    grpc::serve(start_ledger(config)?, addr)
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc(_config: &Config, _addr: &str) -> Result<(), Box<dyn Error>> {
+    Err(From::from("serve-grpc needs the grpc feature"))
+}
+
+#[cfg(feature = "http")]
+fn serve_snapshot(
+    snapshot: &Path,
+    audit_log: Option<&Path>,
+    addr: &str,
+) -> Result<(), Box<dyn Error>> {
+    snapshot_server::serve(snapshot, audit_log, addr)
+}
+
+#[cfg(not(feature = "http"))]
+fn serve_snapshot(
+    _snapshot: &Path,
+    _audit_log: Option<&Path>,
+    _addr: &str,
+) -> Result<(), Box<dyn Error>> {
+    Err(From::from("serve-snapshot needs the http feature"))
+}
+
+/// Records a shard can be behind the reader by.
+const SHARD_BACKLOG: usize = 1024;
+
+/// Applies every transaction in `paths` to a fresh client map and writes the output for the
+/// mode to `out`.
+fn process_input<W: Write + Send>(
+    config: &Config,
+    paths: &[PathBuf],
+    out: W,
+) -> Result<(), Box<dyn Error>> {
+    let policy = &config.policy;
+    // what the metadata and the run report name as the input
+    let path = paths.first().map_or(Path::new(STDIN), PathBuf::as_path);
+    if let Mode::Simulate { base } = &config.mode {
+        return simulate(config, base, paths, out);
+    }
+    let mut pipeline = pipeline_for(config)?;
+    let records = match &config.broker {
+        Some(broker) => open_broker(broker, config.queue_capacity, &mut pipeline)?,
+        None => open_inputs(config, paths)?,
+    };
+    #[cfg_attr(not(feature = "sql"), allow(unused_mut))]
+    let mut state = loaded_state(config)?;
+    #[cfg(feature = "sql")]
+    {
+        state.audit = matches!(config.mode, Mode::QuerySql { .. }).then(sql::AuditTrail::default);
+    }
+    pipeline = match config.shards {
+        Some(shards) => {
+            let state = shard::run(records, shards, SHARD_BACKLOG, state, |_| {
+                pipeline_for(config)
+            })?;
+            // nothing left to do but hand the merged state to the sinks
+            engine_for(config)?
+                .source(iter::empty::<Result<Record, TxError>>())
+                .state(state)
+        }
+        None => pipeline.source(records).state(state),
+    };
+
+    if let Some(min_interval) = config.stream_output {
+        // the stream replaces the final output, so the metadata is not written either
+        pipeline = pipeline.sink(stream_output::AccountStream::new(out, min_interval));
+    } else {
+        if let Some(metadata_path) = &config.metadata {
+            pipeline = pipeline.sink(sinks::MetadataSidecar {
+                path: metadata_path.clone(),
+                input: path.to_path_buf(),
+                policy: policy.clone(),
+            });
+        }
+        pipeline = match config.mode {
+            Mode::Balances => pipeline.sink(sinks::Balances {
+                out,
+                aliases: config.client_remap.is_some(),
+                withdrawal_usage: config.withdrawal_limits.is_set(),
+                lock_reason: config.lock_reasons,
+                units: config.amount_units,
+                format: config.format,
+                sorted: !config.unsorted,
+                header: config
+                    .metadata_header
+                    .then(|| (path.to_path_buf(), policy.clone())),
+            }),
+            Mode::Validate => {
+                let events = pipeline.subscribe();
+                pipeline.sink(sinks::Validation { out, events })
+            }
+            Mode::ReportByTag { global, by_month } => pipeline.sink(sinks::TagReport {
+                out,
+                global,
+                by_month,
+            }),
+            Mode::RunReport { format, ref base } => {
+                let events = pipeline.subscribe();
+                pipeline.sink(run_report::RunReport {
+                    out,
+                    format,
+                    input: path.to_path_buf(),
+                    base: base.clone(),
+                    events,
+                })
+            }
+            Mode::MigrateSnapshot { .. } | Mode::ServeSnapshot { .. } => {
+                unreachable!("snapshots are migrated and served without processing")
+            }
+            Mode::Simulate { .. } => unreachable!("simulations are run on their own"),
+            Mode::Query { .. } | Mode::Rebuild => {
+                unreachable!("queries and rebuilds replay the WAL, not an input")
+            }
+            Mode::Serve { .. } | Mode::ServeGrpc { .. } => {
+                unreachable!("the server applies records as they are submitted")
+            }
+            Mode::Generate(_) | Mode::Bench { .. } => {
+                unreachable!("transactions are generated without processing")
+            }
+            Mode::Completions(_) | Mode::Explain(_) => {
+                unreachable!("completions and explanations are written without processing")
+            }
+            #[cfg(feature = "sql")]
+            Mode::QuerySql { ref sql } => pipeline.sink(sinks::SqlQuery {
+                out,
+                sql: sql.clone(),
+            }),
+            #[cfg(not(feature = "sql"))]
+            Mode::QuerySql { .. } => return Err(From::from("query-sql needs the sql feature")),
+        };
+    }
+    if let Some(path) = &config.review_queue {
+        pipeline = pipeline.sink(sinks::ReviewQueue(path.clone()));
+    }
+    if let Some(path) = &config.lock_history {
+        pipeline = pipeline.sink(sinks::LockHistory(path.clone()));
+    }
+    if let Some(path) = &config.rejects {
+        let events = pipeline.subscribe();
+        pipeline = pipeline.sink(rejects::Rejects::new(path, events)?);
+    }
+    if let Some(path) = &config.events {
+        let events = pipeline.subscribe();
+        pipeline = pipeline.sink(event_log::EventLog::new(path, events)?);
+    }
+    if let Some(path) = &config.sar_report {
+        pipeline = pipeline.sink(sinks::SarReport {
+            path: path.clone(),
+            rules: config.aml_rules.clone(),
+        });
+    }
+    #[cfg(feature = "delta")]
+    if let Some(path) = &config.delta_table {
+        pipeline = pipeline.sink(delta::DeltaBalances::new(path));
+    }
+    if let Some((url, table)) = &config.sink {
+        pipeline = with_postgres_sink(pipeline, url, table)?;
+    }
+    if let Some(options) = &config.checkpoint {
+        pipeline = pipeline.sink(checkpoint::Checkpoints {
+            path: options.path.clone(),
+            input: path.to_path_buf(),
+            every: options.every,
+            taken: 0,
+        });
+    }
+
+    let state = persisted(pipeline, config)?.build().run()?;
+    save_state(config, &state)
+}
+
+/// The checkpoint `--resume` continues from, if the run it belongs to left one.
+fn resume_checkpoint(config: &Config) -> Option<&Path> {
+    config
+        .checkpoint
+        .as_ref()
+        .filter(|options| options.resume && options.path.exists())
+        .map(|options| options.path.as_path())
+}
+
+/// The state of the checkpoint to resume from or of `--load-state`, an empty one without
+/// either.
+fn loaded_state(config: &Config) -> Result<pipeline::State, Box<dyn Error>> {
+    if let Some(path) = resume_checkpoint(config) {
+        let (position, state) = checkpoint::read(path)?;
+        info!(
+            "resuming {} at line {}, after {} records",
+            position.input.display(),
+            position.line,
+            state.processed
+        );
+        return Ok(state);
+    }
+    match &config.load_state {
+        Some(path) => Ok(state_file::read(io::BufReader::new(File::open(path)?))?),
+        None => Ok(pipeline::State::default()),
+    }
+}
+
+/// Writes the state to `--save-state` for `--load-state` to pick up, atomically, if given.
+/// Once it is written where the next run loads it from, the WAL holds nothing that state
+/// does not have, and is emptied.
+fn save_state(config: &Config, state: &pipeline::State) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = &config.save_state {
+        write_atomic(path, |out| Ok(state_file::write(state, out)?))?;
+        info!("state saved to {}", path.display());
+        if let (Some(wal), true) = (&config.wal, config.load_state.as_ref() == Some(path)) {
+            open_wal(wal, config)?.truncate()?;
+        }
+    }
+    Ok(())
+}
+
+/// Replays the whole WAL onto an empty state with the engine options of `config`, and writes
+/// the accounts that differ from the stored state to `out`.
+fn rebuild_accounts<W: Write>(config: &Config, out: W) -> Result<(), Box<dyn Error>> {
+    let wal = config.wal.as_deref().ok_or("rebuild needs --wal")?;
+    if !wal.exists() {
+        return Err(From::from(format!("there is no WAL at {}", wal.display())));
+    }
+    let nothing = iter::empty::<Result<Record, TxError>>;
+    let snapshot = match &config.state_store {
+        Some(store) => with_store(
+            engine_for(config)?.source(nothing()),
+            store,
+            config.state_cache,
+        )?
+        .build()
+        .run()?,
+        None => loaded_state(config)?,
+    };
+    let rebuilt = engine_for(config)?
+        .source(nothing())
+        .replay(wal.to_path_buf(), None)
+        .build()
+        .run()?;
+    let divergences = rebuild::compare(&snapshot, &rebuilt);
+    rebuild::write(out, &divergences)?;
+    if snapshot.sequence != rebuilt.sequence {
+        return Err(From::from(format!(
+            "the stored state is at record {}, the WAL holds {} records",
+            snapshot.sequence, rebuilt.sequence
+        )));
+    }
+    if !divergences.is_empty() {
+        return Err(From::from(format!(
+            "{} accounts differ from the stored state",
+            divergences.len()
+        )));
+    }
+    info!(
+        "rebuilt {} accounts from {} records, they match the stored state",
+        rebuilt.client_map.len(),
+        rebuilt.sequence
+    );
+    Ok(())
+}
+
+/// Writes the balances left by replaying the WAL onto an empty state up to `as_of`.
+fn query_as_of<W: Write + Send>(
+    config: &Config,
+    as_of: wal::AsOf,
+    out: W,
+) -> Result<(), Box<dyn Error>> {
+    let wal = config.wal.as_deref().ok_or("query needs --wal")?;
+    if !wal.exists() {
+        return Err(From::from(format!("there is no WAL at {}", wal.display())));
+    }
+    let state = engine_for(config)?
+        .source(iter::empty::<Result<Record, TxError>>())
+        .replay(wal.to_path_buf(), Some(as_of))
+        .sink(sinks::Balances {
+            out,
+            aliases: false,
+            withdrawal_usage: false,
+            lock_reason: config.lock_reasons,
+            units: config.amount_units,
+            format: config.format,
+            sorted: !config.unsorted,
+            header: None,
+        })
+        .build()
+        .run()?;
+    info!("balances as of record {} of the WAL", state.sequence);
+    Ok(())
+}
+
+/// Replays `base` to rebuild the existing state, then applies the records of `paths` on top
+/// of it and writes how they would change the accounts to `out`. Nothing else is written.
+fn simulate<W: Write + Send>(
+    config: &Config,
+    base: &Path,
+    paths: &[PathBuf],
+    out: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut state = pipeline_for(config)?
+        .source(open_records(config, base)?)
+        .state(loaded_state(config)?)
+        .build()
+        .run()?;
+    // positions of the rejects count from the first hypothetical record
+    state.processed = 0;
+    let before = simulate::balances(&state);
+    let mut pipeline = pipeline_for(config)?
+        .source(open_inputs(config, paths)?)
+        .state(state);
+    let events = pipeline.subscribe();
+    pipeline
+        .sink(simulate::Simulation {
+            out,
+            before,
+            events,
+        })
+        .build()
+        .run()?;
+    Ok(())
+}
+
+/// Hands the record to the handler of its transaction type, fails on an unknown type if the
+/// policy says to abort. Returns what the handler did with it.
+fn apply_record<A: Amount>(
+    client_map: &mut HashMap<u16, ClientInfo<A>>,
+    record: Record<A>,
+    policy: &Policy,
+) -> Result<Outcome, TxError> {
+    Ok(match record.tx_type {
+        TxType::Deposit => handle_deposit(client_map, record),
+        TxType::Withdrawal => handle_widthdrawal(client_map, record),
+        TxType::Dispute => handle_dispute(client_map, record),
+        TxType::Resolve => handle_resolve(client_map, record, policy),
+        TxType::Chargeback => handle_chargeback(client_map, record, policy),
+        TxType::Void => handle_void(client_map, record),
+        TxType::Other(_) => match policy.unknown_type {
+            // this should be logged/sent into some secondary transaction validation queue for further review
+            UnknownType::Skip => {
+                error!(
+                    "transaction type not specified in tx number: {:?}",
+                    record.tx
+                );
+                Outcome::Refused(RejectReason::UnknownType)
+            }
+            UnknownType::Abort => {
+                return Err(TxError::UnknownTxType {
+                    tx: record.tx,
+                    tx_type: record.tx_type.to_string(),
+                })
+            }
+        },
+    })
+}
+
+fn is_spreadsheet(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            matches!(
+                ext.to_ascii_lowercase().as_str(),
+                "xlsx" | "xlsm" | "xls" | "ods"
+            )
+        })
+}
+
+/// Input path that reads the records from stdin, also used when no path is given.
+const STDIN: &str = "-";
+
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN)
+}
+
+/// Whether the input is an `s3://bucket/key` URL rather than a local path.
+fn is_s3_url(input: &str) -> bool {
+    input.starts_with("s3://")
+}
+
+/// Reads the files one after the other. All are opened up front, so a missing one fails
+/// before anything is applied.
+fn open_inputs(config: &Config, paths: &[PathBuf]) -> Result<Records, Box<dyn Error>> {
+    if config.merge_by_time {
+        return merge_inputs(config, paths);
+    }
+    let records = paths
+        .iter()
+        .map(|path| open_records(config, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Box::new(records.into_iter().flatten()))
+}
+
+/// Opens every input up front and merges them in time order. Each file is read once to
+/// find how it is ordered, and one that is not sorted by timestamp is sorted first.
+fn merge_inputs(config: &Config, paths: &[PathBuf]) -> Result<Records, Box<dyn Error>> {
+    let mut inputs = Vec::with_capacity(paths.len());
+    for path in paths {
+        // stdin can not be read twice, it is sorted whichever order it is in
+        let order = if is_stdin(path) {
+            merge::Order::Unsorted
+        } else {
+            merge::scan(open_records(config, path)?)
+        };
+        let records = open_records(config, path)?;
+        inputs.push(match order {
+            merge::Order::Untimed => {
+                return Err(From::from(format!(
+                    "--merge-by-time needs a timestamp column in every input, {} has none",
+                    path.display()
+                )))
+            }
+            merge::Order::Sorted => records,
+            merge::Order::Unsorted => {
+                info!("{} is not sorted by timestamp, sorting it", path.display());
+                merge::sort(records)?
+            }
+        });
+    }
+    Ok(merge::chronological(inputs))
+}
+
+/// Opens the input with the reader for its format.
+fn open_records(config: &Config, path: &Path) -> Result<Records, Box<dyn Error>> {
+    if is_stdin(path) {
+        let format = config.input_format.unwrap_or(InputFormat::Csv);
+        return open_stream(
+            config,
+            format,
+            compression::decompress(io::stdin())?,
+            "stdin",
+        );
+    }
+    if let Some(url) = path.to_str().filter(|path| is_s3_url(path)) {
+        return open_object(config, url);
+    }
+    let format = config
+        .input_format
+        .unwrap_or_else(|| InputFormat::from_path(&compression::content_path(path)));
+    if compression::is_compressed(path)? {
+        let input = compression::decompress(File::open(path)?)?;
+        return open_stream(config, format, input, "a compressed file");
+    }
+    match format {
+        #[cfg(feature = "xlsx")]
+        InputFormat::Xlsx => {
+            let records =
+                xlsx::read_records(path, config.sheet.as_deref(), config.xlsx_columns.as_ref())?;
+            return Ok(Box::new(records.into_iter()));
+        }
+        #[cfg(not(feature = "xlsx"))]
+        InputFormat::Xlsx => return Err(From::from("xlsx input needs the xlsx feature")),
+        #[cfg(feature = "protobuf")]
+        InputFormat::Protobuf => return Ok(Box::new(protobuf::ProtobufReader::from_path(path)?)),
+        #[cfg(not(feature = "protobuf"))]
+        InputFormat::Protobuf => {
+            return Err(From::from("protobuf input needs the protobuf feature"))
+        }
+        #[cfg(feature = "parquet")]
+        InputFormat::Parquet => {
+            return Ok(Box::new(crate::parquet::ParquetReader::from_path(path)?))
+        }
+        #[cfg(not(feature = "parquet"))]
+        InputFormat::Parquet => return Err(From::from("parquet input needs the parquet feature")),
+        #[cfg(feature = "avro")]
+        InputFormat::Avro => return Ok(Box::new(avro::AvroReader::from_path(path)?)),
+        #[cfg(not(feature = "avro"))]
+        InputFormat::Avro => return Err(From::from("avro input needs the avro feature")),
+        #[cfg(feature = "msgpack")]
+        InputFormat::Msgpack => return Ok(Box::new(msgpack::MsgpackReader::from_path(path)?)),
+        #[cfg(not(feature = "msgpack"))]
+        InputFormat::Msgpack => return Err(From::from("msgpack input needs the msgpack feature")),
+        InputFormat::Csv => {}
+    }
+
+    let dialect = dialect::detect(path, config.dialect)?;
+    if config.mmap {
+        return open_mapped(config, path, dialect);
+    }
+    let mut reader = dialect.reader_builder().from_path(path)?;
+    seek_checkpoint(config, path, &mut reader)?;
+    csv_records(reader, config.parse_threads)
+}
+
+/// Moves `reader` of the CSV file `path` to where the checkpoint to resume from left it.
+fn seek_checkpoint<R: io::Read + io::Seek>(
+    config: &Config,
+    path: &Path,
+    reader: &mut csv::Reader<R>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(checkpoint) = resume_checkpoint(config) {
+        let position = checkpoint::read_position(checkpoint)?;
+        if position.input != path {
+            return Err(From::from(format!(
+                "the checkpoint is of {}, not {}",
+                position.input.display(),
+                path.display()
+            )));
+        }
+        if reader.has_headers() {
+            // they are not at the position
+            reader.headers()?;
+        }
+        reader.seek(position.to_csv())?;
+    }
+    Ok(())
+}
+
+/// Reads the CSV file `path` from a memory mapping. With more than one parse thread they
+/// split the file between them, unless its dialect makes it ambiguous where rows end.
+#[cfg(feature = "mmap")]
+fn open_mapped(
+    config: &Config,
+    path: &Path,
+    dialect: dialect::Dialect,
+) -> Result<Records, Box<dyn Error>> {
+    let file = mmap::Mapped::open(path)?;
+    let mut reader = file.reader(dialect);
+    seek_checkpoint(config, path, &mut reader)?;
+    if config.parse_threads > 1 && mmap::splittable(&dialect) {
+        return Ok(Box::new(parallel_csv::ParallelCsv::mapped(
+            reader,
+            config.parse_threads,
+            file,
+            dialect,
+        )?));
+    }
+    csv_records(reader, config.parse_threads)
+}
+
+#[cfg(not(feature = "mmap"))]
+fn open_mapped(
+    _config: &Config,
+    _path: &Path,
+    _dialect: dialect::Dialect,
+) -> Result<Records, Box<dyn Error>> {
+    Err(From::from("--mmap needs the mmap feature"))
+}
+
+/// Deserializes the rows of `reader` into records that know their line in the file.
+fn csv_records<R: io::Read + Send + 'static>(
+    mut reader: csv::Reader<R>,
+    threads: usize,
+) -> Result<Records, Box<dyn Error>> {
+    if threads > 1 {
+        return Ok(Box::new(parallel_csv::ParallelCsv::new(reader, threads)?));
+    }
+    let headers = if reader.has_headers() {
+        Some(reader.byte_headers()?.clone())
+    } else {
+        None
+    };
+    let columns = Columns::new(headers.as_ref());
+    let mut row = csv::ByteRecord::new();
+    Ok(Box::new(iter::from_fn(move || {
+        match reader.read_byte_record(&mut row) {
+            Ok(false) => None,
+            Ok(true) => Some(
+                columns
+                    .record(&row, row.position().map_or(0, csv::Position::line))
+                    .map(|mut record| {
+                        record.next_position = Some(reader.position().clone());
+                        record
+                    })
+                    .map_err(TxError::from),
+            ),
+            Err(err) => Some(Err(TxError::from(err))),
+        }
+    })))
+}
+
+/// Reads the records of a stream, e.g. stdin, in any format but the ones that need a file.
+/// `origin` names the stream in errors.
+fn open_stream<R: io::Read + Send + 'static>(
+    config: &Config,
+    format: InputFormat,
+    input: R,
+    origin: &str,
+) -> Result<Records, Box<dyn Error>> {
+    match format {
+        InputFormat::Xlsx => Err(From::from(format!(
+            "xlsx input can not be read from {}",
+            origin
+        ))),
+        InputFormat::Parquet => Err(From::from(format!(
+            "parquet input can not be read from {}",
+            origin
+        ))),
+        #[cfg(feature = "avro")]
+        InputFormat::Avro => Ok(Box::new(avro::AvroReader::new(input)?)),
+        #[cfg(not(feature = "avro"))]
+        InputFormat::Avro => Err(From::from("avro input needs the avro feature")),
+        #[cfg(feature = "msgpack")]
+        InputFormat::Msgpack => Ok(Box::new(msgpack::MsgpackReader::new(io::BufReader::new(
+            input,
+        )))),
+        #[cfg(not(feature = "msgpack"))]
+        InputFormat::Msgpack => Err(From::from("msgpack input needs the msgpack feature")),
+        #[cfg(feature = "protobuf")]
+        InputFormat::Protobuf => Ok(Box::new(protobuf::ProtobufReader::new(io::BufReader::new(
+            input,
+        )))),
+        #[cfg(not(feature = "protobuf"))]
+        InputFormat::Protobuf => Err(From::from("protobuf input needs the protobuf feature")),
+        InputFormat::Csv => {
+            let (dialect, input) = dialect::detect_stream(input, config.dialect)?;
+            csv_records(
+                dialect.reader_builder().from_reader(input),
+                config.parse_threads,
+            )
+        }
+    }
+}
+
+/// Streams the records of an object in S3, the format is picked like for a local file.
+#[cfg(feature = "s3")]
+fn open_object(config: &Config, url: &str) -> Result<Records, Box<dyn Error>> {
+    let format = config
+        .input_format
+        .unwrap_or_else(|| InputFormat::from_path(&compression::content_path(Path::new(url))));
+    let input = compression::decompress(s3::ObjectReader::open(url)?)?;
+    open_stream(config, format, input, "object storage")
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_object(_config: &Config, _url: &str) -> Result<Records, Box<dyn Error>> {
+    Err(From::from("s3:// input needs the s3 feature"))
+}
+
+/// Consumes the records from the broker until the process is stopped, `input` is not read.
+/// Brokers that need to know what happened to a record subscribe to `pipeline`.
+#[cfg_attr(not(feature = "amqp"), allow(unused_variables))]
+fn open_broker(
+    broker: &Broker,
+    capacity: usize,
+    pipeline: &mut PipelineBuilder,
+) -> Result<Records, Box<dyn Error>> {
+    match broker {
+        #[cfg(feature = "kafka")]
+        Broker::Kafka {
+            brokers,
+            group,
+            topic,
+        } => Ok(Box::new(kafka::KafkaSource::new(brokers, group, topic)?)),
+        #[cfg(not(feature = "kafka"))]
+        Broker::Kafka { .. } => Err(From::from("--source kafka needs the kafka feature")),
+        #[cfg(feature = "nats")]
+        Broker::Nats {
+            url,
+            subject,
+            durable,
+        } => Ok(Box::new(nats::NatsSource::new(url, subject, durable)?)),
+        #[cfg(not(feature = "nats"))]
+        Broker::Nats { .. } => Err(From::from("--source nats needs the nats feature")),
+        #[cfg(feature = "amqp")]
+        Broker::Amqp {
+            url,
+            queue,
+            prefetch,
+            dead_letter_queue,
+        } => {
+            let options = amqp::AmqpOptions {
+                url: url.clone(),
+                queue: queue.clone(),
+                prefetch: *prefetch,
+                dead_letter_queue: dead_letter_queue.clone(),
+            };
+            Ok(Box::new(amqp::AmqpSource::new(
+                options,
+                pipeline.subscribe(),
+            )?))
+        }
+        #[cfg(not(feature = "amqp"))]
+        Broker::Amqp { .. } => Err(From::from("--source amqp needs the amqp feature")),
+        #[cfg(feature = "redis")]
+        Broker::Redis {
+            url,
+            stream,
+            group,
+            consumer,
+        } => Ok(Box::new(redis_stream::RedisSource::new(
+            url, stream, group, consumer,
+        )?)),
+        #[cfg(not(feature = "redis"))]
+        Broker::Redis { .. } => Err(From::from("--source redis needs the redis feature")),
+        Broker::Tcp { addr, framing } => Ok(Box::new(socket::SocketSource::tcp(
+            addr, *framing, capacity,
+        )?)),
+        #[cfg(unix)]
+        Broker::Unix { path, framing } => Ok(Box::new(socket::SocketSource::unix(
+            path, *framing, capacity,
+        )?)),
+        #[cfg(not(unix))]
+        Broker::Unix { .. } => Err(From::from("--source unix needs a unix platform")),
+    }
+}
+
+/// Optional columns of the balances snapshot. They are either written for every row or
+/// for none, so the header stays aligned.
+#[derive(Debug, Default, Clone, Copy)]
+struct ExtraColumns {
+    aliases: bool,
+    fees: bool,
+    /// `withdrawals_24h` and `withdrawn_24h`
+    withdrawal_usage: bool,
+    lock_reason: bool,
+}
+
+/// Writes a row per account, sorted by client unless `sorted` is off, which leaves them in
+/// the arbitrary order of the map.
+fn write_balances<W: Write>(
+    mut out: W,
+    client_map: &HashMap<u16, ClientInfo>,
+    extra: ExtraColumns,
+    units: AmountUnits,
+    format: OutputFormat,
+    sorted: bool,
+) -> Result<(), TxError> {
+    // the usage of every account ends at the same time, so they add up across accounts
+    let now = if extra.withdrawal_usage {
+        withdrawal_limits::latest(client_map)
+    } else {
+        None
+    };
+    let mut accounts: Vec<(&u16, &ClientInfo)> = client_map.iter().collect();
+    if sorted {
+        accounts.sort_unstable_by_key(|(client, _)| **client);
+    }
+    let rows = accounts
+        .into_iter()
+        .map(|(client, info)| output_info(*client, info, extra, now, units));
+    match format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(out);
+            for row in rows {
+                wtr.serialize(row?)?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Json => {
+            let rows = rows.collect::<Result<Vec<_>, _>>()?;
+            serde_json::to_writer_pretty(&mut out, &rows)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => {
+            let rows = rows.collect::<Result<Vec<_>, _>>()?;
+            crate::parquet::write_balances(out, &rows, extra, units).map_err(TxError::Other)?;
+        }
+        #[cfg(not(feature = "parquet"))]
+        OutputFormat::Parquet => {
+            return Err(TxError::other("parquet output needs the parquet feature"))
+        }
+    }
+    Ok(())
+}
+
+/// The row of an account, with the withdrawal usage of the 24 hours up to `now`.
+fn output_info(
+    client: u16,
+    info: &ClientInfo,
+    extra: ExtraColumns,
+    now: Option<DateTime<Utc>>,
+    units: AmountUnits,
+) -> Result<OutputInfo, TxError> {
+    let aliases: Vec<String> = info.aliases.iter().map(u16::to_string).collect();
+    let usage = match now {
+        Some(now) => withdrawal_limits::usage(info, now),
+        None => (0, Decimal::ZERO),
+    };
+    Ok(OutputInfo {
+        client,
+        available: units.convert(info.available_funds)?,
+        held: units.convert(info.held_funds)?,
+        total: units.convert(info.total_funds)?,
+        locked: info.locked,
+        aliases: if extra.aliases {
+            Some(aliases.join(";"))
+        } else {
+            None
+        },
+        fees: if extra.fees {
+            Some(units.convert(info.fees)?)
+        } else {
+            None
+        },
+        withdrawals_24h: extra.withdrawal_usage.then_some(usage.0),
+        withdrawn_24h: if extra.withdrawal_usage {
+            Some(units.convert(usage.1)?)
+        } else {
+            None
+        },
+        lock_reason: extra.lock_reason.then(|| info.lock_reasons()),
+    })
+}
+
+/// A decimal stored as a big-endian two's complement unscaled value, as Parquet and Avro do.
+#[cfg(any(feature = "parquet", feature = "avro"))]
+fn decimal_from_be_bytes(bytes: &[u8], scale: u32) -> Option<Decimal> {
+    if bytes.is_empty() || bytes.len() > 16 {
+        return None;
+    }
+    let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+    let mut unscaled = [fill; 16];
+    unscaled[16 - bytes.len()..].copy_from_slice(bytes);
+    Decimal::try_from_i128_with_scale(i128::from_be_bytes(unscaled), scale).ok()
+}
+
+/// Returns true if `tx` has been disputed and not resolved or charged back since.
+fn has_open_dispute<A: Amount>(client_info: &ClientInfo<A>, tx: u32) -> bool {
+    client_info
+        .transaction(tx)
+        .is_some_and(|entry| entry.has_open_dispute())
+}
+
+/// Locked accounts reject everything, unless the policy allows settling disputes opened before the lock.
+fn can_settle_dispute<A: Amount>(client_info: &ClientInfo<A>, tx: u32, policy: &Policy) -> bool {
+    !client_info.locked
+        || (policy.locked_account == LockedAccount::SettleOpenDisputes
+            && has_open_dispute(client_info, tx))
+}
+
+/// Logs a dispute state change with its case reference under the `audit` log target.
+fn audit_dispute_event<A>(action: &str, client_id: u16, record: &Record<A>) {
+    info!(
+        target: "audit",
+        "{} seq {} client {} tx {} evidence {}",
+        action,
+        record.sequence,
+        client_id,
+        record.tx,
+        record.evidence.as_deref().unwrap_or("-")
+    );
+}
+
+/// Books charged back funds on the internal suspense account, keeping totals across all
+/// accounts explainable.
+fn credit_suspense(client_map: &mut HashMap<u16, ClientInfo>, suspense: u16, amount: Decimal) {
+    let info = client_map.entry(suspense).or_default();
+    match (
+        info.available_funds.checked_add(amount),
+        info.total_funds.checked_add(amount),
+    ) {
+        (Some(available), Some(total)) => {
+            info.available_funds = available;
+            info.total_funds = total;
+        }
+        _ => {
+            error!(
+                "chargeback of {} overflows suspense account {}, not booked",
+                amount, suspense
+            );
+            return;
+        }
+    }
+    info!(target: "audit", "chargeback of {} booked to suspense account {}", amount, suspense);
+}
+
+/// Refuses a deposit or withdrawal with a negative amount or fee, which would move funds
+/// the other way and, once disputed, hold negative funds.
+fn negative<A: Amount>(record: &Record<A>) -> Option<Outcome> {
+    let negative = [record.amount, record.fee]
+        .iter()
+        .flatten()
+        .any(|amount| *amount < A::ZERO);
+    negative.then(|| {
+        error!("negative amount or fee, refused {:?}", record);
+        Outcome::Refused(RejectReason::InvalidAmount)
+    })
+}
+
+/// Logs a record that is not applied because it would overflow the balances of its client.
+fn overflow_error<A: Amount>(client_id: u16, record: &Record<A>) {
+    error!(
+        "tx {} overflows the balances of client {}, balances not modified {:?}",
+        record.tx, client_id, record
+    );
+}
+
+/// Charges back a disputed transaction, locking the account.
+fn handle_chargeback<A: Amount>(
+    client_map: &mut HashMap<u16, ClientInfo<A>>,
+    record: Record<A>,
+    policy: &Policy,
+) -> Outcome {
+    let client_id = match record.client {
+        Some(client_id) => client_id,
+        None => return Outcome::Refused(RejectReason::MissingClient),
+    };
+    // is client has transacted so far
+    let current_client_info = match client_map.get_mut(&client_id) {
+        Some(info) => info,
+        None => {
+            //client has no recorded transactions
+            error!("Client has no transactions to chargeback on {:?}", record);
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if !can_settle_dispute(current_client_info, record.tx, policy) {
+        error!(
+            "locked account id: {:} attempted chargeback, handle errors here",
+            &client_id
+        );
+        return Outcome::Refused(RejectReason::AccountLocked);
+    }
+    let tx = match current_client_info.transaction(record.tx) {
+        Some(tx) => tx,
+        None => {
+            // transaction to dispute not found
+            error!("tx id: {:} not found handle errors here", record.tx);
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if !tx.has_open_dispute() {
+        error!(
+            "tx id: {:} is {} and not under dispute, chargeback refused {:?}",
+            record.tx, tx.state, record
+        );
+        return Outcome::Refused(RejectReason::InvalidTransition);
+    }
+    if let Some(val) = tx.net_amount() {
+        match (
+            current_client_info.total_funds.checked_sub(val),
+            current_client_info.held_funds.checked_sub(val),
+        ) {
+            (Some(total), Some(held)) => {
+                current_client_info.total_funds = total;
+                current_client_info.held_funds = held;
+            }
+            _ => {
+                overflow_error(client_id, &record);
+                return Outcome::Refused(RejectReason::Overflow);
+            }
+        }
+    } else {
+        error!("chargeback amount value not found")
+    }
+    // lock account after chargeback
+    current_client_info.lock(locks::LockReason::Chargeback { tx: record.tx }, &record);
+    audit_dispute_event("chargeback", client_id, &record);
+    // kept so the dispute is no longer considered open
+    current_client_info.keep(record);
+    Outcome::Applied
+}
+
+/// The amount a chargeback of `tx` took from the client, which is owed to the suspense
+/// account.
+fn charged_back<A: Amount>(info: &ClientInfo<A>, tx: u32) -> Option<A> {
+    info.transaction(tx).and_then(TxEntry::net_amount)
+}
+
+fn handle_resolve<A: Amount>(
+    client_map: &mut HashMap<u16, ClientInfo<A>>,
+    record: Record<A>,
+    policy: &Policy,
+) -> Outcome {
+    let client_id = match record.client {
+        Some(client_id) => client_id,
+        None => return Outcome::Refused(RejectReason::MissingClient),
+    };
+    // is client has transacted so far
+    let current_client_info = match client_map.get_mut(&client_id) {
+        Some(info) => info,
+        None => {
+            // no client id found w that info
+            error!(
+                "Client ID: {:} not found while processing resolve tx request",
+                client_id,
+            );
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if !can_settle_dispute(current_client_info, record.tx, policy) {
+        // TODO
+        error!(
+            "locked account attempted to resolve transaction resolve {:?}",
+            record
+        );
+        return Outcome::Refused(RejectReason::AccountLocked);
+    }
+    let tx = match current_client_info.transaction(record.tx) {
+        Some(tx) => tx,
+        None => {
+            // transaction to dispute not found
+            error!("Tx ID: not found {:} in handle resolve", record.tx,);
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if !tx.has_open_dispute() {
+        error!(
+            "tx id: {:} is {} and not under dispute, resolve refused {:?}",
+            record.tx, tx.state, record
+        );
+        return Outcome::Refused(RejectReason::InvalidTransition);
+    }
+    if let Some(amt) = tx.net_amount() {
+        match (
+            current_client_info.available_funds.checked_add(amt),
+            current_client_info.held_funds.checked_sub(amt),
+        ) {
+            (Some(available), Some(held)) => {
+                current_client_info.available_funds = available;
+                current_client_info.held_funds = held;
+            }
+            _ => {
+                overflow_error(client_id, &record);
+                return Outcome::Refused(RejectReason::Overflow);
+            }
+        }
+    } else {
+        error!("resolved amount not found");
+    }
+    audit_dispute_event("resolve", client_id, &record);
+    current_client_info.keep(record);
+    Outcome::Applied
+}
+
+fn handle_dispute<A: Amount>(
+    client_map: &mut HashMap<u16, ClientInfo<A>>,
+    record: Record<A>,
+) -> Outcome {
+    let client_id = match record.client {
+        Some(client_id) => client_id,
+        None => return Outcome::Refused(RejectReason::MissingClient),
+    };
+    // is client has transacted so far
+    let current_client_info = match client_map.get_mut(&client_id) {
+        Some(info) => info,
+        None => {
+            // no client id found w that info
+            error!(
+                "Client ID: {:} not found in client map, handle errors here {:?}",
+                client_id, &record
+            );
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if current_client_info.locked {
+        // TODO
+        error!("locked account attempted dispute {:?}", record);
+        return Outcome::Refused(RejectReason::AccountLocked);
+    }
+    let tx = current_client_info.transaction(record.tx);
+    if tx.is_some_and(|tx| tx.voided) {
+        error!(
+            "voided tx id: {:} cannot be disputed {:?}",
+            record.tx, record
+        );
+        return Outcome::Refused(RejectReason::InvalidTransition);
+    }
+    let tx = match tx {
+        Some(tx) => tx,
+        None => {
+            // transaction to dispute not found
+            error!(
+                "Tx ID: not found {:} within historical transactions while processing dispute",
+                record.tx
+            );
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if tx.state != TxState::Normal {
+        error!(
+            "tx id: {:} is {} and can not be disputed again {:?}",
+            record.tx, tx.state, record
+        );
+        return Outcome::Refused(RejectReason::InvalidTransition);
+    }
+    if let Some(amount) = tx.net_amount() {
+        match (
+            current_client_info.available_funds.checked_sub(amount),
+            current_client_info.held_funds.checked_add(amount),
+        ) {
+            (Some(available), Some(held)) => {
+                current_client_info.available_funds = available;
+                current_client_info.held_funds = held;
+            }
+            _ => {
+                overflow_error(client_id, &record);
+                return Outcome::Refused(RejectReason::Overflow);
+            }
+        }
+    } else {
+        error!("disputed amount not found");
+    }
+
+    audit_dispute_event("dispute", client_id, &record);
+    current_client_info.keep(record);
+    Outcome::Applied
+}
+
+/// Cancels an unsettled deposit or withdrawal, restoring balances and fees exactly as if it never
+/// happened. A transaction is settled once its day is over: when both records have a timestamp,
+/// only one of the same (UTC) day can be voided. Transactions that are voided already or have
+/// been disputed can not be voided, and a deposit whose funds are no longer available can not
+/// be voided either.
+fn handle_void<A: Amount>(
+    client_map: &mut HashMap<u16, ClientInfo<A>>,
+    record: Record<A>,
+) -> Outcome {
+    let client_id = match record.client {
+        Some(client_id) => client_id,
+        None => return Outcome::Refused(RejectReason::MissingClient),
+    };
+    // is client has transacted so far
+    let current_client_info = match client_map.get_mut(&client_id) {
+        Some(info) => info,
+        None => {
+            error!("Client has no transactions to void {:?}", record);
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if current_client_info.locked {
+        error!("locked account attempted void {:?}", record);
+        return Outcome::Refused(RejectReason::AccountLocked);
+    }
+    let original = current_client_info.transaction(record.tx);
+    if original.is_some_and(|tx| tx.voided || tx.state != TxState::Normal) {
+        error!(
+            "tx id: {:} already voided or disputed {:?}",
+            record.tx, record
+        );
+        return Outcome::Refused(RejectReason::InvalidTransition);
+    }
+    let original = match original {
+        Some(tx) => tx,
+        None => {
+            error!("tx id: {:} not found to void {:?}", record.tx, record);
+            return Outcome::Refused(RejectReason::TxNotFound);
+        }
+    };
+    if let (Some(made), Some(now)) = (original.timestamp(), record.timestamp) {
+        if made.date_naive() != now.date_naive() {
+            error!(
+                "tx id: {:} of {} is settled, can not be voided on {} {:?}",
+                record.tx,
+                made.date_naive(),
+                now.date_naive(),
+                record
+            );
+            return Outcome::Refused(RejectReason::InvalidTransition);
+        }
+    }
+    let (is_deposit, amount, fee) = (
+        original.kind == TxKind::Deposit,
+        original.amount,
+        original.fee,
+    );
+    let amount = match amount {
+        Some(amount) => amount,
+        None => {
+            // nothing was applied for the original, marking it voided is enough
+            current_client_info.keep(record);
+            return Outcome::Applied;
+        }
+    };
+    let restored = if is_deposit {
+        let credited = amount.checked_sub(fee);
+        if credited.is_some_and(|credited| credited > current_client_info.available_funds) {
+            error!(
+                "funds of deposit tx id: {:} no longer available to void {:?}",
+                record.tx, record
+            );
+            return Outcome::Refused(RejectReason::InsufficientFunds);
+        }
+        credited.and_then(|credited| {
+            Some((
+                current_client_info.available_funds.checked_sub(credited)?,
+                current_client_info.total_funds.checked_sub(credited)?,
+            ))
+        })
+    } else {
+        amount.checked_add(fee).and_then(|debited| {
+            Some((
+                current_client_info.available_funds.checked_add(debited)?,
+                current_client_info.total_funds.checked_add(debited)?,
+            ))
+        })
+    };
+    match (restored, current_client_info.fees.checked_sub(fee)) {
+        (Some((available, total)), Some(fees)) => {
+            current_client_info.available_funds = available;
+            current_client_info.total_funds = total;
+            current_client_info.fees = fees;
+        }
+        _ => {
+            overflow_error(client_id, &record);
+            return Outcome::Refused(RejectReason::Overflow);
+        }
+    }
+    current_client_info.keep(record);
+    Outcome::Applied
+}
+
+/// Credits the deposit net of its fee to the account, unless a balance would overflow or the
+/// fee is more than the amount.
+fn credit_deposit<A: Amount>(
+    info: &mut ClientInfo<A>,
+    client_id: u16,
+    record: &Record<A>,
+) -> Outcome {
+    let value = match record.net_amount() {
+        Some(value) => value,
+        None => {
+            overflow_error(client_id, record);
+            return Outcome::Refused(RejectReason::Overflow);
+        }
+    };
+    if value < A::ZERO {
+        // not kept, so it can not be disputed
+        error!(
+            "deposit fee exceeds amount, balances not modified {:?}",
+            record
+        );
+        return Outcome::Refused(RejectReason::InvalidAmount);
+    }
+    match (
+        info.available_funds.checked_add(value),
+        info.total_funds.checked_add(value),
+        info.fees.checked_add(record.fee.unwrap_or(A::ZERO)),
+    ) {
+        (Some(available), Some(total), Some(fees)) => {
+            info.available_funds = available;
+            info.total_funds = total;
+            info.fees = fees;
+            Outcome::Applied
+        }
+        _ => {
+            overflow_error(client_id, record);
+            Outcome::Refused(RejectReason::Overflow)
+        }
+    }
+}
+
+fn handle_deposit<A: Amount>(
+    client_map: &mut HashMap<u16, ClientInfo<A>>,
+    record: Record<A>,
+) -> Outcome {
+    let client_id = match record.client {
+        Some(client_id) => client_id,
+        None => return Outcome::Refused(RejectReason::MissingClient),
+    };
+    if let Some(refused) = negative(&record) {
+        return refused;
+    }
+    // is client has transacted so far
+    if let Some(current_client_info) = client_map.get_mut(&client_id) {
+        if current_client_info.locked {
+            // handle locked account
+            error!(
+                "Locked account with id: {:} attempted deposit {:?}",
+                client_id, &record
+            );
+            return Outcome::Refused(RejectReason::AccountLocked);
+        }
+        if record.amount.is_some() {
+            let outcome = credit_deposit(current_client_info, client_id, &record);
+            if outcome != Outcome::Applied {
+                return outcome;
+            }
+        } else {
+            error!("deposit value not provided, balances not modified");
+        }
+        // kept anyways to save tx
+        current_client_info.keep(record);
+    } else {
+        // else, first tx with that id, set up the account
+        let mut new_info = ClientInfo::default();
+        if record.amount.is_some() {
+            let outcome = credit_deposit(&mut new_info, client_id, &record);
+            if outcome != Outcome::Applied {
+                return outcome;
+            }
+        } else {
+            error!("no amount provided in transaction");
+        }
+        // keep tx for client id regardless of amount being present
+        new_info.keep(record);
+        // insert value into client map to track client activity
+        client_map.insert(client_id, new_info);
+    }
+    Outcome::Applied
+}
+
+fn handle_widthdrawal<A: Amount>(
+    client_map: &mut HashMap<u16, ClientInfo<A>>,
+    record: Record<A>,
+) -> Outcome {
+    let client_id = match record.client {
+        Some(client_id) => client_id,
+        None => return Outcome::Refused(RejectReason::MissingClient),
+    };
+    if let Some(refused) = negative(&record) {
+        return refused;
+    }
+    // is client has transacted so far
+    let current_client_info = match client_map.get_mut(&client_id) {
+        Some(info) => info,
+        None => {
+            // first tx with that id, set up the account
+            // log withdrawl attempt
+            error!(
+                "Client Id without history attempted withdrawl, logging client id and attempt {:?}",
+                record
+            );
+            // the account is opened, but the refused withdrawal is not kept
+            client_map.insert(client_id, ClientInfo::default());
+            return Outcome::Refused(RejectReason::InsufficientFunds);
+        }
+    };
+    if current_client_info.locked {
+        // TODO
+        error!(
+            "locked account with id: {:} attempted withdrawal {:?}, handle errors here",
+            client_id, record
+        );
+        return Outcome::Refused(RejectReason::AccountLocked);
+    }
+    if let Some(amount) = record.amount {
+        // the fee is taken together with the amount or not at all
+        let fee = record.fee.unwrap_or(A::ZERO);
+        let debited = match amount.checked_add(fee) {
+            Some(debited) => debited,
+            None => {
+                overflow_error(client_id, &record);
+                return Outcome::Refused(RejectReason::Overflow);
+            }
+        };
+        if debited > current_client_info.available_funds {
+            error!(
+                "OVERDRAFT: Client ID: {:?}, attempted to withdraw more funds than available {:?}",
+                client_id, record
+            );
+            // not kept, so it can not be disputed or voided
+            return Outcome::Refused(RejectReason::InsufficientFunds);
+        }
+        match (
+            current_client_info.available_funds.checked_sub(debited),
+            current_client_info.total_funds.checked_sub(debited),
+            current_client_info.fees.checked_add(fee),
+        ) {
+            (Some(available), Some(total), Some(fees)) => {
+                current_client_info.available_funds = available;
+                current_client_info.total_funds = total;
+                current_client_info.fees = fees;
+            }
+            _ => {
+                overflow_error(client_id, &record);
+                return Outcome::Refused(RejectReason::Overflow);
+            }
+        }
+    } else {
+        error!("amount not provided for withdrawal tx {:?}", record);
+    }
+    // add tx to the client's transactions
+    current_client_info.keep(record);
+    Outcome::Applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::Trim;
+    use policy::{DuplicateTx, MissingAmount};
+
+    /// Applies the records of `input`, CSV with a `type,client,tx,amount` header.
+    fn run(input: &str, policy: &Policy) -> HashMap<u16, ClientInfo> {
+        let mut client_map = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        for record in reader.deserialize() {
+            let record: Record = record.unwrap();
+            apply_record(&mut client_map, record, policy).unwrap();
+        }
+        client_map
+    }
+
+    fn balances(
+        client_map: &HashMap<u16, ClientInfo>,
+        client: u16,
+    ) -> (Decimal, Decimal, Decimal, bool) {
+        let info = &client_map[&client];
+        (
+            info.available_funds,
+            info.held_funds,
+            info.total_funds,
+            info.locked,
+        )
+    }
+
+    #[test]
+    fn handlers_tell_why_they_refuse_a_record() {
+        let mut client_map: HashMap<u16, ClientInfo> = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(
+            "type,client,tx,amount,fee
+            deposit,1,1,10,
+            withdrawal,1,2,20,
+            resolve,1,7,,
+            deposit,1,3,1,2
+            dispute,1,1,,
+            void,1,1,,
+            chargeback,1,1,,
+            deposit,1,4,5,
+            withdrawal,2,5,1,"
+                .as_bytes(),
+        );
+        let outcomes: Vec<Outcome> = reader
+            .deserialize()
+            .map(|record| apply_record(&mut client_map, record.unwrap(), &Policy::default()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Applied,
+                Outcome::Refused(RejectReason::InsufficientFunds),
+                Outcome::Refused(RejectReason::TxNotFound),
+                Outcome::Refused(RejectReason::InvalidAmount),
+                Outcome::Applied,
+                Outcome::Refused(RejectReason::InvalidTransition),
+                Outcome::Applied,
+                Outcome::Refused(RejectReason::AccountLocked),
+                Outcome::Refused(RejectReason::InsufficientFunds),
+            ]
+        );
+    }
+
+    /// The second dispute is still open when the first chargeback locks the account.
+    const LOCKED_WITH_OPEN_DISPUTE: &str = "type,client,tx,amount
+        deposit,1,1,10
+        deposit,1,2,5
+        dispute,1,1,
+        dispute,1,2,
+        chargeback,1,1,
+        resolve,1,2,";
+
+    #[test]
+    fn locked_account_keeps_open_dispute_by_default() {
+        let client_map = run(LOCKED_WITH_OPEN_DISPUTE, &Policy::default());
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::ZERO, Decimal::from(5), Decimal::from(5), true)
+        );
+    }
+
+    #[test]
+    fn locked_account_settles_dispute_opened_before_the_lock() {
+        let policy = Policy {
+            locked_account: LockedAccount::SettleOpenDisputes,
+            ..Policy::default()
+        };
+        let client_map = run(LOCKED_WITH_OPEN_DISPUTE, &policy);
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(5), Decimal::ZERO, Decimal::from(5), true)
+        );
+    }
+
+    #[test]
+    fn locked_account_does_not_open_new_disputes() {
+        let policy = Policy {
+            locked_account: LockedAccount::SettleOpenDisputes,
+            ..Policy::default()
+        };
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,1,2,5
+            dispute,1,1,
+            chargeback,1,1,
+            dispute,1,2,
+            resolve,1,2,",
+            &policy,
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(5), Decimal::ZERO, Decimal::from(5), true)
+        );
+    }
+
+    /// Applies the records of `input` as the run loop does, policy checks included.
+    fn run_checked(input: &str, policy: &Policy) -> Result<HashMap<u16, ClientInfo>, TxError> {
+        let records = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .map(|record| record.map_err(TxError::from));
+        let state = PipelineBuilder::new()
+            .policy(policy.clone())
+            .source(records)
+            .build()
+            .run()?;
+        Ok(state.client_map)
+    }
+
+    const PROBLEM_RECORDS: &str = "type,client,tx,amount
+        deposit,1,1,10
+        deposit,,2,3
+        deposit,1,3,
+        deposit,1,1,10
+        refund,1,4,1";
+
+    #[test]
+    fn lenient_profile_keeps_going() {
+        let client_map = run_checked(PROBLEM_RECORDS, &Policy::default()).unwrap();
+        // the record without a client got an account of its own
+        assert_eq!(client_map.len(), 2);
+        // the duplicate is applied again, the deposit without an amount kept without funds
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(20), Decimal::ZERO, Decimal::from(20), false)
+        );
+        assert_eq!(client_map[&1].kept, 3);
+    }
+
+    #[test]
+    fn spec_profile_skips_problem_records() {
+        let client_map = run_checked(
+            PROBLEM_RECORDS,
+            &Policy::from_profile(policy::Profile::Spec),
+        )
+        .unwrap();
+        assert_eq!(client_map.len(), 1);
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(10), Decimal::ZERO, Decimal::from(10), false)
+        );
+        assert_eq!(client_map[&1].kept, 1);
+    }
+
+    #[test]
+    fn strict_profile_aborts_on_the_first_problem() {
+        let strict = Policy::from_profile(policy::Profile::Strict);
+        let err = run_checked(PROBLEM_RECORDS, &strict)
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(err, TxError::MissingClient { tx: 2 }), "{}", err);
+        let err = run_checked(
+            "type,client,tx,amount
+            deposit,1,1,10
+            refund,1,2,1",
+            &strict,
+        )
+        .map(|_| ())
+        .unwrap_err();
+        assert!(
+            matches!(err, TxError::UnknownTxType { tx: 2, ref tx_type } if tx_type == "refund"),
+            "{}",
+            err
+        );
+    }
+
+    const MALFORMED_ROW: &str = "type,client,tx,amount
+        deposit,1,1,10
+        deposit,1,two,5
+        withdrawal,1,3,4";
+
+    #[test]
+    fn malformed_rows_are_skipped_unless_strict() {
+        let client_map = run_checked(MALFORMED_ROW, &Policy::default()).unwrap();
+        assert_eq!(client_map[&1].available_funds, Decimal::from(6));
+        let strict = Policy::from_profile(policy::Profile::Strict);
+        let err = run_checked(MALFORMED_ROW, &strict).map(|_| ()).unwrap_err();
+        assert!(
+            matches!(err, TxError::ParseError { line: Some(3), .. }),
+            "{}",
+            err
+        );
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[test]
+    fn refused_records_abort_the_run_if_the_policy_says_so() {
+        let input = "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,1,2,15
+            resolve,1,1,";
+        let client_map = run_checked(input, &Policy::default()).unwrap();
+        assert_eq!(client_map[&1].available_funds, Decimal::from(10));
+        let policy = Policy {
+            refused: policy::Refused::Abort,
+            ..Policy::default()
+        };
+        let err = run_checked(input, &policy).map(|_| ()).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                TxError::InsufficientFunds {
+                    client: Some(1),
+                    tx: 2
+                }
+            ),
+            "{}",
+            err
+        );
+        assert_eq!(err.exit_code(), 3);
+    }
+
+    #[test]
+    fn settings_are_set_one_by_one() {
+        let policy = Policy {
+            duplicate_tx: DuplicateTx::Abort,
+            ..Policy::default()
+        };
+        let err = run_checked(PROBLEM_RECORDS, &policy)
+            .map(|_| ())
+            .unwrap_err();
+        assert!(err.to_string().starts_with("duplicate tx id"), "{}", err);
+        let policy = Policy {
+            missing_amount: MissingAmount::Skip,
+            ..Policy::default()
+        };
+        let client_map = run_checked(PROBLEM_RECORDS, &policy).unwrap();
+        assert_eq!(client_map[&1].kept, 2);
+    }
+
+    #[test]
+    fn the_reject_duplicate_policy_expects_a_rejects_file() {
+        let config = Config {
+            policy: Policy {
+                duplicate_tx: DuplicateTx::Reject,
+                ..Policy::default()
+            },
+            ..Config::default()
+        };
+        let err = pipeline_for(&config).map(|_| ()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "duplicate tx policy reject expects --duplicate-rejects"
+        );
+    }
+
+    #[test]
+    fn fees_are_taken_with_the_amount_or_not_at_all() {
+        let client_map = run(
+            "type,client,tx,amount,fee
+            deposit,1,1,10,0.5
+            withdrawal,1,2,9,1
+            withdrawal,1,3,4,1
+            deposit,2,4,3,",
+            &Policy::default(),
+        );
+        // the first withdrawal and its fee are over the 9.5 available
+        assert_eq!(
+            balances(&client_map, 1),
+            (
+                Decimal::new(45, 1),
+                Decimal::ZERO,
+                Decimal::new(45, 1),
+                false
+            )
+        );
+        assert_eq!(client_map[&1].fees, Decimal::new(15, 1));
+        assert_eq!(client_map[&2].fees, Decimal::ZERO);
+    }
+
+    #[test]
+    fn disputes_hold_what_the_deposit_credited() {
+        let client_map = run(
+            "type,client,tx,amount,fee
+            deposit,1,1,10,2
+            dispute,1,1,,
+            chargeback,1,1,,",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, true)
+        );
+        assert_eq!(client_map[&1].fees, Decimal::from(2));
+    }
+
+    #[test]
+    fn deposit_whose_fee_exceeds_the_amount_is_not_kept() {
+        let client_map = run(
+            "type,client,tx,amount,fee
+            deposit,1,1,10,
+            deposit,1,2,1,5
+            dispute,1,2,,
+            deposit,2,3,1,5",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(10), Decimal::ZERO, Decimal::from(10), false)
+        );
+        assert_eq!(client_map[&1].fees, Decimal::ZERO);
+        assert_eq!(client_map[&1].kept, 1);
+        assert!(!client_map.contains_key(&2));
+    }
+
+    #[test]
+    fn balances_in_minor_units_are_integers() {
+        let mut pipeline = PipelineBuilder::new()
+            .transform(minor_units::MinorUnits::new(4).unwrap())
+            .build();
+        let mut reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(
+            "type,client,tx,amount,fee
+                deposit,1,1,12.3456,0.01
+                deposit,2,2,0.0001,
+                deposit,2,3,0.00006,"
+                .as_bytes(),
+        );
+        let mut results = reader
+            .deserialize()
+            .map(|record| pipeline.process(record.unwrap()));
+        assert!(results.next().unwrap().is_ok());
+        assert!(results.next().unwrap().is_ok());
+        // more decimal places than the scale
+        match results.next().unwrap().unwrap_err() {
+            TxError::Amount(err) => assert_eq!(
+                err,
+                minor_units::AmountError::Fractional {
+                    tx: 3,
+                    amount: Decimal::new(6, 5)
+                }
+            ),
+            err => panic!("{}", err),
+        }
+        drop(results);
+        let client_map = pipeline.finish().unwrap().client_map;
+        let extra = ExtraColumns {
+            fees: true,
+            ..ExtraColumns::default()
+        };
+        let mut out = Vec::new();
+        write_balances(
+            &mut out,
+            &client_map,
+            extra,
+            AmountUnits::Minor { scale: 4 },
+            OutputFormat::Csv,
+            true,
+        )
+        .unwrap();
+        let mut rows: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        rows[1..].sort_unstable();
+        assert_eq!(
+            rows,
+            vec![
+                "client,available,held,total,locked,fees",
+                "1,123356,0,123356,false,100",
+                "2,1,0,1,false,0",
+            ]
+        );
+        assert!(AmountUnits::Minor { scale: 4 }
+            .convert(Decimal::MAX)
+            .is_err());
+        assert!(AmountUnits::Minor { scale: 4 }
+            .convert(Decimal::new(5, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn records_without_a_client_get_theirs_from_the_allocator() {
+        let mut pipeline = PipelineBuilder::new()
+            .client_ids(id_allocator::IdStrategy::Sequential.allocator(0))
+            .build();
+        let mut reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(
+            "type,client,tx,amount
+            deposit,,1,10
+            deposit,65535,2,5
+            deposit,,3,1"
+                .as_bytes(),
+        );
+        for record in reader.deserialize() {
+            pipeline.process(record.unwrap()).unwrap();
+        }
+        let state = pipeline.into_state();
+        let mut clients: Vec<u16> = state.client_map.keys().copied().collect();
+        clients.sort_unstable();
+        assert_eq!(clients, [65534, 65535]);
+        assert_eq!(
+            balances(&state.client_map, 65535),
+            (Decimal::from(15), Decimal::ZERO, Decimal::from(15), false)
+        );
+    }
+
+    #[test]
+    fn chargebacks_are_booked_to_the_suspense_account() {
+        let mut pipeline = PipelineBuilder::new().suspense_account(Some(9999)).build();
+        let state = pipeline.state();
+        assert_eq!(
+            balances(&state.client_map, 9999),
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, false)
+        );
+        let mut touched = Vec::new();
+        let mut reader = csv::ReaderBuilder::new().trim(Trim::All).from_reader(
+            "type,client,tx,amount,fee
+            deposit,1,1,10,1
+            deposit,2,2,5,
+            dispute,1,1,,
+            chargeback,1,1,,
+            dispute,2,2,,
+            resolve,2,2,,"
+                .as_bytes(),
+        );
+        for record in reader.deserialize() {
+            touched.push(pipeline.process(record.unwrap()).unwrap());
+        }
+        let state = pipeline.into_state();
+        // the net amount the deposit credited
+        assert_eq!(
+            balances(&state.client_map, 9999),
+            (Decimal::from(9), Decimal::ZERO, Decimal::from(9), false)
+        );
+        assert_eq!(
+            balances(&state.client_map, 1),
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, true)
+        );
+        assert_eq!(touched[3], vec![1, 9999]);
+        assert_eq!(touched[5], vec![2]);
+        let total: Decimal = state.client_map.values().map(|info| info.total_funds).sum();
+        assert_eq!(total, Decimal::from(14));
+    }
+
+    #[test]
+    fn balances_are_exact_and_never_in_scientific_notation() {
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,0.1
+            deposit,1,2,0.2
+            deposit,2,3,12345678901234.5678
+            withdrawal,2,4,0.0001",
+            &Policy::default(),
+        );
+        let mut out = Vec::new();
+        write_balances(
+            &mut out,
+            &client_map,
+            ExtraColumns::default(),
+            AmountUnits::default(),
+            OutputFormat::Csv,
+            true,
+        )
+        .unwrap();
+        let mut rows: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        rows[1..].sort_unstable();
+        assert_eq!(
+            rows,
+            vec![
+                "client,available,held,total,locked",
+                "1,0.3000,0.0000,0.3000,false",
+                "2,12345678901234.5677,0.0000,12345678901234.5677,false",
+            ]
+        );
+    }
+
+    #[test]
+    fn major_units_are_rounded_to_four_places() {
+        let amount = |text: &str, rounding| match (AmountUnits::Major { rounding })
+            .convert(text.parse().unwrap())
+            .unwrap()
+        {
+            OutputAmount::Major(amount) => amount.to_string(),
+            OutputAmount::Minor(_) => unreachable!(),
+        };
+        assert_eq!(amount("1.5", Rounding::HalfEven), "1.5000");
+        assert_eq!(amount("0.00125", Rounding::HalfEven), "0.0012");
+        assert_eq!(amount("0.00125", Rounding::HalfUp), "0.0013");
+        assert_eq!(amount("-0.00129", Rounding::Down), "-0.0012");
+        assert_eq!(amount("0.00121", Rounding::Up), "0.0013");
+    }
+
+    #[test]
+    fn json_balances_are_account_objects_with_exact_amounts() {
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,1.5",
+            &Policy::default(),
+        );
+        let mut out = Vec::new();
+        let extra = ExtraColumns {
+            lock_reason: true,
+            ..ExtraColumns::default()
+        };
+        write_balances(
+            &mut out,
+            &client_map,
+            extra,
+            AmountUnits::default(),
+            OutputFormat::Json,
+            true,
+        )
+        .unwrap();
+        let accounts: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            accounts,
+            serde_json::json!([{
+                "client": 1,
+                "available": "1.5000",
+                "held": "0.0000",
+                "total": "1.5000",
+                "locked": false,
+                "lock_reason": "",
+            }])
+        );
+        // minor units are already converted as they are read
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,15000",
+            &Policy::default(),
+        );
+        let mut out = Vec::new();
+        write_balances(
+            &mut out,
+            &client_map,
+            ExtraColumns::default(),
+            AmountUnits::Minor { scale: 4 },
+            OutputFormat::Json,
+            true,
+        )
+        .unwrap();
+        let accounts: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(accounts[0]["available"], 15000);
+    }
+
+    #[test]
+    fn balances_are_sorted_by_client_unless_unsorted() {
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,3,1,1
+            deposit,1,2,1
+            deposit,2,3,1",
+            &Policy::default(),
+        );
+        let clients = |sorted| {
+            let mut out = Vec::new();
+            write_balances(
+                &mut out,
+                &client_map,
+                ExtraColumns::default(),
+                AmountUnits::default(),
+                OutputFormat::Csv,
+                sorted,
+            )
+            .unwrap();
+            let out = String::from_utf8(out).unwrap();
+            out.lines()
+                .skip(1)
+                .map(|row| row[..1].to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clients(true), ["1", "2", "3"]);
+        let mut unsorted = clients(false);
+        unsorted.sort_unstable();
+        assert_eq!(unsorted, clients(true));
+    }
+
+    #[test]
+    fn balances_get_a_fees_column_only_with_fees() {
+        let client_map = run(
+            "type,client,tx,amount,fee
+            deposit,1,1,10,0.5",
+            &Policy::default(),
+        );
+        let mut out = Vec::new();
+        write_balances(
+            &mut out,
+            &client_map,
+            ExtraColumns::default(),
+            AmountUnits::default(),
+            OutputFormat::Csv,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n1,9.5000,0.0000,9.5000,false\n"
+        );
+        let mut out = Vec::new();
+        let extra = ExtraColumns {
+            fees: true,
+            ..ExtraColumns::default()
+        };
+        write_balances(
+            &mut out,
+            &client_map,
+            extra,
+            AmountUnits::default(),
+            OutputFormat::Csv,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,fees\n1,9.5000,0.0000,9.5000,false,0.5000\n"
+        );
+    }
+
+    #[test]
+    fn balances_get_the_withdrawal_usage_of_the_last_24_hours() {
+        let client_map = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,2022-03-01T08:00:00Z
+            withdrawal,1,2,1,2022-03-01T09:00:00Z
+            withdrawal,1,3,2,2022-03-02T09:30:00Z",
+            &Policy::default(),
+        );
+        let mut out = Vec::new();
+        let extra = ExtraColumns {
+            withdrawal_usage: true,
+            ..ExtraColumns::default()
+        };
+        write_balances(
+            &mut out,
+            &client_map,
+            extra,
+            AmountUnits::default(),
+            OutputFormat::Csv,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,withdrawals_24h,withdrawn_24h\n\
+             1,7.0000,0.0000,7.0000,false,1,2.0000\n"
+        );
+    }
+
+    #[test]
+    fn balances_get_the_lock_reasons() {
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            dispute,1,1,
+            chargeback,1,1,",
+            &Policy::default(),
+        );
+        let mut out = Vec::new();
+        let extra = ExtraColumns {
+            lock_reason: true,
+            ..ExtraColumns::default()
+        };
+        write_balances(
+            &mut out,
+            &client_map,
+            extra,
+            AmountUnits::default(),
+            OutputFormat::Csv,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,lock_reason\n\
+             1,0.0000,0.0000,0.0000,true,chargeback:1\n"
+        );
+    }
+
+    #[test]
+    fn void_restores_balances_and_fees_exactly() {
+        let client_map = run(
+            "type,client,tx,amount,fee
+            deposit,1,1,10,1
+            withdrawal,1,2,3,0.5
+            deposit,1,3,4,
+            void,1,2,,
+            void,1,3,,",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(9), Decimal::ZERO, Decimal::from(9), false)
+        );
+        assert_eq!(client_map[&1].fees, Decimal::ONE);
+    }
+
+    #[test]
+    fn voided_transactions_can_not_be_disputed_and_disputed_ones_not_voided() {
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,1,2,5
+            void,1,1,
+            dispute,1,1,
+            dispute,1,2,
+            void,1,2,
+            void,1,1,",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::ZERO, Decimal::from(5), Decimal::from(5), false)
+        );
+    }
+
+    #[test]
+    fn void_of_a_spent_deposit_is_refused() {
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,1,2,8
+            void,1,1,",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(2), Decimal::ZERO, Decimal::from(2), false)
+        );
+    }
+
+    #[test]
+    fn refused_withdrawals_can_not_be_voided_or_disputed() {
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,1,2,50
+            void,1,2,
+            dispute,1,2,
+            chargeback,1,2,",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(10), Decimal::ZERO, Decimal::from(10), false)
+        );
+        // retried with the same id, the applied withdrawal is the one voided
+        let client_map = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,1,2,50
+            withdrawal,1,2,5
+            void,1,2,",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(10), Decimal::ZERO, Decimal::from(10), false)
+        );
+        // a withdrawal opening an account is not kept either
+        let client_map = run(
+            "type,client,tx,amount
+            withdrawal,2,3,5
+            void,2,3,",
+            &Policy::default(),
+        );
+        assert_eq!(
+            balances(&client_map, 2),
+            (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, false)
+        );
+        assert!(client_map[&2].transactions().is_empty());
+    }
+
+    #[test]
+    fn settled_transactions_can_only_be_voided_the_same_day() {
+        let client_map = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,2022-03-01T09:00:00Z
+            deposit,1,2,5,2022-03-01T10:00:00Z
+            deposit,1,3,2,2022-03-01T11:00:00Z
+            void,1,1,,2022-03-01T23:59:59Z
+            void,1,2,,2022-03-02T00:00:00Z
+            void,1,3,,",
+            &Policy::default(),
+        );
+        // tx 2 was settled at midnight, tx 3 has no void timestamp to compare
+        assert_eq!(
+            balances(&client_map, 1),
+            (Decimal::from(5), Decimal::ZERO, Decimal::from(5), false)
+        );
+    }
+
+    #[test]
+    fn several_inputs_are_applied_like_one_concatenated_file() {
+        let dir = std::env::temp_dir().join(format!("inputs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("00.csv");
+        let second = dir.join("01.csv");
+        let whole = dir.join("all.csv");
+        // the second file has its own header and dialect
+        fs::write(
+            &first,
+            "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\n",
+        )
+        .unwrap();
+        fs::write(
+            &second,
+            "tx;type;client;amount\n3;withdrawal;1;4\n2;dispute;2;\n",
+        )
+        .unwrap();
+        fs::write(
+            &whole,
+            "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,5\nwithdrawal,1,3,4\ndispute,2,2,\n",
+        )
+        .unwrap();
+        let output = |paths: &[PathBuf]| {
+            let mut out = Vec::new();
+            process_input(&Config::default(), paths, &mut out).unwrap();
+            // the accounts are written in no particular order
+            let mut lines: Vec<String> = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            lines.sort();
+            lines
+        };
+        let combined = output(&[first.clone(), second.clone()]);
+        let concatenated = output(&[whole]);
+        let missing = process_input(&Config::default(), &[first, dir.join("02.csv")], Vec::new());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(combined, concatenated);
+        assert_eq!(combined[0], "1,6.0000,0.0000,6.0000,false");
+        // nothing is applied when one of the files can not be opened
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn merge_by_time_applies_the_inputs_in_timestamp_order() {
+        let dir = std::env::temp_dir().join(format!("merge-inputs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("a.csv");
+        let second = dir.join("b.csv");
+        let untimed = dir.join("c.csv");
+        fs::write(
+            &first,
+            "type,client,tx,amount,timestamp
+deposit,1,1,5,2022-03-01T10:00:00Z
+withdrawal,1,2,8,2022-03-01T12:00:00Z
+",
+        )
+        .unwrap();
+        // not sorted, it is sorted before the merge
+        fs::write(
+            &second,
+            "type,client,tx,amount,timestamp
+deposit,1,3,1,2022-03-01T11:30:00Z
+deposit,1,4,5,2022-03-01T11:00:00Z
+",
+        )
+        .unwrap();
+        fs::write(&untimed, "type,client,tx,amount\ndeposit,1,5,1\n").unwrap();
+        let run = |merge_by_time, paths: &[PathBuf]| {
+            let config = Config {
+                merge_by_time,
+                ..Config::default()
+            };
+            let mut out = Vec::new();
+            process_input(&config, paths, &mut out).map(|_| String::from_utf8(out).unwrap())
+        };
+        let inputs = [first.clone(), second.clone()];
+        let merged = run(true, &inputs).unwrap();
+        let concatenated = run(false, &inputs).unwrap();
+        let err = run(true, &[first, untimed.clone()])
+            .unwrap_err()
+            .to_string();
+        fs::remove_dir_all(&dir).unwrap();
+        // the withdrawal comes after every deposit instead of being refused
+        assert_eq!(
+            merged,
+            "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+        );
+        assert_eq!(
+            concatenated,
+            "client,available,held,total,locked\n1,11.0000,0.0000,11.0000,false\n"
+        );
+        assert_eq!(
+            err,
+            format!(
+                "--merge-by-time needs a timestamp column in every input, {} has none",
+                untimed.display()
+            )
+        );
+    }
+
+    #[test]
+    fn outputs_are_only_replaced_once_complete() {
+        let path = std::env::temp_dir().join(format!("atomic-{}.csv", std::process::id()));
+        let partial = path.with_extension("csv.partial");
+        fs::write(&path, "old\n").unwrap();
+        let failed = write_atomic(&path, |out| {
+            out.write_all(b"half")?;
+            Err(From::from("input broke off"))
+        });
+        assert_eq!(failed.unwrap_err().to_string(), "input broke off");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        assert!(!partial.exists());
+        write_atomic(&path, |out| Ok(out.write_all(b"new\n")?)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert!(!partial.exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
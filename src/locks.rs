@@ -0,0 +1,177 @@
+use crate::{ClientInfo, Record, TxError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, path::Path};
+
+/// Why an account got locked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LockReason {
+    /// chargeback of the given tx id
+    Chargeback { tx: u32 },
+    /// client is on the denylist
+    Denylist,
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockReason::Chargeback { tx } => write!(f, "chargeback:{}", tx),
+            LockReason::Denylist => write!(f, "denylist"),
+        }
+    }
+}
+
+/// One entry of an account's lock history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockEvent {
+    pub reason: LockReason,
+    /// position of the input record that caused the lock
+    pub position: u64,
+    /// timestamp of that record, when it has one
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl<A> ClientInfo<A> {
+    /// Locks the account for `reason`, caused by `record`. An account can be locked for
+    /// several reasons, each distinct reason is kept once.
+    pub(crate) fn lock(&mut self, reason: LockReason, record: &Record<A>) {
+        self.locked = true;
+        if !self.lock_history.iter().any(|event| event.reason == reason) {
+            self.lock_history.push(LockEvent {
+                reason,
+                position: record.position,
+                timestamp: record.timestamp,
+            });
+        }
+    }
+
+    /// `;` separated reasons the account is locked for, empty when unlocked.
+    pub(crate) fn lock_reasons(&self) -> String {
+        let reasons: Vec<String> = self
+            .lock_history
+            .iter()
+            .map(|event| event.reason.to_string())
+            .collect();
+        reasons.join(";")
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct LockHistoryRow {
+    client: u16,
+    event: &'static str,
+    reason: String,
+    position: u64,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+/// Writes the lock history of every client as CSV, sorted by client and in the order the
+/// locks happened.
+pub fn write_history(path: &Path, client_map: &HashMap<u16, ClientInfo>) -> Result<(), TxError> {
+    let mut clients: Vec<&u16> = client_map.keys().collect();
+    clients.sort();
+
+    let mut wtr = csv::Writer::from_path(path)?;
+    for client in clients {
+        for event in client_map[client].lock_history.iter() {
+            wtr.serialize(LockHistoryRow {
+                client: *client,
+                event: "lock",
+                reason: event.reason.to_string(),
+                position: event.position,
+                timestamp: event.timestamp,
+            })?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline::PipelineBuilder,
+        screening::{Denylist, Screening},
+        Record, TxType,
+    };
+    use std::fs;
+
+    #[test]
+    fn keeps_each_reason_once_in_the_order_of_the_locks() {
+        let record = |position| Record {
+            tx_type: TxType::Chargeback,
+            client: Some(1),
+            tx: 3,
+            amount: None,
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        };
+        let mut info: ClientInfo = ClientInfo::default();
+        assert_eq!(info.lock_reasons(), "");
+        info.lock(LockReason::Chargeback { tx: 3 }, &record(5));
+        info.lock(LockReason::Denylist, &record(7));
+        info.lock(LockReason::Denylist, &record(9));
+        assert!(info.locked);
+        assert_eq!(info.lock_reasons(), "chargeback:3;denylist");
+        assert_eq!(
+            info.lock_history,
+            vec![
+                LockEvent {
+                    reason: LockReason::Chargeback { tx: 3 },
+                    position: 5,
+                    timestamp: None,
+                },
+                LockEvent {
+                    reason: LockReason::Denylist,
+                    position: 7,
+                    timestamp: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn history_has_the_locks_of_chargebacks_and_the_denylist() {
+        let dir = std::env::temp_dir();
+        let list = dir.join(format!("locks-denylist-{}.txt", std::process::id()));
+        fs::write(&list, "2\n").unwrap();
+        let screening = Screening::new(Denylist::from_path(&list).unwrap(), None).unwrap();
+        fs::remove_file(&list).unwrap();
+        let input = "type,client,tx,amount,timestamp
+            deposit,1,1,10,
+            deposit,3,2,10,
+            dispute,1,1,,
+            chargeback,1,1,,2022-03-01T12:00:00Z
+            dispute,3,2,,
+            chargeback,3,2,,
+            deposit,2,3,5,";
+        let mut pipeline = PipelineBuilder::new().transform(screening).build();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        for record in reader.deserialize::<Record>() {
+            pipeline.process(record.unwrap()).unwrap();
+        }
+        let state = pipeline.finish().unwrap();
+
+        let path = dir.join(format!("lock-history-{}.csv", std::process::id()));
+        write_history(&path, &state.client_map).unwrap();
+        let history = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // client 2 had no account to lock
+        assert_eq!(
+            history,
+            "client,event,reason,position,timestamp\n\
+             1,lock,chargeback:1,4,2022-03-01T12:00:00Z\n\
+             3,lock,chargeback:2,6,\n"
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use crate::{Record, Records, TxError, TxType};
+use chrono::{DateTime, Utc};
+use log::info;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    error::Error,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+};
+
+/// Records held in memory per sorted run of the external sort.
+const RUN_LENGTH: usize = 100_000;
+
+/// Where a record goes in time order: its timestamp, or the one of the last record before
+/// it in its input that had one, then its place in the input. Rows that could not be read
+/// stay where they were the same way.
+type Key = (Option<DateTime<Utc>>, u64);
+
+type Entry = (Key, Result<Record, Unread>);
+
+/// An error read from an input, held as its parts until its place in the merge comes up,
+/// since a `TxError` can not be sent to the thread the records are read on.
+enum Unread {
+    /// a row that could not be read as a record
+    Row { line: Option<u64>, message: String },
+    /// any other error, which fails the run when it is reached
+    Failed(String),
+}
+
+impl From<TxError> for Unread {
+    fn from(err: TxError) -> Self {
+        match err {
+            TxError::ParseError { line, message } => Unread::Row { line, message },
+            err => Unread::Failed(err.to_string()),
+        }
+    }
+}
+
+impl From<Unread> for TxError {
+    fn from(unread: Unread) -> Self {
+        match unread {
+            Unread::Row { line, message } => TxError::ParseError { line, message },
+            Unread::Failed(message) => TxError::other(message),
+        }
+    }
+}
+
+/// How the timestamps of an input are ordered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    /// no record has a timestamp
+    Untimed,
+    Sorted,
+    Unsorted,
+}
+
+/// Reads `records` through to find how they are ordered. Rows that can not be read are
+/// skipped, they are reported when the input is applied.
+pub fn scan(records: Records) -> Order {
+    let mut last = None;
+    let mut order = Order::Untimed;
+    for timestamp in records.filter_map(|record| record.ok()?.timestamp) {
+        if last.is_some_and(|last| timestamp < last) {
+            return Order::Unsorted;
+        }
+        last = Some(timestamp);
+        order = Order::Sorted;
+    }
+    order
+}
+
+/// Keys the records of one input as they are read.
+struct Keyed {
+    records: Records,
+    last: Option<DateTime<Utc>>,
+    read: u64,
+}
+
+impl Keyed {
+    fn new(records: Records) -> Self {
+        Keyed {
+            records,
+            last: None,
+            read: 0,
+        }
+    }
+}
+
+impl Iterator for Keyed {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.next()?;
+        if let Some(timestamp) = record.as_ref().ok().and_then(|record| record.timestamp) {
+            self.last = Some(timestamp);
+        }
+        self.read += 1;
+        Some(((self.last, self.read), record.map_err(Unread::from)))
+    }
+}
+
+/// The next entry of one input of a merge.
+struct Head {
+    key: Key,
+    input: usize,
+    record: Result<Record, Unread>,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.key, self.input).cmp(&(other.key, other.input))
+    }
+}
+
+/// k-way merge of inputs that are each in key order. Holds one entry per input.
+struct Merge {
+    inputs: Vec<Box<dyn Iterator<Item = Entry> + Send>>,
+    heads: BinaryHeap<Reverse<Head>>,
+}
+
+impl Merge {
+    fn new(mut inputs: Vec<Box<dyn Iterator<Item = Entry> + Send>>) -> Self {
+        let mut heads = BinaryHeap::with_capacity(inputs.len());
+        for (input, entries) in inputs.iter_mut().enumerate() {
+            if let Some((key, record)) = entries.next() {
+                heads.push(Reverse(Head { key, input, record }));
+            }
+        }
+        Merge { inputs, heads }
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse(head) = self.heads.pop()?;
+        if let Some((key, record)) = self.inputs[head.input].next() {
+            self.heads.push(Reverse(Head {
+                key,
+                input: head.input,
+                record,
+            }));
+        }
+        Some((head.key, head.record))
+    }
+}
+
+/// Applies the records of several inputs in time order. Each input must be sorted by
+/// timestamp, see `scan`; unsorted ones go through `sort` first. Records with the same
+/// timestamp are taken from the inputs in the order the inputs are given.
+pub fn chronological(inputs: Vec<Records>) -> Records {
+    let inputs = inputs
+        .into_iter()
+        .map(|records| -> Box<dyn Iterator<Item = Entry> + Send> {
+            // the place in the input only matters within one input, which the merge keeps
+            Box::new(Keyed::new(records).map(|((timestamp, _), record)| ((timestamp, 0), record)))
+        })
+        .collect();
+    Box::new(Merge::new(inputs).map(|(_, record)| record.map_err(TxError::from)))
+}
+
+/// Sorts `records` by timestamp, keeping the input order of records with the same one.
+/// Runs of `RUN_LENGTH` records are sorted in memory and spilled to temporary files, which
+/// are then merged, so inputs larger than memory can be sorted. Rows that could not be
+/// read are held in memory.
+pub fn sort(records: Records) -> Result<Records, Box<dyn Error>> {
+    sort_in_runs(records, RUN_LENGTH)
+}
+
+fn sort_in_runs(records: Records, run_length: usize) -> Result<Records, Box<dyn Error>> {
+    let mut runs: Vec<Box<dyn Iterator<Item = Entry> + Send>> = Vec::new();
+    let mut malformed = Vec::new();
+    let mut run = Vec::new();
+    for (key, record) in Keyed::new(records) {
+        match record {
+            Ok(record) => run.push((key, record)),
+            Err(err) => malformed.push((key, Err(err))),
+        }
+        if run.len() == run_length {
+            runs.push(Box::new(SpillFile::write(&mut run)?));
+        }
+    }
+    run.sort_by_key(|(key, _)| *key);
+    if !runs.is_empty() {
+        info!("merging {} sorted runs", runs.len() + 1);
+    }
+    runs.push(Box::new(
+        run.into_iter().map(|(key, record)| (key, Ok(record))),
+    ));
+    // keys are unique, the order within an input
+    malformed.sort_by_key(|(key, _)| *key);
+    runs.push(Box::new(malformed.into_iter()));
+    Ok(Box::new(
+        Merge::new(runs).map(|(_, record)| record.map_err(TxError::from)),
+    ))
+}
+
+/// What a spilled record keeps; fields set later by the pipeline are not needed.
+#[derive(Serialize, Deserialize)]
+struct Spilled {
+    key: Key,
+    tx_type: TxType,
+    client: Option<u16>,
+    tx: u32,
+    amount: Option<Decimal>,
+    fee: Option<Decimal>,
+    memo: Option<String>,
+    evidence: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+    line: u64,
+}
+
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A sorted run in a temporary file, a JSON line per record, removed when dropped.
+struct SpillFile {
+    path: PathBuf,
+    reader: BufReader<File>,
+    remaining: usize,
+    line: String,
+}
+
+impl SpillFile {
+    /// Sorts `run` and moves it to a new temporary file.
+    fn write(run: &mut Vec<(Key, Record)>) -> Result<SpillFile, Box<dyn Error>> {
+        run.sort_by_key(|(key, _)| *key);
+        let path = std::env::temp_dir().join(format!(
+            "csvtest-sort-{}-{}.jsonl",
+            process::id(),
+            SPILL_FILES.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let mut out = BufWriter::new(File::create(&path)?);
+        let remaining = run.len();
+        for (key, record) in run.drain(..) {
+            let spilled = Spilled {
+                key,
+                tx_type: record.tx_type,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+                fee: record.fee,
+                memo: record.memo,
+                evidence: record.evidence,
+                timestamp: record.timestamp,
+                line: record.line,
+            };
+            serde_json::to_writer(&mut out, &spilled)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        Ok(SpillFile {
+            reader: BufReader::new(File::open(&path)?),
+            path,
+            remaining,
+            line: String::new(),
+        })
+    }
+}
+
+impl Iterator for SpillFile {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.line.clear();
+        let spilled = match self.reader.read_line(&mut self.line) {
+            Ok(_) => serde_json::from_str::<Spilled>(&self.line).map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        match spilled {
+            Ok(spilled) => Some((
+                spilled.key,
+                Ok(Record {
+                    tx_type: spilled.tx_type,
+                    client: spilled.client,
+                    tx: spilled.tx,
+                    amount: spilled.amount,
+                    fee: spilled.fee,
+                    memo: spilled.memo,
+                    evidence: spilled.evidence,
+                    timestamp: spilled.timestamp,
+                    position: 0,
+                    line: spilled.line,
+                    sequence: 0,
+                    next_position: None,
+                    tags: Vec::new(),
+                }),
+            )),
+            Err(err) => {
+                // the rest of the run can not be found again
+                self.remaining = 0;
+                Some((
+                    (None, 0),
+                    Err(Unread::Failed(format!(
+                        "failed to read back sorted run: {}",
+                        err
+                    ))),
+                ))
+            }
+        }
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_records;
+
+    fn records(input: &'static str) -> Records {
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        csv_records(reader, 1).unwrap()
+    }
+
+    fn txs(records: Records) -> Vec<u32> {
+        records.map(|record| record.unwrap().tx).collect()
+    }
+
+    #[test]
+    fn tells_how_an_input_is_ordered() {
+        let order = |input| scan(records(input));
+        assert_eq!(
+            order("type,client,tx,amount\ndeposit,1,1,5"),
+            Order::Untimed
+        );
+        assert_eq!(
+            order(
+                "type,client,tx,amount,timestamp
+                deposit,1,1,5,2022-03-01T10:00:00Z
+                deposit,1,2,5,
+                deposit,1,3,5,2022-03-01T10:00:00Z"
+            ),
+            Order::Sorted
+        );
+        assert_eq!(
+            order(
+                "type,client,tx,amount,timestamp
+                deposit,1,1,5,2022-03-01T10:00:00Z
+                deposit,1,2,5,2022-03-01T09:00:00Z"
+            ),
+            Order::Unsorted
+        );
+    }
+
+    #[test]
+    fn merges_inputs_in_time_order_taking_ties_in_input_order() {
+        let first = records(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,5,2022-03-01T10:00:00Z
+            deposit,1,2,5,2022-03-01T12:00:00Z
+            deposit,1,3,5,",
+        );
+        let second = records(
+            "type,client,tx,amount,timestamp
+            deposit,2,4,5,2022-03-01T09:00:00Z
+            deposit,2,5,5,2022-03-01T12:00:00Z
+            deposit,2,6,5,2022-03-01T13:00:00Z",
+        );
+        // tx 3 has no timestamp and stays behind tx 2
+        assert_eq!(txs(chronological(vec![first, second])), [4, 1, 2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn sorts_an_input_through_spilled_runs_keeping_the_order_of_ties() {
+        let input = "type,client,tx,amount,timestamp
+            deposit,1,1,5,2022-03-01T12:00:00Z
+            deposit,1,2,5,2022-03-01T10:00:00Z
+            deposit,1,3,5,
+            deposit,1,x,5,2022-03-01T09:00:00Z
+            deposit,1,4,5,2022-03-01T12:00:00Z
+            deposit,1,5,5,2022-03-01T08:00:00Z";
+        let sorted = |run_length| -> Vec<_> {
+            sort_in_runs(records(input), run_length)
+                .unwrap()
+                .map(|record| record.map(|record| (record.tx, record.line)).ok())
+                .collect()
+        };
+        // the malformed row keeps the key of tx 3, the timestamp of tx 2
+        assert_eq!(
+            sorted(2),
+            [
+                Some((5, 7)),
+                Some((2, 3)),
+                Some((3, 4)),
+                None,
+                Some((1, 2)),
+                Some((4, 6)),
+            ]
+        );
+        assert_eq!(sorted(2), sorted(RUN_LENGTH));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{timestamp, Record, TxType};
+use chrono::{DateTime, Utc};
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use serde::Deserialize;
+use std::error::Error;
+
+/// A transaction as encoded by the collector: a map with the CSV header names as keys,
+/// or an array of the values in the same order. The body of `POST /transactions` and the
+/// messages of the brokers have this shape, JSON or MessagePack encoded.
+#[derive(Deserialize, Debug)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    tx_type: String,
+    #[serde(default)]
+    client: Option<i64>,
+    tx: u32,
+    #[serde(default)]
+    amount: Option<Amount>,
+    #[serde(default)]
+    fee: Option<Amount>,
+    #[serde(default)]
+    memo: Option<String>,
+    #[serde(default)]
+    evidence: Option<String>,
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+/// Amounts are decimal strings, so no precision is lost in transit, but plain numbers
+/// are taken as well.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Amount {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl Amount {
+    fn decimal(self) -> Option<Decimal> {
+        match self {
+            Amount::Text(s) => s.trim().parse().ok(),
+            Amount::Integer(i) => Some(Decimal::from(i)),
+            Amount::Float(f) => Decimal::from_f64(f),
+        }
+    }
+}
+
+/// RFC 3339 text or milliseconds since the unix epoch.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Timestamp {
+    Text(String),
+    Millis(i64),
+}
+
+impl Timestamp {
+    fn parse(self) -> Option<DateTime<Utc>> {
+        match self {
+            Timestamp::Text(s) => timestamp::parse(&s),
+            Timestamp::Millis(millis) => timestamp::from_millis(millis),
+        }
+    }
+}
+
+impl From<Transaction> for Record {
+    fn from(message: Transaction) -> Self {
+        Record {
+            tx_type: TxType::from(message.tx_type.trim()),
+            // like the CSV reader, invalid optional values are treated as missing
+            client: message.client.and_then(|c| u16::try_from(c).ok()),
+            tx: message.tx,
+            amount: message.amount.and_then(Amount::decimal),
+            fee: message.fee.and_then(Amount::decimal),
+            memo: message.memo.filter(|m| !m.is_empty()),
+            evidence: message.evidence.filter(|e| !e.is_empty()),
+            timestamp: message.timestamp.and_then(Timestamp::parse),
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// How a transaction is encoded, as told by the content type of the message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Json,
+    /// the compact binary encoding, smaller and cheaper to parse than JSON
+    Msgpack,
+}
+
+impl Encoding {
+    /// The encoding of a content type, parameters such as `charset` ignored. `None` for one
+    /// that is neither JSON nor MessagePack.
+    pub fn from_content_type(content_type: &str) -> Option<Encoding> {
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        match media_type.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Encoding::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Encoding::Msgpack)
+            }
+            _ => None,
+        }
+    }
+
+    /// The encoding of a message that does not tell it, as a broker's: JSON when it starts
+    /// with `{` or `[`, which never start a MessagePack map or array, MessagePack otherwise.
+    #[cfg(any(feature = "kafka", feature = "nats", feature = "amqp", test))]
+    pub fn sniff(payload: &[u8]) -> Encoding {
+        match payload.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') | Some(b'[') | None => Encoding::Json,
+            Some(_) => Encoding::Msgpack,
+        }
+    }
+}
+
+/// Reads a transaction in the given encoding.
+pub fn decode(encoding: Encoding, payload: &[u8]) -> Result<Record, Box<dyn Error>> {
+    match encoding {
+        Encoding::Json => from_json(payload),
+        Encoding::Msgpack => from_msgpack(payload),
+    }
+}
+
+fn from_json(payload: &[u8]) -> Result<Record, Box<dyn Error>> {
+    let transaction: Transaction = serde_json::from_slice(payload)?;
+    Ok(Record::from(transaction))
+}
+
+#[cfg(feature = "msgpack")]
+fn from_msgpack(payload: &[u8]) -> Result<Record, Box<dyn Error>> {
+    let transaction: Transaction = rmp_serde::from_slice(payload)?;
+    Ok(Record::from(transaction))
+}
+
+#[cfg(not(feature = "msgpack"))]
+fn from_msgpack(_payload: &[u8]) -> Result<Record, Box<dyn Error>> {
+    Err(From::from(
+        "MessagePack transactions need the msgpack feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types_pick_the_encoding() {
+        assert_eq!(
+            Encoding::from_content_type("application/json; charset=utf-8"),
+            Some(Encoding::Json)
+        );
+        assert_eq!(
+            Encoding::from_content_type("Application/MsgPack"),
+            Some(Encoding::Msgpack)
+        );
+        assert_eq!(
+            Encoding::from_content_type("application/x-msgpack"),
+            Some(Encoding::Msgpack)
+        );
+        assert_eq!(Encoding::from_content_type("application/cbor"), None);
+        assert_eq!(Encoding::from_content_type(""), None);
+    }
+
+    #[test]
+    fn messages_without_a_content_type_are_sniffed() {
+        assert_eq!(Encoding::sniff(b" {\"type\": \"deposit\"}"), Encoding::Json);
+        assert_eq!(Encoding::sniff(b"[\"dispute\", 1, 1]"), Encoding::Json);
+        assert_eq!(Encoding::sniff(b""), Encoding::Json);
+        // fixmap and fixarray markers
+        assert_eq!(Encoding::sniff(&[0x84, 0xa4]), Encoding::Msgpack);
+        assert_eq!(Encoding::sniff(&[0x93, 0xa7]), Encoding::Msgpack);
+    }
+
+    #[test]
+    fn reads_json_maps_and_arrays() {
+        let record = decode(
+            Encoding::Json,
+            br#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5", "memo": "salary",
+                "timestamp": 1646136000000}"#,
+        )
+        .unwrap();
+        assert_eq!(record.tx_type, TxType::Deposit);
+        assert_eq!(record.client, Some(1));
+        assert_eq!(record.amount, Some(Decimal::new(15, 1)));
+        assert_eq!(record.memo.as_deref(), Some("salary"));
+        assert_eq!(record.timestamp, timestamp::parse("2022-03-01T12:00:00Z"));
+
+        let record = decode(Encoding::Json, br#"["withdrawal", 2, 3, 4]"#).unwrap();
+        assert_eq!(record.tx_type, TxType::Withdrawal);
+        assert_eq!(record.client, Some(2));
+        assert_eq!(record.tx, 3);
+        assert_eq!(record.amount, Some(Decimal::from(4)));
+    }
+
+    #[test]
+    fn invalid_optional_values_are_missing() {
+        let record = decode(
+            Encoding::Json,
+            br#"{"type": "deposit", "client": 70000, "tx": 1, "amount": "lots", "memo": ""}"#,
+        )
+        .unwrap();
+        assert_eq!(record.client, None);
+        assert_eq!(record.amount, None);
+        assert_eq!(record.memo, None);
+    }
+
+    #[test]
+    fn a_transaction_without_tx_is_an_error() {
+        assert!(decode(Encoding::Json, br#"{"type": "deposit", "client": 1}"#).is_err());
+        assert!(decode(Encoding::Json, b"type,client,tx").is_err());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn reads_msgpack_maps_and_arrays() {
+        let map = serde_json::json!({"type": "deposit", "client": 1, "tx": 1, "amount": 1.5});
+        let payload = rmp_serde::to_vec_named(&map).unwrap();
+        assert!(payload.len() < serde_json::to_vec(&map).unwrap().len());
+        let record = decode(Encoding::Msgpack, &payload).unwrap();
+        assert_eq!(record.tx_type, TxType::Deposit);
+        assert_eq!(record.client, Some(1));
+        assert_eq!(record.amount, Some(Decimal::new(15, 1)));
+
+        let payload = rmp_serde::to_vec(&("dispute", 1, 1)).unwrap();
+        let record = decode(Encoding::Msgpack, &payload).unwrap();
+        assert_eq!(record.tx_type, TxType::Dispute);
+        assert_eq!(record.amount, None);
+    }
+
+    #[cfg(not(feature = "msgpack"))]
+    #[test]
+    fn msgpack_needs_the_feature() {
+        let err = decode(Encoding::Msgpack, &[0x93]).unwrap_err();
+        assert!(err.to_string().contains("msgpack feature"));
+    }
+}
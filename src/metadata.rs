@@ -0,0 +1,138 @@
+use crate::{policy::Policy, TxError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Version of the balances output columns, bumped whenever a column is added or changed.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Everything needed to trace which code and settings produced a balances file.
+#[derive(Serialize, Debug)]
+pub struct RunMetadata {
+    engine_version: &'static str,
+    schema_version: u32,
+    input: String,
+    input_sha256: String,
+    policy: Policy,
+    /// records read from the input, including skipped ones
+    records: u64,
+    clients: usize,
+}
+
+impl RunMetadata {
+    pub fn new(
+        input: &Path,
+        policy: &Policy,
+        records: u64,
+        clients: usize,
+    ) -> Result<Self, TxError> {
+        Ok(RunMetadata {
+            engine_version: env!("CARGO_PKG_VERSION"),
+            schema_version: SCHEMA_VERSION,
+            input: input.display().to_string(),
+            input_sha256: sha256(input)?,
+            policy: policy.clone(),
+            records,
+            clients,
+        })
+    }
+
+    pub fn write_sidecar(&self, path: &Path) -> Result<(), TxError> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Writes one `# key: value` line per field, policy settings are flattened to `policy.<setting>`.
+    pub fn write_header<W: Write>(&self, out: &mut W) -> Result<(), TxError> {
+        let value = serde_json::to_value(self)?;
+        if let serde_json::Value::Object(fields) = value {
+            for (key, value) in fields {
+                match value {
+                    serde_json::Value::Object(nested) => {
+                        for (nested_key, nested_value) in nested {
+                            writeln!(out, "# {}.{}: {}", key, nested_key, plain(&nested_value))?;
+                        }
+                    }
+                    _ => writeln!(out, "# {}: {}", key, plain(&value))?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Strings without their JSON quotes, everything else as JSON.
+fn plain(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Profile;
+    use std::fs;
+
+    fn metadata(name: &str) -> (RunMetadata, std::path::PathBuf) {
+        let input =
+            std::env::temp_dir().join(format!("metadata-{}-{}.csv", name, std::process::id()));
+        fs::write(&input, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        let policy = Policy::from_profile(Profile::Strict);
+        let run_metadata = RunMetadata::new(&input, &policy, 1, 1).unwrap();
+        (run_metadata, input)
+    }
+
+    #[test]
+    fn header_flattens_the_policy() {
+        let (run_metadata, input) = metadata("header");
+        fs::remove_file(&input).unwrap();
+        let mut out = Vec::new();
+        run_metadata.write_header(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().all(|line| line.starts_with("# ")), "{}", out);
+        assert!(out.contains("# schema_version: 1\n"), "{}", out);
+        assert!(out.contains("# policy.profile: strict\n"), "{}", out);
+        assert!(out.contains("# policy.duplicate_tx: abort\n"), "{}", out);
+        assert!(out.contains("# records: 1\n"), "{}", out);
+        assert!(
+            out.contains(&format!("# input: {}\n", input.display())),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn sidecar_records_the_input_checksum() {
+        let (run_metadata, input) = metadata("sidecar");
+        let sidecar = input.with_extension("json");
+        run_metadata.write_sidecar(&sidecar).unwrap();
+        let value: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&sidecar).unwrap()).unwrap();
+        fs::remove_file(&sidecar).unwrap();
+        fs::remove_file(&input).unwrap();
+        assert_eq!(value["engine_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(value["clients"], 1);
+        assert_eq!(value["policy"]["missing_client"], "abort");
+        let digest = value["input_sha256"].as_str().unwrap();
+        assert_eq!(digest.len(), 64);
+        assert_eq!(
+            digest,
+            format!(
+                "{:x}",
+                Sha256::digest(b"type,client,tx,amount\ndeposit,1,1,1.0\n")
+            )
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    pipeline::{State, Transform},
+    ClientInfo, Outcome, Record, TxError,
+};
+use rust_decimal::Decimal;
+use std::{error::Error, fmt};
+
+/// Why an amount can not be handled in integer minor units.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmountError {
+    /// the amount has more decimal places than the currency's minor unit
+    Fractional { tx: u32, amount: Decimal },
+    /// an amount or balance is outside the `i64` range
+    Overflow { client: Option<u16>, tx: u32 },
+    /// the total of an account is not its available plus its held funds
+    Invariant { client: u16, tx: u32 },
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountError::Fractional { tx, amount } => write!(
+                f,
+                "amount {} of tx {} is not a whole number of minor units",
+                amount, tx
+            ),
+            AmountError::Overflow {
+                client: Some(client),
+                tx,
+            } => write!(
+                f,
+                "amounts of client {} overflow minor units at tx {}",
+                client, tx
+            ),
+            AmountError::Overflow { client: None, tx } => {
+                write!(f, "amount of tx {} overflows minor units", tx)
+            }
+            AmountError::Invariant { client, tx } => write!(
+                f,
+                "total of client {} is not its available plus held funds at tx {}",
+                client, tx
+            ),
+        }
+    }
+}
+
+impl Error for AmountError {}
+
+/// Converts amounts to `i64` minor units as they are read, so everything after runs on
+/// whole numbers, and checks the balances of every account a record touched still are.
+/// Conversions use checked integer arithmetic; a fractional amount, one that overflows or an
+/// account whose total no longer adds up aborts the run with an [`AmountError`].
+pub struct MinorUnits {
+    /// minor units per major unit
+    factor: i64,
+    last_tx: u32,
+}
+
+impl MinorUnits {
+    /// `scale` is the number of decimal places of the currency, e.g. 2 for cents.
+    pub fn new(scale: u32) -> Result<Self, Box<dyn Error>> {
+        let factor = 10i64
+            .checked_pow(scale)
+            .ok_or_else(|| format!("amount scale {} is too large for minor units", scale))?;
+        Ok(MinorUnits { factor, last_tx: 0 })
+    }
+
+    fn convert(&self, amount: Decimal, record: &Record) -> Result<Decimal, AmountError> {
+        match to_minor(amount, self.factor) {
+            Ok(minor) => Ok(Decimal::from(minor)),
+            Err(true) => Err(AmountError::Overflow {
+                client: record.client,
+                tx: record.tx,
+            }),
+            Err(false) => Err(AmountError::Fractional {
+                tx: record.tx,
+                amount,
+            }),
+        }
+    }
+
+    /// The balances of an account the engine just changed have to be whole minor units that
+    /// fit an `i64`, with the total the available plus the held funds.
+    fn check(&self, client: u16, info: &ClientInfo) -> Result<(), AmountError> {
+        let overflow = AmountError::Overflow {
+            client: Some(client),
+            tx: self.last_tx,
+        };
+        let minor = |amount| to_minor(amount, 1).map_err(|_| overflow.clone());
+        let available = minor(info.available_funds)?;
+        let held = minor(info.held_funds)?;
+        let total = minor(info.total_funds)?;
+        minor(info.fees)?;
+        match available.checked_add(held) {
+            Some(sum) if sum == total => Ok(()),
+            Some(_) => Err(AmountError::Invariant {
+                client,
+                tx: self.last_tx,
+            }),
+            None => Err(overflow),
+        }
+    }
+}
+
+/// `amount` times `factor` as an `i64`. `Err(true)` when it overflows, `Err(false)` when it
+/// is not a whole number.
+fn to_minor(amount: Decimal, factor: i64) -> Result<i64, bool> {
+    // amount is mantissa / 10^scale, with a scale of at most 28
+    let divisor = 10i128.pow(amount.scale());
+    let scaled = amount
+        .mantissa()
+        .checked_mul(i128::from(factor))
+        .ok_or(true)?;
+    if scaled.checked_rem(divisor) != Some(0) {
+        return Err(false);
+    }
+    let minor = scaled.checked_div(divisor).ok_or(true)?;
+    i64::try_from(minor).map_err(|_| true)
+}
+
+impl Transform for MinorUnits {
+    fn apply(&mut self, mut record: Record, _state: &mut State) -> Result<Option<Record>, TxError> {
+        if let Some(amount) = record.amount {
+            record.amount = Some(self.convert(amount, &record)?);
+        }
+        if let Some(fee) = record.fee {
+            record.fee = Some(self.convert(fee, &record)?);
+        }
+        self.last_tx = record.tx;
+        Ok(Some(record))
+    }
+
+    fn applied(
+        &mut self,
+        _client: Option<u16>,
+        _outcome: Outcome,
+        state: &mut State,
+    ) -> Result<(), TxError> {
+        // every account the record changed, funding and suspense accounts included
+        for client in state.touched.iter() {
+            if let Some(info) = state.client_map.get(client) {
+                self.check(*client, info)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::PipelineBuilder, TxType};
+
+    #[test]
+    fn converts_whole_minor_units_only() {
+        assert_eq!(to_minor(Decimal::new(123456, 4), 100), Err(false));
+        assert_eq!(to_minor(Decimal::new(1234, 2), 100), Ok(1234));
+        assert_eq!(to_minor(Decimal::new(-5, 1), 10), Ok(-5));
+        assert_eq!(to_minor(Decimal::from(i64::MAX), 1), Ok(i64::MAX));
+        assert_eq!(to_minor(Decimal::from(i64::MAX), 10), Err(true));
+        assert_eq!(to_minor(Decimal::MAX, 1), Err(true));
+    }
+
+    fn deposit(tx: u32, amount: Decimal) -> Record {
+        Record {
+            tx_type: TxType::Deposit,
+            client: Some(1),
+            tx,
+            amount: Some(amount),
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn balances_past_i64_abort_the_run() {
+        let mut pipeline = PipelineBuilder::new()
+            .transform(MinorUnits::new(0).unwrap())
+            .build();
+        pipeline
+            .process(deposit(1, Decimal::from(i64::MAX)))
+            .unwrap();
+        let err = pipeline.process(deposit(2, Decimal::ONE)).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                TxError::Amount(AmountError::Overflow {
+                    client: Some(1),
+                    tx: 2
+                })
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn a_total_that_does_not_add_up_is_its_own_error() {
+        let units = MinorUnits {
+            factor: 1,
+            last_tx: 7,
+        };
+        let info = ClientInfo {
+            available_funds: Decimal::from(5),
+            total_funds: Decimal::from(4),
+            ..ClientInfo::default()
+        };
+        assert_eq!(
+            units.check(3, &info),
+            Err(AmountError::Invariant { client: 3, tx: 7 })
+        );
+    }
+}
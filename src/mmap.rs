@@ -0,0 +1,142 @@
+use crate::{csv_row::Columns, dialect::Dialect, parallel_csv::Parsed};
+use csv::{ByteRecord, Position};
+use memmap2::Mmap;
+use std::{error::Error, fs::File, io::Cursor, path::Path, sync::Arc};
+
+/// Bytes of a mapped file handed to a parse thread at a time, before the rest of the row.
+const CHUNK_BYTES: usize = 1 << 20;
+
+/// A file mapped into memory, read-only and cheap to clone.
+#[derive(Clone)]
+pub struct Mapped(Option<Arc<Mmap>>);
+
+impl Mapped {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            // an empty file can not be mapped
+            return Ok(Mapped(None));
+        }
+        // Safety: the mapping is only read. A file changed under it reads as garbage, as it
+        // would when changed while read, and truncating it is not supported.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Mapped(Some(Arc::new(map))))
+    }
+
+    /// A reader of the whole file in the layout of `dialect`.
+    pub fn reader(&self, dialect: Dialect) -> csv::Reader<Cursor<Mapped>> {
+        dialect
+            .reader_builder()
+            .from_reader(Cursor::new(self.clone()))
+    }
+}
+
+impl AsRef<[u8]> for Mapped {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_deref().map_or(&[][..], |map| &map[..])
+    }
+}
+
+/// Whether a file in `dialect` can be split at newlines outside quotes: with an escape
+/// character or comments the quotes before a newline do not tell whether a row ends there.
+pub fn splittable(dialect: &Dialect) -> bool {
+    dialect.escape.is_none() && dialect.comment.is_none()
+}
+
+/// Splits a mapped file into ranges of rows of about `CHUNK_BYTES` each. A range ends at a
+/// newline with balanced quotes since its start, so a quoted field is never cut.
+///
+/// Lines are counted as the ranges are split, so positions and line numbers in errors are
+/// as when reading the file in one go; record numbers are not known past the first range.
+pub struct Boundaries {
+    file: Mapped,
+    dialect: Dialect,
+    next: Position,
+}
+
+impl Boundaries {
+    pub fn new(file: Mapped, dialect: Dialect, from: Position) -> Self {
+        Boundaries {
+            file,
+            dialect,
+            next: from,
+        }
+    }
+}
+
+impl Iterator for Boundaries {
+    type Item = Range;
+
+    fn next(&mut self) -> Option<Range> {
+        let bytes = self.file.as_ref();
+        let start = self.next.byte() as usize;
+        if start >= bytes.len() {
+            return None;
+        }
+        let quote = self.dialect.quoting.then_some(self.dialect.quote);
+        let count = |range: &[u8], byte: u8| range.iter().filter(|b| **b == byte).count();
+        let mut end = start;
+        let mut quotes = 0;
+        let mut target = (start + CHUNK_BYTES).min(bytes.len());
+        loop {
+            let next = bytes[target..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(bytes.len(), |at| target + at + 1);
+            quotes += quote.map_or(0, |quote| count(&bytes[end..next], quote));
+            end = next;
+            if quotes % 2 == 0 || end == bytes.len() {
+                break;
+            }
+            target = end;
+        }
+        let from = self.next.clone();
+        let line = from.line() + count(&bytes[start..end], b'\n') as u64;
+        self.next.set_byte(end as u64).set_line(line);
+        Some(Range {
+            file: self.file.clone(),
+            dialect: self.dialect,
+            from,
+            to: end as u64,
+        })
+    }
+}
+
+/// The rows of a mapped file that start from `from` up to the byte offset `to`.
+pub struct Range {
+    file: Mapped,
+    dialect: Dialect,
+    from: Position,
+    to: u64,
+}
+
+impl Range {
+    pub(crate) fn parse(self, columns: &Columns) -> Parsed {
+        let mut records = Parsed::new();
+        let mut reader = self.file.reader(self.dialect);
+        if let Err(err) = reader.seek(self.from) {
+            records.push(Err(From::from(err)));
+            return records;
+        }
+        let mut row = ByteRecord::new();
+        while reader.position().byte() < self.to {
+            match reader.read_byte_record(&mut row) {
+                Ok(false) => break,
+                Ok(true) => {
+                    let line = row.position().map_or(0, Position::line);
+                    records.push(
+                        columns
+                            .record(&row, line)
+                            .map(|mut record| {
+                                record.next_position = Some(reader.position().clone());
+                                record
+                            })
+                            .map_err(From::from),
+                    );
+                }
+                Err(err) => records.push(Err(From::from(err))),
+            }
+        }
+        records
+    }
+}
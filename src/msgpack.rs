@@ -0,0 +1,115 @@
+use crate::{messages::Transaction, Record, TxError};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+/// Streams MessagePack encoded transactions, each prefixed with its length as a 4 byte
+/// big-endian integer. Transactions have the shape of the body of `POST /transactions`.
+pub struct MsgpackReader<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl MsgpackReader<BufReader<File>> {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        Ok(MsgpackReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> MsgpackReader<R> {
+    pub fn new(reader: R) -> Self {
+        MsgpackReader {
+            reader,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Reads the length prefix, `None` on a clean end of input.
+    fn read_length(&mut self) -> io::Result<Option<usize>> {
+        let mut prefix = [0u8; 4];
+        let mut read = 0;
+        while read < prefix.len() {
+            match self.reader.read(&mut prefix[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+        Ok(Some(u32::from_be_bytes(prefix) as usize))
+    }
+
+    fn next_message(&mut self) -> Result<Option<Transaction>, TxError> {
+        let length = match self.read_length()? {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+        self.buffer.resize(length, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        Ok(Some(
+            rmp_serde::from_slice(&self.buffer).map_err(TxError::malformed)?,
+        ))
+    }
+}
+
+impl<R: Read> Iterator for MsgpackReader<R> {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_message()
+            .transpose()
+            .map(|result| result.map(Record::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    /// `message` length-prefixed the way the collector writes it.
+    fn framed(message: &impl Serialize) -> Vec<u8> {
+        let payload = rmp_serde::to_vec(message).unwrap();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend(payload);
+        frame
+    }
+
+    #[test]
+    fn maps_and_arrays_are_read_until_the_input_ends() {
+        let mut input = framed(&serde_json::json!({
+            "type": "deposit", "client": 1, "tx": 1, "amount": "1.5"
+        }));
+        input.extend(framed(&("withdrawal", 1, 2, "0.5")));
+        let records: Vec<Record> = MsgpackReader::new(input.as_slice())
+            .map(Result::unwrap)
+            .collect();
+        let read: Vec<(&str, Option<u16>, u32, Option<String>)> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.tx_type.as_str(),
+                    record.client,
+                    record.tx,
+                    record.amount.map(|amount| amount.to_string()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            read,
+            vec![
+                ("deposit", Some(1), 1, Some("1.5".to_string())),
+                ("withdrawal", Some(1), 2, Some("0.5".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_cut_off_frame_is_an_error() {
+        let mut input = framed(&("deposit", 1, 1, "1.5"));
+        input.truncate(input.len() - 1);
+        let mut reader = MsgpackReader::new(input.as_slice());
+        assert!(matches!(reader.next(), Some(Err(TxError::Io(_)))));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{
+    messages::{self, Encoding},
+    Record, TxError,
+};
+use log::error;
+use nats::jetstream::{self, PushSubscription, SubscribeOptions};
+use std::error::Error;
+
+/// Consumes JSON or MessagePack transactions from a JetStream subject through a durable
+/// consumer. A message is acked once the pipeline asks for the next record, that is after
+/// it went through the engine and every sink; unacked messages are redelivered, also to the
+/// next instance using the same durable name after a crash. A malformed message is handled
+/// like a malformed row, and is acked when the policy skips it.
+pub struct NatsSource {
+    subscription: PushSubscription,
+    /// the message handed out last, acked when the next one is asked for
+    pending: Option<nats::Message>,
+}
+
+impl NatsSource {
+    pub fn new(url: &str, subject: &str, durable: &str) -> Result<Self, Box<dyn Error>> {
+        let connection = nats::connect(url)?;
+        let subscription = jetstream::new(connection).subscribe_with_options(
+            subject,
+            &SubscribeOptions::new()
+                .durable_name(durable.to_string())
+                .ack_explicit(),
+        )?;
+        Ok(NatsSource {
+            subscription,
+            pending: None,
+        })
+    }
+}
+
+impl Iterator for NatsSource {
+    type Item = Result<Record, TxError>;
+
+    /// Ends when the connection is closed for good.
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(message) = self.pending.take() {
+            if let Err(err) = message.ack() {
+                error!("failed to ack message on {}: {}", message.subject, err);
+            }
+        }
+        let message = self.subscription.next()?;
+        let record = messages::decode(Encoding::sniff(&message.data), &message.data)
+            .map_err(TxError::malformed);
+        self.pending = Some(message);
+        Some(record)
+    }
+}
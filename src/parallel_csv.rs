@@ -0,0 +1,346 @@
+use crate::{
+    csv_row::{Columns, RowError},
+    Record, TxError,
+};
+#[cfg(feature = "mmap")]
+use crate::{
+    dialect::Dialect,
+    mmap::{self, Boundaries, Mapped},
+};
+use csv::{ByteRecord, Position};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::{self, Read},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread, vec,
+};
+
+/// Rows handed to a parse thread at a time.
+const CHUNK_ROWS: usize = 1024;
+
+/// A row split from the input with where it is, or the error reading it.
+type Row = Result<(ByteRecord, u64, Position), csv::Error>;
+
+pub(crate) type Parsed = Vec<Result<Record, RowFailure>>;
+
+/// Why a parse thread could not read a row, sent back to the calling thread as is.
+#[derive(Debug)]
+pub(crate) enum RowFailure {
+    Read(csv::Error),
+    Row(RowError),
+}
+
+impl From<csv::Error> for RowFailure {
+    fn from(err: csv::Error) -> Self {
+        RowFailure::Read(err)
+    }
+}
+
+impl From<io::Error> for RowFailure {
+    fn from(err: io::Error) -> Self {
+        RowFailure::Read(err.into())
+    }
+}
+
+impl From<RowError> for RowFailure {
+    fn from(err: RowError) -> Self {
+        RowFailure::Row(err)
+    }
+}
+
+impl From<RowFailure> for TxError {
+    fn from(err: RowFailure) -> Self {
+        match err {
+            RowFailure::Read(err) => err.into(),
+            RowFailure::Row(err) => err.into(),
+        }
+    }
+}
+
+/// Work for a parse thread.
+enum Chunk {
+    /// rows split from the input on the calling thread
+    Rows(Vec<Row>),
+    /// rows of a mapped file, split by the parse thread
+    #[cfg(feature = "mmap")]
+    Mapped(mmap::Range),
+}
+
+impl Chunk {
+    fn parse(self, columns: &Columns) -> Parsed {
+        match self {
+            Chunk::Rows(rows) => rows
+                .into_iter()
+                .map(|row| {
+                    let (row, line, next_position) = row?;
+                    let mut record = columns.record(&row, line)?;
+                    record.next_position = Some(next_position);
+                    Ok(record)
+                })
+                .collect(),
+            #[cfg(feature = "mmap")]
+            Chunk::Mapped(range) => range.parse(columns),
+        }
+    }
+}
+
+/// Streams the records of a CSV reader in input order, deserialized on `threads` threads.
+/// Rows are still split from the input on the calling thread, in chunks sent to the parse
+/// threads, unless the input is a mapped file; at most two chunks per thread are in flight,
+/// and finished chunks are put back in order before they are returned.
+pub struct ParallelCsv<R> {
+    reader: csv::Reader<R>,
+    /// ranges of the mapped file `reader` reads, split off instead of its rows
+    #[cfg(feature = "mmap")]
+    boundaries: Option<Boundaries>,
+    /// `None` once the input is read to the end, which stops the parse threads
+    work: Option<SyncSender<(u64, Chunk)>>,
+    results: Receiver<(u64, Parsed)>,
+    in_flight: u64,
+    /// chunks sent to the parse threads so far
+    sent: u64,
+    /// chunk to return next
+    next: u64,
+    /// chunks parsed ahead of `next`
+    done: BTreeMap<u64, Parsed>,
+    /// whether the parse threads went away before the input was read
+    stopped: bool,
+    current: vec::IntoIter<Result<Record, RowFailure>>,
+}
+
+impl<R: Read> ParallelCsv<R> {
+    pub fn new(mut reader: csv::Reader<R>, threads: usize) -> Result<Self, Box<dyn Error>> {
+        let columns = columns(&mut reader)?;
+        Ok(ParallelCsv::start(reader, columns, threads))
+    }
+
+    /// Like `new` for the reader of a mapped file, which the parse threads split themselves
+    /// from where `reader` is, in ranges that end at a newline.
+    #[cfg(feature = "mmap")]
+    pub fn mapped(
+        mut reader: csv::Reader<R>,
+        threads: usize,
+        file: Mapped,
+        dialect: Dialect,
+    ) -> Result<Self, Box<dyn Error>> {
+        let columns = columns(&mut reader)?;
+        let from = reader.position().clone();
+        let mut parallel = ParallelCsv::start(reader, columns, threads);
+        parallel.boundaries = Some(Boundaries::new(file, dialect, from));
+        Ok(parallel)
+    }
+
+    fn start(reader: csv::Reader<R>, columns: Columns, threads: usize) -> Self {
+        let threads = threads.max(1);
+        let in_flight = 2 * threads;
+        let (work, chunks) = mpsc::sync_channel::<(u64, Chunk)>(in_flight);
+        let (parsed, results) = mpsc::channel();
+        let chunks = Arc::new(Mutex::new(chunks));
+        let columns = Arc::new(columns);
+        for _ in 0..threads {
+            let chunks = Arc::clone(&chunks);
+            let columns = Arc::clone(&columns);
+            let parsed = parsed.clone();
+            thread::spawn(move || loop {
+                let next = chunks.lock().map(|chunks| chunks.recv());
+                let (index, chunk) = match next {
+                    Ok(Ok(chunk)) => chunk,
+                    _ => break,
+                };
+                if parsed.send((index, chunk.parse(&columns))).is_err() {
+                    break;
+                }
+            });
+        }
+        ParallelCsv {
+            reader,
+            #[cfg(feature = "mmap")]
+            boundaries: None,
+            work: Some(work),
+            results,
+            in_flight: in_flight as u64,
+            sent: 0,
+            next: 0,
+            done: BTreeMap::new(),
+            stopped: false,
+            current: Vec::new().into_iter(),
+        }
+    }
+
+    /// Splits the next chunk from the input, `None` at its end.
+    fn next_chunk(&mut self) -> Option<Chunk> {
+        #[cfg(feature = "mmap")]
+        if let Some(boundaries) = self.boundaries.as_mut() {
+            return boundaries.next().map(Chunk::Mapped);
+        }
+        let mut rows = Vec::with_capacity(CHUNK_ROWS);
+        while rows.len() < CHUNK_ROWS {
+            let mut row = ByteRecord::new();
+            match self.reader.read_byte_record(&mut row) {
+                Ok(false) => break,
+                Ok(true) => {
+                    let line = row.position().map_or(0, Position::line);
+                    rows.push(Ok((row, line, self.reader.position().clone())));
+                }
+                Err(err) => rows.push(Err(err)),
+            }
+        }
+        (!rows.is_empty()).then_some(Chunk::Rows(rows))
+    }
+
+    /// Sends the next chunk to the parse threads, or stops them at the end of the input.
+    fn send_chunk(&mut self) {
+        let chunk = match self.next_chunk() {
+            Some(chunk) => chunk,
+            None => {
+                self.work = None;
+                return;
+            }
+        };
+        if let Some(work) = self.work.as_ref() {
+            if work.send((self.sent, chunk)).is_err() {
+                self.stopped = true;
+                self.work = None;
+            } else {
+                self.sent += 1;
+            }
+        }
+    }
+}
+
+/// Columns of the records, from the header row when the input has one.
+fn columns<R: Read>(reader: &mut csv::Reader<R>) -> Result<Columns, Box<dyn Error>> {
+    if reader.has_headers() {
+        Ok(Columns::new(Some(reader.byte_headers()?)))
+    } else {
+        Ok(Columns::new(None))
+    }
+}
+
+impl<R: Read> Iterator for ParallelCsv<R> {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.current.next() {
+                return Some(record.map_err(TxError::from));
+            }
+            while self.work.is_some() && self.sent - self.next < self.in_flight {
+                self.send_chunk();
+            }
+            if self.stopped {
+                self.stopped = false;
+                self.work = None;
+                self.next = self.sent;
+                return Some(Err(TxError::other("the CSV parse threads stopped")));
+            }
+            if self.next == self.sent {
+                return None;
+            }
+            let chunk = loop {
+                if let Some(chunk) = self.done.remove(&self.next) {
+                    break Some(chunk);
+                }
+                match self.results.recv() {
+                    Ok((index, chunk)) => {
+                        self.done.insert(index, chunk);
+                    }
+                    Err(_) => break None,
+                }
+            };
+            match chunk {
+                Some(chunk) => {
+                    self.next += 1;
+                    self.current = chunk.into_iter();
+                }
+                None => self.stopped = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_records;
+    use std::io::Cursor;
+
+    /// Rows over three chunks, with a malformed one in the second.
+    fn input() -> String {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=2500 {
+            if tx == 1500 {
+                input.push_str("deposit,1,x,1\n");
+            } else {
+                input.push_str(&format!("deposit,{},{},{}.5\n", tx % 7, tx, tx));
+            }
+        }
+        input
+    }
+
+    fn read(records: impl Iterator<Item = Result<Record, TxError>>) -> Vec<String> {
+        records
+            .map(|record| format!("{:?}", record.map_err(|err| err.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_same_records_in_the_same_order_as_one_thread() {
+        let input = input();
+        let serial =
+            read(csv_records(csv::Reader::from_reader(Cursor::new(input.clone())), 1).unwrap());
+        let parallel =
+            read(ParallelCsv::new(csv::Reader::from_reader(Cursor::new(input)), 3).unwrap());
+        assert_eq!(serial.len(), 2500);
+        assert_eq!(parallel, serial);
+        assert!(parallel[1499].contains("line 1501"), "{}", parallel[1499]);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn a_mapped_file_split_by_the_parse_threads_reads_as_one_thread() {
+        // quoted types, some spanning a newline, over more than one chunk
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=120_000 {
+            let tx_type = if tx % 1000 == 0 {
+                "\"depo\nsit\""
+            } else {
+                "\"deposit\""
+            };
+            input.push_str(&format!("{},{},{},{}.5\n", tx_type, tx % 7, tx, tx));
+        }
+        let path = std::env::temp_dir().join(format!("{}-mapped.csv", std::process::id()));
+        std::fs::write(&path, &input).unwrap();
+        let file = Mapped::open(&path).unwrap();
+        let dialect = Dialect::default();
+
+        // record numbers are not counted past the first chunk, bytes and lines are
+        let without_record_numbers = |result: Result<Record, TxError>| {
+            result.map(|mut record| {
+                if let Some(position) = record.next_position.as_mut() {
+                    position.set_record(0);
+                }
+                record
+            })
+        };
+        let serial = read(
+            csv_records(file.reader(dialect), 1)
+                .unwrap()
+                .map(without_record_numbers),
+        );
+        let parallel = read(
+            ParallelCsv::mapped(file.reader(dialect), 3, file.clone(), dialect)
+                .unwrap()
+                .map(without_record_numbers),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(input.len() > 2 << 20);
+        assert_eq!(serial.len(), 120_000);
+        assert_eq!(parallel, serial);
+        assert!(parallel[1999].contains("line: 2002,"), "{}", parallel[1999]);
+    }
+}
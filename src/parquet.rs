@@ -0,0 +1,355 @@
+use crate::{
+    decimal_from_be_bytes, timestamp, AmountUnits, ExtraColumns, OutputAmount, OutputInfo, Record,
+    TxError, TxType, OUTPUT_DECIMALS,
+};
+use ::parquet::{
+    arrow::ArrowWriter,
+    data_type::Decimal as ParquetDecimal,
+    file::{
+        reader::{FileReader, SerializedFileReader},
+        writer::InMemoryWriteableCursor,
+    },
+    record::{reader::RowIter, Field, Row},
+};
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, DecimalBuilder, Int64Array, StringArray, UInt16Array, UInt32Array,
+    },
+    datatypes::{DataType, Field as Column, Schema},
+    record_batch::RecordBatch,
+};
+use chrono::{DateTime, Utc};
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use std::{error::Error, fs::File, io::Write, path::Path, sync::Arc};
+
+/// Column names of the transaction fields, the same as the CSV headers. Only `type` and
+/// `tx` are required.
+const COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "fee",
+    "memo",
+    "evidence",
+    "timestamp",
+];
+
+/// Streams the rows of a Parquet file as records. Columns are matched to the record fields
+/// by name, case insensitive, other columns are ignored.
+pub struct ParquetReader {
+    rows: RowIter<'static>,
+}
+
+impl ParquetReader {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let reader = SerializedFileReader::new(File::open(path)?)?;
+        let schema = reader.metadata().file_metadata().schema();
+        for required in ["type", "tx"] {
+            if !schema
+                .get_fields()
+                .iter()
+                .any(|field| field.name().eq_ignore_ascii_case(required))
+            {
+                return Err(From::from(format!(
+                    "{}: parquet file without a {} column",
+                    path.display(),
+                    required
+                )));
+            }
+        }
+        Ok(ParquetReader {
+            rows: RowIter::from_file_into(Box::new(reader)),
+        })
+    }
+}
+
+impl Iterator for ParquetReader {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next().map(|row| to_record(&row))
+    }
+}
+
+fn to_record(row: &Row) -> Result<Record, TxError> {
+    let mut fields: [Option<&Field>; 8] = [None; 8];
+    for (name, field) in row.get_column_iter() {
+        if let Some(at) = COLUMNS
+            .iter()
+            .position(|column| name.eq_ignore_ascii_case(column))
+        {
+            fields[at] = Some(field);
+        }
+    }
+    let [tx_type, client, tx, amount, fee, memo, evidence, timestamp] = fields;
+    let tx_type = tx_type
+        .and_then(text)
+        .ok_or_else(|| TxError::malformed("parquet row without a transaction type"))?;
+    let tx = tx
+        .and_then(integer)
+        .and_then(|tx| u32::try_from(tx).ok())
+        .ok_or_else(|| TxError::malformed(format!("parquet row with invalid tx id: {:?}", tx)))?;
+    Ok(Record {
+        tx_type: TxType::from(tx_type),
+        // like the CSV reader, invalid optional values are treated as missing
+        client: client
+            .and_then(integer)
+            .and_then(|client| u16::try_from(client).ok()),
+        tx,
+        amount: amount.and_then(decimal),
+        fee: fee.and_then(decimal),
+        memo: memo.and_then(text),
+        evidence: evidence.and_then(text),
+        timestamp: timestamp.and_then(datetime),
+        position: 0,
+        line: 0,
+        sequence: 0,
+        next_position: None,
+        tags: Vec::new(),
+    })
+}
+
+fn text(field: &Field) -> Option<String> {
+    match field {
+        Field::Str(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        _ => None,
+    }
+}
+
+/// Timestamps may be parquet timestamps, RFC 3339 strings or epoch millis.
+fn datetime(field: &Field) -> Option<DateTime<Utc>> {
+    match *field {
+        Field::TimestampMillis(millis) => timestamp::from_millis(i64::try_from(millis).ok()?),
+        Field::TimestampMicros(micros) => {
+            timestamp::from_millis(i64::try_from(micros / 1000).ok()?)
+        }
+        Field::Str(ref s) => timestamp::parse(s),
+        ref field => integer(field).and_then(timestamp::from_millis),
+    }
+}
+
+fn integer(field: &Field) -> Option<i64> {
+    match *field {
+        Field::Byte(i) => Some(i64::from(i)),
+        Field::Short(i) => Some(i64::from(i)),
+        Field::Int(i) => Some(i64::from(i)),
+        Field::Long(i) => Some(i),
+        Field::UByte(i) => Some(i64::from(i)),
+        Field::UShort(i) => Some(i64::from(i)),
+        Field::UInt(i) => Some(i64::from(i)),
+        Field::ULong(i) => i64::try_from(i).ok(),
+        Field::Str(ref s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Amounts may be parquet decimals, decimal strings, integers or floats.
+fn decimal(field: &Field) -> Option<Decimal> {
+    match field {
+        Field::Decimal(value) => from_parquet_decimal(value),
+        Field::Str(s) => s.trim().parse().ok(),
+        Field::Float(f) => Decimal::from_f32(*f),
+        Field::Double(f) => Decimal::from_f64(*f),
+        field => integer(field).map(Decimal::from),
+    }
+}
+
+fn from_parquet_decimal(value: &ParquetDecimal) -> Option<Decimal> {
+    decimal_from_be_bytes(value.data(), u32::try_from(value.scale()).ok()?)
+}
+
+/// Digits of major unit amounts in the output, the most a 128-bit decimal holds.
+const AMOUNT_PRECISION: usize = 38;
+
+/// Writes the balances snapshot as a Parquet file with the same columns as the CSV one.
+/// Major unit amounts are `decimal(38, 4)`, minor unit amounts `int64`. The file is built in
+/// memory, as the writer needs to seek and `out` may be stdout; it is at most one row
+/// per client id.
+pub(crate) fn write_balances<W: Write>(
+    mut out: W,
+    rows: &[OutputInfo],
+    extra: ExtraColumns,
+    units: AmountUnits,
+) -> Result<(), Box<dyn Error>> {
+    let amount_type = match units {
+        AmountUnits::Major { .. } => DataType::Decimal(AMOUNT_PRECISION, OUTPUT_DECIMALS as usize),
+        AmountUnits::Minor { .. } => DataType::Int64,
+    };
+    let mut columns = vec![Column::new("client", DataType::UInt16, false)];
+    let mut arrays: Vec<ArrayRef> = vec![Arc::new(UInt16Array::from_iter_values(
+        rows.iter().map(|row| row.client),
+    ))];
+    let amounts =
+        |amount: fn(&OutputInfo) -> OutputAmount| rows.iter().map(move |row| Some(amount(row)));
+    for (name, amount) in [
+        ("available", amounts(|row| row.available)),
+        ("held", amounts(|row| row.held)),
+        ("total", amounts(|row| row.total)),
+    ] {
+        columns.push(Column::new(name, amount_type.clone(), false));
+        arrays.push(amount_array(amount, units)?);
+    }
+    columns.push(Column::new("locked", DataType::Boolean, false));
+    arrays.push(Arc::new(BooleanArray::from(
+        rows.iter().map(|row| row.locked).collect::<Vec<_>>(),
+    )));
+    if extra.aliases {
+        columns.push(Column::new("aliases", DataType::Utf8, true));
+        arrays.push(Arc::new(
+            rows.iter()
+                .map(|row| row.aliases.as_deref())
+                .collect::<StringArray>(),
+        ));
+    }
+    if extra.fees {
+        columns.push(Column::new("fees", amount_type.clone(), true));
+        arrays.push(amount_array(rows.iter().map(|row| row.fees), units)?);
+    }
+    if extra.withdrawal_usage {
+        columns.push(Column::new("withdrawals_24h", DataType::UInt32, true));
+        arrays.push(Arc::new(
+            rows.iter()
+                .map(|row| row.withdrawals_24h)
+                .collect::<UInt32Array>(),
+        ));
+        columns.push(Column::new("withdrawn_24h", amount_type, true));
+        arrays.push(amount_array(
+            rows.iter().map(|row| row.withdrawn_24h),
+            units,
+        )?);
+    }
+    if extra.lock_reason {
+        columns.push(Column::new("lock_reason", DataType::Utf8, true));
+        arrays.push(Arc::new(
+            rows.iter()
+                .map(|row| row.lock_reason.as_deref())
+                .collect::<StringArray>(),
+        ));
+    }
+
+    let schema = Arc::new(Schema::new(columns));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+    let cursor = InMemoryWriteableCursor::default();
+    let mut writer = ArrowWriter::try_new(cursor.clone(), schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    out.write_all(&cursor.data())?;
+    out.flush()?;
+    Ok(())
+}
+
+fn amount_array(
+    amounts: impl Iterator<Item = Option<OutputAmount>>,
+    units: AmountUnits,
+) -> Result<ArrayRef, Box<dyn Error>> {
+    match units {
+        AmountUnits::Major { .. } => {
+            let mut builder = DecimalBuilder::new(0, AMOUNT_PRECISION, OUTPUT_DECIMALS as usize);
+            for amount in amounts {
+                match amount {
+                    // already rescaled to the output decimals, so the mantissa is the value
+                    Some(OutputAmount::Major(amount)) => builder.append_value(amount.mantissa())?,
+                    _ => builder.append_null()?,
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        AmountUnits::Minor { .. } => Ok(Arc::new(
+            amounts
+                .map(|amount| match amount {
+                    Some(OutputAmount::Minor(amount)) => Some(amount),
+                    _ => None,
+                })
+                .collect::<Int64Array>(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::parquet::util::cursor::SliceableCursor;
+
+    #[test]
+    fn balances_read_back_with_their_columns_and_exact_amounts() {
+        let row = OutputInfo {
+            client: 7,
+            available: OutputAmount::Major("1.5000".parse().unwrap()),
+            held: OutputAmount::Major("0.0000".parse().unwrap()),
+            total: OutputAmount::Major("1.5000".parse().unwrap()),
+            locked: true,
+            aliases: None,
+            fees: None,
+            withdrawals_24h: None,
+            withdrawn_24h: None,
+            lock_reason: Some("chargeback".to_string()),
+        };
+        let extra = ExtraColumns {
+            lock_reason: true,
+            ..ExtraColumns::default()
+        };
+        let mut out = Vec::new();
+        write_balances(&mut out, &[row], extra, AmountUnits::default()).unwrap();
+        let reader = SerializedFileReader::new(SliceableCursor::new(out)).unwrap();
+        let rows: Vec<Row> = reader.get_row_iter(None).unwrap().collect();
+        assert_eq!(rows.len(), 1);
+        let columns: Vec<(&str, &Field)> = rows[0]
+            .get_column_iter()
+            .map(|(name, field)| (name.as_str(), field))
+            .collect();
+        let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            [
+                "client",
+                "available",
+                "held",
+                "total",
+                "locked",
+                "lock_reason"
+            ]
+        );
+        assert_eq!(integer(columns[0].1), Some(7));
+        assert_eq!(decimal(columns[1].1), Some("1.5".parse().unwrap()));
+        assert_eq!(columns[4].1, &Field::Bool(true));
+        assert_eq!(text(columns[5].1), Some("chargeback".to_string()));
+    }
+
+    #[test]
+    fn amounts_of_any_numeric_column_are_exact() {
+        let amount = |field: Field| decimal(&field).map(|amount| amount.to_string());
+        assert_eq!(
+            amount(Field::Decimal(ParquetDecimal::from_i32(-15, 5, 1))),
+            Some("-1.5".to_string())
+        );
+        assert_eq!(
+            amount(Field::Decimal(ParquetDecimal::from_i64(123_456, 18, 4))),
+            Some("12.3456".to_string())
+        );
+        assert_eq!(
+            amount(Field::Str(" 2.50 ".to_string())),
+            Some("2.50".to_string())
+        );
+        assert_eq!(amount(Field::Long(7)), Some("7".to_string()));
+        assert_eq!(amount(Field::Str("x".to_string())), None);
+    }
+
+    #[test]
+    fn timestamps_are_parquet_timestamps_strings_or_millis() {
+        let expected = timestamp::parse("2022-03-01T09:00:00Z");
+        assert_eq!(
+            datetime(&Field::TimestampMillis(1_646_125_200_000)),
+            expected
+        );
+        assert_eq!(
+            datetime(&Field::TimestampMicros(1_646_125_200_000_000)),
+            expected
+        );
+        assert_eq!(
+            datetime(&Field::Str("2022-03-01T09:00:00Z".to_string())),
+            expected
+        );
+        assert_eq!(datetime(&Field::Long(1_646_125_200_000)), expected);
+    }
+}
@@ -0,0 +1,781 @@
+use crate::{
+    dedupe::SeenTx,
+    engine::Engine,
+    events::{self, AccountMark, EngineEvent, RejectReason, Subscribers},
+    funding::FundingLinks,
+    id_allocator::{IdAllocator, IdStrategy},
+    invariants::{InvariantCheck, OnViolation},
+    policy::{MalformedRow, Policy},
+    store::{Paging, StateStore},
+    transforms::{AmountCheck, DuplicateCheck, DuplicateRejects, MissingClientCheck},
+    wal::{self, AsOf, Logged, Wal, WalEntry},
+    Balance, ClientInfo, Outcome, Record, TxError,
+};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+};
+
+/// The accounts of a run and what it keeps from one record to the next, shared by every
+/// stage of a pipeline.
+#[derive(Default)]
+pub struct State {
+    pub client_map: HashMap<u16, ClientInfo>,
+    /// records read so far, dropped ones included
+    pub processed: u64,
+    /// whether any record had a fee, which adds the `fees` column to the balances
+    pub fees_seen: bool,
+    /// sequence number of the last accepted record, 0 before the first
+    pub sequence: u64,
+    /// where the record after the last one read starts in CSV file input
+    pub next_position: Option<csv::Position>,
+    /// time of the run, the timestamp of the last record that had one
+    pub clock: Option<DateTime<Utc>>,
+    /// accounts the current record changed, handed to the sinks once it is done
+    pub touched: Vec<u16>,
+    /// events of the current record, published to the subscribers once it is done
+    pub events: Vec<EngineEvent>,
+    /// clients whose accounts are only in the state store, not in `client_map`
+    pub(crate) paged_out: HashSet<u16>,
+    /// rows of the `audit` table of query-sql
+    #[cfg(feature = "sql")]
+    pub audit: Option<crate::sql::AuditTrail>,
+}
+
+impl State {
+    /// Account of the client of a record, if it has one.
+    pub fn account(&self, client: Option<u16>) -> Option<&ClientInfo> {
+        client.and_then(|client| self.client_map.get(&client))
+    }
+}
+
+/// Where records come from. Every iterator of records is a source, so the file readers plug
+/// in as they are.
+pub trait Source {
+    fn next_record(&mut self) -> Option<Result<Record, TxError>>;
+}
+
+impl<I: Iterator<Item = Result<Record, TxError>>> Source for I {
+    fn next_record(&mut self) -> Option<Result<Record, TxError>> {
+        self.next()
+    }
+}
+
+/// A step between the source and the handlers: checks, rewrites or drops records.
+pub trait Transform {
+    /// Returns the record to pass on, `None` drops it. Errors abort the run.
+    fn apply(&mut self, record: Record, state: &mut State) -> Result<Option<Record>, TxError>;
+
+    /// Called once the record passed on by `apply` went through its handler, with its
+    /// client id and whether the handler applied or refused it.
+    fn applied(
+        &mut self,
+        _client: Option<u16>,
+        _outcome: Outcome,
+        _state: &mut State,
+    ) -> Result<(), TxError> {
+        Ok(())
+    }
+
+    /// Called for a record replayed from the WAL once its handler applied it. Replayed
+    /// records went through `apply` in the run that logged them, not again.
+    fn replayed(&mut self, _record: &Record, _state: &mut State) -> Result<(), TxError> {
+        Ok(())
+    }
+
+    /// Called once the input ends.
+    fn finish(&mut self) -> Result<(), TxError> {
+        Ok(())
+    }
+
+    /// Reason given for the records this transform drops.
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::Filtered
+    }
+}
+
+/// Receives the results: called for every account a record changed, and once at the end.
+pub trait Sink {
+    fn applied(&mut self, _client: u16, _state: &State) -> Result<(), TxError> {
+        Ok(())
+    }
+
+    fn finish(&mut self, state: &State) -> Result<(), TxError>;
+}
+
+/// Wires a source, transforms and sinks around an [`Engine`].
+///
+/// Records go through the missing client, missing amount and duplicate tx checks of the
+/// policy first, then through the transforms in the order they were added.
+#[derive(Default)]
+pub struct PipelineBuilder<'a> {
+    source: Option<Box<dyn Source + Send + 'a>>,
+    transforms: Vec<Box<dyn Transform + Send + 'a>>,
+    sinks: Vec<Box<dyn Sink + Send + 'a>>,
+    policy: Policy,
+    duplicate_rejects: Option<DuplicateRejects>,
+    seen_tx: Option<Box<dyn SeenTx + Send>>,
+    client_ids: Option<Box<dyn IdAllocator + Send>>,
+    funding_links: FundingLinks,
+    suspense_account: Option<u16>,
+    dispute_sla: Option<Duration>,
+    subscribers: Subscribers,
+    state: State,
+    store: Option<(Box<dyn StateStore + Send + 'a>, usize)>,
+    wal: Option<Wal>,
+    replay: Option<PathBuf>,
+    as_of: Option<AsOf>,
+    check_invariants: Option<OnViolation>,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    pub fn new() -> Self {
+        PipelineBuilder::default()
+    }
+
+    pub fn source(mut self, source: impl Source + Send + 'a) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Transforms run in the order they are added.
+    pub fn transform(mut self, transform: impl Transform + Send + 'a) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn sink(mut self, sink: impl Sink + Send + 'a) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// The policy matrix, lenient by default.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Where the `reject` duplicate tx policy writes the duplicates it skips.
+    pub fn duplicate_rejects(mut self, rejects: Option<DuplicateRejects>) -> Self {
+        self.duplicate_rejects = rejects;
+        self
+    }
+
+    /// Where the duplicate tx policy keeps the tx ids it has seen, a map in memory by default.
+    pub fn seen_tx(mut self, seen: Box<dyn SeenTx + Send>) -> Self {
+        self.seen_tx = Some(seen);
+        self
+    }
+
+    /// Picks the ids of the `generate` missing client policy, random ids seeded with 0 by
+    /// default.
+    pub fn client_ids(mut self, ids: Box<dyn IdAllocator + Send>) -> Self {
+        self.client_ids = Some(ids);
+        self
+    }
+
+    /// Sweeps the shortfall of a withdrawal from the funding client before it is applied.
+    pub fn funding_links(mut self, funding_links: FundingLinks) -> Self {
+        self.funding_links = funding_links;
+        self
+    }
+
+    /// Internal account chargeback funds are booked to. It is opened when the pipeline is
+    /// built, so it is always in the output and never handed out as a generated client id.
+    pub fn suspense_account(mut self, suspense_account: Option<u16>) -> Self {
+        self.suspense_account = suspense_account;
+        self
+    }
+
+    /// Disputes still open `sla` after they were opened are resolved.
+    pub fn dispute_sla(mut self, sla: Option<Duration>) -> Self {
+        self.dispute_sla = sla;
+        self
+    }
+
+    /// Returns a receiver of every event of the run, in the order they happen. The channel
+    /// is unbounded and closes when the pipeline is dropped.
+    pub fn subscribe(&mut self) -> Receiver<EngineEvent> {
+        self.subscribers.subscribe()
+    }
+
+    /// Starts from `state` instead of an empty one, e.g. the one an earlier run left.
+    pub fn state(mut self, state: State) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Keeps the accounts in `store`, with at most `cache` of them in memory, and continues
+    /// from what it holds: the counters and accounts of `state` are replaced by the stored
+    /// ones before the first record. The sinks still get every account at the end of the
+    /// run.
+    #[cfg_attr(not(any(feature = "sled", feature = "sqlite")), allow(dead_code))]
+    pub fn store(mut self, store: impl StateStore + Send + 'a, cache: usize) -> Self {
+        self.store = Some((Box::new(store), cache));
+        self
+    }
+
+    /// Logs every record to `wal` before the engine applies it. The run starts by replaying
+    /// the records of the log the state does not have yet.
+    pub fn wal(mut self, wal: Wal) -> Self {
+        self.replay = Some(wal.path().to_path_buf());
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Starts by replaying the records of the log at `path` the state does not have yet, or
+    /// those up to `as_of`, without logging anything, e.g. to rebuild the state from the log
+    /// alone.
+    pub fn replay(mut self, path: PathBuf, as_of: Option<AsOf>) -> Self {
+        self.replay = Some(path);
+        self.as_of = as_of;
+        self
+    }
+
+    /// Checks the balance invariants of the accounts every record touched once it is applied,
+    /// see [`InvariantCheck`]. `None` leaves them unchecked.
+    pub fn check_invariants(mut self, on_violation: Option<OnViolation>) -> Self {
+        self.check_invariants = on_violation;
+        self
+    }
+
+    pub fn build(self) -> Pipeline<'a> {
+        let invariants = self
+            .check_invariants
+            .map(|on_violation| InvariantCheck::new(on_violation, self.policy.locked_account));
+        let mut transforms: Vec<Box<dyn Transform + Send + 'a>> = vec![
+            Box::new(MissingClientCheck::new(
+                self.policy.missing_client,
+                self.client_ids
+                    .unwrap_or_else(|| IdStrategy::default().allocator(0)),
+            )),
+            Box::new(AmountCheck(self.policy.missing_amount)),
+            Box::new(DuplicateCheck::new(
+                self.policy.duplicate_tx,
+                self.duplicate_rejects,
+                self.seen_tx
+                    .unwrap_or_else(|| Box::new(HashMap::<u32, u64>::new())),
+            )),
+        ];
+        transforms.extend(self.transforms);
+        let engine = Engine::new(self.policy)
+            .with_funding_links(self.funding_links)
+            .with_dispute_sla(self.dispute_sla)
+            .with_state(self.state)
+            .with_suspense_account(self.suspense_account);
+        let paging = self.store.map(|(store, cache)| {
+            let pinned = self.suspense_account.into_iter().collect();
+            Paging::new(store, cache, pinned)
+        });
+        Pipeline {
+            source: self.source,
+            transforms,
+            sinks: self.sinks,
+            engine,
+            subscribers: self.subscribers,
+            paging,
+            wal: self.wal,
+            replay: self.replay,
+            as_of: self.as_of,
+            invariants,
+        }
+    }
+}
+
+pub struct Pipeline<'a> {
+    source: Option<Box<dyn Source + Send + 'a>>,
+    transforms: Vec<Box<dyn Transform + Send + 'a>>,
+    sinks: Vec<Box<dyn Sink + Send + 'a>>,
+    engine: Engine,
+    subscribers: Subscribers,
+    paging: Option<Paging<'a>>,
+    wal: Option<Wal>,
+    /// the log whose records the state does not have are still to be applied
+    replay: Option<PathBuf>,
+    /// the last record of the log to apply, all of them if not given
+    as_of: Option<AsOf>,
+    invariants: Option<InvariantCheck>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Pulls every record of the source through the pipeline, then finishes all stages.
+    pub fn run(mut self) -> Result<State, TxError> {
+        let mut source = self
+            .source
+            .take()
+            .ok_or_else(|| TxError::other("the pipeline has no source"))?;
+        let malformed_row = self.engine.policy().malformed_row;
+        while let Some(result) = source.next_record() {
+            let record = match (result, malformed_row) {
+                (Ok(record), _) => record,
+                (Err(TxError::ParseError { line, message }), MalformedRow::Skip) => {
+                    error!("malformed row, skipping: {}", message);
+                    self.subscribers.publish(EngineEvent::Malformed {
+                        line,
+                        error: message,
+                    });
+                    continue;
+                }
+                // unreadable rows under the abort policy, or the source failed reading
+                (Err(err), _) => return Err(err),
+            };
+            self.process(record)?;
+        }
+        self.finish()
+    }
+
+    /// Takes the next record of the run through the dispute SLA and the transforms to its
+    /// handler, then hands the accounts it changed to the sinks. Returns those accounts,
+    /// none if the record was dropped without changing any.
+    pub fn process(&mut self, mut record: Record) -> Result<Vec<u16>, TxError> {
+        self.open()?;
+        // every account the record brings into memory, to store them once it is done
+        let mut paged_in = Vec::new();
+        if let (Some(_), Some(now)) = (&self.paging, record.timestamp) {
+            // the disputes the record brings past their deadline are resolved on their accounts
+            let due = self.engine.due_disputes(now);
+            page_in(
+                &mut self.paging,
+                self.engine.state_mut(),
+                due,
+                &mut paged_in,
+            )?;
+        }
+        let resolved = self.engine.read(&mut record);
+        if let Some(wal) = self.wal.as_mut() {
+            // applied as the record moved the clock, logged before any of it is stored
+            for resolve in resolved.iter() {
+                let sequence = resolve.sequence;
+                let record = Logged::of(resolve);
+                wal.append(&WalEntry::Record { sequence, record })?;
+            }
+        }
+        // the transactions the record and the resolves can add or change
+        let mut txs: Vec<u32> = resolved.iter().map(|resolve| resolve.tx).collect();
+        txs.push(record.tx);
+        if !self.subscribers.is_empty() {
+            let state = self.engine.state_mut();
+            for resolve in resolved {
+                let info = state.account(resolve.client);
+                let mark = AccountMark::of(info);
+                let handled = events::handled(&resolve, Outcome::Applied, &mark, info);
+                state.events.extend(handled);
+            }
+        }
+        let observed = (!self.subscribers.is_empty()).then(|| record.clone());
+
+        let mut passed = Some(record);
+        let mut reason = RejectReason::Filtered;
+        for transform in self.transforms.iter_mut() {
+            passed = match passed {
+                Some(record) => {
+                    // again for every transform, as they can change the client
+                    let state = self.engine.state_mut();
+                    page_in(&mut self.paging, state, record.client, &mut paged_in)?;
+                    transform.apply(record, self.engine.state_mut())?
+                }
+                None => break,
+            };
+            if passed.is_none() {
+                reason = transform.reject_reason();
+            }
+        }
+        match (passed, observed) {
+            (Some(record), _) => {
+                let accounts = self.engine.accounts_of(&record);
+                let state = self.engine.state_mut();
+                page_in(&mut self.paging, state, accounts, &mut paged_in)?;
+                self.handle(record)?
+            }
+            (None, Some(original)) => {
+                let rejected = events::rejected(&original, reason);
+                self.engine.state_mut().events.push(rejected);
+            }
+            (None, None) => {}
+        }
+        let state = self.engine.state_mut();
+        for event in state.events.drain(..) {
+            self.subscribers.publish(event);
+        }
+
+        let touched = mem::take(&mut state.touched);
+        for client in touched.iter() {
+            for sink in self.sinks.iter_mut() {
+                sink.applied(*client, self.engine.state())?;
+            }
+        }
+        // also when the record was dropped, a check can still have changed the account,
+        // e.g. frozen it
+        if let Some(paging) = self.paging.as_mut() {
+            paging.write(self.engine.state_mut(), &paged_in, &txs)?;
+        }
+        Ok(touched)
+    }
+
+    /// Hands a record that passed every transform to the engine, noting the events of what
+    /// its handler did with it.
+    fn handle(&mut self, record: Record) -> Result<(), TxError> {
+        let client = record.client;
+        let accounts = self.engine.accounts_of(&record);
+        if let Some(wal) = self.wal.as_mut() {
+            let sequence = self.engine.state().sequence + 1;
+            wal.append(&WalEntry::Record {
+                sequence,
+                record: Logged::of(&record),
+            })?;
+        }
+        let checked = self.invariants.as_mut().map(|check| {
+            check.before(self.engine.state(), &accounts);
+            record.clone()
+        });
+        let observed = (!self.subscribers.is_empty()).then(|| {
+            let mark = AccountMark::of(self.engine.state().account(client));
+            (record.clone(), mark)
+        });
+        let outcome = self.engine.accept(record)?;
+        if let (Some(check), Some(record)) = (self.invariants.as_ref(), checked) {
+            check.after(self.engine.state(), &record, &accounts)?;
+        }
+        let state = self.engine.state_mut();
+        if let Some((mut record, mark)) = observed {
+            record.sequence = state.sequence;
+            let handled = events::handled(&record, outcome, &mark, state.account(client));
+            state.events.extend(handled);
+        }
+        for transform in self.transforms.iter_mut() {
+            transform.applied(client, outcome, state)?;
+        }
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&effect(state, &accounts))?;
+        }
+        Ok(())
+    }
+
+    /// Logs the records from here on to `wal`, after replaying those of it the state does not
+    /// have yet. For a pipeline that already applied the records it starts from, e.g. the
+    /// input of a server.
+    #[cfg_attr(not(any(feature = "http", feature = "grpc")), allow(dead_code))]
+    pub fn log_to(&mut self, wal: Wal) {
+        self.replay = Some(wal.path().to_path_buf());
+        self.wal = Some(wal);
+    }
+
+    /// Takes the counters and pinned accounts from the state store and replays the WAL, the
+    /// first time only.
+    fn open(&mut self) -> Result<(), TxError> {
+        if let Some(paging) = self.paging.as_mut() {
+            paging.open(self.engine.state_mut())?;
+        }
+        if let Some(path) = self.replay.take() {
+            self.replay(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the records of the WAL at `path` after the last one in the state, as the run
+    /// that logged them did, and checks their logged effects. They reach the sinks and
+    /// [`Transform::replayed`], not the subscribers.
+    fn replay(&mut self, path: &Path) -> Result<(), TxError> {
+        let mut replayed = 0;
+        let mut last = None;
+        for entry in wal::read(path)? {
+            match entry? {
+                WalEntry::Record { sequence, record } => {
+                    let state = self.engine.state_mut();
+                    if sequence <= state.sequence {
+                        continue;
+                    }
+                    if self
+                        .as_of
+                        .is_some_and(|as_of| as_of.is_past(sequence, &record))
+                    {
+                        break;
+                    }
+                    if sequence != state.sequence + 1 {
+                        return Err(TxError::other(format!(
+                            "the WAL {} continues at record {}, the state ends at {}",
+                            path.display(),
+                            sequence,
+                            state.sequence
+                        )));
+                    }
+                    let record = record.into_record();
+                    state.processed = state.processed.max(record.position);
+                    let txs = [record.tx];
+                    let mut paged_in = Vec::new();
+                    let accounts = self.engine.accounts_of(&record);
+                    let state = self.engine.state_mut();
+                    page_in(
+                        &mut self.paging,
+                        state,
+                        accounts.iter().copied(),
+                        &mut paged_in,
+                    )?;
+                    let logged = record.clone();
+                    if let Some(check) = self.invariants.as_mut() {
+                        check.before(self.engine.state(), &accounts);
+                    }
+                    // the run that logged it failed the same way, and went no further
+                    let outcome = self.engine.accept(record);
+                    if let Some(check) = self.invariants.as_ref() {
+                        check.after(self.engine.state(), &logged, &accounts)?;
+                    }
+                    match outcome {
+                        Ok(Outcome::Applied) => {
+                            let state = self.engine.state_mut();
+                            for transform in self.transforms.iter_mut() {
+                                transform.replayed(&logged, state)?;
+                            }
+                        }
+                        Ok(Outcome::Refused(_)) => {}
+                        Err(err) => error!("replaying record {} of the WAL: {}", sequence, err),
+                    }
+                    let state = self.engine.state_mut();
+                    let touched = mem::take(&mut state.touched);
+                    for client in touched {
+                        for sink in self.sinks.iter_mut() {
+                            sink.applied(client, self.engine.state())?;
+                        }
+                    }
+                    if let Some(paging) = self.paging.as_mut() {
+                        paging.write(self.engine.state_mut(), &paged_in, &txs)?;
+                    }
+                    replayed += 1;
+                    last = Some(sequence);
+                }
+                WalEntry::Effect { sequence, balances } if last == Some(sequence) => {
+                    // loaded to compare, the next write pages them out again
+                    let clients = balances.iter().map(|balance| balance.client);
+                    let state = self.engine.state_mut();
+                    page_in(&mut self.paging, state, clients, &mut Vec::new())?;
+                    for divergence in wal::diverged(state, &balances) {
+                        error!(
+                            "record {} of the WAL replayed differently, {}",
+                            sequence, divergence
+                        );
+                    }
+                }
+                WalEntry::Effect { .. } => {}
+            }
+        }
+        if replayed > 0 {
+            info!(
+                "replayed {} records from the WAL {}",
+                replayed,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Finishes the transforms, then the sinks with the final state. A state store is read
+    /// back whole first, for the sinks that report on every account.
+    pub fn finish(mut self) -> Result<State, TxError> {
+        self.open()?;
+        if let Some(paging) = self.paging.as_mut() {
+            paging.finish(self.engine.state_mut())?;
+            // every record of the log is in the store now
+            if let Some(wal) = self.wal.as_mut() {
+                wal.truncate()?;
+            }
+        }
+        for transform in self.transforms.iter_mut() {
+            transform.finish()?;
+        }
+        for sink in self.sinks.iter_mut() {
+            sink.finish(self.engine.state())?;
+        }
+        Ok(self.engine.into_state())
+    }
+
+    #[cfg(any(test, feature = "test-util", feature = "http"))]
+    pub fn state(&self) -> &State {
+        self.engine.state()
+    }
+
+    /// The state as it is, without finishing any stage.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn into_state(self) -> State {
+        self.engine.into_state()
+    }
+}
+
+/// The balances a record left on the accounts it could touch, for the WAL.
+fn effect(state: &State, accounts: &[u16]) -> WalEntry {
+    let balances = accounts
+        .iter()
+        .filter_map(|client| {
+            let info = state.client_map.get(client)?;
+            Some(Balance::of(*client, info))
+        })
+        .collect();
+    WalEntry::Effect {
+        sequence: state.sequence,
+        balances,
+    }
+}
+
+/// Loads the accounts of `clients` from the state store, if there is one.
+fn page_in(
+    paging: &mut Option<Paging>,
+    state: &mut State,
+    clients: impl IntoIterator<Item = u16>,
+    paged_in: &mut Vec<u16>,
+) -> Result<(), TxError> {
+    if let Some(paging) = paging {
+        for client in clients {
+            paging.load(state, client)?;
+            if !paged_in.contains(&client) {
+                paged_in.push(client);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::{DisputeOutcome, RejectReason},
+        locks::LockReason,
+        policy::DuplicateTx,
+        transactions::TxState,
+    };
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn accepted_records_and_auto_resolves_are_numbered_without_gaps() {
+        let policy = Policy {
+            duplicate_tx: DuplicateTx::Skip,
+            ..Policy::default()
+        };
+        let mut builder = PipelineBuilder::new()
+            .policy(policy)
+            .dispute_sla(Some(Duration::days(1)));
+        let events = builder.subscribe();
+        let mut pipeline = builder.build();
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(
+            "type,client,tx,amount,timestamp
+                deposit,1,1,10,2022-03-01T00:00:00Z
+                deposit,1,1,10,2022-03-01T00:00:00Z
+                dispute,1,1,,2022-03-01T01:00:00Z
+                deposit,2,2,5,2022-03-03T00:00:00Z"
+                .as_bytes(),
+        );
+        for record in reader.deserialize() {
+            pipeline.process(record.unwrap()).unwrap();
+        }
+        let state = pipeline.into_state();
+        let applied: Vec<(u16, String, u64)> = events
+            .iter()
+            .filter_map(|event| match event {
+                EngineEvent::Applied {
+                    sequence,
+                    client,
+                    tx_type,
+                    ..
+                } => Some((client, tx_type, sequence)),
+                _ => None,
+            })
+            .collect();
+        // the duplicate was dropped, the dispute resolved once the deposit of client 2 told
+        // the time
+        assert_eq!(
+            applied,
+            vec![
+                (1, "deposit".to_string(), 1),
+                (1, "dispute".to_string(), 2),
+                (1, "resolve".to_string(), 3),
+                (2, "deposit".to_string(), 4),
+            ]
+        );
+        assert_eq!(state.sequence, 4);
+    }
+
+    #[test]
+    fn subscribers_get_the_events_of_every_record() {
+        let policy = Policy {
+            duplicate_tx: DuplicateTx::Skip,
+            ..Policy::default()
+        };
+        let mut builder = PipelineBuilder::new()
+            .policy(policy)
+            .suspense_account(Some(9));
+        let events = builder.subscribe();
+        let mut pipeline = builder.build();
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(
+            "type,client,tx,amount
+                deposit,1,1,10
+                deposit,1,1,10
+                withdrawal,1,2,20
+                resolve,1,1,
+                dispute,1,1,
+                chargeback,1,1,"
+                .as_bytes(),
+        );
+        for record in reader.deserialize() {
+            pipeline.process(record.unwrap()).unwrap();
+        }
+        drop(pipeline);
+        let rejected =
+            |position, tx, tx_type: &str, amount: Option<u32>, reason| EngineEvent::Rejected {
+                position,
+                line: 0,
+                client: Some(1),
+                tx,
+                tx_type: tx_type.to_string(),
+                amount: amount.map(Decimal::from),
+                fee: None,
+                timestamp: None,
+                reason,
+            };
+        let applied = |sequence, tx_type: &str| EngineEvent::Applied {
+            sequence,
+            client: 1,
+            tx: 1,
+            tx_type: tx_type.to_string(),
+            amount: Some(Decimal::from(10)),
+            tags: Vec::new(),
+            timestamp: None,
+        };
+        assert_eq!(
+            events.iter().collect::<Vec<EngineEvent>>(),
+            vec![
+                applied(1, "deposit"),
+                // dropped by the duplicate check
+                rejected(2, 1, "deposit", Some(10), RejectReason::DuplicateTx),
+                rejected(
+                    3,
+                    2,
+                    "withdrawal",
+                    Some(20),
+                    RejectReason::InsufficientFunds
+                ),
+                rejected(4, 1, "resolve", None, RejectReason::InvalidTransition),
+                EngineEvent::InvalidTransition {
+                    client: 1,
+                    tx: 1,
+                    state: TxState::Normal,
+                    tx_type: "resolve".to_string(),
+                },
+                applied(4, "dispute"),
+                EngineEvent::DisputeOpened { client: 1, tx: 1 },
+                applied(5, "chargeback"),
+                EngineEvent::DisputeClosed {
+                    client: 1,
+                    tx: 1,
+                    outcome: DisputeOutcome::ChargedBack
+                },
+                EngineEvent::AccountLocked {
+                    client: 1,
+                    reason: LockReason::Chargeback { tx: 1 }
+                },
+            ]
+        );
+    }
+}
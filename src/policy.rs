@@ -0,0 +1,271 @@
+use serde::{de::value::StrDeserializer, de::DeserializeOwned, Deserialize, Serialize};
+use std::{error::Error, fs, path::Path};
+
+/// Named presets for the whole policy matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    /// best effort, logs problems and keeps going. The default
+    Lenient,
+    /// aborts the run on the first problem
+    Strict,
+    /// follows the original task spec, problem records are skipped and never stored
+    Spec,
+}
+
+/// Records without a client id.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingClient {
+    /// generate a new unique client id for the record
+    Generate,
+    Skip,
+    Abort,
+}
+
+/// Records with a type other than deposit, withdrawal, dispute, resolve or chargeback.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownType {
+    Skip,
+    Abort,
+}
+
+/// Deposits and withdrawals with a missing or malformed amount.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingAmount {
+    /// keep the transaction without changing balances
+    Record,
+    Skip,
+    Abort,
+}
+
+/// Deposits and withdrawals reusing a tx id already seen in this run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateTx {
+    /// apply it again
+    Allow,
+    Skip,
+    Abort,
+    /// skip it and write it to the duplicate rejects file
+    Reject,
+}
+
+/// Input rows that can not be read as a record, e.g. a non numeric tx id.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MalformedRow {
+    /// log it and read on
+    Skip,
+    Abort,
+}
+
+/// Records a handler refuses: overdrafts, unknown txs, locked account activity and
+/// invalid dispute steps.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Refused {
+    /// log it and leave the balances as they are
+    Log,
+    Abort,
+}
+
+/// Activity on accounts locked by a chargeback.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockedAccount {
+    /// reject everything
+    Reject,
+    /// still allow resolve/chargeback on disputes that were open when the account got locked
+    SettleOpenDisputes,
+}
+
+/// Every behavioural choice of the transaction handlers in one place.
+///
+/// | setting          | lenient (default) | strict | spec  |
+/// |------------------|-------------------|--------|-------|
+/// | `missing_client` | generate          | abort  | skip  |
+/// | `unknown_type`   | skip              | abort  | skip  |
+/// | `missing_amount` | record            | abort  | skip  |
+/// | `duplicate_tx`   | allow             | abort  | skip  |
+/// | `locked_account` | reject            | reject | reject|
+/// | `malformed_row`  | skip              | abort  | skip  |
+/// | `refused`        | log               | abort  | log   |
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Policy {
+    /// preset the settings started from
+    pub profile: Profile,
+    pub missing_client: MissingClient,
+    pub unknown_type: UnknownType,
+    pub missing_amount: MissingAmount,
+    pub duplicate_tx: DuplicateTx,
+    pub locked_account: LockedAccount,
+    pub malformed_row: MalformedRow,
+    pub refused: Refused,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::from_profile(Profile::Lenient)
+    }
+}
+
+impl Policy {
+    pub fn from_profile(profile: Profile) -> Self {
+        match profile {
+            Profile::Lenient => Policy {
+                profile,
+                missing_client: MissingClient::Generate,
+                unknown_type: UnknownType::Skip,
+                missing_amount: MissingAmount::Record,
+                duplicate_tx: DuplicateTx::Allow,
+                locked_account: LockedAccount::Reject,
+                malformed_row: MalformedRow::Skip,
+                refused: Refused::Log,
+            },
+            Profile::Strict => Policy {
+                profile,
+                missing_client: MissingClient::Abort,
+                unknown_type: UnknownType::Abort,
+                missing_amount: MissingAmount::Abort,
+                duplicate_tx: DuplicateTx::Abort,
+                locked_account: LockedAccount::Reject,
+                malformed_row: MalformedRow::Abort,
+                refused: Refused::Abort,
+            },
+            Profile::Spec => Policy {
+                profile,
+                missing_client: MissingClient::Skip,
+                unknown_type: UnknownType::Skip,
+                missing_amount: MissingAmount::Skip,
+                duplicate_tx: DuplicateTx::Skip,
+                locked_account: LockedAccount::Reject,
+                malformed_row: MalformedRow::Skip,
+                refused: Refused::Log,
+            },
+        }
+    }
+}
+
+/// Policy settings from the policy file or flags, unset ones fall back to the profile.
+///
+/// The policy file is TOML with the same keys, e.g.
+/// ```toml
+/// profile = "strict"
+/// locked_account = "settle_open_disputes"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyOverrides {
+    pub profile: Option<Profile>,
+    pub missing_client: Option<MissingClient>,
+    pub unknown_type: Option<UnknownType>,
+    pub missing_amount: Option<MissingAmount>,
+    pub duplicate_tx: Option<DuplicateTx>,
+    pub locked_account: Option<LockedAccount>,
+    pub malformed_row: Option<MalformedRow>,
+    pub refused: Option<Refused>,
+}
+
+impl PolicyOverrides {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Settings in `other` win over the ones in `self`.
+    pub fn merge(self, other: PolicyOverrides) -> Self {
+        PolicyOverrides {
+            profile: other.profile.or(self.profile),
+            missing_client: other.missing_client.or(self.missing_client),
+            unknown_type: other.unknown_type.or(self.unknown_type),
+            missing_amount: other.missing_amount.or(self.missing_amount),
+            duplicate_tx: other.duplicate_tx.or(self.duplicate_tx),
+            locked_account: other.locked_account.or(self.locked_account),
+            malformed_row: other.malformed_row.or(self.malformed_row),
+            refused: other.refused.or(self.refused),
+        }
+    }
+
+    pub fn build(&self) -> Policy {
+        let base = Policy::from_profile(self.profile.unwrap_or(Profile::Lenient));
+        Policy {
+            profile: base.profile,
+            missing_client: self.missing_client.unwrap_or(base.missing_client),
+            unknown_type: self.unknown_type.unwrap_or(base.unknown_type),
+            missing_amount: self.missing_amount.unwrap_or(base.missing_amount),
+            duplicate_tx: self.duplicate_tx.unwrap_or(base.duplicate_tx),
+            locked_account: self.locked_account.unwrap_or(base.locked_account),
+            malformed_row: self.malformed_row.unwrap_or(base.malformed_row),
+            refused: self.refused.unwrap_or(base.refused),
+        }
+    }
+}
+
+/// Parses a flag value using the same snake_case names as the policy file.
+pub fn parse_choice<T: DeserializeOwned>(value: &str) -> Result<T, Box<dyn Error>> {
+    let deserializer = StrDeserializer::<serde::de::value::Error>::new(value);
+    T::deserialize(deserializer).map_err(|err| From::from(format!("{}: {}", value, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_choices_by_their_snake_case_name() {
+        assert_eq!(parse_choice::<Profile>("spec").unwrap(), Profile::Spec);
+        assert_eq!(
+            parse_choice::<LockedAccount>("settle_open_disputes").unwrap(),
+            LockedAccount::SettleOpenDisputes
+        );
+        assert!(parse_choice::<MissingClient>("allow").is_err());
+    }
+
+    #[test]
+    fn unset_settings_fall_back_to_the_profile() {
+        let overrides = PolicyOverrides {
+            profile: Some(Profile::Strict),
+            duplicate_tx: Some(DuplicateTx::Skip),
+            ..PolicyOverrides::default()
+        };
+        assert_eq!(
+            overrides.build(),
+            Policy {
+                duplicate_tx: DuplicateTx::Skip,
+                ..Policy::from_profile(Profile::Strict)
+            }
+        );
+        assert_eq!(PolicyOverrides::default().build(), Policy::default());
+    }
+
+    #[test]
+    fn flags_win_over_the_policy_file() {
+        let path = std::env::temp_dir().join(format!("policy-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "profile = \"spec\"\nmissing_client = \"generate\"\nunknown_type = \"abort\"\n",
+        )
+        .unwrap();
+        let file = PolicyOverrides::from_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let flags = PolicyOverrides {
+            unknown_type: Some(UnknownType::Skip),
+            ..PolicyOverrides::default()
+        };
+        let policy = file.merge(flags).build();
+        assert_eq!(policy.missing_client, MissingClient::Generate);
+        assert_eq!(policy.unknown_type, UnknownType::Skip);
+        assert_eq!(policy.missing_amount, MissingAmount::Skip);
+    }
+
+    #[test]
+    fn policy_file_refuses_unknown_keys() {
+        let path = std::env::temp_dir().join(format!("policy-keys-{}.toml", std::process::id()));
+        fs::write(&path, "duplicates = \"skip\"\n").unwrap();
+        let result = PolicyOverrides::from_path(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
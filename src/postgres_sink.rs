@@ -0,0 +1,104 @@
+use crate::{
+    pipeline::{Sink, State},
+    Balance, TxError,
+};
+use log::info;
+use postgres::{Client, NoTls};
+use std::error::Error;
+
+/// Upserts the final balances into a Postgres table, created if missing, in one transaction.
+/// Accounts that are not in the run keep their rows.
+pub struct PostgresBalances {
+    client: Client,
+    table: String,
+}
+
+impl PostgresBalances {
+    /// Connects up front, so a wrong url fails the run before anything is processed.
+    pub fn connect(url: &str, table: &str) -> Result<Self, Box<dyn Error>> {
+        if !is_identifier(table) {
+            return Err(From::from(format!(
+                "{} is not a table name, use letters, digits and underscores, optionally schema qualified",
+                table
+            )));
+        }
+        Ok(PostgresBalances {
+            client: Client::connect(url, NoTls)?,
+            table: table.to_string(),
+        })
+    }
+
+    /// Writes every row in one transaction.
+    fn upsert(&mut self, state: &State) -> Result<(), postgres::Error> {
+        let mut transaction = self.client.transaction()?;
+        transaction.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                client INTEGER PRIMARY KEY,
+                available NUMERIC NOT NULL,
+                held NUMERIC NOT NULL,
+                total NUMERIC NOT NULL,
+                locked BOOLEAN NOT NULL,
+                updated_at TIMESTAMPTZ NOT NULL
+            )",
+            self.table
+        ))?;
+        let upsert = transaction.prepare(&format!(
+            "INSERT INTO {} (client, available, held, total, locked, updated_at)
+             VALUES ($1, $2, $3, $4, $5, now())
+             ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available,
+             held = EXCLUDED.held, total = EXCLUDED.total, locked = EXCLUDED.locked,
+             updated_at = EXCLUDED.updated_at",
+            self.table
+        ))?;
+        let mut clients: Vec<&u16> = state.client_map.keys().collect();
+        clients.sort();
+        for client in clients.iter() {
+            let balance = Balance::of(**client, &state.client_map[client]);
+            transaction.execute(
+                &upsert,
+                &[
+                    &i32::from(balance.client),
+                    &balance.available,
+                    &balance.held,
+                    &balance.total,
+                    &balance.locked,
+                ],
+            )?;
+        }
+        transaction.commit()?;
+        info!("upserted {} balances into {}", clients.len(), self.table);
+        Ok(())
+    }
+}
+
+/// The table name goes into the statements as is.
+fn is_identifier(name: &str) -> bool {
+    name.split('.').count() <= 2
+        && name.split('.').all(|part| {
+            part.chars()
+                .next()
+                .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+impl Sink for PostgresBalances {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        self.upsert(state).map_err(TxError::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_names_are_plain_identifiers() {
+        assert!(is_identifier("balances"));
+        assert!(is_identifier("recon.balances_2022"));
+        assert!(!is_identifier("a.b.c"));
+        assert!(!is_identifier("1balances"));
+        assert!(!is_identifier("balances; DROP TABLE accounts"));
+        assert!(!is_identifier(""));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{timestamp, Record, TxError, TxType};
+use prost::Message;
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+/// Mirrors `proto/transaction.proto`, keep the two in sync.
+#[derive(Clone, PartialEq, Message)]
+pub struct Transaction {
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    #[prost(uint32, optional, tag = "2")]
+    pub client: Option<u32>,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    #[prost(string, optional, tag = "4")]
+    pub amount: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub memo: Option<String>,
+    /// milliseconds since the unix epoch
+    #[prost(int64, optional, tag = "6")]
+    pub timestamp: Option<i64>,
+    #[prost(string, optional, tag = "7")]
+    pub fee: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub evidence: Option<String>,
+}
+
+impl From<Transaction> for Record {
+    fn from(message: Transaction) -> Self {
+        Record {
+            tx_type: TxType::from(message.r#type.trim()),
+            // like the CSV reader, invalid optional values are treated as missing
+            client: message.client.and_then(|c| u16::try_from(c).ok()),
+            tx: message.tx,
+            amount: message.amount.and_then(|a| a.trim().parse().ok()),
+            fee: message.fee.and_then(|f| f.trim().parse().ok()),
+            memo: message.memo.filter(|m| !m.is_empty()),
+            evidence: message.evidence.filter(|e| !e.is_empty()),
+            timestamp: message.timestamp.and_then(timestamp::from_millis),
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Streams length-delimited `Transaction` messages from a file.
+pub(crate) struct ProtobufReader<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl ProtobufReader<BufReader<File>> {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        Ok(ProtobufReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> ProtobufReader<R> {
+    pub fn new(reader: R) -> Self {
+        ProtobufReader {
+            reader,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Reads the varint length prefix, `None` on a clean end of input.
+    fn read_length(&mut self) -> io::Result<Option<usize>> {
+        let mut length: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8];
+            if self.reader.read(&mut byte)? == 0 {
+                return if shift == 0 {
+                    Ok(None)
+                } else {
+                    Err(io::ErrorKind::UnexpectedEof.into())
+                };
+            }
+            length |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(length as usize));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "protobuf length prefix too long",
+        ))
+    }
+
+    fn next_message(&mut self) -> Result<Option<Transaction>, TxError> {
+        let length = match self.read_length()? {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+        self.buffer.resize(length, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        Ok(Some(
+            Transaction::decode(self.buffer.as_slice()).map_err(TxError::malformed)?,
+        ))
+    }
+}
+
+impl<R: Read> Iterator for ProtobufReader<R> {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_message()
+            .transpose()
+            .map(|result| result.map(Record::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn transaction(
+        tx_type: &str,
+        client: Option<u32>,
+        tx: u32,
+        amount: Option<&str>,
+    ) -> Transaction {
+        Transaction {
+            r#type: tx_type.to_string(),
+            client,
+            tx,
+            amount: amount.map(str::to_string),
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+        }
+    }
+
+    fn encode(messages: &[Transaction]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for message in messages {
+            message.encode_length_delimited(&mut bytes).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_length_delimited_messages_as_records() {
+        let mut deposit = transaction("deposit", Some(1), 1, Some(" 1.5"));
+        deposit.memo = Some("salary".to_string());
+        deposit.timestamp = Some(1_646_136_000_000);
+        deposit.fee = Some("0.25".to_string());
+        let bytes = encode(&[deposit, transaction("dispute", Some(1), 1, None)]);
+        let records: Vec<Record> = ProtobufReader::new(bytes.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].tx_type, TxType::Deposit);
+        assert_eq!(records[0].client, Some(1));
+        assert_eq!(records[0].amount, Some(Decimal::new(15, 1)));
+        assert_eq!(records[0].fee, Some(Decimal::new(25, 2)));
+        assert_eq!(records[0].memo.as_deref(), Some("salary"));
+        assert_eq!(
+            records[0].timestamp,
+            timestamp::parse("2022-03-01T12:00:00Z")
+        );
+        assert_eq!(records[1].tx_type, TxType::Dispute);
+        assert_eq!(records[1].amount, None);
+    }
+
+    #[test]
+    fn invalid_optional_values_are_missing() {
+        let mut message = transaction("deposit", Some(70_000), 1, Some("lots"));
+        message.memo = Some(String::new());
+        let record = Record::from(message);
+        assert_eq!(record.client, None);
+        assert_eq!(record.amount, None);
+        assert_eq!(record.memo, None);
+    }
+
+    #[test]
+    fn a_message_cut_short_is_an_error() {
+        let mut bytes = encode(&[transaction("deposit", Some(1), 1, Some("1.0"))]);
+        bytes.pop();
+        let mut reader = ProtobufReader::new(bytes.as_slice());
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn empty_input_has_no_records() {
+        assert!(ProtobufReader::new(&[][..]).next().is_none());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{pipeline::State, Balance};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::BTreeSet, error::Error, io::Write};
+
+/// An account whose balances rebuilt from the WAL differ from the stored snapshot, or that
+/// only one of them has.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Divergence {
+    pub client: u16,
+    pub snapshot_available: Option<Decimal>,
+    pub snapshot_held: Option<Decimal>,
+    pub snapshot_total: Option<Decimal>,
+    pub snapshot_locked: Option<bool>,
+    pub rebuilt_available: Option<Decimal>,
+    pub rebuilt_held: Option<Decimal>,
+    pub rebuilt_total: Option<Decimal>,
+    pub rebuilt_locked: Option<bool>,
+}
+
+/// Every account of either state whose balances differ, by client.
+pub fn compare(snapshot: &State, rebuilt: &State) -> Vec<Divergence> {
+    let clients: BTreeSet<u16> = snapshot
+        .client_map
+        .keys()
+        .chain(rebuilt.client_map.keys())
+        .copied()
+        .collect();
+    clients
+        .into_iter()
+        .filter_map(|client| {
+            let stored = snapshot
+                .client_map
+                .get(&client)
+                .map(|info| Balance::of(client, info));
+            let replayed = rebuilt
+                .client_map
+                .get(&client)
+                .map(|info| Balance::of(client, info));
+            if stored == replayed {
+                return None;
+            }
+            Some(Divergence {
+                client,
+                snapshot_available: stored.as_ref().map(|b| b.available),
+                snapshot_held: stored.as_ref().map(|b| b.held),
+                snapshot_total: stored.as_ref().map(|b| b.total),
+                snapshot_locked: stored.as_ref().map(|b| b.locked),
+                rebuilt_available: replayed.as_ref().map(|b| b.available),
+                rebuilt_held: replayed.as_ref().map(|b| b.held),
+                rebuilt_total: replayed.as_ref().map(|b| b.total),
+                rebuilt_locked: replayed.as_ref().map(|b| b.locked),
+            })
+        })
+        .collect()
+}
+
+/// Writes the divergences as CSV, a missing account has empty columns.
+pub fn write<W: Write>(out: W, divergences: &[Divergence]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(out);
+    if divergences.is_empty() {
+        writer.write_record([
+            "client",
+            "snapshot_available",
+            "snapshot_held",
+            "snapshot_total",
+            "snapshot_locked",
+            "rebuilt_available",
+            "rebuilt_held",
+            "rebuilt_total",
+            "rebuilt_locked",
+        ])?;
+    }
+    for divergence in divergences {
+        writer.serialize(divergence)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "msgpack"))]
+mod tests {
+    use crate::{
+        pipeline::PipelineBuilder, rebuild_accounts, state_file, wal::Wal, Config, Record, TxError,
+    };
+    use rust_decimal::Decimal;
+    use std::{env, fs, process};
+
+    #[test]
+    fn reports_the_accounts_the_stored_state_has_wrong() {
+        let wal = env::temp_dir().join(format!("csvtest-rebuild-{}.wal", process::id()));
+        let saved = env::temp_dir().join(format!("csvtest-rebuild-{}.state", process::id()));
+        let _ = fs::remove_file(&wal);
+        let records = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(
+                "type,client,tx,amount
+                deposit,1,1,10
+                deposit,2,2,20
+                dispute,1,1,"
+                    .as_bytes(),
+            )
+            .into_deserialize::<Record>()
+            .map(|record| record.map_err(TxError::from));
+        let mut state = PipelineBuilder::new()
+            .source(records)
+            .wal(Wal::open(&wal).unwrap())
+            .build()
+            .run()
+            .unwrap();
+        let config = Config {
+            wal: Some(wal.clone()),
+            load_state: Some(saved.clone()),
+            ..Config::default()
+        };
+        let rebuild = |state: &_| {
+            state_file::write(state, fs::File::create(&saved).unwrap()).unwrap();
+            let mut out = Vec::new();
+            let result = rebuild_accounts(&config, &mut out).map_err(|err| err.to_string());
+            (result, String::from_utf8(out).unwrap())
+        };
+
+        let (result, out) = rebuild(&state);
+        assert_eq!(result, Ok(()));
+        assert_eq!(out.lines().count(), 1);
+
+        // a stored account that went wrong somewhere
+        state.client_map.get_mut(&2).unwrap().available_funds = Decimal::from(25);
+        let (result, out) = rebuild(&state);
+        assert_eq!(
+            result,
+            Err("1 accounts differ from the stored state".to_string())
+        );
+        assert_eq!(out.lines().nth(1), Some("2,25,0,20,false,20,0,20,false"));
+        fs::remove_file(&wal).unwrap();
+        fs::remove_file(&saved).unwrap();
+    }
+}
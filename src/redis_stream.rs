@@ -0,0 +1,154 @@
+use crate::{Record, TxError};
+use log::{error, info};
+use redis::{
+    streams::{StreamId, StreamReadOptions, StreamReadReply},
+    Commands, Connection, RedisResult,
+};
+use std::{collections::VecDeque, error::Error, thread, time::Duration};
+
+/// Entries read at once.
+const BATCH: usize = 100;
+/// How long a read waits for new entries, in milliseconds.
+const BLOCK_MS: usize = 5000;
+/// Pause before reading again after a failed read.
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Consumes transactions from a Redis stream as a member of a consumer group, so several
+/// instances share the stream. Entries have the CSV header names as fields, with the values
+/// as in a CSV row. An entry is acked once the pipeline asks for the next record, that is
+/// after it went through the engine and every sink. After a restart the entries delivered
+/// to the same consumer name but never acked are read again first.
+pub struct RedisSource {
+    connection: Connection,
+    stream: String,
+    group: String,
+    consumer: String,
+    /// entries read but not handed out yet
+    buffered: VecDeque<StreamId>,
+    /// id of the entry handed out last
+    pending: Option<String>,
+    /// still reading the entries delivered before a restart
+    backlog: bool,
+}
+
+impl RedisSource {
+    /// Creates the group, reading the stream from its start, when it does not exist yet.
+    pub fn new(
+        url: &str,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut connection = redis::Client::open(url)?.get_connection()?;
+        let created: RedisResult<()> = redis::cmd("XGROUP")
+            .arg("CREATE")
+            .arg(stream)
+            .arg(group)
+            .arg("0")
+            .arg("MKSTREAM")
+            .query(&mut connection);
+        match created {
+            Ok(()) => info!("created consumer group {} of stream {}", group, stream),
+            Err(err) if err.code() == Some("BUSYGROUP") => {}
+            Err(err) => return Err(From::from(err)),
+        }
+        Ok(RedisSource {
+            connection,
+            stream: stream.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            buffered: VecDeque::new(),
+            pending: None,
+            backlog: true,
+        })
+    }
+
+    fn read(&mut self) -> RedisResult<()> {
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(BATCH)
+            .block(BLOCK_MS);
+        // `0` reads this consumer's delivered but unacked entries, `>` new ones
+        let from = if self.backlog { "0" } else { ">" };
+        let reply: StreamReadReply =
+            self.connection
+                .xread_options(&[&self.stream], &[from], &options)?;
+        self.buffered
+            .extend(reply.keys.into_iter().flat_map(|key| key.ids));
+        if self.backlog && self.buffered.is_empty() {
+            self.backlog = false;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for RedisSource {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(id) = self.pending.take() {
+            let acked: RedisResult<i64> = self.connection.xack(&self.stream, &self.group, &[&id]);
+            if let Err(err) = acked {
+                error!("failed to ack entry {} of {}: {}", id, self.stream, err);
+            }
+        }
+        loop {
+            if let Some(entry) = self.buffered.pop_front() {
+                let record = to_record(&entry);
+                self.pending = Some(entry.id);
+                return Some(record);
+            }
+            if let Err(err) = self.read() {
+                error!("failed to read stream {}: {}", self.stream, err);
+                thread::sleep(RETRY_AFTER);
+            }
+        }
+    }
+}
+
+/// Reads the entry like a CSV row with the field names as headers, so missing and invalid
+/// optional values are treated the same.
+fn to_record(entry: &StreamId) -> Result<Record, TxError> {
+    let mut headers = csv::StringRecord::new();
+    let mut row = csv::StringRecord::new();
+    for (field, value) in &entry.map {
+        let value: String = redis::from_redis_value(value).map_err(TxError::malformed)?;
+        headers.push_field(field);
+        row.push_field(value.trim());
+    }
+    Ok(row.deserialize(Some(&headers))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Value;
+
+    fn entry(fields: &[(&str, &str)]) -> StreamId {
+        StreamId {
+            id: "1-0".to_string(),
+            map: fields
+                .iter()
+                .map(|(field, value)| (field.to_string(), Value::Data(value.as_bytes().to_vec())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn reads_entries_like_csv_rows() {
+        let record = to_record(&entry(&[
+            ("type", "deposit"),
+            ("client", "1"),
+            ("tx", "7"),
+            ("amount", " 1.5 "),
+        ]))
+        .unwrap();
+        assert_eq!((record.client, record.tx), (Some(1), 7));
+        assert_eq!(record.amount, Some("1.5".parse().unwrap()));
+        // a dispute has no amount field at all
+        let dispute = to_record(&entry(&[("type", "dispute"), ("client", "1"), ("tx", "7")]));
+        assert_eq!(dispute.unwrap().amount, None);
+        let err = to_record(&entry(&[("type", "deposit"), ("client", "1"), ("tx", "x")]));
+        assert!(matches!(err, Err(TxError::ParseError { .. })));
+    }
+}
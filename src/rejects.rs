@@ -0,0 +1,198 @@
+use crate::{
+    events::{EngineEvent, RejectReason},
+    pipeline::{Sink, State},
+    TxError,
+};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::mpsc::Receiver,
+};
+
+/// A rejected record or malformed row as written to the rejects file.
+#[derive(Serialize, Debug)]
+struct RejectRow {
+    /// line in CSV input, empty for other inputs
+    line: Option<u64>,
+    /// position of the record in the input, empty for malformed rows
+    position: Option<u64>,
+    #[serde(rename = "type")]
+    tx_type: Option<String>,
+    client: Option<u16>,
+    tx: Option<u32>,
+    amount: Option<Decimal>,
+    fee: Option<Decimal>,
+    timestamp: Option<DateTime<Utc>>,
+    reason: RejectReason,
+    /// parse error of a malformed row
+    detail: Option<String>,
+}
+
+enum Output {
+    Csv(Box<csv::Writer<File>>),
+    Jsonl(BufWriter<File>),
+}
+
+/// Dead letter file of every record dropped by a check or refused by its handler, and of
+/// every input row that could not be read, with a reason code. Written as JSON lines when
+/// the path ends in `.jsonl`, CSV otherwise.
+pub struct Rejects {
+    out: Output,
+    events: Receiver<EngineEvent>,
+}
+
+impl Rejects {
+    pub fn new(path: &Path, events: Receiver<EngineEvent>) -> Result<Self, TxError> {
+        let out = if path.extension().is_some_and(|ext| ext == "jsonl") {
+            Output::Jsonl(BufWriter::new(File::create(path)?))
+        } else {
+            Output::Csv(Box::new(csv::Writer::from_path(path)?))
+        };
+        Ok(Rejects { out, events })
+    }
+
+    /// Writes the rejects published since the last call, so the file keeps up with the run.
+    fn drain(&mut self) -> Result<(), TxError> {
+        for event in self.events.try_iter() {
+            let row = match event {
+                EngineEvent::Rejected {
+                    position,
+                    line,
+                    client,
+                    tx,
+                    tx_type,
+                    amount,
+                    fee,
+                    timestamp,
+                    reason,
+                } => RejectRow {
+                    line: Some(line).filter(|line| *line > 0),
+                    position: Some(position),
+                    tx_type: Some(tx_type),
+                    client,
+                    tx: Some(tx),
+                    amount,
+                    fee,
+                    timestamp,
+                    reason,
+                    detail: None,
+                },
+                EngineEvent::Malformed { line, error } => RejectRow {
+                    line,
+                    position: None,
+                    tx_type: None,
+                    client: None,
+                    tx: None,
+                    amount: None,
+                    fee: None,
+                    timestamp: None,
+                    reason: RejectReason::Malformed,
+                    detail: Some(error),
+                },
+                _ => continue,
+            };
+            match &mut self.out {
+                Output::Csv(wtr) => wtr.serialize(row)?,
+                Output::Jsonl(out) => {
+                    serde_json::to_writer(&mut *out, &row)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for Rejects {
+    fn applied(&mut self, _client: u16, _state: &State) -> Result<(), TxError> {
+        self.drain()
+    }
+
+    fn finish(&mut self, _state: &State) -> Result<(), TxError> {
+        self.drain()?;
+        match &mut self.out {
+            Output::Csv(wtr) => wtr.flush()?,
+            Output::Jsonl(out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        csv_records,
+        pipeline::PipelineBuilder,
+        policy::{DuplicateTx, Policy},
+    };
+    use std::fs;
+
+    /// Runs `input` with duplicates skipped, writing the rejects to a file named `name`.
+    fn rejects_of(input: &'static str, name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        let records = csv_records(reader, 1).unwrap();
+        let mut pipeline = PipelineBuilder::new()
+            .policy(Policy {
+                duplicate_tx: DuplicateTx::Skip,
+                ..Policy::default()
+            })
+            .source(records);
+        let events = pipeline.subscribe();
+        pipeline
+            .sink(Rejects::new(&path, events).unwrap())
+            .build()
+            .run()
+            .unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        written
+    }
+
+    const INPUT: &str = "type,client,tx,amount,timestamp
+        deposit,1,1,10,
+        deposit,1,1,10,
+        withdrawal,1,2,15,2022-03-01T12:00:00Z
+        deposit,1,x,3,
+        resolve,1,1,,";
+
+    #[test]
+    fn writes_every_rejected_record_with_its_reason() {
+        let written = rejects_of(INPUT, "rejects.csv");
+        let mut lines = written.lines();
+        assert_eq!(
+            lines.next(),
+            Some("line,position,type,client,tx,amount,fee,timestamp,reason,detail")
+        );
+        assert_eq!(lines.next(), Some("3,2,deposit,1,1,10,,,duplicate_tx,"));
+        assert_eq!(
+            lines.next(),
+            Some("4,3,withdrawal,1,2,15,,2022-03-01T12:00:00Z,insufficient_funds,")
+        );
+        let malformed = lines.next().unwrap();
+        assert!(
+            malformed.starts_with("5,,,,,,,,malformed,") && malformed.contains("invalid tx id"),
+            "{}",
+            malformed
+        );
+        assert_eq!(lines.next(), Some("6,4,resolve,1,1,,,,invalid_transition,"));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn writes_json_lines_by_extension() {
+        let written = rejects_of(INPUT, "rejects.jsonl");
+        let first: serde_json::Value =
+            serde_json::from_str(written.lines().next().unwrap()).unwrap();
+        assert_eq!(first["reason"], "duplicate_tx");
+        assert_eq!(first["line"], 3);
+        assert_eq!(written.lines().count(), 4);
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, path::Path};
+
+#[derive(Debug, Deserialize)]
+struct RemapRow {
+    old_id: u16,
+    new_id: u16,
+}
+
+/// Client ids of migrated or duplicate accounts mapped onto the surviving id.
+#[derive(Debug, Default)]
+pub struct ClientRemap {
+    ids: HashMap<u16, u16>,
+}
+
+impl ClientRemap {
+    /// Loads an `old_id,new_id` CSV. Chains (1 -> 2, 2 -> 3) are followed to the final id,
+    /// cycles are rejected.
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let mut direct = HashMap::new();
+        for result in reader.deserialize() {
+            let row: RemapRow = result?;
+            if let Some(previous) = direct.insert(row.old_id, row.new_id) {
+                if previous != row.new_id {
+                    return Err(From::from(format!(
+                        "client {} remapped to both {} and {}",
+                        row.old_id, previous, row.new_id
+                    )));
+                }
+            }
+        }
+
+        let mut ids = HashMap::new();
+        for old_id in direct.keys() {
+            let mut new_id = direct[old_id];
+            let mut hops = 0;
+            while let Some(next) = direct.get(&new_id) {
+                new_id = *next;
+                hops += 1;
+                if new_id == *old_id || hops > direct.len() {
+                    return Err(From::from(format!("client remap cycle through {}", old_id)));
+                }
+            }
+            ids.insert(*old_id, new_id);
+        }
+        Ok(ClientRemap { ids })
+    }
+
+    /// Returns the surviving id if `client` was folded into another account.
+    pub fn get(&self, client: u16) -> Option<u16> {
+        self.ids.get(&client).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remap(rows: &str) -> Result<ClientRemap, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!(
+            "client-remap-{}-{}.csv",
+            std::process::id(),
+            rows.len()
+        ));
+        std::fs::write(&path, format!("old_id,new_id\n{}", rows)).unwrap();
+        let remap = ClientRemap::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+        remap
+    }
+
+    #[test]
+    fn follows_chains_to_the_surviving_id() {
+        let remap = remap("1,2\n2,3\n7,3\n").unwrap();
+        assert_eq!(remap.get(1), Some(3));
+        assert_eq!(remap.get(2), Some(3));
+        assert_eq!(remap.get(7), Some(3));
+        assert_eq!(remap.get(3), None);
+    }
+
+    #[test]
+    fn accepts_the_same_row_twice() {
+        let remap = remap("1,2\n1,2\n").unwrap();
+        assert_eq!(remap.get(1), Some(2));
+    }
+
+    #[test]
+    fn refuses_conflicting_rows_and_cycles() {
+        assert!(remap("1,2\n1,3\n").is_err());
+        assert!(remap("1,2\n2,1\n").is_err());
+        assert!(remap("1,2\n2,3\n3,1\n").is_err());
+    }
+}
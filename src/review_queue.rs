@@ -0,0 +1,161 @@
+use crate::{ClientInfo, TxError};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::HashMap, fs::File, path::Path};
+
+/// One open dispute as imported by back-office case management.
+#[derive(Serialize, Debug)]
+struct ReviewItem {
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    /// number of input records processed since the dispute was opened
+    age: u64,
+    memo: Option<String>,
+    /// case reference given with the dispute
+    evidence: Option<String>,
+    /// `;` separated tags of the disputed transaction
+    tags: String,
+    /// timestamp of the disputed transaction
+    timestamp: Option<DateTime<Utc>>,
+    /// timestamp of the dispute record
+    disputed_at: Option<DateTime<Utc>>,
+}
+
+/// Writes every dispute still open at the end of the run to `path`.
+/// A `.json` extension writes a JSON array, anything else writes CSV.
+pub fn write(
+    path: &Path,
+    client_map: &HashMap<u16, ClientInfo>,
+    processed: u64,
+) -> Result<(), TxError> {
+    let mut items = open_disputes(client_map, processed);
+    items.sort_by_key(|item| (item.client, item.tx));
+    let file = File::create(path)?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_writer_pretty(file, &items)?;
+    } else {
+        let mut wtr = csv::Writer::from_writer(file);
+        for item in items {
+            wtr.serialize(item)?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+fn open_disputes(client_map: &HashMap<u16, ClientInfo>, processed: u64) -> Vec<ReviewItem> {
+    let mut items = Vec::new();
+    for (client, info) in client_map.iter() {
+        for original in info.transactions() {
+            let dispute = match &original.open_case {
+                Some(dispute) if original.has_open_dispute() => dispute,
+                _ => continue,
+            };
+            items.push(ReviewItem {
+                client: *client,
+                tx: original.tx,
+                amount: original.amount,
+                age: processed.saturating_sub(dispute.position),
+                // prefer the note on the dispute itself, fall back to the disputed transaction
+                memo: dispute
+                    .memo
+                    .clone()
+                    .or_else(|| original.memo().map(String::from)),
+                evidence: dispute.evidence.clone(),
+                tags: original.tags().join(";"),
+                timestamp: original.timestamp(),
+                disputed_at: dispute.timestamp,
+            });
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_record, Policy, Record};
+    use csv::Trim;
+
+    /// Applies the records of `input` and returns the client map with the number of records read.
+    fn run(input: &str) -> (HashMap<u16, ClientInfo>, u64) {
+        let mut client_map = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        let mut processed = 0;
+        for record in reader.deserialize() {
+            let mut record: Record = record.unwrap();
+            processed += 1;
+            record.position = processed;
+            apply_record(&mut client_map, record, &Policy::default()).unwrap();
+        }
+        (client_map, processed)
+    }
+
+    #[test]
+    fn reports_open_disputes_with_age_and_memo() {
+        let (client_map, processed) = run("type,client,tx,amount,memo
+            deposit,1,1,10,first
+            deposit,1,2,5,
+            dispute,1,1,,
+            dispute,1,2,,card stolen
+            resolve,1,1,,
+            deposit,1,3,1,");
+        let items = open_disputes(&client_map, processed);
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].client, items[0].tx), (1, 2));
+        assert_eq!(items[0].amount, Some(Decimal::from(5)));
+        assert_eq!(items[0].age, 2);
+        assert_eq!(items[0].memo.as_deref(), Some("card stolen"));
+    }
+
+    #[test]
+    fn reports_the_evidence_of_the_open_dispute() {
+        let (client_map, processed) = run("type,client,tx,amount,evidence
+            deposit,1,1,10,
+            deposit,1,2,5,
+            dispute,1,1,,CB-1001
+            dispute,1,2,,CB-1002");
+        let mut items = open_disputes(&client_map, processed);
+        items.sort_by_key(|item| item.tx);
+        assert_eq!(items[0].evidence.as_deref(), Some("CB-1001"));
+        assert_eq!(items[1].evidence.as_deref(), Some("CB-1002"));
+    }
+
+    #[test]
+    fn memo_falls_back_to_the_disputed_transaction() {
+        let (client_map, processed) = run("type,client,tx,amount,memo
+            deposit,1,1,10,first
+            dispute,1,1,,");
+        let items = open_disputes(&client_map, processed);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].memo.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn resolved_transaction_is_not_reopened() {
+        let (client_map, processed) = run("type,client,tx,amount
+            deposit,1,1,10
+            dispute,1,1,
+            resolve,1,1,
+            dispute,1,1,");
+        assert!(open_disputes(&client_map, processed).is_empty());
+    }
+
+    #[test]
+    fn writes_json_by_extension() {
+        let (client_map, processed) = run("type,client,tx,amount
+            deposit,1,1,10
+            dispute,1,1,");
+        let path = std::env::temp_dir().join(format!("review-queue-{}.json", std::process::id()));
+        write(&path, &client_map, processed).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written[0]["tx"], 1);
+        assert_eq!(written[0]["age"], 0);
+    }
+}
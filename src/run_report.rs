@@ -0,0 +1,398 @@
+use crate::{
+    events::{DisputeOutcome, EngineEvent},
+    pipeline::{Sink, State},
+    TxError,
+};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+};
+
+/// How many of the largest balance changes are listed.
+const TOP_CHANGES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+}
+
+/// The columns of a previous balances snapshot the report compares against.
+#[derive(Deserialize, Debug)]
+struct BaseRow {
+    client: u16,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Reads a balances snapshot written in major units, `#` metadata lines are skipped.
+fn read_base(path: &Path) -> Result<HashMap<u16, BaseRow>, TxError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_path(path)?;
+    let mut base = HashMap::new();
+    for row in reader.deserialize::<BaseRow>() {
+        let row = row?;
+        base.insert(row.client, row);
+    }
+    Ok(base)
+}
+
+/// Everything the report shows, gathered once the run is done.
+#[derive(Default)]
+struct Summary {
+    input: String,
+    base: Option<String>,
+    processed: u64,
+    accepted: u64,
+    clients: usize,
+    applied: u64,
+    total_funds: Decimal,
+    held_funds: Decimal,
+    locked: usize,
+    disputes_opened: u64,
+    resolved: u64,
+    charged_back: u64,
+    /// rejected records per transaction type
+    rejects: BTreeMap<String, u64>,
+    /// (client, base total, total), largest absolute change first
+    changes: Vec<(u16, Decimal, Decimal)>,
+    /// (client, reasons) of accounts locked now that were not locked in the base
+    newly_locked: Vec<(u16, String)>,
+}
+
+/// Change of a `(client, base total, total)` entry, a report figure that saturates rather
+/// than failing the report.
+fn change(&(_, before, after): &(u16, Decimal, Decimal)) -> Decimal {
+    after.saturating_sub(before)
+}
+
+/// Self-contained run report for the daily close: summary, largest balance changes since
+/// the base snapshot, newly locked accounts and a breakdown of rejected records.
+pub struct RunReport<W: Write> {
+    pub out: W,
+    pub format: ReportFormat,
+    pub input: PathBuf,
+    pub base: Option<PathBuf>,
+    pub events: Receiver<EngineEvent>,
+}
+
+impl<W: Write> RunReport<W> {
+    fn summarize(&self, state: &State) -> Result<Summary, TxError> {
+        let base = match &self.base {
+            Some(path) => read_base(path)?,
+            None => HashMap::new(),
+        };
+        let mut summary = Summary {
+            input: self.input.display().to_string(),
+            base: self.base.as_ref().map(|path| path.display().to_string()),
+            processed: state.processed,
+            accepted: state.sequence,
+            clients: state.client_map.len(),
+            ..Summary::default()
+        };
+        for event in self.events.try_iter() {
+            match event {
+                EngineEvent::Applied { .. } => summary.applied += 1,
+                EngineEvent::Rejected { tx_type, .. } => {
+                    *summary.rejects.entry(tx_type).or_default() += 1
+                }
+                EngineEvent::DisputeOpened { .. } => summary.disputes_opened += 1,
+                EngineEvent::DisputeClosed { outcome, .. } => match outcome {
+                    DisputeOutcome::Resolved => summary.resolved += 1,
+                    DisputeOutcome::ChargedBack => summary.charged_back += 1,
+                },
+                EngineEvent::AccountLocked { .. }
+                | EngineEvent::InvalidTransition { .. }
+                | EngineEvent::Malformed { .. } => {}
+            }
+        }
+
+        let mut clients: Vec<&u16> = state.client_map.keys().collect();
+        clients.sort();
+        for client in clients {
+            let info = &state.client_map[client];
+            // summed over every account, which can exceed what one account holds
+            summary.total_funds = summary.total_funds.saturating_add(info.total_funds);
+            summary.held_funds = summary.held_funds.saturating_add(info.held_funds);
+            let before = base.get(client);
+            if info.locked {
+                summary.locked += 1;
+                if !before.is_some_and(|row| row.locked) {
+                    summary.newly_locked.push((*client, info.lock_reasons()));
+                }
+            }
+            let base_total = before.map_or(Decimal::ZERO, |row| row.total);
+            let total = info.total_funds;
+            if total != base_total {
+                summary.changes.push((*client, base_total, total));
+            }
+        }
+        // accounts that disappeared since the base changed by their whole balance
+        for (client, row) in base.iter() {
+            if !state.client_map.contains_key(client) && !row.total.is_zero() {
+                summary.changes.push((*client, row.total, Decimal::ZERO));
+            }
+        }
+        summary
+            .changes
+            .sort_by(|a, b| change(b).abs().cmp(&change(a).abs()).then(a.0.cmp(&b.0)));
+        summary.changes.truncate(TOP_CHANGES);
+        Ok(summary)
+    }
+}
+
+impl<W: Write> Sink for RunReport<W> {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        let summary = self.summarize(state)?;
+        let report = match self.format {
+            ReportFormat::Html => html(&summary)?,
+            ReportFormat::Markdown => markdown(&summary)?,
+        };
+        self.out.write_all(report.as_bytes())?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+fn summary_rows(summary: &Summary) -> Vec<(&'static str, String)> {
+    vec![
+        ("input", summary.input.clone()),
+        (
+            "base snapshot",
+            summary.base.clone().unwrap_or_else(|| "-".to_string()),
+        ),
+        ("records read", summary.processed.to_string()),
+        ("records accepted", summary.accepted.to_string()),
+        ("records applied", summary.applied.to_string()),
+        (
+            "records rejected",
+            summary.rejects.values().sum::<u64>().to_string(),
+        ),
+        ("clients", summary.clients.to_string()),
+        ("total funds", format!("{:.4}", summary.total_funds)),
+        ("held funds", format!("{:.4}", summary.held_funds)),
+        ("locked accounts", summary.locked.to_string()),
+        ("disputes opened", summary.disputes_opened.to_string()),
+        ("disputes resolved", summary.resolved.to_string()),
+        ("chargebacks", summary.charged_back.to_string()),
+    ]
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A single HTML page without external resources, the reject chart is plain CSS bars.
+fn html(summary: &Summary) -> Result<String, TxError> {
+    let mut page = String::new();
+    page.push_str(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Run report</title>\n\
+         <style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; margin-bottom: 2em; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n\
+         td.num { text-align: right; }\n\
+         .up { color: #070; } .down { color: #a00; }\n\
+         .bar { background: #c33; height: 1em; }\n\
+         </style>\n</head>\n<body>\n<h1>Run report</h1>\n",
+    );
+
+    page.push_str("<h2>Summary</h2>\n<table>\n");
+    for (name, value) in summary_rows(summary) {
+        writeln!(
+            page,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            name,
+            escape(&value)
+        )?;
+    }
+    page.push_str("</table>\n");
+
+    page.push_str("<h2>Largest balance changes</h2>\n");
+    if summary.changes.is_empty() {
+        page.push_str("<p>No balance changes.</p>\n");
+    } else {
+        page.push_str(
+            "<table>\n<tr><th>client</th><th>before</th><th>after</th><th>change</th></tr>\n",
+        );
+        for entry in summary.changes.iter() {
+            let (client, before, after) = entry;
+            let change = change(entry);
+            writeln!(
+                page,
+                "<tr><td>{}</td><td class=\"num\">{:.4}</td><td class=\"num\">{:.4}</td><td class=\"num {}\">{:+.4}</td></tr>",
+                client,
+                before,
+                after,
+                if change >= Decimal::ZERO { "up" } else { "down" },
+                change
+            )?;
+        }
+        page.push_str("</table>\n");
+    }
+
+    page.push_str("<h2>Newly locked accounts</h2>\n");
+    if summary.newly_locked.is_empty() {
+        page.push_str("<p>No accounts were locked.</p>\n");
+    } else {
+        page.push_str("<table>\n<tr><th>client</th><th>reason</th></tr>\n");
+        for (client, reasons) in summary.newly_locked.iter() {
+            writeln!(
+                page,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                client,
+                escape(reasons)
+            )?;
+        }
+        page.push_str("</table>\n");
+    }
+
+    page.push_str("<h2>Rejected records</h2>\n");
+    let most = summary
+        .rejects
+        .values()
+        .copied()
+        .max()
+        .filter(|most| *most > 0);
+    if let Some(most) = most {
+        page.push_str("<table>\n<tr><th>type</th><th>count</th><th></th></tr>\n");
+        for (tx_type, count) in summary.rejects.iter() {
+            writeln!(
+                page,
+                "<tr><td>{}</td><td class=\"num\">{}</td><td style=\"width: 300px\"><div class=\"bar\" style=\"width: {}%\"></div></td></tr>",
+                escape(tx_type),
+                count,
+                count * 100 / most
+            )?;
+        }
+        page.push_str("</table>\n");
+    } else {
+        page.push_str("<p>No records were rejected.</p>\n");
+    }
+
+    page.push_str("</body>\n</html>\n");
+    Ok(page)
+}
+
+fn markdown(summary: &Summary) -> Result<String, TxError> {
+    let mut doc = String::from("# Run report\n\n## Summary\n\n| | |\n|---|---|\n");
+    for (name, value) in summary_rows(summary) {
+        writeln!(doc, "| {} | {} |", name, value.replace('|', "\\|"))?;
+    }
+
+    doc.push_str("\n## Largest balance changes\n\n");
+    if summary.changes.is_empty() {
+        doc.push_str("No balance changes.\n");
+    } else {
+        doc.push_str("| client | before | after | change |\n|---|---:|---:|---:|\n");
+        for entry in summary.changes.iter() {
+            let (client, before, after) = entry;
+            writeln!(
+                doc,
+                "| {} | {:.4} | {:.4} | {:+.4} |",
+                client,
+                before,
+                after,
+                change(entry)
+            )?;
+        }
+    }
+
+    doc.push_str("\n## Newly locked accounts\n\n");
+    if summary.newly_locked.is_empty() {
+        doc.push_str("No accounts were locked.\n");
+    } else {
+        doc.push_str("| client | reason |\n|---|---|\n");
+        for (client, reasons) in summary.newly_locked.iter() {
+            writeln!(doc, "| {} | {} |", client, reasons)?;
+        }
+    }
+
+    doc.push_str("\n## Rejected records\n\n");
+    if summary.rejects.is_empty() {
+        doc.push_str("No records were rejected.\n");
+    } else {
+        doc.push_str("| type | count |\n|---|---:|\n");
+        for (tx_type, count) in summary.rejects.iter() {
+            writeln!(doc, "| {} | {} |", tx_type.replace('|', "\\|"), count)?;
+        }
+    }
+    Ok(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::PipelineBuilder;
+    use std::fs;
+
+    #[test]
+    fn compares_the_run_with_the_base_snapshot() {
+        let base = std::env::temp_dir().join(format!("run-report-base-{}.csv", std::process::id()));
+        fs::write(
+            &base,
+            "# input: yesterday.csv\n\
+             client,available,held,total,locked\n\
+             1,5,0,5,false\n\
+             2,1,0,1,true\n\
+             3,7,0,7,false\n",
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let mut builder = PipelineBuilder::new();
+        let events = builder.subscribe();
+        let records = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(
+                "type,client,tx,amount
+                deposit,1,1,10
+                deposit,2,2,3
+                dispute,2,2,
+                chargeback,2,2,
+                withdrawal,1,3,50
+                deposit,4,4,1
+                dispute,4,4,
+                chargeback,4,4,"
+                    .as_bytes(),
+            )
+            .into_deserialize()
+            .map(|record| record.map_err(From::from));
+        builder
+            .source(records)
+            .sink(RunReport {
+                out: &mut out,
+                format: ReportFormat::Markdown,
+                input: PathBuf::from("today.csv"),
+                base: Some(base.clone()),
+                events,
+            })
+            .build()
+            .run()
+            .unwrap();
+        fs::remove_file(&base).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("| records read | 8 |"));
+        assert!(report.contains("| records applied | 7 |"));
+        assert!(report.contains("| chargebacks | 2 |"));
+        // client 3 is gone from the run, client 2 was locked in the base already
+        assert!(report.contains(
+            "| client | before | after | change |\n|---|---:|---:|---:|\n\
+             | 3 | 7.0000 | 0.0000 | -7.0000 |\n\
+             | 1 | 5.0000 | 10.0000 | +5.0000 |\n\
+             | 2 | 1.0000 | 0.0000 | -1.0000 |\n"
+        ));
+        assert!(report.contains("| client | reason |\n|---|---|\n| 4 | chargeback:4 |\n"));
+        assert!(report.contains("| withdrawal | 1 |"));
+    }
+}
@@ -0,0 +1,58 @@
+use bytes::{Buf, Bytes};
+use futures::{stream::BoxStream, StreamExt};
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore};
+use std::{
+    error::Error,
+    io::{self, Read},
+};
+use tokio::runtime::Runtime;
+
+/// Streams an object from S3 as it is read, without downloading it first. Credentials,
+/// region and endpoint come from the usual `AWS_*` environment variables.
+pub struct ObjectReader {
+    runtime: Runtime,
+    chunks: BoxStream<'static, object_store::Result<Bytes>>,
+    /// rest of the chunk being read
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    pub fn open(url: &str) -> Result<Self, Box<dyn Error>> {
+        let (bucket, key) = url
+            .strip_prefix("s3://")
+            .and_then(|rest| rest.split_once('/'))
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| format!("expected s3://bucket/key, got {}", url))?;
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let chunks = runtime
+            .block_on(store.get(&ObjectPath::from(key)))
+            .map_err(|err| format!("{}: {}", url, err))?
+            .into_stream();
+        Ok(ObjectReader {
+            runtime,
+            chunks,
+            chunk: Bytes::new(),
+        })
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.chunks.next()) {
+                Some(Ok(chunk)) => self.chunk = chunk,
+                Some(Err(err)) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk[..n]);
+        self.chunk.advance(n);
+        Ok(n)
+    }
+}
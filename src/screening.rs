@@ -0,0 +1,242 @@
+use crate::{locks::LockReason, ClientInfo, Record, TxError};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs::{self, File},
+    path::Path,
+};
+
+/// Clients that must not transact, loaded from a file with one client id per line.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct Denylist {
+    clients: HashSet<u16>,
+}
+
+impl Denylist {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut clients = HashSet::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let client = line
+                .parse()
+                .map_err(|_| format!("invalid client id in denylist: {}", line))?;
+            clients.insert(client);
+        }
+        Ok(Denylist { clients })
+    }
+
+    /// Returns the client of the record if it is listed, after freezing (locking) the
+    /// client's account if it has one. The record must then not be applied. `frozen` tells
+    /// whether this record is the one that froze the account.
+    pub fn block(
+        &self,
+        record: &Record,
+        client_map: &mut HashMap<u16, ClientInfo>,
+    ) -> Option<(u16, bool)> {
+        let client = record
+            .client
+            .filter(|client| self.clients.contains(client))?;
+        let mut frozen = false;
+        if let Some(info) = client_map.get_mut(&client) {
+            frozen = !info.locked;
+            info.lock(LockReason::Denylist, record);
+        }
+        warn!("blocked tx {} of denylisted client {}", record.tx, client);
+        Some((client, frozen))
+    }
+}
+
+/// The denylist with the report the blocked records are written to, if any.
+pub struct Screening {
+    denylist: Denylist,
+    quarantine: Option<Quarantine>,
+}
+
+impl Screening {
+    pub fn new(denylist: Denylist, quarantine: Option<&Path>) -> Result<Self, TxError> {
+        Ok(Screening {
+            denylist,
+            quarantine: quarantine.map(Quarantine::create).transpose()?,
+        })
+    }
+
+    /// Blocks the record if its client is listed, as [`Denylist::block`] does, and writes it
+    /// to the quarantine.
+    pub fn block(
+        &mut self,
+        record: &Record,
+        client_map: &mut HashMap<u16, ClientInfo>,
+    ) -> Result<Option<(u16, bool)>, TxError> {
+        let blocked = self.denylist.block(record, client_map);
+        if let (Some((client, frozen)), Some(quarantine)) = (blocked, self.quarantine.as_mut()) {
+            quarantine.write(record, client, frozen)?;
+        }
+        Ok(blocked)
+    }
+
+    pub fn finish(&mut self) -> Result<(), TxError> {
+        match self.quarantine.as_mut() {
+            Some(quarantine) => quarantine.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A blocked record as written to the quarantine report.
+#[derive(Serialize, Debug)]
+struct Quarantined<'a> {
+    #[serde(rename = "type")]
+    tx_type: &'a str,
+    client: u16,
+    tx: u32,
+    amount: Option<Decimal>,
+    position: u64,
+    /// true when the client had an account that got frozen by this record
+    frozen: bool,
+}
+
+/// The report the records of denylisted clients are written to.
+pub struct Quarantine {
+    report: csv::Writer<File>,
+}
+
+impl Quarantine {
+    pub fn create(path: &Path) -> Result<Self, TxError> {
+        Ok(Quarantine {
+            report: csv::Writer::from_path(path)?,
+        })
+    }
+
+    pub fn write(&mut self, record: &Record, client: u16, frozen: bool) -> Result<(), TxError> {
+        self.report.serialize(Quarantined {
+            tx_type: record.tx_type.as_str(),
+            client,
+            tx: record.tx,
+            amount: record.amount,
+            position: record.position,
+            frozen,
+        })?;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), TxError> {
+        self.report.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline::{PipelineBuilder, State},
+        remap::ClientRemap,
+        transforms::Remap,
+        TxType,
+    };
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", name, std::process::id()))
+    }
+
+    /// Runs `input` with client 2 listed, client 3 folded into client 2 and every record of
+    /// client 2 quarantined into `report`.
+    fn run(input: &str, report: &Path) -> State {
+        let list = temp_path("denylist.txt");
+        fs::write(&list, "# screened\n2\n\n").unwrap();
+        let remap = temp_path("denylist-remap.csv");
+        fs::write(&remap, "old_id,new_id\n3,2\n").unwrap();
+        let screening = Screening::new(Denylist::from_path(&list).unwrap(), Some(report)).unwrap();
+        let mut pipeline = PipelineBuilder::new()
+            .transform(Remap::new(ClientRemap::from_path(&remap).unwrap()))
+            .transform(screening)
+            .build();
+        fs::remove_file(&list).unwrap();
+        fs::remove_file(&remap).unwrap();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        for record in reader.deserialize::<Record>() {
+            pipeline.process(record.unwrap()).unwrap();
+        }
+        pipeline.finish().unwrap()
+    }
+
+    #[test]
+    fn blocks_listed_clients_after_the_remap() {
+        let report = temp_path("quarantine.csv");
+        let state = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,10
+            deposit,3,3,5
+            withdrawal,1,4,1",
+            &report,
+        );
+        let quarantined = fs::read_to_string(&report).unwrap();
+        fs::remove_file(&report).unwrap();
+        assert_eq!(
+            quarantined,
+            "type,client,tx,amount,position,frozen\n\
+             deposit,2,2,10,2,false\n\
+             deposit,2,3,5,3,false\n"
+        );
+        assert_eq!(state.client_map[&1].available_funds, Decimal::from(9));
+        assert!(!state.client_map.contains_key(&2));
+    }
+
+    #[test]
+    fn freezes_an_existing_account() {
+        let mut pipeline = PipelineBuilder::new().build();
+        let deposit = Record {
+            tx_type: TxType::Deposit,
+            client: Some(2),
+            tx: 1,
+            amount: Some(Decimal::from(10)),
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        };
+        // the account was opened before the client was listed
+        pipeline.process(deposit.clone()).unwrap();
+        let mut state = pipeline.into_state();
+        let denylist = Denylist {
+            clients: HashSet::from([2]),
+        };
+        assert_eq!(
+            denylist.block(&deposit, &mut state.client_map),
+            Some((2, true))
+        );
+        assert!(state.client_map[&2].locked);
+        assert_eq!(state.client_map[&2].available_funds, Decimal::from(10));
+        // already frozen
+        assert_eq!(
+            denylist.block(&deposit, &mut state.client_map),
+            Some((2, false))
+        );
+    }
+
+    #[test]
+    fn refuses_ids_that_are_not_client_ids() {
+        let list = temp_path("denylist-invalid.txt");
+        fs::write(&list, "1\nbob\n").unwrap();
+        let err = Denylist::from_path(&list).unwrap_err();
+        fs::remove_file(&list).unwrap();
+        assert!(err
+            .to_string()
+            .contains("invalid client id in denylist: bob"));
+    }
+}
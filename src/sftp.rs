@@ -0,0 +1,187 @@
+#[cfg(feature = "sftp")]
+use crate::process_input;
+use crate::Config;
+use glob::Pattern;
+#[cfg(feature = "sftp")]
+use log::{error, info};
+#[cfg(feature = "sftp")]
+use ssh2::{Session, Sftp};
+#[cfg(feature = "sftp")]
+use std::{
+    env,
+    fs::{self, File},
+    io,
+    net::TcpStream,
+    path::Path,
+    slice, thread,
+    time::Duration,
+};
+use std::{error::Error, path::PathBuf};
+
+/// Flags that tune the SFTP polling mode.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "sftp"), allow(dead_code))]
+pub struct SftpOptions {
+    /// glob the remote file names have to match
+    pub pattern: String,
+    pub interval_secs: u64,
+    /// private key used to log in, the ssh agent or `SFTP_PASSWORD` are tried without one
+    pub key: Option<PathBuf>,
+    /// remote directory, relative to the polled one, processed files are moved to
+    pub done_dir: String,
+}
+
+impl Default for SftpOptions {
+    fn default() -> Self {
+        SftpOptions {
+            pattern: "*.csv".to_string(),
+            interval_secs: 60,
+            key: None,
+            done_dir: "processed".to_string(),
+        }
+    }
+}
+
+/// Remote directory polled for transaction files.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "sftp"), allow(dead_code))]
+pub struct SftpSource {
+    user: String,
+    host: String,
+    port: u16,
+    dir: PathBuf,
+    pattern: Pattern,
+    options: SftpOptions,
+}
+
+impl SftpSource {
+    /// Parses `user@host[:port]/remote/dir`.
+    pub fn parse(url: &str, options: SftpOptions) -> Result<Self, Box<dyn Error>> {
+        let invalid = || format!("--sftp expects user@host[:port]/dir, got {}", url);
+        let (user, rest) = url.split_once('@').ok_or_else(invalid)?;
+        let (address, dir) = rest.split_once('/').ok_or_else(invalid)?;
+        let (host, port) = match address.split_once(':') {
+            Some((host, port)) => (host, port.parse()?),
+            None => (address, 22),
+        };
+        Ok(SftpSource {
+            user: user.to_string(),
+            host: host.to_string(),
+            port,
+            dir: PathBuf::from(format!("/{}", dir)),
+            pattern: Pattern::new(&options.pattern)?,
+            options,
+        })
+    }
+
+    #[cfg(feature = "sftp")]
+    fn connect(&self) -> Result<Sftp, Box<dyn Error>> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        match (&self.options.key, env::var("SFTP_PASSWORD")) {
+            (Some(key), _) => session.userauth_pubkey_file(&self.user, None, key, None)?,
+            (None, Ok(password)) => session.userauth_password(&self.user, &password)?,
+            (None, Err(_)) => session.userauth_agent(&self.user)?,
+        }
+        Ok(session.sftp()?)
+    }
+}
+
+/// Polls the remote directory forever. Every new file matching the pattern is downloaded
+/// into the input directory, processed, and on success moved into the remote done directory.
+/// The output for `name` is written next to the download as `name.out.csv`.
+/// Files that fail stay in place and are retried on the next poll.
+#[cfg(feature = "sftp")]
+pub fn poll(source: &SftpSource, config: &Config) -> Result<(), Box<dyn Error>> {
+    let local_dir = config.input();
+    fs::create_dir_all(local_dir)?;
+    loop {
+        // connection problems are logged and retried on the next poll, the daemon keeps running
+        if let Err(err) = poll_once(source, config, local_dir) {
+            error!("sftp poll of {} failed: {}", source.host, err);
+        }
+        thread::sleep(Duration::from_secs(source.options.interval_secs));
+    }
+}
+
+#[cfg(not(feature = "sftp"))]
+pub fn poll(_source: &SftpSource, _config: &Config) -> Result<(), Box<dyn Error>> {
+    Err(From::from("--sftp needs the sftp feature"))
+}
+
+#[cfg(feature = "sftp")]
+fn poll_once(source: &SftpSource, config: &Config, local_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let sftp = source.connect()?;
+    let done_dir = source.dir.join(&source.options.done_dir);
+    if sftp.stat(&done_dir).is_err() {
+        sftp.mkdir(&done_dir, 0o755)?;
+    }
+
+    let mut entries = sftp.readdir(&source.dir)?;
+    // oldest name first so files are applied in the order they were delivered
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    for (remote_path, stat) in entries {
+        let name = match remote_path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if !stat.is_file() || !source.pattern.matches(&name) {
+            continue;
+        }
+
+        let local_path = local_dir.join(&name);
+        io::copy(
+            &mut sftp.open(&remote_path)?,
+            &mut File::create(&local_path)?,
+        )?;
+        let output = File::create(local_dir.join(format!("{}.out.csv", name)))?;
+        match process_input(config, slice::from_ref(&local_path), output) {
+            Ok(()) => {
+                sftp.rename(&remote_path, &done_dir.join(&name), None)?;
+                info!("processed sftp file {}", name);
+            }
+            Err(err) => error!("processing sftp file {} failed: {}", name, err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_user_host_port_and_directory() {
+        let source = SftpSource::parse(
+            "bank@sftp.example.com:2222/outbox/daily",
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(source.user, "bank");
+        assert_eq!(source.host, "sftp.example.com");
+        assert_eq!(source.port, 2222);
+        assert_eq!(source.dir, PathBuf::from("/outbox/daily"));
+        assert!(source.pattern.matches("2022-03-01.csv"));
+        assert!(!source.pattern.matches("2022-03-01.csv.part"));
+    }
+
+    #[test]
+    fn defaults_to_port_22() {
+        let options = SftpOptions {
+            pattern: "*.txt".to_string(),
+            ..Default::default()
+        };
+        let source = SftpSource::parse("bank@host/outbox", options).unwrap();
+        assert_eq!(source.port, 22);
+        assert!(source.pattern.matches("a.txt"));
+    }
+
+    #[test]
+    fn refuses_urls_without_user_or_directory() {
+        assert!(SftpSource::parse("host/outbox", Default::default()).is_err());
+        assert!(SftpSource::parse("bank@host", Default::default()).is_err());
+        assert!(SftpSource::parse("bank@host:ssh/outbox", Default::default()).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{
+    pipeline::{PipelineBuilder, Source, State},
+    Record, TxError,
+};
+use log::info;
+use std::{
+    error::Error,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+};
+
+/// Shard of the accounts of `client`: records without a client go to the first one.
+pub fn shard_of(client: Option<u16>, shards: usize) -> usize {
+    client.map_or(0, |client| usize::from(client) % shards)
+}
+
+/// A source error as it crosses to a shard thread: unreadable rows keep their line, anything
+/// else only its text.
+enum Routed {
+    Malformed { line: Option<u64>, message: String },
+    Failed(String),
+}
+
+impl From<TxError> for Routed {
+    fn from(err: TxError) -> Self {
+        match err {
+            TxError::ParseError { line, message } => Routed::Malformed { line, message },
+            err => Routed::Failed(err.to_string()),
+        }
+    }
+}
+
+/// Records routed to one shard.
+struct ShardSource {
+    records: Receiver<Result<Record, Routed>>,
+}
+
+impl Iterator for ShardSource {
+    type Item = Result<Record, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.records.recv().ok()?;
+        Some(result.map_err(|err| match err {
+            Routed::Malformed { line, message } => TxError::ParseError { line, message },
+            Routed::Failed(message) => TxError::other(message),
+        }))
+    }
+}
+
+/// Reads `source` on the calling thread and routes every record by client to one of
+/// `shards` pipelines, each on its own thread and owning the accounts of its clients. The
+/// pipelines come from `pipeline`, given the shard number, and get their source and their
+/// part of `state` here. Channels hold at most `capacity` records per shard, so reading waits
+/// for the slowest shard. Returns the states of the shards merged into one.
+///
+/// Records are numbered in input order before they are routed, both their position, so
+/// disputes age as in a single pipeline, and their sequence number, so no two shards hand
+/// out the same one. The numbers of records a check drops are not used. Malformed rows go to
+/// the first shard.
+pub fn run<F>(
+    mut source: impl Source,
+    shards: usize,
+    capacity: usize,
+    state: State,
+    pipeline: F,
+) -> Result<State, TxError>
+where
+    F: Fn(usize) -> Result<PipelineBuilder<'static>, Box<dyn Error>> + Sync,
+{
+    let shards = shards.max(1);
+    let (mut position, mut sequence) = (state.processed, state.sequence);
+    let mut parts = split(state, shards);
+    let pipeline = &pipeline;
+    let results = thread::scope(|scope| {
+        let mut senders: Vec<SyncSender<Result<Record, Routed>>> = Vec::with_capacity(shards);
+        let mut workers = Vec::with_capacity(shards);
+        for (shard, part) in parts.drain(..).enumerate() {
+            let (sender, records) = mpsc::sync_channel(capacity);
+            senders.push(sender);
+            workers.push(scope.spawn(move || {
+                pipeline(shard)
+                    .and_then(|builder| {
+                        let state = builder
+                            .state(part)
+                            .source(ShardSource { records })
+                            .build()
+                            .run()?;
+                        Ok(state)
+                    })
+                    .map_err(|err| err.to_string())
+            }));
+        }
+
+        while let Some(result) = source.next_record() {
+            let (shard, routed) = match result {
+                Ok(mut record) => {
+                    position += 1;
+                    sequence += 1;
+                    record.position = position;
+                    record.sequence = sequence;
+                    (shard_of(record.client, shards), Ok(record))
+                }
+                Err(err) => (0, Err(Routed::from(err))),
+            };
+            // a shard that stopped early failed, its error is reported below
+            if senders[shard].send(routed).is_err() {
+                break;
+            }
+        }
+        drop(senders);
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err("shard panicked".to_string()))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut merged = State::default();
+    for result in results {
+        let state = result.map_err(TxError::other)?;
+        merged.client_map.extend(state.client_map);
+        merged.processed += state.processed;
+        merged.fees_seen |= state.fees_seen;
+        merged.sequence = merged.sequence.max(state.sequence);
+    }
+    info!(
+        "{} records applied over {} shards",
+        merged.processed, shards
+    );
+    Ok(merged)
+}
+
+/// Splits the accounts of `state` by shard, the counters go with the first.
+fn split(state: State, shards: usize) -> Vec<State> {
+    let mut parts: Vec<State> = (0..shards).map(|_| State::default()).collect();
+    parts[0].processed = state.processed;
+    parts[0].fees_seen = state.fees_seen;
+    parts[0].sequence = state.sequence;
+    for (client, info) in state.client_map {
+        parts[shard_of(Some(client), shards)]
+            .client_map
+            .insert(client, info);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::Transform, Balance, Outcome};
+    use std::sync::{Arc, Mutex};
+
+    const INPUT: &str = "type,client,tx,amount
+        deposit,1,1,10
+        deposit,2,2,20
+        deposit,3,3,30
+        dispute,1,1,
+        withdrawal,2,4,25
+        withdrawal,3,5,5
+        chargeback,1,1,
+        deposit,4,6,1";
+
+    fn records() -> impl Iterator<Item = Result<Record, TxError>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(INPUT.as_bytes())
+            .into_deserialize()
+            .map(|record| record.map_err(TxError::from))
+    }
+
+    fn balances(state: &State) -> Vec<Balance> {
+        let mut balances: Vec<Balance> = state
+            .client_map
+            .iter()
+            .map(|(client, info)| Balance::of(*client, info))
+            .collect();
+        balances.sort_by_key(|balance| balance.client);
+        balances
+    }
+
+    /// Notes the sequence number of every record its shard handles.
+    struct Sequences(Arc<Mutex<Vec<u64>>>);
+
+    impl Transform for Sequences {
+        fn apply(&mut self, record: Record, _state: &mut State) -> Result<Option<Record>, TxError> {
+            Ok(Some(record))
+        }
+
+        fn applied(
+            &mut self,
+            _client: Option<u16>,
+            _outcome: Outcome,
+            state: &mut State,
+        ) -> Result<(), TxError> {
+            self.0.lock().unwrap().push(state.sequence);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn shards_end_as_one_pipeline_with_numbers_handed_out_once() {
+        let single = PipelineBuilder::new()
+            .source(records())
+            .build()
+            .run()
+            .unwrap();
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        let sharded = run(records(), 3, 2, State::default(), |_| {
+            Ok(PipelineBuilder::new().transform(Sequences(Arc::clone(&sequences))))
+        })
+        .unwrap();
+
+        assert_eq!(balances(&sharded), balances(&single));
+        assert_eq!(
+            (sharded.processed, sharded.sequence),
+            (single.processed, single.sequence)
+        );
+        let mut sequences = sequences.lock().unwrap().clone();
+        sequences.sort_unstable();
+        assert_eq!(sequences, (1..=8).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn numbers_carry_on_from_the_state() {
+        let loaded = PipelineBuilder::new()
+            .source(records())
+            .build()
+            .run()
+            .unwrap();
+        let sharded = run(records().take(2), 2, 2, loaded, |_| {
+            Ok(PipelineBuilder::new())
+        })
+        .unwrap();
+        assert_eq!((sharded.processed, sharded.sequence), (10, 10));
+        assert_eq!(sharded.client_map.len(), 4);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    events::{EngineEvent, RejectReason},
+    pipeline::{Sink, State},
+    TxError,
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+    sync::mpsc::Receiver,
+};
+
+/// Balances of an account at one point of the run.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Balances {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Balances of every account in `state`.
+pub fn balances(state: &State) -> HashMap<u16, Balances> {
+    state
+        .client_map
+        .iter()
+        .map(|(client, info)| {
+            let balances = Balances {
+                available: info.available_funds,
+                held: info.held_funds,
+                total: info.total_funds,
+                locked: info.locked,
+            };
+            (*client, balances)
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+struct Delta {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    /// whether the hypothetical records lock the account
+    locks: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Reject {
+    position: u64,
+    client: Option<u16>,
+    tx: u32,
+    #[serde(rename = "type")]
+    tx_type: String,
+    reason: RejectReason,
+}
+
+#[derive(Serialize, Debug)]
+struct SimulationReport {
+    deltas: Vec<Delta>,
+    rejects: Vec<Reject>,
+}
+
+/// Writes, as JSON, how the hypothetical records would change each account compared to
+/// `before`, and which of them would be rejected. Nothing else is written for the run.
+pub struct Simulation<W: Write> {
+    pub out: W,
+    pub before: HashMap<u16, Balances>,
+    pub events: Receiver<EngineEvent>,
+}
+
+impl<W: Write> Sink for Simulation<W> {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        let after = balances(state);
+        let clients: BTreeSet<&u16> = self.before.keys().chain(after.keys()).collect();
+        let mut deltas = Vec::new();
+        for client in clients {
+            let before = self.before.get(client).copied().unwrap_or_default();
+            let after = after.get(client).copied().unwrap_or_default();
+            if before == after {
+                continue;
+            }
+            // a report figure, it saturates rather than failing the simulation
+            deltas.push(Delta {
+                client: *client,
+                available: after.available.saturating_sub(before.available),
+                held: after.held.saturating_sub(before.held),
+                total: after.total.saturating_sub(before.total),
+                locks: after.locked && !before.locked,
+            });
+        }
+        let rejects = self
+            .events
+            .try_iter()
+            .filter_map(|event| match event {
+                EngineEvent::Rejected {
+                    position,
+                    client,
+                    tx,
+                    tx_type,
+                    reason,
+                    ..
+                } => Some(Reject {
+                    position,
+                    client,
+                    tx,
+                    tx_type,
+                    reason,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        serde_json::to_writer_pretty(&mut self.out, &SimulationReport { deltas, rejects })?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::PipelineBuilder, Record};
+
+    fn records(input: &'static str) -> impl Iterator<Item = Result<Record, TxError>> + Send {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize::<Record>()
+            .map(|record| record.map_err(From::from))
+    }
+
+    #[test]
+    fn reports_deltas_and_rejects_on_top_of_the_base() {
+        let mut base = PipelineBuilder::new()
+            .source(records(
+                "type,client,tx,amount
+                deposit,1,1,10
+                deposit,2,2,5",
+            ))
+            .build()
+            .run()
+            .unwrap();
+        base.processed = 0;
+        let before = balances(&base);
+        let mut out = Vec::new();
+        let mut pipeline = PipelineBuilder::new()
+            .source(records(
+                "type,client,tx,amount
+                dispute,1,1,
+                chargeback,1,1,
+                withdrawal,2,3,8",
+            ))
+            .state(base);
+        let events = pipeline.subscribe();
+        pipeline
+            .sink(Simulation {
+                out: &mut out,
+                before,
+                events,
+            })
+            .build()
+            .run()
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "deltas": [
+                    {"client": 1, "available": "-10", "held": "0", "total": "-10", "locks": true}
+                ],
+                "rejects": [
+                    {
+                        "position": 3,
+                        "client": 2,
+                        "tx": 3,
+                        "type": "withdrawal",
+                        "reason": "insufficient_funds"
+                    }
+                ]
+            })
+        );
+    }
+}
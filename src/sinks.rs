@@ -0,0 +1,250 @@
+use crate::{
+    aml::{self, AmlRules},
+    events::{EngineEvent, RejectReason},
+    locks, metadata,
+    pipeline::{Sink, State},
+    policy::Policy,
+    review_queue,
+    stream_output::{AccountStream, AccountUpdate},
+    tag_report, write_balances, AmountUnits, ExtraColumns, OutputFormat, TxError,
+};
+use std::{collections::BTreeMap, io::Write, path::PathBuf, sync::mpsc::Receiver};
+
+/// The balances snapshot, optionally preceded by the run metadata as `#` comment lines.
+pub struct Balances<W: Write> {
+    pub out: W,
+    pub aliases: bool,
+    /// `withdrawals_24h` and `withdrawn_24h`
+    pub withdrawal_usage: bool,
+    pub lock_reason: bool,
+    pub units: AmountUnits,
+    pub format: OutputFormat,
+    /// rows sorted by client, off for `--unsorted`
+    pub sorted: bool,
+    /// input and policy to describe in the metadata header
+    pub header: Option<(PathBuf, Policy)>,
+}
+
+impl<W: Write> Sink for Balances<W> {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        if let Some((input, policy)) = &self.header {
+            metadata::RunMetadata::new(input, policy, state.processed, state.client_map.len())?
+                .write_header(&mut self.out)?;
+        }
+        let extra = ExtraColumns {
+            aliases: self.aliases,
+            fees: state.fees_seen,
+            withdrawal_usage: self.withdrawal_usage,
+            lock_reason: self.lock_reason,
+        };
+        write_balances(
+            &mut self.out,
+            &state.client_map,
+            extra,
+            self.units,
+            self.format,
+            self.sorted,
+        )
+    }
+}
+
+/// `report by-tag` output.
+pub struct TagReport<W: Write> {
+    pub out: W,
+    pub global: bool,
+    pub by_month: bool,
+}
+
+impl<W: Write> Sink for TagReport<W> {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        tag_report::write(&mut self.out, &state.client_map, self.global, self.by_month)
+    }
+}
+
+/// `query-sql` output, the `audit` table read from the state.
+#[cfg(feature = "sql")]
+pub struct SqlQuery<W: Write> {
+    pub out: W,
+    pub sql: String,
+}
+
+#[cfg(feature = "sql")]
+impl<W: Write> Sink for SqlQuery<W> {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        let empty = crate::sql::AuditTrail::default();
+        let audit = state.audit.as_ref().unwrap_or(&empty);
+        crate::sql::query(&self.sql, &state.client_map, audit, &mut self.out)
+            .map_err(TxError::Other)
+    }
+}
+
+impl<W: Write> Sink for AccountStream<W> {
+    fn applied(&mut self, client: u16, state: &State) -> Result<(), TxError> {
+        match state.client_map.get(&client) {
+            Some(info) => self.update(AccountUpdate::new(
+                client,
+                info,
+                state.processed,
+                state.sequence,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, _state: &State) -> Result<(), TxError> {
+        AccountStream::finish(self)
+    }
+}
+
+/// `validate` output: a `reason,count` row per reason records were rejected or rows could
+/// not be read for. The run fails when there are any, so the exit code tells whether the
+/// input is clean.
+pub struct Validation<W: Write> {
+    pub out: W,
+    pub events: Receiver<EngineEvent>,
+}
+
+impl<W: Write> Sink for Validation<W> {
+    fn finish(&mut self, _state: &State) -> Result<(), TxError> {
+        let mut counts: BTreeMap<RejectReason, u64> = BTreeMap::new();
+        for event in self.events.try_iter() {
+            let reason = match event {
+                EngineEvent::Rejected { reason, .. } => reason,
+                EngineEvent::Malformed { .. } => RejectReason::Malformed,
+                _ => continue,
+            };
+            *counts.entry(reason).or_default() += 1;
+        }
+        let mut writer = csv::Writer::from_writer(&mut self.out);
+        writer.write_record(["reason", "count"])?;
+        for (reason, count) in counts.iter() {
+            writer.serialize((reason, count))?;
+        }
+        writer.flush()?;
+        // a date window skips records on purpose
+        let problems: u64 = counts
+            .iter()
+            .filter(|(reason, _)| **reason != RejectReason::OutsideWindow)
+            .map(|(_, count)| count)
+            .sum();
+        if problems > 0 {
+            return Err(TxError::other(format!(
+                "input is not valid, {} records rejected",
+                problems
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Sidecar JSON describing the run.
+pub struct MetadataSidecar {
+    pub path: PathBuf,
+    pub input: PathBuf,
+    pub policy: Policy,
+}
+
+impl Sink for MetadataSidecar {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        metadata::RunMetadata::new(
+            &self.input,
+            &self.policy,
+            state.processed,
+            state.client_map.len(),
+        )?
+        .write_sidecar(&self.path)
+    }
+}
+
+/// Open disputes for back-office case management.
+pub struct ReviewQueue(pub PathBuf);
+
+impl Sink for ReviewQueue {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        review_queue::write(&self.0, &state.client_map, state.processed)
+    }
+}
+
+/// Lock history of every account.
+pub struct LockHistory(pub PathBuf);
+
+impl Sink for LockHistory {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        locks::write_history(&self.0, &state.client_map)
+    }
+}
+
+/// Suspicious activity report.
+pub struct SarReport {
+    pub path: PathBuf,
+    pub rules: AmlRules,
+}
+
+impl Sink for SarReport {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        aml::write_report(&self.path, &self.rules, &state.client_map)
+    }
+}
+
+/// Final balances committed as one version of a Delta table.
+#[cfg(feature = "delta")]
+impl Sink for crate::delta::DeltaBalances {
+    fn finish(&mut self, state: &State) -> Result<(), TxError> {
+        self.commit(&state.client_map).map_err(TxError::Other)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::PipelineBuilder, Record, TxError};
+
+    /// Validates `input`, returning the counts written and whether the run failed.
+    fn validate(input: &'static str) -> (String, bool) {
+        let records = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize::<Record>()
+            .map(|record| record.map_err(TxError::from));
+        let mut out = Vec::new();
+        let mut pipeline = PipelineBuilder::new().source(records);
+        let events = pipeline.subscribe();
+        let result = pipeline
+            .sink(Validation {
+                out: &mut out,
+                events,
+            })
+            .build()
+            .run();
+        (String::from_utf8(out).unwrap(), result.is_err())
+    }
+
+    #[test]
+    fn validation_counts_the_rejects_per_reason() {
+        let (counts, failed) = validate(
+            "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,1,2,15
+            deposit,1,x,3
+            resolve,1,1,
+            withdrawal,1,3,20",
+        );
+        assert_eq!(
+            counts,
+            "reason,count\ninsufficient_funds,2\ninvalid_transition,1\nmalformed,1\n"
+        );
+        assert!(failed);
+    }
+
+    #[test]
+    fn clean_input_passes_validation() {
+        let (counts, failed) = validate(
+            "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,1,2,5",
+        );
+        assert_eq!(counts, "reason,count\n");
+        assert!(!failed);
+    }
+}
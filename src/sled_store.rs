@@ -0,0 +1,130 @@
+use crate::{
+    store::{self, Counters, StateStore},
+    ClientInfo,
+};
+use sled::{transaction::TransactionError, Transactional, Tree};
+use std::{error::Error, path::Path};
+
+const COUNTERS: &[u8] = b"counters";
+
+/// [`StateStore`] in a sled database directory: the accounts by big-endian client id in
+/// one tree, the counters in another.
+pub struct SledStore {
+    db: sled::Db,
+    accounts: Tree,
+    meta: Tree,
+}
+
+impl SledStore {
+    /// Opens the database at `path`, creating it if missing.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        SledStore::of(sled::open(path)?)
+    }
+
+    pub(crate) fn of(db: sled::Db) -> Result<Self, Box<dyn Error>> {
+        Ok(SledStore {
+            accounts: db.open_tree("accounts")?,
+            meta: db.open_tree("meta")?,
+            db,
+        })
+    }
+}
+
+impl StateStore for SledStore {
+    fn account(&mut self, client: u16) -> Result<Option<ClientInfo>, Box<dyn Error>> {
+        match self.accounts.get(client.to_be_bytes())? {
+            Some(bytes) => Ok(Some(store::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn clients(&mut self) -> Result<Vec<u16>, Box<dyn Error>> {
+        let mut clients = Vec::with_capacity(self.accounts.len());
+        for key in self.accounts.iter().keys() {
+            let key = key?;
+            let id: [u8; 2] = key.as_ref().try_into()?;
+            clients.push(u16::from_be_bytes(id));
+        }
+        Ok(clients)
+    }
+
+    fn counters(&mut self) -> Result<Counters, Box<dyn Error>> {
+        match self.meta.get(COUNTERS)? {
+            Some(bytes) => Ok(rmp_serde::from_slice(&bytes)?),
+            None => Ok(Counters::default()),
+        }
+    }
+
+    fn write(
+        &mut self,
+        accounts: &[(u16, &ClientInfo)],
+        _txs: &[u32],
+        counters: &Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut encoded = Vec::with_capacity(accounts.len());
+        for (client, info) in accounts {
+            encoded.push((client.to_be_bytes(), store::encode(info)?));
+        }
+        let counters = rmp_serde::to_vec(counters)?;
+        (&self.accounts, &self.meta)
+            .transaction(|(accounts, meta)| {
+                for (key, value) in encoded.iter() {
+                    accounts.insert(&key[..], value.as_slice())?;
+                }
+                meta.insert(COUNTERS, counters.as_slice())?;
+                Ok(())
+            })
+            .map_err(|err: TransactionError| format!("state store write failed: {:?}", err))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::PipelineBuilder, Record, TxError};
+    use rust_decimal::Decimal;
+
+    fn records(input: &'static str) -> impl Iterator<Item = Result<Record, TxError>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .map(|record| record.map_err(TxError::from))
+    }
+
+    #[test]
+    fn pages_accounts_out_and_continues_in_the_next_run() {
+        // one database for both runs, sled keeps it open a while after it is dropped
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let run = |input| {
+            PipelineBuilder::new()
+                .source(records(input))
+                .store(SledStore::of(db.clone()).unwrap(), 1)
+                .build()
+                .run()
+                .unwrap()
+        };
+        let first = run("type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,20
+            deposit,3,3,30
+            dispute,1,1,");
+        // every account is read back for the sinks, also the ones paged out
+        assert_eq!(first.client_map.len(), 3);
+        assert_eq!(first.client_map[&1].held_funds, Decimal::from(10));
+        let second = run("type,client,tx,amount
+            resolve,1,1,
+            withdrawal,2,4,5");
+        assert_eq!((second.processed, second.sequence), (6, 6));
+        assert_eq!(second.client_map.len(), 3);
+        assert_eq!(second.client_map[&1].available_funds, Decimal::from(10));
+        assert_eq!(second.client_map[&2].available_funds, Decimal::from(15));
+        assert_eq!(second.client_map[&3].available_funds, Decimal::from(30));
+    }
+}
@@ -0,0 +1,194 @@
+use csv::StringRecord;
+use std::{error::Error, io::Write, path::Path};
+
+/// Columns of each balances snapshot schema version, v1 is what the engine writes today.
+const V1: &[&str] = &["client", "available", "held", "total", "locked"];
+const V2: &[&str] = &["client", "available", "held", "total", "locked", "currency"];
+const V3: &[&str] = &[
+    "client",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "currency",
+    "dispute_count",
+    "account_type",
+];
+
+/// Values for columns that did not exist in the source version.
+#[derive(Debug, Clone)]
+pub struct MigrationDefaults {
+    pub currency: String,
+    pub account_type: String,
+}
+
+impl Default for MigrationDefaults {
+    fn default() -> Self {
+        MigrationDefaults {
+            currency: "USD".to_string(),
+            account_type: "standard".to_string(),
+        }
+    }
+}
+
+/// Parses `v1`, `v2` or `v3`.
+pub fn parse_version(value: &str) -> Result<u32, Box<dyn Error>> {
+    match value {
+        "v1" => Ok(1),
+        "v2" => Ok(2),
+        "v3" => Ok(3),
+        _ => Err(From::from(format!(
+            "unknown snapshot schema version {}, expected v1, v2 or v3",
+            value
+        ))),
+    }
+}
+
+fn columns(version: u32) -> &'static [&'static str] {
+    match version {
+        1 => V1,
+        2 => V2,
+        _ => V3,
+    }
+}
+
+/// Rewrites a snapshot from schema version `from` to `to`. Upgrading fills new columns
+/// with defaults, downgrading drops them. Columns outside every schema (e.g. `aliases`)
+/// are kept after the schema columns, and `#` metadata lines are skipped.
+pub fn migrate<W: Write>(
+    input: &Path,
+    from: u32,
+    to: u32,
+    defaults: &MigrationDefaults,
+    out: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_path(input)?;
+    let headers = reader.headers()?.clone();
+    let position = |name: &str| headers.iter().position(|h| h == name);
+
+    for column in columns(from) {
+        if position(column).is_none() {
+            return Err(From::from(format!(
+                "snapshot is not schema v{}, column {} missing",
+                from, column
+            )));
+        }
+    }
+    let extra: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| !V3.contains(h))
+        .map(|(i, _)| i)
+        .collect();
+
+    let target = columns(to);
+    let mut wtr = csv::Writer::from_writer(out);
+    let mut out_headers: StringRecord = target.iter().copied().collect();
+    out_headers.extend(extra.iter().map(|i| &headers[*i]));
+    wtr.write_record(&out_headers)?;
+
+    for result in reader.records() {
+        let row = result?;
+        let mut out_row = StringRecord::new();
+        for column in target {
+            // only trust columns that belong to the source version
+            let existing = if columns(from).contains(column) {
+                position(column).and_then(|i| row.get(i))
+            } else {
+                None
+            };
+            match existing {
+                Some(value) => out_row.push_field(value),
+                None => out_row.push_field(default_value(column, defaults)),
+            }
+        }
+        for i in extra.iter() {
+            out_row.push_field(row.get(*i).unwrap_or(""));
+        }
+        wtr.write_record(&out_row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn default_value<'a>(column: &str, defaults: &'a MigrationDefaults) -> &'a str {
+    match column {
+        "currency" => &defaults.currency,
+        "account_type" => &defaults.account_type,
+        "dispute_count" => "0",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn migrated(name: &str, snapshot: &str, from: u32, to: u32) -> Result<String, Box<dyn Error>> {
+        let input =
+            std::env::temp_dir().join(format!("snapshot-{}-{}.csv", name, std::process::id()));
+        fs::write(&input, snapshot).unwrap();
+        let mut out = Vec::new();
+        let result = migrate(&input, from, to, &MigrationDefaults::default(), &mut out);
+        fs::remove_file(&input).unwrap();
+        result?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn upgrade_fills_defaults_and_keeps_extra_columns() {
+        let out = migrated(
+            "upgrade",
+            "# schema_version: 1\nclient,available,held,total,locked,aliases\n1,1.5,0,1.5,false,7;8\n",
+            1,
+            3,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "client,available,held,total,locked,currency,dispute_count,account_type,aliases\n\
+             1,1.5,0,1.5,false,USD,0,standard,7;8\n"
+        );
+    }
+
+    #[test]
+    fn downgrade_drops_newer_columns() {
+        let out = migrated(
+            "downgrade",
+            "client,available,held,total,locked,currency,dispute_count,account_type\n\
+             2,3,1,4,true,EUR,2,business\n",
+            3,
+            2,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "client,available,held,total,locked,currency\n2,3,1,4,true,EUR\n"
+        );
+    }
+
+    #[test]
+    fn columns_missing_from_the_source_version_are_refused() {
+        let err = migrated(
+            "missing",
+            "client,available,held,total,locked\n1,1,0,1,false\n",
+            2,
+            3,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "snapshot is not schema v2, column currency missing"
+        );
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(parse_version("v2").unwrap(), 2);
+        assert!(parse_version("3").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::bind_address;
+use axum::{
+    extract::{Extension, Path as UrlPath},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use log::info;
+use serde_json::{json, Map, Value};
+use std::{collections::BTreeMap, error::Error, fs, net::SocketAddr, path::Path, sync::Arc};
+
+type Failure = (StatusCode, Json<Value>);
+
+fn not_found(error: &str) -> Failure {
+    (StatusCode::NOT_FOUND, Json(json!({ "error": error })))
+}
+
+/// A finished run loaded for queries: snapshot rows by client and the audit log lines.
+struct Snapshot {
+    accounts: BTreeMap<u16, Value>,
+    audit_log: Option<Vec<String>>,
+}
+
+/// Turns a snapshot cell into a JSON number or bool where it is one, keeping the rest as text.
+fn cell_value(cell: &str) -> Value {
+    if let Ok(flag) = cell.parse::<bool>() {
+        return Value::Bool(flag);
+    }
+    match cell
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        Some(number) => Value::Number(number),
+        None => Value::String(cell.to_string()),
+    }
+}
+
+fn load(snapshot: &Path, audit_log: Option<&Path>) -> Result<Snapshot, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_path(snapshot)?;
+    let headers = reader.headers()?.clone();
+    let client_column = headers
+        .iter()
+        .position(|h| h == "client")
+        .ok_or("snapshot has no client column")?;
+
+    let mut accounts = BTreeMap::new();
+    for row in reader.records() {
+        let row = row?;
+        let client: u16 = row[client_column].parse()?;
+        let mut account = Map::new();
+        for (name, cell) in headers.iter().zip(row.iter()) {
+            let value = if name == "client" {
+                json!(client)
+            } else {
+                cell_value(cell)
+            };
+            account.insert(name.to_string(), value);
+        }
+        accounts.insert(client, Value::Object(account));
+    }
+
+    let audit_log = match audit_log {
+        Some(path) => Some(
+            fs::read_to_string(path)?
+                .lines()
+                .map(String::from)
+                .collect(),
+        ),
+        None => None,
+    };
+    Ok(Snapshot {
+        accounts,
+        audit_log,
+    })
+}
+
+/// Serves read-only queries over a finished snapshot until the process is stopped:
+/// - `GET /accounts`, every account
+/// - `GET /accounts/{client}`, one account
+/// - `GET /accounts/{client}/history`, the audit log lines of the client, when an audit log
+///   is loaded
+pub fn serve(snapshot: &Path, audit_log: Option<&Path>, addr: &str) -> Result<(), Box<dyn Error>> {
+    let snapshot = load(snapshot, audit_log)?;
+    let addr: SocketAddr = bind_address(addr).parse()?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    info!(
+        "serving {} accounts over HTTP on {}",
+        snapshot.accounts.len(),
+        addr
+    );
+    runtime.block_on(axum::Server::bind(&addr).serve(router(snapshot).into_make_service()))?;
+    Ok(())
+}
+
+/// Only `GET` is routed, any other method is `405`.
+fn router(snapshot: Snapshot) -> Router {
+    Router::new()
+        .route("/accounts", get(accounts))
+        .route("/accounts/:client", get(account))
+        .route("/accounts/:client/history", get(history))
+        .layer(Extension(Arc::new(snapshot)))
+}
+
+async fn accounts(Extension(snapshot): Extension<Arc<Snapshot>>) -> Json<Value> {
+    Json(json!(snapshot.accounts.values().collect::<Vec<_>>()))
+}
+
+/// `404` for a client that is not in the snapshot or an id that is not one.
+async fn account(
+    Extension(snapshot): Extension<Arc<Snapshot>>,
+    UrlPath(client): UrlPath<String>,
+) -> Result<Json<Value>, Failure> {
+    client
+        .parse::<u16>()
+        .ok()
+        .and_then(|client| snapshot.accounts.get(&client))
+        .map(|account| Json(account.clone()))
+        .ok_or_else(|| not_found("not found"))
+}
+
+/// The audit log lines that name the client, `404` when no audit log is loaded.
+async fn history(
+    Extension(snapshot): Extension<Arc<Snapshot>>,
+    UrlPath(client): UrlPath<String>,
+) -> Result<Json<Value>, Failure> {
+    let client = client.parse::<u16>().map_err(|_| not_found("not found"))?;
+    match &snapshot.audit_log {
+        Some(lines) => Ok(Json(json!(client_history(lines, client)))),
+        None => Err(not_found("no audit log loaded")),
+    }
+}
+
+/// Audit log lines that name the client, in log order.
+fn client_history(lines: &[String], client: u16) -> Vec<&str> {
+    let needle = format!("client {}", client);
+    lines
+        .iter()
+        .filter(|line| {
+            line.match_indices(&needle).any(|(at, _)| {
+                // `client 1` must not match `client 12`
+                !line[at + needle.len()..].starts_with(|c: char| c.is_ascii_digit())
+            })
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{Body, HttpBody},
+        http::Request,
+    };
+    use tower::ServiceExt;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", name, std::process::id()))
+    }
+
+    /// Status and body of `request` to a server over a snapshot of clients 1 and 12, its
+    /// files named after `test`.
+    fn get(test: &str, request: Request<Body>) -> (StatusCode, String) {
+        let snapshot = temp_path(&format!("{}-snapshot.csv", test));
+        fs::write(
+            &snapshot,
+            "# input: transactions.csv\n\
+             client,available,held,total,locked\n\
+             1,1.5,0,1.5,false\n\
+             12,0,2,2,true\n",
+        )
+        .unwrap();
+        let audit_log = temp_path(&format!("{}-audit.log", test));
+        fs::write(
+            &audit_log,
+            "seq 1 deposit client 1 tx 1 tags -\n\
+             seq 2 deposit client 12 tx 2 tags -\n\
+             seq 3 withdrawal client 1 tx 3 tags -\n",
+        )
+        .unwrap();
+        let loaded = load(&snapshot, Some(&audit_log)).unwrap();
+        fs::remove_file(&snapshot).unwrap();
+        fs::remove_file(&audit_log).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let response = router(loaded).oneshot(request).await.unwrap();
+            let status = response.status();
+            let mut body = response.into_body();
+            let mut text = String::new();
+            while let Some(chunk) = body.data().await {
+                text.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap());
+            }
+            (status, text)
+        })
+    }
+
+    fn request(method: &str, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn answers_account_and_history_queries() {
+        assert_eq!(
+            get("queries", request("GET", "/accounts/12")),
+            (
+                StatusCode::OK,
+                r#"{"available":0.0,"client":12,"held":2.0,"locked":true,"total":2.0}"#.to_string()
+            )
+        );
+        let (status, accounts) = get("queries", request("GET", "/accounts"));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<Vec<Value>>(&accounts).unwrap().len(),
+            2
+        );
+        // the lines of client 12 are not the history of client 1
+        assert_eq!(
+            get("queries", request("GET", "/accounts/1/history")),
+            (
+                StatusCode::OK,
+                r#"["seq 1 deposit client 1 tx 1 tags -","seq 3 withdrawal client 1 tx 3 tags -"]"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn is_read_only() {
+        assert_eq!(
+            get("read-only", request("POST", "/accounts/1")).0,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            get("read-only", request("GET", "/accounts/2")).0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            get("read-only", request("GET", "/accounts/bob")).0,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{
+    bind_address,
+    messages::{self, Encoding},
+    Record, TxError,
+};
+use log::{error, info};
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Read},
+    net::TcpListener,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+};
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{fs::FileTypeExt, net::UnixListener},
+    path::Path,
+};
+
+/// How the clients of a [`SocketSource`] frame their transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// one per line: a JSON object with the CSV header names as keys, or a CSV row. A
+    /// connection sending CSV may start with a header row, without one the columns are in
+    /// the order of the CSV input.
+    Lines,
+    /// length-delimited `Transaction` messages, as in protobuf input
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+    /// MessagePack transactions prefixed with their length, as in msgpack input
+    #[cfg(feature = "msgpack")]
+    Msgpack,
+}
+
+/// Transactions pushed by any number of clients over plain connections, framed as
+/// `framing`. They are applied in the order they arrive across all connections. Nothing is
+/// sent back.
+///
+/// At most `capacity` transactions wait for the pipeline; past that the connections are not
+/// read until it catches up, so the senders are slowed down by TCP flow control.
+pub struct SocketSource {
+    records: Receiver<Result<Record, String>>,
+}
+
+impl SocketSource {
+    /// Listens on `addr`, `:9000` listens on every interface.
+    pub fn tcp(addr: &str, framing: Framing, capacity: usize) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(bind_address(addr))?;
+        info!("listening for transactions on {}", listener.local_addr()?);
+        Ok(SocketSource::accept(framing, capacity, move || {
+            listener.accept().map(|(stream, _)| stream)
+        }))
+    }
+
+    /// Listens on the unix domain socket `path`, for services on the same host. A stale
+    /// socket file left by an earlier run is replaced.
+    #[cfg(unix)]
+    pub fn unix(path: &Path, framing: Framing, capacity: usize) -> Result<Self, Box<dyn Error>> {
+        if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        info!("listening for transactions on {}", path.display());
+        Ok(SocketSource::accept(framing, capacity, move || {
+            listener.accept().map(|(stream, _)| stream)
+        }))
+    }
+
+    /// Reads every connection `accept` returns on its own thread.
+    fn accept<S, A>(framing: Framing, capacity: usize, mut accept: A) -> Self
+    where
+        S: Read + Send + 'static,
+        A: FnMut() -> io::Result<S> + Send + 'static,
+    {
+        let (sender, records) = mpsc::sync_channel(capacity.max(1));
+        thread::spawn(move || loop {
+            match accept() {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    thread::spawn(move || read_connection(stream, framing, sender));
+                }
+                Err(err) => error!("failed to accept connection: {}", err),
+            }
+        });
+        SocketSource { records }
+    }
+}
+
+impl Iterator for SocketSource {
+    type Item = Result<Record, TxError>;
+
+    /// Waits for the next transaction, forever.
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.records.recv().ok()?.map_err(TxError::malformed))
+    }
+}
+
+/// Reads the transactions of one connection until it is closed. Errors are sent as text,
+/// as a `TxError` can not cross threads.
+fn read_connection<R: Read>(
+    stream: R,
+    framing: Framing,
+    records: SyncSender<Result<Record, String>>,
+) {
+    match framing {
+        Framing::Lines => read_lines(stream, records),
+        #[cfg(feature = "protobuf")]
+        Framing::Protobuf => read_messages(
+            crate::protobuf::ProtobufReader::new(BufReader::new(stream)),
+            records,
+        ),
+        #[cfg(feature = "msgpack")]
+        Framing::Msgpack => read_messages(
+            crate::msgpack::MsgpackReader::new(BufReader::new(stream)),
+            records,
+        ),
+    }
+}
+
+/// Forwards the records of a length prefixed stream. A message that does not decode was read
+/// whole, so the ones after it are still found; a broken frame ends the connection.
+#[cfg(any(feature = "protobuf", feature = "msgpack"))]
+fn read_messages<I>(messages: I, records: SyncSender<Result<Record, String>>)
+where
+    I: Iterator<Item = Result<Record, TxError>>,
+{
+    for message in messages {
+        let record = match message {
+            Ok(record) => Ok(record),
+            Err(TxError::ParseError { message, .. }) => Err(message),
+            Err(err) => {
+                error!("connection failed: {}", err);
+                return;
+            }
+        };
+        if records.send(record).is_err() {
+            // the pipeline stopped
+            return;
+        }
+    }
+}
+
+/// Reads the lines of one connection until it is closed. Lines that are not records are sent
+/// as the text of their parse error.
+fn read_lines<R: Read>(stream: R, records: SyncSender<Result<Record, String>>) {
+    let mut headers = None;
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                error!("connection failed: {}", err);
+                return;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = if line.starts_with('{') {
+            messages::decode(Encoding::Json, line.as_bytes()).map_err(|err| err.to_string())
+        } else {
+            match csv_row(line) {
+                Ok(row) if headers.is_none() && row.get(0) == Some("type") => {
+                    headers = Some(row);
+                    continue;
+                }
+                Ok(row) => row
+                    .deserialize::<Record>(headers.as_ref())
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            }
+        };
+        if records.send(record).is_err() {
+            // the pipeline stopped
+            return;
+        }
+    }
+}
+
+fn csv_row(line: &str) -> Result<csv::StringRecord, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes());
+    let mut row = csv::StringRecord::new();
+    reader.read_record(&mut row)?;
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TxType;
+
+    #[test]
+    fn reads_json_and_csv_lines_with_or_without_a_header() {
+        let (sender, records) = mpsc::sync_channel(4);
+        read_lines(
+            "deposit,1,1,2.5\n\
+             \n\
+             {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"1\"}\n\
+             deposit,1,x,1\n"
+                .as_bytes(),
+            sender.clone(),
+        );
+        read_lines(
+            "type,client,tx,amount,memo\ndeposit, 2, 3, 4, rent\n".as_bytes(),
+            sender,
+        );
+        let read: Vec<Result<Record, String>> = records.iter().collect();
+        assert_eq!(read.len(), 4);
+        let record = read[0].as_ref().unwrap();
+        assert_eq!((record.tx_type.as_str(), record.tx), ("deposit", 1));
+        assert_eq!(read[1].as_ref().unwrap().tx_type, TxType::Withdrawal);
+        assert!(read[2].is_err());
+        let record = read[3].as_ref().unwrap();
+        assert_eq!((record.tx, record.memo.as_deref()), (3, Some("rent")));
+    }
+
+    #[test]
+    fn a_full_queue_stops_the_connection_being_read() {
+        let (sender, records) = mpsc::sync_channel(1);
+        let reader = thread::spawn(move || {
+            read_lines(
+                "deposit,1,1,1\ndeposit,1,2,1\ndeposit,1,3,1\n".as_bytes(),
+                sender,
+            )
+        });
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!reader.is_finished());
+        assert_eq!(records.iter().count(), 3);
+        reader.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_a_stale_unix_socket_and_reads_its_connections() {
+        use std::{io::Write, os::unix::net::UnixStream};
+
+        let path = std::env::temp_dir().join(format!("socket-{}.sock", std::process::id()));
+        // left behind by an earlier run that was killed
+        drop(UnixListener::bind(&path).unwrap());
+        let mut source = SocketSource::unix(&path, Framing::Lines, 1).unwrap();
+        UnixStream::connect(&path)
+            .unwrap()
+            .write_all(b"deposit,1,1,2.5\n")
+            .unwrap();
+        let record = source.next().unwrap().unwrap();
+        assert_eq!(
+            (record.tx_type.as_str(), record.client),
+            ("deposit", Some(1))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
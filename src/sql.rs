@@ -0,0 +1,339 @@
+use crate::{ClientInfo, Record};
+use datafusion::{
+    arrow::{
+        array::{
+            ArrayRef, BooleanArray, DecimalBuilder, StringArray, UInt16Array, UInt32Array,
+            UInt64Array,
+        },
+        csv::Writer,
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    },
+    datasource::MemTable,
+    prelude::SessionContext,
+};
+use rust_decimal::Decimal;
+use std::{collections::HashMap, error::Error, io::Write, sync::Arc};
+
+/// Amounts are `decimal(38, 4)` columns, exact up to four decimal places; amounts with
+/// more are rounded to four.
+const AMOUNT_PRECISION: usize = 38;
+const AMOUNT_SCALE: u32 = 4;
+
+fn amount_type() -> DataType {
+    DataType::Decimal(AMOUNT_PRECISION, AMOUNT_SCALE as usize)
+}
+
+fn amounts(amounts: impl Iterator<Item = Option<Decimal>>) -> datafusion::error::Result<ArrayRef> {
+    let mut builder = DecimalBuilder::new(0, AMOUNT_PRECISION, AMOUNT_SCALE as usize);
+    for amount in amounts {
+        match amount {
+            Some(mut amount) => {
+                amount.rescale(AMOUNT_SCALE);
+                builder.append_value(amount.mantissa())?;
+            }
+            None => builder.append_null()?,
+        }
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// A record that reached the handlers, and the account it left behind.
+#[derive(Debug)]
+struct AuditRow {
+    position: u64,
+    client: Option<u16>,
+    tx_type: String,
+    tx: u32,
+    amount: Option<Decimal>,
+    /// whether the account kept the record, refused ones leave it as it was
+    kept: bool,
+    /// balances after the record, none if there is no account
+    balances: Option<(Decimal, Decimal, Decimal, bool)>,
+}
+
+/// Every record the run applied or refused, in input order, for the `audit` table.
+#[derive(Debug, Default)]
+pub struct AuditTrail {
+    rows: Vec<AuditRow>,
+    /// records kept by the account of the last record read, before it was applied
+    kept_before: u64,
+}
+
+impl AuditTrail {
+    /// Notes `record` before it is handed to the handlers.
+    pub fn read(&mut self, record: &Record, client_map: &HashMap<u16, ClientInfo>) {
+        self.kept_before = kept(record.client, client_map);
+        self.rows.push(AuditRow {
+            position: record.position,
+            client: record.client,
+            tx_type: record.tx_type.to_string(),
+            tx: record.tx,
+            amount: record.amount,
+            kept: false,
+            balances: None,
+        });
+    }
+
+    /// Completes the row of the last record read once the handlers are done with it.
+    pub fn applied(&mut self, client_map: &HashMap<u16, ClientInfo>) {
+        let kept_before = self.kept_before;
+        if let Some(row) = self.rows.last_mut() {
+            // handlers keep every record they take
+            row.kept = kept(row.client, client_map) > kept_before;
+            row.balances = row
+                .client
+                .and_then(|client| client_map.get(&client))
+                .map(|info| {
+                    (
+                        info.available_funds,
+                        info.held_funds,
+                        info.total_funds,
+                        info.locked,
+                    )
+                });
+        }
+    }
+}
+
+fn kept(client: Option<u16>, client_map: &HashMap<u16, ClientInfo>) -> u64 {
+    client
+        .and_then(|client| client_map.get(&client))
+        .map_or(0, |info| info.kept)
+}
+
+/// Runs `sql` over the in-memory results and writes the rows as CSV.
+///
+/// Three tables are registered:
+/// - `balances`: client, available, held, total, locked
+/// - `transactions`: client, type, tx, amount, memo, tags (`;` separated), for every
+///   deposit and withdrawal applied to an account
+/// - `audit`: position, client, type, tx, amount, kept, available, held, total, locked,
+///   for every record that reached the handlers
+pub fn query<W: Write>(
+    sql: &str,
+    client_map: &HashMap<u16, ClientInfo>,
+    audit: &AuditTrail,
+    out: W,
+) -> Result<(), Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    let batches = runtime.block_on(async {
+        let ctx = SessionContext::new();
+        register(&ctx, "balances", balances(client_map)?)?;
+        register(&ctx, "transactions", transactions(client_map)?)?;
+        register(&ctx, "audit", audit_rows(audit)?)?;
+        let df = ctx.sql(sql).await?;
+        df.collect().await
+    })?;
+
+    let mut writer = Writer::new(out);
+    for batch in batches.iter() {
+        writer.write(batch)?;
+    }
+    Ok(())
+}
+
+fn register(ctx: &SessionContext, name: &str, batch: RecordBatch) -> datafusion::error::Result<()> {
+    let table = MemTable::try_new(batch.schema(), vec![vec![batch]])?;
+    ctx.register_table(name, Arc::new(table))?;
+    Ok(())
+}
+
+fn balances(client_map: &HashMap<u16, ClientInfo>) -> datafusion::error::Result<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", amount_type(), false),
+        Field::new("held", amount_type(), false),
+        Field::new("total", amount_type(), false),
+        Field::new("locked", DataType::Boolean, false),
+    ]);
+    let mut clients: Vec<(&u16, &ClientInfo)> = client_map.iter().collect();
+    // queries without an ORDER BY then come out in the same order every run
+    clients.sort_unstable_by_key(|(client, _)| **client);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt16Array::from_iter_values(
+            clients.iter().map(|(c, _)| **c),
+        )),
+        amounts(clients.iter().map(|(_, v)| Some(v.available_funds)))?,
+        amounts(clients.iter().map(|(_, v)| Some(v.held_funds)))?,
+        amounts(clients.iter().map(|(_, v)| Some(v.total_funds)))?,
+        Arc::new(BooleanArray::from(
+            clients.iter().map(|(_, v)| v.locked).collect::<Vec<bool>>(),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn transactions(client_map: &HashMap<u16, ClientInfo>) -> datafusion::error::Result<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("amount", amount_type(), true),
+        Field::new("memo", DataType::Utf8, true),
+        Field::new("tags", DataType::Utf8, false),
+    ]);
+    let mut clients: Vec<(&u16, &ClientInfo)> = client_map.iter().collect();
+    clients.sort_unstable_by_key(|(client, _)| **client);
+    let records: Vec<(u16, &crate::transactions::TxEntry)> = clients
+        .into_iter()
+        .flat_map(|(client, info)| info.transactions().iter().map(move |r| (*client, r)))
+        .collect();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt16Array::from_iter_values(
+            records.iter().map(|(c, _)| *c),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            records.iter().map(|(_, r)| r.kind.as_str()),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            records.iter().map(|(_, r)| r.tx),
+        )),
+        amounts(records.iter().map(|(_, r)| r.amount))?,
+        Arc::new(StringArray::from(
+            records
+                .iter()
+                .map(|(_, r)| r.memo())
+                .collect::<Vec<Option<&str>>>(),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            records.iter().map(|(_, r)| r.tags().join(";")),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn audit_rows(audit: &AuditTrail) -> datafusion::error::Result<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("position", DataType::UInt64, false),
+        Field::new("client", DataType::UInt16, true),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("amount", amount_type(), true),
+        Field::new("kept", DataType::Boolean, false),
+        Field::new("available", amount_type(), true),
+        Field::new("held", amount_type(), true),
+        Field::new("total", amount_type(), true),
+        Field::new("locked", DataType::Boolean, true),
+    ]);
+    let rows = &audit.rows;
+    let balance = |pick: fn(&(Decimal, Decimal, Decimal, bool)) -> Decimal| {
+        amounts(rows.iter().map(|row| row.balances.as_ref().map(pick)))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.position),
+        )),
+        Arc::new(UInt16Array::from(
+            rows.iter()
+                .map(|row| row.client)
+                .collect::<Vec<Option<u16>>>(),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.tx_type.as_str()),
+        )),
+        Arc::new(UInt32Array::from_iter_values(rows.iter().map(|row| row.tx))),
+        amounts(rows.iter().map(|row| row.amount))?,
+        Arc::new(BooleanArray::from(
+            rows.iter().map(|row| row.kept).collect::<Vec<bool>>(),
+        )),
+        balance(|b| b.0)?,
+        balance(|b| b.1)?,
+        balance(|b| b.2)?,
+        Arc::new(BooleanArray::from(
+            rows.iter()
+                .map(|row| row.balances.map(|b| b.3))
+                .collect::<Vec<Option<bool>>>(),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_record, policy::Policy};
+    use csv::Trim;
+
+    /// Applies the records of `input` as the run loop does, with the audit trail.
+    fn run(input: &str) -> (HashMap<u16, ClientInfo>, AuditTrail) {
+        let mut client_map = HashMap::new();
+        let mut audit = AuditTrail::default();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        for (i, record) in reader.deserialize().enumerate() {
+            let mut record: Record = record.unwrap();
+            record.position = i as u64 + 1;
+            audit.read(&record, &client_map);
+            apply_record(&mut client_map, record, &Policy::default()).unwrap();
+            audit.applied(&client_map);
+        }
+        (client_map, audit)
+    }
+
+    fn query_csv(input: &str, sql: &str) -> String {
+        let (client_map, audit) = run(input);
+        let mut out = Vec::new();
+        query(sql, &client_map, &audit, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const INPUT: &str = "type,client,tx,amount
+        deposit,1,1,10.5
+        deposit,2,2,3
+        withdrawal,2,3,5
+        dispute,1,1,
+        chargeback,1,1,
+        deposit,1,4,1";
+
+    #[test]
+    fn queries_the_balances() {
+        let out = query_csv(
+            INPUT,
+            "SELECT client, total, locked FROM balances ORDER BY client",
+        );
+        assert_eq!(out, "client,total,locked\n1,0.0000,true\n2,3.0000,false\n");
+    }
+
+    #[test]
+    fn queries_the_transactions_applied_to_accounts() {
+        // the dispute and chargeback update the deposit, the refused deposit is left out
+        let out = query_csv(
+            INPUT,
+            "SELECT type, tx FROM transactions WHERE client = 1 ORDER BY tx, type",
+        );
+        assert_eq!(out, "type,tx\ndeposit,1\n");
+    }
+
+    #[test]
+    fn audit_lists_every_record_with_its_outcome() {
+        // the overdraft withdrawal and the deposit on the locked account are refused
+        let out = query_csv(
+            INPUT,
+            "SELECT position, type, kept, available, locked FROM audit ORDER BY position",
+        );
+        assert_eq!(
+            out,
+            "position,type,kept,available,locked\n\
+             1,deposit,true,10.5000,false\n\
+             2,deposit,true,3.0000,false\n\
+             3,withdrawal,false,3.0000,false\n\
+             4,dispute,true,0.0000,false\n\
+             5,chargeback,true,0.0000,true\n\
+             6,deposit,false,0.0000,true\n"
+        );
+    }
+
+    #[test]
+    fn amounts_are_exact_to_four_places() {
+        let out = query_csv(
+            "type,client,tx,amount
+            deposit,1,1,0.1
+            deposit,1,2,0.2
+            deposit,2,3,1.23456",
+            "SELECT client, SUM(amount) AS amount FROM transactions GROUP BY client ORDER BY client",
+        );
+        assert_eq!(out, "client,amount\n1,0.3000\n2,1.2346\n");
+    }
+}
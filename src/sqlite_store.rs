@@ -0,0 +1,225 @@
+use crate::{
+    store::{self, Counters, StateStore},
+    Balance, ClientInfo,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{error::Error, path::Path};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    client INTEGER PRIMARY KEY,
+    available TEXT NOT NULL,
+    held TEXT NOT NULL,
+    total TEXT NOT NULL,
+    locked INTEGER NOT NULL,
+    -- everything the engine keeps of the account, to continue from
+    state BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS transactions (
+    client INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    tx INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    amount TEXT,
+    fee TEXT NOT NULL,
+    dispute_state TEXT NOT NULL,
+    voided INTEGER NOT NULL,
+    timestamp TEXT,
+    PRIMARY KEY (client, position)
+);
+CREATE TABLE IF NOT EXISTS counters (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    processed INTEGER NOT NULL,
+    fees_seen INTEGER NOT NULL,
+    sequence INTEGER NOT NULL
+);
+";
+
+/// [`StateStore`] in a SQLite database, with the balances and the applied deposits and
+/// withdrawals in plain tables next to the state the engine continues from. Amounts are
+/// decimal text.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and its tables if missing.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStore { conn })
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn account(&mut self, client: u16) -> Result<Option<ClientInfo>, Box<dyn Error>> {
+        let state: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT state FROM accounts WHERE client = ?",
+                params![client],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match state {
+            Some(bytes) => Ok(Some(store::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn clients(&mut self) -> Result<Vec<u16>, Box<dyn Error>> {
+        let mut statement = self.conn.prepare("SELECT client FROM accounts")?;
+        let clients = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<u16>, _>>()?;
+        Ok(clients)
+    }
+
+    fn counters(&mut self) -> Result<Counters, Box<dyn Error>> {
+        let counters = self
+            .conn
+            .query_row(
+                "SELECT processed, fees_seen, sequence FROM counters WHERE id = 0",
+                [],
+                |row| {
+                    Ok(Counters {
+                        processed: row.get(0)?,
+                        fees_seen: row.get(1)?,
+                        sequence: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(counters.unwrap_or_default())
+    }
+
+    fn write(
+        &mut self,
+        accounts: &[(u16, &ClientInfo)],
+        txs: &[u32],
+        counters: &Counters,
+    ) -> Result<(), Box<dyn Error>> {
+        let transaction = self.conn.transaction()?;
+        for (client, info) in accounts {
+            let balance = Balance::of(*client, info);
+            transaction.execute(
+                "INSERT INTO accounts (client, available, held, total, locked, state)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (client) DO UPDATE SET available = ?2, held = ?3, total = ?4,
+                 locked = ?5, state = ?6",
+                params![
+                    client,
+                    balance.available.to_string(),
+                    balance.held.to_string(),
+                    balance.total.to_string(),
+                    balance.locked,
+                    store::encode(info)?,
+                ],
+            )?;
+            let entries = info
+                .transactions()
+                .iter()
+                .filter(|entry| txs.contains(&entry.tx));
+            for entry in entries {
+                transaction.execute(
+                    "INSERT INTO transactions (client, position, tx, type, amount, fee,
+                     dispute_state, voided, timestamp)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                     ON CONFLICT (client, position) DO UPDATE SET dispute_state = ?7,
+                     voided = ?8",
+                    params![
+                        client,
+                        entry.position,
+                        entry.tx,
+                        entry.kind.as_str(),
+                        entry.amount.map(|amount| amount.to_string()),
+                        entry.fee.to_string(),
+                        entry.state.to_string(),
+                        entry.voided,
+                        entry.timestamp().map(|timestamp| timestamp.to_rfc3339()),
+                    ],
+                )?;
+            }
+        }
+        transaction.execute(
+            "INSERT INTO counters (id, processed, fees_seen, sequence) VALUES (0, ?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET processed = ?1, fees_seen = ?2, sequence = ?3",
+            params![counters.processed, counters.fees_seen, counters.sequence],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        // every write is committed
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::PipelineBuilder, Record, TxError};
+    use std::fs;
+
+    fn records(input: &'static str) -> impl Iterator<Item = Result<Record, TxError>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .map(|record| record.map_err(TxError::from))
+    }
+
+    #[test]
+    fn keeps_balances_and_dispute_states_in_tables_across_runs() {
+        let path = std::env::temp_dir().join(format!("{}-accounts.db", std::process::id()));
+        let run = |input| {
+            PipelineBuilder::new()
+                .source(records(input))
+                .store(SqliteStore::open(&path).unwrap(), 1)
+                .build()
+                .run()
+                .unwrap()
+        };
+        let dispute_state = |tx: u32| -> String {
+            let conn = Connection::open(&path).unwrap();
+            conn.query_row(
+                "SELECT dispute_state FROM transactions WHERE tx = ?",
+                params![tx],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        run("type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,20
+            dispute,1,1,");
+        assert_eq!(dispute_state(1), "disputed");
+        assert_eq!(dispute_state(2), "normal");
+        run("type,client,tx,amount
+            resolve,1,1,
+            withdrawal,2,3,5");
+        assert_eq!(dispute_state(1), "resolved");
+
+        let conn = Connection::open(&path).unwrap();
+        let balances: Vec<(u16, String, String)> = conn
+            .prepare("SELECT client, available, held FROM accounts ORDER BY client")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let transactions: u32 = conn
+            .query_row("SELECT COUNT(*) FROM transactions", [], |row| row.get(0))
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            balances,
+            vec![
+                (1, "10".to_string(), "0".to_string()),
+                (2, "15".to_string(), "0".to_string())
+            ]
+        );
+        assert_eq!(transactions, 3);
+    }
+}
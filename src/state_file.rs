@@ -0,0 +1,150 @@
+use crate::{pipeline::State, store::Counters, ClientInfo, TxError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+};
+
+/// Start of every state file, the number is the layout version. Files of another version are
+/// refused rather than misread.
+const MAGIC: &[u8; 8] = b"TXSTATE1";
+
+/// The whole engine state: the counters and every account with its transactions, tx index,
+/// dispute states and lock history.
+#[derive(Serialize, Deserialize)]
+struct Saved<A> {
+    counters: Counters,
+    accounts: A,
+}
+
+/// Writes `state` as MessagePack after the version header.
+pub fn write<W: Write>(state: &State, mut out: W) -> Result<(), TxError> {
+    out.write_all(MAGIC)?;
+    let saved = Saved {
+        counters: Counters::of(state),
+        // by client, so the same state writes the same file
+        accounts: state.client_map.iter().collect::<BTreeMap<_, _>>(),
+    };
+    encode(&mut out, &saved)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Reads a state written by [`write`], to continue from.
+pub fn read<R: Read>(mut input: R) -> Result<State, TxError> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(TxError::other(
+            "not a state file, or one written by another version",
+        ));
+    }
+    let saved: Saved<HashMap<u16, ClientInfo>> = decode(input)?;
+    Ok(State {
+        client_map: saved.accounts,
+        processed: saved.counters.processed,
+        fees_seen: saved.counters.fees_seen,
+        sequence: saved.counters.sequence,
+        ..State::default()
+    })
+}
+
+/// Writes `value` as MessagePack, the encoding of state files, checkpoints and the WAL.
+#[cfg(feature = "msgpack")]
+pub(crate) fn encode<W: Write, T: Serialize>(out: &mut W, value: &T) -> Result<(), TxError> {
+    rmp_serde::encode::write(out, value).map_err(TxError::other)
+}
+
+#[cfg(not(feature = "msgpack"))]
+pub(crate) fn encode<W: Write, T: Serialize>(_out: &mut W, _value: &T) -> Result<(), TxError> {
+    Err(TxError::other("state files need the msgpack feature"))
+}
+
+/// Reads a value written by [`encode`].
+#[cfg(feature = "msgpack")]
+pub(crate) fn decode<R: Read, T: DeserializeOwned>(input: R) -> Result<T, TxError> {
+    rmp_serde::from_read(input).map_err(TxError::other)
+}
+
+#[cfg(not(feature = "msgpack"))]
+pub(crate) fn decode<R: Read, T: DeserializeOwned>(_input: R) -> Result<T, TxError> {
+    Err(TxError::other("state files need the msgpack feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "msgpack")]
+    fn run(input: &'static str, state: State) -> State {
+        use crate::{pipeline::PipelineBuilder, Record};
+        let records = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .map(|record: csv::Result<Record>| record.map_err(TxError::from));
+        PipelineBuilder::new()
+            .source(records)
+            .state(state)
+            .build()
+            .run()
+            .unwrap()
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn a_loaded_state_continues_where_the_saved_run_stopped() {
+        use rust_decimal::Decimal;
+        let first = run(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,5
+            dispute,1,1,",
+            State::default(),
+        );
+        let mut file = Vec::new();
+        write(&first, &mut file).unwrap();
+        let loaded = read(file.as_slice()).unwrap();
+        assert_eq!((loaded.processed, loaded.sequence), (3, 3));
+
+        let second = run(
+            "type,client,tx,amount
+            resolve,1,1,
+            withdrawal,2,3,2",
+            loaded,
+        );
+        assert_eq!(second.sequence, 5);
+        assert_eq!(second.client_map[&1].available_funds, Decimal::from(10));
+        assert_eq!(second.client_map[&1].held_funds, Decimal::ZERO);
+        assert_eq!(second.client_map[&2].available_funds, Decimal::from(3));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn the_same_state_writes_the_same_file() {
+        let input = "type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,5
+            deposit,3,3,5
+            deposit,4,4,5
+            deposit,1,5,1
+            deposit,1,6,1
+            deposit,1,7,1
+            dispute,1,1,";
+        let file = || {
+            let mut file = Vec::new();
+            write(&run(input, State::default()), &mut file).unwrap();
+            file
+        };
+        // every map has its own hash seed, so only a sorted file comes out the same
+        for _ in 0..5 {
+            assert_eq!(file(), file());
+        }
+    }
+
+    #[test]
+    fn refuses_a_file_of_another_layout() {
+        let err = read(&b"TXSTATE0rest"[..]).map(|_| ()).unwrap_err();
+        assert!(err.to_string().contains("another version"), "{}", err);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{pipeline::State, ClientInfo, TxError};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    error::Error,
+};
+
+/// Counters of the state that are not per account.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Counters {
+    pub processed: u64,
+    pub fees_seen: bool,
+    pub sequence: u64,
+}
+
+impl Counters {
+    pub fn of(state: &State) -> Self {
+        Counters {
+            processed: state.processed,
+            fees_seen: state.fees_seen,
+            sequence: state.sequence,
+        }
+    }
+}
+
+/// Keeps accounts outside of memory, so a pipeline only holds the ones it is working on and
+/// the state survives the process. Accounts are written as the records change them.
+pub trait StateStore {
+    /// The stored account of `client`, `None` if it has none yet.
+    fn account(&mut self, client: u16) -> Result<Option<ClientInfo>, Box<dyn Error>>;
+
+    /// Every client with a stored account.
+    fn clients(&mut self) -> Result<Vec<u16>, Box<dyn Error>>;
+
+    /// The stored counters, zero in a new store.
+    fn counters(&mut self) -> Result<Counters, Box<dyn Error>>;
+
+    /// Stores the accounts one record changed and the counters after it, all or nothing.
+    /// `txs` are the ids of the record and of the disputes it resolved, the transactions of
+    /// the accounts with those ids are the ones it can have added or changed.
+    fn write(
+        &mut self,
+        accounts: &[(u16, &ClientInfo)],
+        txs: &[u32],
+        counters: &Counters,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Makes every write so far durable.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>>;
+}
+
+/// Encoding of an account in a store.
+#[cfg(any(feature = "sled", feature = "sqlite"))]
+pub fn encode(info: &ClientInfo) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(rmp_serde::to_vec(info)?)
+}
+
+#[cfg(any(feature = "sled", feature = "sqlite"))]
+pub fn decode(bytes: &[u8]) -> Result<ClientInfo, Box<dyn Error>> {
+    Ok(rmp_serde::from_slice(bytes)?)
+}
+
+/// Moves accounts between a store and the state of a pipeline, holding at most `capacity`
+/// accounts in memory besides the pinned ones.
+pub(crate) struct Paging<'a> {
+    store: Box<dyn StateStore + Send + 'a>,
+    capacity: usize,
+    /// accounts in memory that may be written out, oldest first
+    resident: VecDeque<u16>,
+    queued: HashSet<u16>,
+    /// accounts that stay in memory, such as the suspense account
+    pinned: HashSet<u16>,
+    /// whether the counters and the pinned accounts were taken from the store yet
+    opened: bool,
+}
+
+impl<'a> Paging<'a> {
+    pub(crate) fn new(
+        store: Box<dyn StateStore + Send + 'a>,
+        capacity: usize,
+        pinned: HashSet<u16>,
+    ) -> Self {
+        Paging {
+            store,
+            capacity,
+            resident: VecDeque::new(),
+            queued: HashSet::new(),
+            pinned,
+            opened: false,
+        }
+    }
+
+    /// Takes the counters and the pinned accounts from the store, the first time only. The
+    /// other stored accounts are loaded as records refer to them.
+    pub(crate) fn open(&mut self, state: &mut State) -> Result<(), TxError> {
+        if self.opened {
+            return Ok(());
+        }
+        self.opened = true;
+        let counters = self.store.counters().map_err(TxError::Other)?;
+        state.processed = counters.processed;
+        state.fees_seen = counters.fees_seen;
+        state.sequence = counters.sequence;
+        state.paged_out = self
+            .store
+            .clients()
+            .map_err(TxError::Other)?
+            .into_iter()
+            .collect();
+        info!(
+            "state store holds {} accounts, {} records applied",
+            state.paged_out.len(),
+            counters.sequence
+        );
+        let pinned: Vec<u16> = self.pinned.iter().copied().collect();
+        for client in pinned {
+            self.load(state, client)?;
+        }
+        Ok(())
+    }
+
+    /// Brings the account of `client` back into memory if it was written out.
+    pub(crate) fn load(&mut self, state: &mut State, client: u16) -> Result<(), TxError> {
+        if state.paged_out.remove(&client) {
+            let info = self
+                .store
+                .account(client)
+                .map_err(TxError::Other)?
+                .unwrap_or_default();
+            state.client_map.insert(client, info);
+            self.queue(client);
+        }
+        Ok(())
+    }
+
+    /// Stores the accounts of `clients` as the records with ids `txs` left them, then writes
+    /// out the oldest accounts above the capacity.
+    pub(crate) fn write(
+        &mut self,
+        state: &mut State,
+        clients: &[u16],
+        txs: &[u32],
+    ) -> Result<(), TxError> {
+        let accounts: Vec<(u16, &ClientInfo)> = clients
+            .iter()
+            .filter_map(|client| Some((*client, state.client_map.get(client)?)))
+            .collect();
+        self.store
+            .write(&accounts, txs, &Counters::of(state))
+            .map_err(TxError::Other)?;
+
+        for client in clients {
+            // new accounts
+            if state.client_map.contains_key(client) {
+                self.queue(*client);
+            }
+        }
+        // every account in memory is stored by now, so it can be dropped
+        while self.resident.len() > self.capacity {
+            if let Some(client) = self.resident.pop_front() {
+                self.queued.remove(&client);
+                state.client_map.remove(&client);
+                state.paged_out.insert(client);
+            }
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, client: u16) {
+        if !self.pinned.contains(&client) && self.queued.insert(client) {
+            self.resident.push_back(client);
+        }
+    }
+
+    /// Stores the counters and loads every account, for the sinks that report on all of them.
+    pub(crate) fn finish(&mut self, state: &mut State) -> Result<(), TxError> {
+        self.open(state)?;
+        self.store
+            .write(&[], &[], &Counters::of(state))
+            .map_err(TxError::Other)?;
+        self.store.flush().map_err(TxError::Other)?;
+        for client in state.paged_out.drain() {
+            let info = self
+                .store
+                .account(client)
+                .map_err(TxError::Other)?
+                .unwrap_or_default();
+            state.client_map.insert(client, info);
+        }
+        Ok(())
+    }
+}
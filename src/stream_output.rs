@@ -0,0 +1,207 @@
+use crate::{ClientInfo, TxError};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// One NDJSON line, the balances of an account right after a change.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AccountUpdate {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    /// position of the record that caused the change
+    position: u64,
+    /// sequence number of the record that caused the change
+    sequence: u64,
+}
+
+impl AccountUpdate {
+    pub fn new(client: u16, info: &ClientInfo, position: u64, sequence: u64) -> Self {
+        AccountUpdate {
+            client,
+            available: info.available_funds,
+            held: info.held_funds,
+            total: info.total_funds,
+            locked: info.locked,
+            position,
+            sequence,
+        }
+    }
+
+    fn same_state(&self, other: &AccountUpdate) -> bool {
+        self.available == other.available
+            && self.held == other.held
+            && self.total == other.total
+            && self.locked == other.locked
+    }
+}
+
+/// Writes a JSON line per account each time its balances change, at most one line per
+/// account per `min_interval`. Changes inside the interval are held back and the latest
+/// one is written once the interval passes or the stream finishes, so the last line per
+/// account always matches the final state.
+pub struct AccountStream<W: Write> {
+    out: W,
+    min_interval: Duration,
+    last_written: HashMap<u16, (Instant, AccountUpdate)>,
+    pending: BTreeMap<u16, AccountUpdate>,
+}
+
+impl<W: Write> AccountStream<W> {
+    pub fn new(out: W, min_interval: Duration) -> Self {
+        AccountStream {
+            out,
+            min_interval,
+            last_written: HashMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, update: AccountUpdate) -> Result<(), TxError> {
+        let now = Instant::now();
+        match self.last_written.get(&update.client) {
+            Some((_, last)) if last.same_state(&update) => {
+                self.pending.remove(&update.client);
+            }
+            Some((at, _)) if now.duration_since(*at) < self.min_interval => {
+                self.pending.insert(update.client, update);
+            }
+            _ => self.write(update, now)?,
+        }
+        self.write_due(now)
+    }
+
+    /// Writes everything still held back.
+    pub fn finish(&mut self) -> Result<(), TxError> {
+        let now = Instant::now();
+        for (_, update) in std::mem::take(&mut self.pending) {
+            self.write(update, now)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn write_due(&mut self, now: Instant) -> Result<(), TxError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let min_interval = self.min_interval;
+        let last_written = &self.last_written;
+        let due: Vec<u16> = self
+            .pending
+            .keys()
+            .filter(|client| {
+                last_written
+                    .get(client)
+                    .is_none_or(|(at, _)| now.duration_since(*at) >= min_interval)
+            })
+            .copied()
+            .collect();
+        for client in due {
+            if let Some(update) = self.pending.remove(&client) {
+                self.write(update, now)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, update: AccountUpdate, now: Instant) -> Result<(), TxError> {
+        serde_json::to_writer(&mut self.out, &update)?;
+        self.out.write_all(b"\n")?;
+        self.last_written.insert(update.client, (now, update));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(client: u16, available: &str, position: u64) -> AccountUpdate {
+        let available = available.parse().unwrap();
+        AccountUpdate {
+            client,
+            available,
+            held: Decimal::ZERO,
+            total: available,
+            locked: false,
+            position,
+            sequence: position,
+        }
+    }
+
+    /// The `(client, available, position)` of every line written.
+    fn lines(out: &[u8]) -> Vec<(u16, String, u64)> {
+        std::str::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                (
+                    value["client"].as_u64().unwrap() as u16,
+                    value["available"].as_str().unwrap().to_string(),
+                    value["position"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn without_an_interval_every_change_is_written() {
+        let mut out = Vec::new();
+        let mut stream = AccountStream::new(&mut out, Duration::ZERO);
+        stream.update(update(1, "1", 1)).unwrap();
+        stream.update(update(2, "5", 2)).unwrap();
+        stream.update(update(1, "3", 3)).unwrap();
+        // no change, e.g. a refused withdrawal
+        stream.update(update(1, "3", 4)).unwrap();
+        stream.finish().unwrap();
+        assert_eq!(
+            lines(&out),
+            vec![
+                (1, "1".to_string(), 1),
+                (2, "5".to_string(), 2),
+                (1, "3".to_string(), 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_inside_the_interval_are_held_back_until_the_end() {
+        let mut out = Vec::new();
+        let mut stream = AccountStream::new(&mut out, Duration::from_secs(3600));
+        stream.update(update(1, "1", 1)).unwrap();
+        stream.update(update(1, "2", 2)).unwrap();
+        stream.update(update(2, "5", 3)).unwrap();
+        stream.update(update(1, "4", 4)).unwrap();
+        stream.update(update(2, "6", 5)).unwrap();
+        stream.finish().unwrap();
+        // the first change of each account at once, then only the latest one
+        assert_eq!(
+            lines(&out),
+            vec![
+                (1, "1".to_string(), 1),
+                (2, "5".to_string(), 3),
+                (1, "4".to_string(), 4),
+                (2, "6".to_string(), 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn a_change_undone_inside_the_interval_is_not_written() {
+        let mut out = Vec::new();
+        let mut stream = AccountStream::new(&mut out, Duration::from_secs(3600));
+        stream.update(update(1, "1", 1)).unwrap();
+        stream.update(update(1, "2", 2)).unwrap();
+        stream.update(update(1, "1", 3)).unwrap();
+        stream.finish().unwrap();
+        assert_eq!(lines(&out), vec![(1, "1".to_string(), 1)]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{ClientInfo, TxError};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
+
+/// One line of the `report by-tag` output.
+#[derive(Serialize, Debug)]
+struct TagSummary<'a> {
+    /// empty for the global report
+    client: Option<u16>,
+    tag: &'a str,
+    #[serde(rename = "type")]
+    tx_type: &'a str,
+    count: u64,
+    amount: Decimal,
+}
+
+/// One line of the `report by-tag --by-month` output.
+#[derive(Serialize, Debug)]
+struct TagMonthSummary<'a> {
+    /// empty for the global report
+    client: Option<u16>,
+    /// `YYYY-MM`, empty for the records without a timestamp
+    month: &'a str,
+    tag: &'a str,
+    #[serde(rename = "type")]
+    tx_type: &'a str,
+    count: u64,
+    amount: Decimal,
+}
+
+/// client, month, tag and type
+type TotalsKey<'a> = (Option<u16>, String, &'a str, &'a str);
+
+/// Writes counts and summed amounts per tag and transaction type for every record applied
+/// to an account, per client or across all clients when `global` is set, and per month of
+/// the records' timestamps when `by_month` is set.
+/// Untagged transactions are not reported.
+pub fn write<W: Write>(
+    out: W,
+    client_map: &HashMap<u16, ClientInfo>,
+    global: bool,
+    by_month: bool,
+) -> Result<(), TxError> {
+    // keyed by (client, month, tag, type) so the output is sorted, the month is left
+    // empty unless grouping by it
+    let mut totals: BTreeMap<TotalsKey, (u64, Decimal)> = BTreeMap::new();
+    for (client, info) in client_map.iter() {
+        let client = if global { None } else { Some(*client) };
+        for ((tag, tx_type, month), (count, amount)) in info.tag_totals.iter() {
+            let month = if by_month {
+                month.clone()
+            } else {
+                String::new()
+            };
+            let entry = totals
+                .entry((client, month, tag.as_str(), tx_type.as_str()))
+                .or_insert((0, Decimal::ZERO));
+            entry.0 += *count;
+            // a report figure, it saturates rather than failing the report
+            entry.1 = entry.1.saturating_add(*amount);
+        }
+    }
+
+    let mut wtr = csv::Writer::from_writer(out);
+    for ((client, month, tag, tx_type), (count, amount)) in totals {
+        if by_month {
+            wtr.serialize(TagMonthSummary {
+                client,
+                month: &month,
+                tag,
+                tx_type,
+                count,
+                amount,
+            })?;
+        } else {
+            wtr.serialize(TagSummary {
+                client,
+                tag,
+                tx_type,
+                count,
+                amount,
+            })?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apply_record, policy::Policy, tagging::TagRules, Record};
+    use csv::Trim;
+
+    /// Applies the deposits and withdrawals of `input`, tagging every record `all` and the
+    /// withdrawals `out`, and returns the report.
+    fn report(input: &str, global: bool, by_month: bool) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "tag-report-rules-{}-{}-{}.csv",
+            std::process::id(),
+            global,
+            by_month
+        ));
+        std::fs::write(
+            &path,
+            "tag,type,min_amount,max_amount,memo_regex,clients\nall,,,,,\nout,withdrawal,,,,",
+        )
+        .unwrap();
+        let rules = TagRules::from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut client_map = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        for record in reader.deserialize() {
+            let mut record: Record = record.unwrap();
+            rules.tag(&mut record);
+            apply_record(&mut client_map, record, &Policy::default()).unwrap();
+        }
+        let mut out = Vec::new();
+        write(&mut out, &client_map, global, by_month).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const INPUT: &str = "type,client,tx,amount,timestamp
+        deposit,1,1,10,2022-03-01T00:00:00Z
+        deposit,2,2,5,2022-04-01T00:00:00Z
+        withdrawal,1,3,4,2022-04-02T00:00:00Z
+        deposit,1,4,1,";
+
+    #[test]
+    fn sums_per_client_tag_and_type() {
+        assert_eq!(
+            report(INPUT, false, false),
+            "client,tag,type,count,amount\n\
+             1,all,deposit,2,11\n\
+             1,all,withdrawal,1,4\n\
+             1,out,withdrawal,1,4\n\
+             2,all,deposit,1,5\n"
+        );
+    }
+
+    #[test]
+    fn sums_across_clients_when_global() {
+        assert_eq!(
+            report(INPUT, true, false),
+            "client,tag,type,count,amount\n\
+             ,all,deposit,3,16\n\
+             ,all,withdrawal,1,4\n\
+             ,out,withdrawal,1,4\n"
+        );
+    }
+
+    #[test]
+    fn groups_by_month_of_the_timestamp() {
+        assert_eq!(
+            report(INPUT, true, true),
+            "client,month,tag,type,count,amount\n\
+             ,,all,deposit,1,1\n\
+             ,2022-03,all,deposit,1,10\n\
+             ,2022-04,all,deposit,1,5\n\
+             ,2022-04,all,withdrawal,1,4\n\
+             ,2022-04,out,withdrawal,1,4\n"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{Record, TxType};
+use regex::Regex;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::HashSet, error::Error, path::Path};
+
+/// Row of the tagging rules file. Empty columns match everything.
+///
+/// ```text
+/// tag,type,min_amount,max_amount,memo_regex,clients
+/// gambling,withdrawal,,,(?i)casino|bet,
+/// large,deposit,10000,,,
+/// vip,,,,,1;7;42
+/// ```
+#[derive(Debug, Deserialize)]
+struct RuleRow {
+    tag: String,
+    #[serde(rename = "type", deserialize_with = "csv::invalid_option")]
+    tx_type: Option<TxType>,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    min_amount: Option<Decimal>,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    max_amount: Option<Decimal>,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    memo_regex: Option<String>,
+    /// `;` separated client ids
+    #[serde(deserialize_with = "csv::invalid_option")]
+    clients: Option<String>,
+}
+
+#[derive(Debug)]
+struct TagRule {
+    tag: String,
+    tx_type: Option<TxType>,
+    min_amount: Option<Decimal>,
+    max_amount: Option<Decimal>,
+    memo: Option<Regex>,
+    clients: Option<HashSet<u16>>,
+}
+
+/// Ordered set of rules, every matching rule attaches its tag.
+#[derive(Debug, Default)]
+pub struct TagRules {
+    rules: Vec<TagRule>,
+}
+
+impl TagRules {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        let mut rules = Vec::new();
+        for result in reader.deserialize() {
+            let row: RuleRow = result?;
+            let memo = match row.memo_regex {
+                Some(pattern) => Some(Regex::new(&pattern)?),
+                None => None,
+            };
+            let clients = match row.clients {
+                Some(list) => Some(
+                    list.split(';')
+                        .map(|id| id.trim().parse::<u16>())
+                        .collect::<Result<HashSet<u16>, _>>()?,
+                ),
+                None => None,
+            };
+            rules.push(TagRule {
+                tag: row.tag,
+                tx_type: row.tx_type,
+                min_amount: row.min_amount,
+                max_amount: row.max_amount,
+                memo,
+                clients,
+            });
+        }
+        Ok(TagRules { rules })
+    }
+
+    /// Attaches the tag of every matching rule to the record, skipping tags it already has.
+    pub fn tag(&self, record: &mut Record) {
+        let matched: Vec<&String> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(record))
+            .map(|rule| &rule.tag)
+            .collect();
+        for tag in matched {
+            if !record.tags.contains(tag) {
+                record.tags.push(tag.clone());
+            }
+        }
+    }
+}
+
+impl TagRule {
+    fn matches(&self, record: &Record) -> bool {
+        if let Some(tx_type) = &self.tx_type {
+            if *tx_type != record.tx_type {
+                return false;
+            }
+        }
+        if self.min_amount.is_some() || self.max_amount.is_some() {
+            // amount ranges never match records without an amount
+            let amount = match record.amount {
+                Some(amount) => amount,
+                None => return false,
+            };
+            if self.min_amount.is_some_and(|min| amount < min)
+                || self.max_amount.is_some_and(|max| amount > max)
+            {
+                return false;
+            }
+        }
+        if let Some(memo) = &self.memo {
+            if !record.memo.as_deref().is_some_and(|m| memo.is_match(m)) {
+                return false;
+            }
+        }
+        if let Some(clients) = &self.clients {
+            if !record.client.is_some_and(|c| clients.contains(&c)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rows: &str) -> TagRules {
+        let path = std::env::temp_dir().join(format!(
+            "tag-rules-{}-{}.csv",
+            std::process::id(),
+            rows.len()
+        ));
+        std::fs::write(
+            &path,
+            format!(
+                "tag,type,min_amount,max_amount,memo_regex,clients\n{}",
+                rows
+            ),
+        )
+        .unwrap();
+        let rules = TagRules::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+        rules.unwrap()
+    }
+
+    fn tags(rules: &TagRules, record: &str) -> Vec<String> {
+        let input = format!("type,client,tx,amount,memo\n{}", record);
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        let mut record: Record = reader.deserialize().next().unwrap().unwrap();
+        rules.tag(&mut record);
+        record.tags
+    }
+
+    #[test]
+    fn attaches_the_tag_of_every_matching_rule() {
+        let rules = rules(
+            "gambling,withdrawal,,,(?i)casino|bet,
+            large,,10000,,,
+            vip,,,,,1;7;42",
+        );
+        assert_eq!(
+            tags(&rules, "withdrawal,7,1,20000,Casino night"),
+            ["gambling", "large", "vip"]
+        );
+        assert_eq!(tags(&rules, "deposit,7,1,20000,casino"), ["large", "vip"]);
+        assert!(tags(&rules, "withdrawal,2,1,5,groceries").is_empty());
+    }
+
+    #[test]
+    fn amount_ranges_do_not_match_records_without_an_amount() {
+        let rules = rules("small,,,100,,");
+        assert_eq!(tags(&rules, "deposit,1,1,99.5,"), ["small"]);
+        assert!(tags(&rules, "dispute,1,1,,").is_empty());
+    }
+
+    #[test]
+    fn tags_a_record_once() {
+        let rules = rules(
+            "vip,,,,,1
+            vip,deposit,,,,",
+        );
+        assert_eq!(tags(&rules, "deposit,1,1,5,"), ["vip"]);
+    }
+}
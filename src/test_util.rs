@@ -0,0 +1,331 @@
+use crate::{
+    has_open_dispute,
+    pipeline::{Pipeline, PipelineBuilder},
+    policy::Policy,
+    Balance, Record, TxType,
+};
+use chrono::{DateTime, Duration, Utc};
+use csv::Trim;
+use rand::{rngs::StdRng, SeedableRng};
+use rust_decimal::Decimal;
+
+/// Seed of [`TestEngine::rng`], the same for every engine.
+pub const SEED: u64 = 0;
+
+/// An engine in memory for the integration tests of code embedding the crate. Records are
+/// built in place instead of read from a file, stamped by a clock the test moves, and the
+/// accounts are checked with assertions naming the account that differs.
+pub struct TestEngine {
+    pipeline: Pipeline<'static>,
+    policy: Policy,
+    dispute_sla: Option<Duration>,
+    clock: DateTime<Utc>,
+    rng: StdRng,
+}
+
+impl Default for TestEngine {
+    fn default() -> Self {
+        TestEngine::new()
+    }
+}
+
+impl TestEngine {
+    /// The clock starts at the unix epoch.
+    pub fn new() -> Self {
+        TestEngine {
+            pipeline: PipelineBuilder::new().build(),
+            policy: Policy::default(),
+            dispute_sla: None,
+            clock: DateTime::UNIX_EPOCH,
+            rng: StdRng::seed_from_u64(SEED),
+        }
+    }
+
+    /// The policy matrix, lenient by default.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self.rebuild()
+    }
+
+    /// As `--dispute-sla-days`, disputes still open `sla` after they were opened are resolved.
+    pub fn dispute_sla(mut self, sla: Duration) -> Self {
+        self.dispute_sla = Some(sla);
+        self.rebuild()
+    }
+
+    /// Builds the pipeline again with the policy and dispute SLA, keeping the accounts.
+    fn rebuild(mut self) -> Self {
+        let state = self.pipeline.into_state();
+        self.pipeline = PipelineBuilder::new()
+            .policy(self.policy.clone())
+            .dispute_sla(self.dispute_sla)
+            .state(state)
+            .build();
+        self
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock
+    }
+
+    pub fn set_time(&mut self, now: DateTime<Utc>) {
+        self.clock = now;
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.clock += by;
+    }
+
+    /// Random generator seeded with [`SEED`], so random test data is the same on every run.
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Applies the records of `input`, CSV with a header as the binary reads it, each stamped
+    /// with the clock unless it has a timestamp of its own.
+    ///
+    /// Panics on a row the binary would fail on, including the ones the policy aborts on.
+    pub fn apply_csv(&mut self, input: &str) {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes());
+        for record in reader.deserialize() {
+            let record = record.unwrap_or_else(|err| panic!("not a record: {}", err));
+            self.apply(record);
+        }
+    }
+
+    /// Amounts are decimal text, e.g. `"10.5"`, so they are exact.
+    pub fn deposit(&mut self, client: u16, tx: u32, amount: &str) {
+        self.apply(record("deposit", client, tx, Some(decimal(amount))));
+    }
+
+    pub fn withdraw(&mut self, client: u16, tx: u32, amount: &str) {
+        self.apply(record("withdrawal", client, tx, Some(decimal(amount))));
+    }
+
+    pub fn dispute(&mut self, client: u16, tx: u32) {
+        self.apply(record("dispute", client, tx, None));
+    }
+
+    pub fn resolve(&mut self, client: u16, tx: u32) {
+        self.apply(record("resolve", client, tx, None));
+    }
+
+    pub fn chargeback(&mut self, client: u16, tx: u32) {
+        self.apply(record("chargeback", client, tx, None));
+    }
+
+    pub fn void(&mut self, client: u16, tx: u32) {
+        self.apply(record("void", client, tx, None));
+    }
+
+    /// Balances of the account, `None` if it does not exist.
+    pub fn balance(&self, client: u16) -> Option<Balance> {
+        let info = self.pipeline.state().client_map.get(&client)?;
+        Some(Balance::of(client, info))
+    }
+
+    /// Whether the deposit `tx` of the account is under a dispute not settled yet.
+    pub fn dispute_open(&self, client: u16, tx: u32) -> bool {
+        self.pipeline
+            .state()
+            .client_map
+            .get(&client)
+            .is_some_and(|info| has_open_dispute(info, tx))
+    }
+
+    /// Panics unless the account has these available, held and total funds.
+    #[track_caller]
+    pub fn assert_balance(&self, client: u16, available: &str, held: &str, total: &str) {
+        let balance = self
+            .balance(client)
+            .unwrap_or_else(|| panic!("client {} has no account", client));
+        assert_eq!(
+            (balance.available, balance.held, balance.total),
+            (decimal(available), decimal(held), decimal(total)),
+            "available, held and total funds of client {}",
+            client
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_locked(&self, client: u16, locked: bool) {
+        let balance = self
+            .balance(client)
+            .unwrap_or_else(|| panic!("client {} has no account", client));
+        assert_eq!(balance.locked, locked, "lock of client {}", client);
+    }
+
+    #[track_caller]
+    pub fn assert_dispute_open(&self, client: u16, tx: u32, open: bool) {
+        assert_eq!(
+            self.dispute_open(client, tx),
+            open,
+            "open dispute on tx {} of client {}",
+            tx,
+            client
+        );
+    }
+
+    /// Applies `record` the way the binary's run loop does.
+    ///
+    /// Panics where the binary would stop the run, on the policy's `abort` settings.
+    fn apply(&mut self, mut record: Record) {
+        record.timestamp = record.timestamp.or(Some(self.clock));
+        self.pipeline
+            .process(record)
+            .unwrap_or_else(|err| panic!("the engine failed: {}", err));
+    }
+}
+
+#[track_caller]
+fn decimal(amount: &str) -> Decimal {
+    amount
+        .parse()
+        .unwrap_or_else(|err| panic!("{:?} is not an amount: {}", amount, err))
+}
+
+/// A record with only the type, client, tx and amount columns given.
+fn record(tx_type: &str, client: u16, tx: u32, amount: Option<Decimal>) -> Record {
+    Record {
+        tx_type: TxType::from(tx_type),
+        client: Some(client),
+        tx,
+        amount,
+        fee: None,
+        memo: None,
+        evidence: None,
+        timestamp: None,
+        position: 0,
+        line: 0,
+        sequence: 0,
+        next_position: None,
+        tags: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{LockedAccount, Profile};
+
+    #[test]
+    fn applies_transactions_and_checks_the_balances() {
+        let mut engine = TestEngine::new();
+        engine.deposit(1, 1, "10");
+        engine.withdraw(1, 2, "4");
+        engine.deposit(2, 3, "2");
+        engine.assert_balance(1, "6", "0", "6");
+        engine.assert_balance(2, "2", "0", "2");
+        engine.assert_locked(1, false);
+        assert_eq!(engine.balance(3), None);
+    }
+
+    #[test]
+    fn follows_a_dispute_to_its_chargeback() {
+        let mut engine = TestEngine::new();
+        engine.deposit(1, 1, "10");
+        engine.dispute(1, 1);
+        engine.assert_dispute_open(1, 1, true);
+        engine.assert_balance(1, "0", "10", "10");
+        engine.chargeback(1, 1);
+        engine.assert_dispute_open(1, 1, false);
+        engine.assert_balance(1, "0", "0", "0");
+        engine.assert_locked(1, true);
+    }
+
+    #[test]
+    fn the_policy_lets_a_locked_account_settle_its_disputes() {
+        let policy = Policy {
+            locked_account: LockedAccount::SettleOpenDisputes,
+            ..Policy::default()
+        };
+        let mut engine = TestEngine::new().policy(policy);
+        engine.deposit(1, 1, "10");
+        engine.deposit(1, 2, "5");
+        engine.dispute(1, 1);
+        engine.dispute(1, 2);
+        engine.chargeback(1, 1);
+        engine.resolve(1, 2);
+        engine.assert_balance(1, "5", "0", "5");
+        engine.assert_dispute_open(1, 2, false);
+    }
+
+    #[test]
+    fn only_transactions_of_the_same_day_can_be_voided() {
+        let mut engine = TestEngine::new();
+        engine.set_time(crate::timestamp::parse("2022-03-01T09:00:00Z").unwrap());
+        engine.deposit(1, 1, "10");
+        engine.deposit(1, 2, "5");
+        engine.advance(Duration::hours(14));
+        engine.void(1, 1);
+        engine.assert_balance(1, "5", "0", "5");
+        engine.advance(Duration::hours(2));
+        // the next day, the deposit is settled
+        engine.void(1, 2);
+        engine.assert_balance(1, "5", "0", "5");
+    }
+
+    #[test]
+    fn the_clock_stamps_records_and_drives_the_dispute_sla() {
+        let mut engine = TestEngine::new().dispute_sla(Duration::days(30));
+        engine.deposit(1, 1, "10");
+        engine.dispute(1, 1);
+        engine.advance(Duration::days(29));
+        engine.deposit(1, 2, "1");
+        engine.assert_dispute_open(1, 1, true);
+        engine.advance(Duration::days(1));
+        assert_eq!(engine.now(), DateTime::UNIX_EPOCH + Duration::days(30));
+        engine.deposit(1, 3, "1");
+        engine.assert_dispute_open(1, 1, false);
+        engine.assert_balance(1, "12", "0", "12");
+    }
+
+    #[test]
+    fn a_timestamp_in_the_csv_wins_over_the_clock() {
+        let mut engine = TestEngine::new().dispute_sla(Duration::days(1));
+        engine.apply_csv(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,3.0,
+            dispute,1,1,,
+            deposit,1,2,1.0,1970-01-03T00:00:00Z",
+        );
+        // the second deposit told the engine two days went by
+        engine.assert_dispute_open(1, 1, false);
+        engine.assert_balance(1, "4", "0", "4");
+    }
+
+    #[test]
+    fn generated_client_ids_are_the_same_every_run() {
+        let ids = || {
+            let mut engine = TestEngine::new();
+            engine.apply_csv(
+                "type,client,tx,amount
+                deposit,,1,1.0
+                deposit,,2,2.0",
+            );
+            let mut ids: Vec<u16> = engine.pipeline.state().client_map.keys().copied().collect();
+            ids.sort_unstable();
+            ids
+        };
+        assert_eq!(ids().len(), 2);
+        assert_eq!(ids(), ids());
+    }
+
+    #[test]
+    #[should_panic(expected = "the engine failed: duplicate tx id")]
+    fn panics_where_the_strict_profile_aborts() {
+        let mut engine = TestEngine::new().policy(Policy::from_profile(Profile::Strict));
+        engine.deposit(1, 1, "1");
+        engine.deposit(1, 1, "1");
+    }
+
+    #[test]
+    #[should_panic(expected = "available, held and total funds of client 1")]
+    fn a_wrong_balance_names_the_account() {
+        let mut engine = TestEngine::new();
+        engine.deposit(1, 1, "1");
+        engine.assert_balance(1, "2", "0", "2");
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    events::RejectReason,
+    pipeline::{State, Transform},
+    Record, TxError,
+};
+use log::error;
+use std::{collections::HashMap, time::Instant};
+
+/// Records accepted per client: up to `burst` at once, refilled at `rate` per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThrottleLimits {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+/// Holds back the records of a client beyond its limits as they arrive, so one integrator
+/// flooding an account can't hold up the others. Time is the wall clock, not the timestamps
+/// of the records.
+pub struct Throttle {
+    limits: ThrottleLimits,
+    /// records each client can still send, and when that was counted
+    buckets: HashMap<u16, (f64, Instant)>,
+}
+
+impl Throttle {
+    pub fn new(limits: ThrottleLimits) -> Self {
+        Throttle {
+            limits,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a record off the client's bucket, false when it is empty.
+    pub fn take(&mut self, client: u16, now: Instant) -> bool {
+        let burst = f64::from(self.limits.burst);
+        let (tokens, since) = self.buckets.entry(client).or_insert((burst, now));
+        let elapsed = now.saturating_duration_since(*since).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.limits.rate).min(burst);
+        *since = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/// Throttles the records of a broker as they are consumed; records without a client are
+/// not throttled.
+impl Transform for Throttle {
+    fn apply(&mut self, record: Record, _state: &mut State) -> Result<Option<Record>, TxError> {
+        match record.client {
+            Some(client) if !self.take(client, Instant::now()) => {
+                error!("client over its rate limit, skipping tx {:?}", record);
+                Ok(None)
+            }
+            _ => Ok(Some(record)),
+        }
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::Throttled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::PipelineBuilder;
+    use std::time::Duration;
+
+    #[test]
+    fn lets_a_burst_through_then_the_rate() {
+        let mut throttle = Throttle::new(ThrottleLimits {
+            rate: 2.0,
+            burst: 3,
+        });
+        let start = Instant::now();
+        assert!((0..3).all(|_| throttle.take(1, start)));
+        assert!(!throttle.take(1, start));
+        // other clients have buckets of their own
+        assert!(throttle.take(2, start));
+        let later = start + Duration::from_millis(500);
+        assert!(throttle.take(1, later));
+        assert!(!throttle.take(1, later));
+    }
+
+    #[test]
+    fn a_bucket_refills_up_to_the_burst() {
+        let mut throttle = Throttle::new(ThrottleLimits {
+            rate: 100.0,
+            burst: 2,
+        });
+        let start = Instant::now();
+        assert!(throttle.take(1, start));
+        assert!(throttle.take(1, start));
+        let later = start + Duration::from_secs(60);
+        assert!(throttle.take(1, later));
+        assert!(throttle.take(1, later));
+        assert!(!throttle.take(1, later));
+    }
+
+    #[test]
+    fn a_pipeline_refuses_the_records_past_the_limit() {
+        let mut builder = PipelineBuilder::new().transform(Throttle::new(ThrottleLimits {
+            rate: 0.001,
+            burst: 2,
+        }));
+        let events = builder.subscribe();
+        let mut pipeline = builder.build();
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(
+            "type,client,tx,amount
+                deposit,1,1,1
+                deposit,1,2,1
+                deposit,1,3,1
+                deposit,2,4,1"
+                .as_bytes(),
+        );
+        for record in reader.deserialize() {
+            pipeline.process(record.unwrap()).unwrap();
+        }
+        let state = pipeline.into_state();
+        assert_eq!(state.client_map[&1].total_funds, 2.into());
+        assert_eq!(state.client_map[&2].total_funds, 1.into());
+        let refused: Vec<(u32, RejectReason)> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                crate::events::EngineEvent::Rejected { tx, reason, .. } => Some((tx, reason)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(refused, vec![(3, RejectReason::Throttled)]);
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Deserializer};
+
+/// Parses a record timestamp, RFC 3339 (`2022-03-01T12:00:00Z`) or milliseconds since
+/// the unix epoch. `None` when it is neither.
+pub fn parse(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(millis) = value.parse::<i64>() {
+        return from_millis(millis);
+    }
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+/// Like `parse`, and a `YYYY-MM-DD` date is midnight UTC of that day. For the bounds of
+/// a date window.
+pub fn parse_bound(value: &str) -> Option<DateTime<Utc>> {
+    parse(value).or_else(|| {
+        let date = NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok()?;
+        Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+    })
+}
+
+/// `None` when out of range.
+pub fn from_millis(millis: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_millis_opt(millis).single()
+}
+
+/// Deserializes the optional `timestamp` column. Like the other optional columns, an
+/// invalid value is treated as missing.
+pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)
+        .ok()
+        .flatten()
+        .as_deref()
+        .and_then(parse))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rfc3339_and_epoch_millis() {
+        let expected = Utc.with_ymd_and_hms(2022, 3, 1, 12, 0, 0).single();
+        assert_eq!(parse("2022-03-01T12:00:00Z"), expected);
+        assert_eq!(parse("2022-03-01T13:00:00+01:00"), expected);
+        assert_eq!(parse(" 1646136000000 "), expected);
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        assert_eq!(parse("2022-03-01"), None);
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(from_millis(i64::MAX), None);
+    }
+
+    #[test]
+    fn a_bound_may_be_a_date() {
+        assert_eq!(
+            parse_bound("2022-03-01"),
+            Utc.with_ymd_and_hms(2022, 3, 1, 0, 0, 0).single()
+        );
+        assert_eq!(
+            parse_bound("2022-03-01T12:00:00Z"),
+            parse("2022-03-01T12:00:00Z")
+        );
+        assert_eq!(parse_bound("2022-02-30"), None);
+    }
+}
@@ -0,0 +1,394 @@
+use crate::{Amount, ClientInfo, Record, TxType};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Deposits and withdrawals are the transactions later records can refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TxKind {
+    Deposit,
+    Withdrawal,
+}
+
+impl TxKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TxKind::Deposit => "deposit",
+            TxKind::Withdrawal => "withdrawal",
+        }
+    }
+}
+
+/// Dispute state of a transaction. A transaction can be disputed once, and only a disputed
+/// transaction can be resolved or charged back; both settle the dispute for good.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+pub enum TxState {
+    #[default]
+    Normal,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl TxState {
+    /// The state a dispute, resolve or chargeback moves the transaction to, `None` if the
+    /// transaction can not take that step from this state.
+    pub fn next(self, tx_type: &TxType) -> Option<TxState> {
+        match (self, tx_type) {
+            (TxState::Normal, TxType::Dispute) => Some(TxState::Disputed),
+            (TxState::Disputed, TxType::Resolve) => Some(TxState::Resolved),
+            (TxState::Disputed, TxType::Chargeback) => Some(TxState::ChargedBack),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TxState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            TxState::Normal => "normal",
+            TxState::Disputed => "disputed",
+            TxState::Resolved => "resolved",
+            TxState::ChargedBack => "charged_back",
+        };
+        f.write_str(state)
+    }
+}
+
+/// The dispute currently open on a transaction, as given by the dispute record.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisputeCase {
+    /// position of the dispute record, used to age open disputes
+    pub position: u64,
+    pub memo: Option<String>,
+    /// case reference given with the dispute
+    pub evidence: Option<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// Memo, tags and timestamp of a transaction, only allocated when it has any of them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TxNotes {
+    pub memo: Option<String>,
+    pub tags: Vec<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// What is kept of a deposit or withdrawal once it is applied: enough to dispute, settle
+/// and void it, and to report on it. Everything else of the record is dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TxEntry<A = Decimal> {
+    pub tx: u32,
+    pub kind: TxKind,
+    pub amount: Option<A>,
+    pub fee: A,
+    /// position of the record in the input
+    pub position: u64,
+    pub notes: Option<Box<TxNotes>>,
+    pub state: TxState,
+    pub open_case: Option<Box<DisputeCase>>,
+    pub voided: bool,
+}
+
+impl<A: Amount> TxEntry<A> {
+    fn new(kind: TxKind, record: Record<A>) -> Self {
+        let notes =
+            if record.memo.is_some() || !record.tags.is_empty() || record.timestamp.is_some() {
+                Some(Box::new(TxNotes {
+                    memo: record.memo,
+                    tags: record.tags,
+                    timestamp: record.timestamp,
+                }))
+            } else {
+                None
+            };
+        TxEntry {
+            tx: record.tx,
+            kind,
+            amount: record.amount,
+            fee: record.fee.unwrap_or(A::ZERO),
+            position: record.position,
+            notes,
+            state: TxState::Normal,
+            open_case: None,
+            voided: false,
+        }
+    }
+
+    /// Funds the transaction moved on the client's account. Deposits are credited net of
+    /// their fee, so that is also what a dispute on them holds. `None` as well when the fee
+    /// can not be taken from the amount without overflowing.
+    pub fn net_amount(&self) -> Option<A> {
+        match self.kind {
+            TxKind::Deposit => self.amount.and_then(|amount| amount.checked_sub(self.fee)),
+            TxKind::Withdrawal => self.amount,
+        }
+    }
+
+    pub fn memo(&self) -> Option<&str> {
+        self.notes.as_ref().and_then(|notes| notes.memo.as_deref())
+    }
+
+    pub fn tags(&self) -> &[String] {
+        self.notes.as_ref().map_or(&[], |notes| &notes.tags)
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.notes.as_ref().and_then(|notes| notes.timestamp)
+    }
+
+    pub fn has_open_dispute(&self) -> bool {
+        self.state == TxState::Disputed
+    }
+}
+
+impl<A: Amount> ClientInfo<A> {
+    /// Records that a handler applied `record` to the account: deposits and withdrawals get
+    /// an entry, disputes, resolves, chargebacks and voids update the entry they refer to.
+    pub(crate) fn keep(&mut self, record: Record<A>) {
+        self.kept += 1;
+        let month = record
+            .timestamp
+            .map(|timestamp| timestamp.format("%Y-%m").to_string())
+            .unwrap_or_default();
+        for tag in record.tags.iter() {
+            let totals = self
+                .tag_totals
+                .entry((tag.clone(), record.tx_type.to_string(), month.clone()))
+                .or_insert((0, A::ZERO));
+            totals.0 += 1;
+            // a report figure, it saturates rather than refusing applied records
+            totals.1 = totals.1.saturating_add(record.amount.unwrap_or(A::ZERO));
+        }
+
+        let kind = match record.tx_type {
+            TxType::Deposit => TxKind::Deposit,
+            TxType::Withdrawal => TxKind::Withdrawal,
+            _ => {
+                let entry = match self.transaction_mut(record.tx) {
+                    Some(entry) => entry,
+                    None => return,
+                };
+                if record.tx_type == TxType::Void {
+                    entry.voided = true;
+                } else if let Some(next) = entry.state.next(&record.tx_type) {
+                    entry.state = next;
+                    entry.open_case = match next {
+                        TxState::Disputed => Some(Box::new(DisputeCase {
+                            position: record.position,
+                            memo: record.memo,
+                            evidence: record.evidence,
+                            timestamp: record.timestamp,
+                        })),
+                        _ => None,
+                    };
+                }
+                return;
+            }
+        };
+        self.tx_index
+            .entry(record.tx)
+            .or_insert(self.transactions.len());
+        self.transactions.push(TxEntry::new(kind, record));
+    }
+
+    /// Adds a deposit or withdrawal that was kept elsewhere, such as one from before the
+    /// date window, unless the account has one with the same id.
+    pub(crate) fn load(&mut self, entry: TxEntry<A>) {
+        if self.tx_index.contains_key(&entry.tx) {
+            return;
+        }
+        self.tx_index.insert(entry.tx, self.transactions.len());
+        self.transactions.push(entry);
+    }
+
+    /// The deposit or withdrawal with id `tx`, the first one if the id was reused.
+    pub fn transaction(&self, tx: u32) -> Option<&TxEntry<A>> {
+        let at = *self.tx_index.get(&tx)?;
+        self.transactions.get(at)
+    }
+
+    fn transaction_mut(&mut self, tx: u32) -> Option<&mut TxEntry<A>> {
+        let at = *self.tx_index.get(&tx)?;
+        self.transactions.get_mut(at)
+    }
+
+    /// Deposits and withdrawals applied to the account, in input order.
+    pub fn transactions(&self) -> &[TxEntry<A>] {
+        &self.transactions
+    }
+
+    /// Whether a record of `tx_type` with id `tx` was marked applied to the account.
+    pub fn was_applied(&self, tx_type: &TxType, tx: u32) -> bool {
+        self.applied.contains(&(tx_type.clone(), tx))
+    }
+
+    pub(crate) fn mark_applied(&mut self, tx_type: TxType, tx: u32) {
+        self.applied.insert((tx_type, tx));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{events::RejectReason, policy::Policy, Engine, Outcome};
+
+    fn record(tx_type: &str, tx: u32, amount: Option<i64>) -> Record {
+        Record {
+            tx_type: TxType::from(tx_type),
+            client: Some(1),
+            tx,
+            amount: amount.map(Decimal::from),
+            fee: None,
+            memo: None,
+            evidence: None,
+            timestamp: None,
+            position: 0,
+            line: 0,
+            sequence: 0,
+            next_position: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn a_reused_tx_id_refers_to_its_first_entry() {
+        let mut info = ClientInfo::default();
+        info.keep(record("deposit", 1, Some(5)));
+        info.keep(record("deposit", 2, Some(7)));
+        info.keep(record("withdrawal", 1, Some(3)));
+        info.keep(record("dispute", 1, None));
+        assert_eq!(info.transactions().len(), 3);
+        let first = info.transaction(1).unwrap();
+        assert_eq!(
+            (first.kind, first.amount),
+            (TxKind::Deposit, Some(Decimal::from(5)))
+        );
+        assert!(first.has_open_dispute());
+        assert_eq!(info.transactions()[2].state, TxState::Normal);
+        assert!(info.transaction(3).is_none());
+    }
+
+    /// Applies the records of `input`, CSV with a `type,client,tx,amount` header.
+    fn outcomes(input: &str) -> (Engine, Vec<Outcome>) {
+        let mut engine = Engine::new(Policy::default());
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        let outcomes = rdr
+            .deserialize::<Record>()
+            .map(|record| engine.apply(record.unwrap()).unwrap())
+            .collect();
+        (engine, outcomes)
+    }
+
+    #[test]
+    fn dispute_opens_on_normal_transaction() {
+        assert_eq!(
+            TxState::Normal.next(&TxType::Dispute),
+            Some(TxState::Disputed)
+        );
+    }
+
+    #[test]
+    fn resolve_and_chargeback_settle_a_dispute() {
+        assert_eq!(
+            TxState::Disputed.next(&TxType::Resolve),
+            Some(TxState::Resolved)
+        );
+        assert_eq!(
+            TxState::Disputed.next(&TxType::Chargeback),
+            Some(TxState::ChargedBack)
+        );
+    }
+
+    #[test]
+    fn undisputed_transaction_can_not_be_settled() {
+        assert_eq!(TxState::Normal.next(&TxType::Resolve), None);
+        assert_eq!(TxState::Normal.next(&TxType::Chargeback), None);
+    }
+
+    #[test]
+    fn disputed_transaction_can_not_be_disputed_again() {
+        assert_eq!(TxState::Disputed.next(&TxType::Dispute), None);
+    }
+
+    #[test]
+    fn settled_dispute_is_final() {
+        for state in [TxState::Resolved, TxState::ChargedBack] {
+            for tx_type in [TxType::Dispute, TxType::Resolve, TxType::Chargeback] {
+                assert_eq!(state.next(&tx_type), None, "{} {}", state, tx_type);
+            }
+        }
+    }
+
+    #[test]
+    fn other_records_do_not_move_the_state() {
+        let states = [
+            TxState::Normal,
+            TxState::Disputed,
+            TxState::Resolved,
+            TxState::ChargedBack,
+        ];
+        let tx_types = [
+            TxType::Deposit,
+            TxType::Withdrawal,
+            TxType::Void,
+            TxType::from("refund"),
+        ];
+        for state in states {
+            for tx_type in &tx_types {
+                assert_eq!(state.next(tx_type), None, "{} {}", state, tx_type);
+            }
+        }
+    }
+
+    #[test]
+    fn refused_transitions_leave_the_entry_as_it_was() {
+        let (engine, outcomes) = outcomes(
+            "type,client,tx,amount
+            deposit,1,1,10
+            resolve,1,1,
+            chargeback,1,1,
+            dispute,1,1,
+            dispute,1,1,
+            resolve,1,1,
+            chargeback,1,1,",
+        );
+        let refused = Outcome::Refused(RejectReason::InvalidTransition);
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Applied,
+                refused,
+                refused,
+                Outcome::Applied,
+                refused,
+                Outcome::Applied,
+                refused,
+            ]
+        );
+        let info = &engine.state().client_map[&1];
+        let entry = info.transaction(1).unwrap();
+        assert_eq!(entry.state, TxState::Resolved);
+        assert!(entry.open_case.is_none());
+        assert_eq!(info.available_funds, Decimal::from(10));
+        assert_eq!(info.held_funds, Decimal::ZERO);
+    }
+
+    #[test]
+    fn disputed_entry_can_not_be_voided() {
+        let (engine, outcomes) = outcomes(
+            "type,client,tx,amount
+            deposit,1,1,10
+            dispute,1,1,
+            void,1,1,",
+        );
+        assert_eq!(
+            outcomes[2],
+            Outcome::Refused(RejectReason::InvalidTransition)
+        );
+        assert!(!engine.state().client_map[&1].transaction(1).unwrap().voided);
+    }
+}
@@ -0,0 +1,717 @@
+use crate::{
+    amount::Amount,
+    apply_record,
+    dedupe::SeenTx,
+    events::{self, AccountMark, RejectReason},
+    id_allocator::IdAllocator,
+    pipeline::{State, Transform},
+    policy::{DuplicateTx, MissingAmount, MissingClient, Policy},
+    remap::ClientRemap,
+    screening::Screening,
+    tagging::TagRules,
+    withdrawal_limits::WithdrawalLimits,
+    ClientInfo, Outcome, Record, TxError, TxType,
+};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::HashMap, fs::File, path::Path};
+
+/// Applies the missing client policy, generating an id for the record if it says so.
+/// Returns false if the record is skipped.
+pub(crate) fn check_client<A: Amount>(
+    record: &mut Record<A>,
+    taken: impl Fn(u16) -> bool,
+    policy: MissingClient,
+    ids: &mut (impl IdAllocator + ?Sized),
+) -> Result<bool, TxError> {
+    if record.client.is_some() {
+        return Ok(true);
+    }
+    match policy {
+        // if recorded transaction does not have a client id provided, generate a new one
+        MissingClient::Generate => {
+            record.client = ids.allocate(record.tx, record.position, &taken);
+            if record.client.is_none() {
+                error!(
+                    "every client id is taken, none generated for tx {:?}",
+                    record
+                );
+            }
+        }
+        MissingClient::Skip => {
+            error!("client id missing, skipping tx {:?}", record);
+            return Ok(false);
+        }
+        MissingClient::Abort => {
+            error!("client id missing in tx {:?}", record);
+            return Err(TxError::MissingClient { tx: record.tx });
+        }
+    }
+    Ok(true)
+}
+
+fn moves_funds<A>(record: &Record<A>) -> bool {
+    matches!(record.tx_type, TxType::Deposit | TxType::Withdrawal)
+}
+
+/// Applies the missing amount policy to deposits and withdrawals. Returns false if the
+/// record is skipped.
+pub(crate) fn check_amount<A: Amount>(
+    record: &Record<A>,
+    policy: MissingAmount,
+) -> Result<bool, TxError> {
+    if !moves_funds(record) || record.amount.is_some() {
+        return Ok(true);
+    }
+    match policy {
+        MissingAmount::Record => Ok(true),
+        MissingAmount::Skip => {
+            error!("amount missing or malformed, skipping tx {:?}", record);
+            Ok(false)
+        }
+        MissingAmount::Abort => {
+            error!("amount missing or malformed in tx {:?}", record);
+            Err(TxError::MissingAmount { tx: record.tx })
+        }
+    }
+}
+
+/// Applies the duplicate tx policy to deposits and withdrawals, `seen_tx` holding the
+/// position of the first one with each tx id, across all clients. Returns false if the
+/// record is skipped; the `reject` policy skips it too, writing it is up to the caller.
+pub(crate) fn check_duplicate<A: Amount>(
+    record: &Record<A>,
+    seen_tx: &mut (impl SeenTx + ?Sized),
+    policy: DuplicateTx,
+) -> Result<bool, TxError> {
+    // nothing to remember the ids for when duplicates are let through
+    if policy == DuplicateTx::Allow || !moves_funds(record) {
+        return Ok(true);
+    }
+    if seen_tx.first_position(record.tx, record.position)? == record.position {
+        return Ok(true);
+    }
+    match policy {
+        DuplicateTx::Allow => Ok(true),
+        DuplicateTx::Skip => {
+            error!("duplicate tx id, skipping tx {:?}", record);
+            Ok(false)
+        }
+        DuplicateTx::Reject => {
+            error!("duplicate tx id, rejecting tx {:?}", record);
+            Ok(false)
+        }
+        DuplicateTx::Abort => {
+            error!("duplicate tx id in tx {:?}", record);
+            Err(TxError::DuplicateTx { tx: record.tx })
+        }
+    }
+}
+
+/// Applies the missing client policy, the ids of the `generate` policy picked by `ids`.
+pub struct MissingClientCheck {
+    policy: MissingClient,
+    ids: Box<dyn IdAllocator + Send>,
+}
+
+impl MissingClientCheck {
+    pub fn new(policy: MissingClient, ids: Box<dyn IdAllocator + Send>) -> Self {
+        MissingClientCheck { policy, ids }
+    }
+}
+
+impl Transform for MissingClientCheck {
+    fn apply(&mut self, mut record: Record, state: &mut State) -> Result<Option<Record>, TxError> {
+        let (client_map, paged_out) = (&state.client_map, &state.paged_out);
+        let taken = |id| client_map.contains_key(&id) || paged_out.contains(&id);
+        let admitted = check_client(&mut record, taken, self.policy, self.ids.as_mut())?;
+        Ok(admitted.then_some(record))
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::MissingClient
+    }
+}
+
+/// Applies the missing amount policy.
+pub struct AmountCheck(pub MissingAmount);
+
+impl Transform for AmountCheck {
+    fn apply(&mut self, record: Record, _state: &mut State) -> Result<Option<Record>, TxError> {
+        Ok(check_amount(&record, self.0)?.then_some(record))
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::MissingAmount
+    }
+}
+
+/// A duplicate deposit or withdrawal as written to the duplicate rejects file.
+#[derive(Serialize, Debug)]
+struct DuplicateReject<'a> {
+    #[serde(rename = "type")]
+    tx_type: &'a str,
+    client: Option<u16>,
+    tx: u32,
+    amount: Option<Decimal>,
+    position: u64,
+    /// position of the first record with the tx id
+    first_position: u64,
+}
+
+/// The file the `reject` duplicate tx policy writes duplicates to.
+pub struct DuplicateRejects {
+    report: csv::Writer<File>,
+}
+
+impl DuplicateRejects {
+    pub fn create(path: &Path) -> Result<Self, TxError> {
+        Ok(DuplicateRejects {
+            report: csv::Writer::from_path(path)?,
+        })
+    }
+}
+
+/// Applies the duplicate tx policy, writing what the `reject` policy skips to the duplicate
+/// rejects file, if any.
+pub struct DuplicateCheck {
+    policy: DuplicateTx,
+    /// position of the first deposit or withdrawal with each tx id seen so far
+    seen_tx: Box<dyn SeenTx + Send>,
+    rejects: Option<DuplicateRejects>,
+}
+
+impl DuplicateCheck {
+    pub fn new(
+        policy: DuplicateTx,
+        rejects: Option<DuplicateRejects>,
+        seen_tx: Box<dyn SeenTx + Send>,
+    ) -> Self {
+        DuplicateCheck {
+            policy,
+            seen_tx,
+            rejects,
+        }
+    }
+}
+
+impl Transform for DuplicateCheck {
+    fn apply(&mut self, record: Record, _state: &mut State) -> Result<Option<Record>, TxError> {
+        if check_duplicate(&record, self.seen_tx.as_mut(), self.policy)? {
+            return Ok(Some(record));
+        }
+        if let (DuplicateTx::Reject, Some(rejects)) = (self.policy, self.rejects.as_mut()) {
+            rejects.report.serialize(DuplicateReject {
+                tx_type: record.tx_type.as_str(),
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+                position: record.position,
+                first_position: self.seen_tx.first_position(record.tx, record.position)?,
+            })?;
+        }
+        Ok(None)
+    }
+
+    fn finish(&mut self) -> Result<(), TxError> {
+        if let Some(rejects) = self.rejects.as_mut() {
+            rejects.report.flush()?;
+        }
+        Ok(())
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::DuplicateTx
+    }
+}
+
+/// Skips records that were already applied to their account, by type and tx id, and marks
+/// the ones the handlers apply. The marks are part of the account, so they last as long as
+/// the state does, and a feed delivered again is not applied twice. A refused record is not
+/// marked, it may be sent again once the account can take it.
+#[derive(Default)]
+pub struct SkipApplied {
+    /// type and tx id of the record passed on last
+    pending: Option<(TxType, u32)>,
+}
+
+impl Transform for SkipApplied {
+    fn apply(&mut self, record: Record, state: &mut State) -> Result<Option<Record>, TxError> {
+        self.pending = None;
+        let applied = state
+            .account(record.client)
+            .is_some_and(|info| info.was_applied(&record.tx_type, record.tx));
+        if applied {
+            error!("already applied, skipping tx {:?}", record);
+            return Ok(None);
+        }
+        self.pending = Some((record.tx_type.clone(), record.tx));
+        Ok(Some(record))
+    }
+
+    fn applied(
+        &mut self,
+        client: Option<u16>,
+        outcome: Outcome,
+        state: &mut State,
+    ) -> Result<(), TxError> {
+        let pending = self.pending.take();
+        if outcome != Outcome::Applied {
+            return Ok(());
+        }
+        let info = client.and_then(|client| state.client_map.get_mut(&client));
+        if let (Some(info), Some((tx_type, tx))) = (info, pending) {
+            info.mark_applied(tx_type, tx);
+        }
+        Ok(())
+    }
+
+    fn replayed(&mut self, record: &Record, state: &mut State) -> Result<(), TxError> {
+        let info = record
+            .client
+            .and_then(|client| state.client_map.get_mut(&client));
+        if let Some(info) = info {
+            info.mark_applied(record.tx_type.clone(), record.tx);
+        }
+        Ok(())
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::DuplicateTx
+    }
+}
+
+/// Folds migrated or duplicate accounts into the surviving id, recording the old id as an
+/// alias once the surviving account exists.
+pub struct Remap {
+    remap: ClientRemap,
+    /// old and new id of the record passed on last, if it was remapped
+    merged: Option<(u16, u16)>,
+}
+
+impl Remap {
+    pub fn new(remap: ClientRemap) -> Self {
+        Remap {
+            remap,
+            merged: None,
+        }
+    }
+}
+
+impl Transform for Remap {
+    fn apply(&mut self, mut record: Record, _state: &mut State) -> Result<Option<Record>, TxError> {
+        self.merged = record
+            .client
+            .and_then(|old_id| self.remap.get(old_id).map(|new_id| (old_id, new_id)));
+        if let Some((_, new_id)) = self.merged {
+            record.client = Some(new_id);
+        }
+        Ok(Some(record))
+    }
+
+    fn applied(
+        &mut self,
+        _client: Option<u16>,
+        _outcome: Outcome,
+        state: &mut State,
+    ) -> Result<(), TxError> {
+        if let Some((old_id, new_id)) = self.merged.take() {
+            if let Some(info) = state.client_map.get_mut(&new_id) {
+                if !info.aliases.contains(&old_id) {
+                    info!("client {} merged into client {}", old_id, new_id);
+                    info.aliases.push(old_id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Skips the records outside a date window, `from` included and `to` not. A record
+/// without a timestamp counts as at the time of the record before it. The records before
+/// the window are applied to accounts of their own under the policy of the run, and the
+/// deposits and withdrawals they kept are loaded onto the account when a record inside the
+/// window refers to them, so disputes of older transactions can still be settled while one
+/// the handlers refused stays unknown. Balances only hold the funds moved inside the
+/// window.
+pub struct DateWindow {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+    policy: Policy,
+    /// accounts as the records before the window left them, by client
+    history: HashMap<u16, ClientInfo>,
+}
+
+impl DateWindow {
+    pub fn new(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>, policy: Policy) -> Self {
+        DateWindow {
+            from,
+            to,
+            last: None,
+            policy,
+            history: HashMap::new(),
+        }
+    }
+}
+
+impl Transform for DateWindow {
+    fn apply(&mut self, record: Record, state: &mut State) -> Result<Option<Record>, TxError> {
+        if record.timestamp.is_some() {
+            self.last = record.timestamp;
+        }
+        let before = match (self.from, self.last) {
+            (Some(from), Some(at)) => at < from,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if before {
+            // an unknown type that would abort the run is only refused inside the window
+            let _ = apply_record(&mut self.history, record, &self.policy);
+            return Ok(None);
+        }
+        if matches!((self.to, self.last), (Some(to), Some(at)) if at >= to) {
+            return Ok(None);
+        }
+        if let Some(client) = record.client.filter(|_| !moves_funds(&record)) {
+            let earlier = self
+                .history
+                .get(&client)
+                .and_then(|history| history.transaction(record.tx));
+            if let Some(entry) = earlier {
+                state
+                    .client_map
+                    .entry(client)
+                    .or_default()
+                    .load(entry.clone());
+            }
+        }
+        Ok(Some(record))
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::OutsideWindow
+    }
+}
+
+/// Drops the records of denylisted clients into the quarantine. An account a record froze
+/// counts as changed by it.
+impl Transform for Screening {
+    fn apply(&mut self, record: Record, state: &mut State) -> Result<Option<Record>, TxError> {
+        let mark = AccountMark::of(state.account(record.client));
+        match self.block(&record, &mut state.client_map)? {
+            Some((client, true)) => {
+                // the account is frozen here, before the record reaches its handler
+                let locks = events::locks_since(client, &mark, &state.client_map[&client]);
+                state.events.extend(locks);
+                state.touched.push(client);
+                Ok(None)
+            }
+            Some(_) => Ok(None),
+            None => Ok(Some(record)),
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), TxError> {
+        Screening::finish(self)
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::Denylisted
+    }
+}
+
+impl Transform for TagRules {
+    fn apply(&mut self, mut record: Record, _state: &mut State) -> Result<Option<Record>, TxError> {
+        self.tag(&mut record);
+        Ok(Some(record))
+    }
+}
+
+/// Drops the withdrawals over the limits of their client in the 24 hours up to the time of
+/// the run.
+impl Transform for WithdrawalLimits {
+    fn apply(&mut self, record: Record, state: &mut State) -> Result<Option<Record>, TxError> {
+        Ok(self
+            .admits(&record, &state.client_map, state.clock)
+            .then_some(record))
+    }
+
+    fn reject_reason(&self) -> RejectReason {
+        RejectReason::WithdrawalLimit
+    }
+}
+
+/// Notes every record the handlers apply as a row of the events table.
+#[cfg(feature = "delta")]
+impl Transform for crate::delta::DeltaEvents {
+    fn apply(&mut self, record: Record, state: &mut State) -> Result<Option<Record>, TxError> {
+        self.read(&record, &state.client_map);
+        Ok(Some(record))
+    }
+
+    fn applied(
+        &mut self,
+        _client: Option<u16>,
+        _outcome: Outcome,
+        state: &mut State,
+    ) -> Result<(), TxError> {
+        crate::delta::DeltaEvents::applied(self, &state.client_map).map_err(TxError::Other)
+    }
+
+    fn finish(&mut self) -> Result<(), TxError> {
+        self.commit().map_err(TxError::Other)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::PipelineBuilder;
+    use crate::policy::Policy;
+    use rust_decimal::Decimal;
+    use std::fs;
+
+    fn records(input: &str) -> Vec<Record> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .deserialize::<Record>()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn policy_checks_come_before_the_transforms() {
+        let policy = Policy {
+            missing_client: MissingClient::Skip,
+            duplicate_tx: DuplicateTx::Skip,
+            ..Policy::default()
+        };
+        let path =
+            std::env::temp_dir().join(format!("transforms-remap-{}.csv", std::process::id()));
+        fs::write(&path, "old_id,new_id\n2,1\n").unwrap();
+        let remap = ClientRemap::from_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut pipeline = PipelineBuilder::new()
+            .policy(policy)
+            .transform(Remap::new(remap))
+            .build();
+        let touched: Vec<Vec<u16>> = records(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,,2,10
+            deposit,2,1,10
+            deposit,2,3,5",
+        )
+        .into_iter()
+        .map(|record| pipeline.process(record).unwrap())
+        .collect();
+        // the duplicate is dropped before its client is remapped
+        assert_eq!(touched, vec![vec![1], vec![], vec![], vec![1]]);
+        let state = pipeline.finish().unwrap();
+        assert_eq!(state.processed, 4);
+        assert_eq!(state.client_map[&1].available_funds, Decimal::from(15));
+        assert_eq!(state.client_map[&1].aliases, vec![2]);
+    }
+
+    #[test]
+    fn transforms_see_the_records_in_the_order_they_were_added() {
+        /// Tags every record with its name, dropping the records of its client.
+        struct Mark(&'static str, u16);
+
+        impl Transform for Mark {
+            fn apply(
+                &mut self,
+                mut record: Record,
+                _state: &mut State,
+            ) -> Result<Option<Record>, TxError> {
+                record.tags.push(self.0.to_string());
+                Ok((record.client != Some(self.1)).then_some(record))
+            }
+        }
+
+        let mut pipeline = PipelineBuilder::new()
+            .transform(Mark("first", 2))
+            .transform(Mark("second", 3))
+            .transform(TagRules::default())
+            .build();
+        for record in records(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,2,2,10
+            deposit,3,3,10",
+        ) {
+            pipeline.process(record).unwrap();
+        }
+        let state = pipeline.finish().unwrap();
+        assert_eq!(
+            state.client_map[&1].transactions()[0].tags(),
+            vec!["first", "second"]
+        );
+        assert!(!state.client_map.contains_key(&2));
+        assert!(!state.client_map.contains_key(&3));
+    }
+
+    #[test]
+    fn rejected_duplicates_are_written_with_the_first_position() {
+        let path =
+            std::env::temp_dir().join(format!("transforms-duplicates-{}.csv", std::process::id()));
+        let mut pipeline = PipelineBuilder::new()
+            .policy(Policy {
+                duplicate_tx: DuplicateTx::Reject,
+                ..Policy::default()
+            })
+            .duplicate_rejects(Some(DuplicateRejects::create(&path).unwrap()))
+            .build();
+        for record in records(
+            "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,2,1,5
+            dispute,1,1,
+            deposit,1,2,3
+            deposit,3,2,4",
+        ) {
+            pipeline.process(record).unwrap();
+        }
+        let state = pipeline.finish().unwrap();
+        let rejects = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // tx ids are unique across clients, disputes refer to them
+        assert_eq!(
+            rejects,
+            "type,client,tx,amount,position,first_position\n\
+             withdrawal,2,1,5,2,1\n\
+             deposit,3,2,4,5,4\n"
+        );
+        assert_eq!(state.client_map[&1].held_funds, Decimal::from(10));
+        assert!(!state.client_map.contains_key(&2));
+        assert!(!state.client_map.contains_key(&3));
+    }
+
+    #[test]
+    fn a_date_window_keeps_what_came_before_it_disputable() {
+        let at = |value| crate::timestamp::parse_bound(value);
+        let mut pipeline = PipelineBuilder::new().transform(DateWindow::new(
+            at("2022-03-01"),
+            at("2022-03-02"),
+            Policy::default(),
+        ));
+        let events = pipeline.subscribe();
+        let mut pipeline = pipeline.build();
+        for record in records(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,2022-02-28T10:00:00Z
+            withdrawal,1,2,50,2022-02-28T11:00:00Z
+            deposit,1,3,5,2022-03-01T09:00:00Z
+            dispute,1,1,,2022-03-01T10:00:00Z
+            dispute,1,2,,
+            deposit,1,4,7,2022-03-02T00:00:00Z
+            deposit,2,5,7,",
+        ) {
+            pipeline.process(record).unwrap();
+        }
+        let state = pipeline.finish().unwrap();
+        let reasons: Vec<RejectReason> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                events::EngineEvent::Rejected { reason, .. } => Some(reason),
+                _ => None,
+            })
+            .collect();
+        // the refused withdrawal can not be disputed, it never moved any funds
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::OutsideWindow,
+                RejectReason::OutsideWindow,
+                RejectReason::TxNotFound,
+                RejectReason::OutsideWindow,
+                RejectReason::OutsideWindow,
+            ]
+        );
+        let account = &state.client_map[&1];
+        assert_eq!(account.available_funds, Decimal::from(-5));
+        assert_eq!(account.held_funds, Decimal::from(10));
+        assert!(account.transaction(1).unwrap().has_open_dispute());
+        assert!(account.transaction(4).is_none());
+        assert!(!state.client_map.contains_key(&2));
+    }
+
+    #[test]
+    fn a_date_window_is_open_ended_without_bounds() {
+        let mut window = DateWindow::new(None, None, Policy::default());
+        let mut state = State::default();
+        for record in records(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,10,
+            deposit,1,2,10,1970-01-01T00:00:00Z",
+        ) {
+            assert!(window.apply(record, &mut state).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn allowed_duplicates_are_not_remembered() {
+        let mut seen_tx = HashMap::new();
+        for record in records(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,1,1,10",
+        ) {
+            assert!(check_duplicate(&record, &mut seen_tx, DuplicateTx::Allow).unwrap());
+        }
+        assert!(seen_tx.is_empty());
+    }
+
+    /// Runs the records of `input` with `--skip-applied` on top of `state`.
+    fn skipping_applied(input: &str, state: State) -> State {
+        PipelineBuilder::new()
+            .source(records(input).into_iter().map(Ok))
+            .state(state)
+            .transform(SkipApplied::default())
+            .build()
+            .run()
+            .unwrap()
+    }
+
+    #[test]
+    fn records_applied_in_an_earlier_run_are_skipped() {
+        let first = skipping_applied(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,1,2,5",
+            State::default(),
+        );
+        // the same feed again, with one new record
+        let second = skipping_applied(
+            "type,client,tx,amount
+            deposit,1,1,10
+            deposit,1,2,5
+            withdrawal,1,3,2",
+            first,
+        );
+        let info = &second.client_map[&1];
+        assert!(info.was_applied(&TxType::Withdrawal, 3));
+        assert_eq!(info.available_funds, Decimal::from(13));
+    }
+
+    #[test]
+    fn refused_record_is_not_skipped_when_sent_again() {
+        let state = skipping_applied(
+            "type,client,tx,amount
+            deposit,1,1,10
+            withdrawal,1,2,50
+            deposit,1,3,50
+            withdrawal,1,2,50
+            withdrawal,1,2,50",
+            State::default(),
+        );
+        let info = &state.client_map[&1];
+        assert!(info.was_applied(&TxType::Withdrawal, 2));
+        assert_eq!(info.available_funds, Decimal::from(10));
+    }
+}
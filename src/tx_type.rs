@@ -0,0 +1,102 @@
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt, str};
+
+/// Type of a record. The known types are recognized without allocating; any other is kept
+/// as given, for the unknown type policy to report.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TxType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Void,
+    Other(String),
+}
+
+impl TxType {
+    /// The type named by `bytes`, as in the `type` column. `None` when it is not UTF-8.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(match bytes {
+            b"deposit" => TxType::Deposit,
+            b"withdrawal" => TxType::Withdrawal,
+            b"dispute" => TxType::Dispute,
+            b"resolve" => TxType::Resolve,
+            b"chargeback" => TxType::Chargeback,
+            b"void" => TxType::Void,
+            other => TxType::Other(str::from_utf8(other).ok()?.to_string()),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Void => "void",
+            TxType::Other(other) => other,
+        }
+    }
+}
+
+impl From<&str> for TxType {
+    fn from(name: &str) -> Self {
+        // a str is UTF-8
+        TxType::from_bytes(name.as_bytes()).unwrap_or_else(|| TxType::Other(name.to_string()))
+    }
+}
+
+impl From<String> for TxType {
+    fn from(name: String) -> Self {
+        match TxType::from(name.as_str()) {
+            TxType::Other(_) => TxType::Other(name),
+            known => known,
+        }
+    }
+}
+
+impl fmt::Display for TxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Written as the name, as the `String` it replaces was.
+impl Serialize for TxType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TxType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TxTypeVisitor)
+    }
+}
+
+struct TxTypeVisitor;
+
+impl<'de> Visitor<'de> for TxTypeVisitor {
+    type Value = TxType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a transaction type")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<TxType, E> {
+        Ok(TxType::from(value))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<TxType, E> {
+        Ok(TxType::from(value))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<TxType, E> {
+        TxType::from_bytes(value).ok_or_else(|| E::custom("transaction type is not UTF-8"))
+    }
+}
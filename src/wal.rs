@@ -0,0 +1,427 @@
+use crate::{pipeline::State, state_file, Balance, Record, TxError, TxType};
+use chrono::{DateTime, Utc};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// One entry of the write-ahead log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WalEntry {
+    /// Logged before the engine applies the record, numbered with the sequence it gets.
+    Record { sequence: u64, record: Logged },
+    /// Logged once the record is applied: the balances of the accounts it could touch.
+    Effect {
+        sequence: u64,
+        balances: Vec<Balance>,
+    },
+}
+
+/// How far to replay the WAL.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    /// up to and including the record with this sequence number
+    Sequence(u64),
+    /// up to the first record with a later timestamp, records without one go with those before
+    Time(DateTime<Utc>),
+}
+
+impl AsOf {
+    /// Whether `record`, numbered `sequence`, comes after the point to replay to.
+    pub fn is_past(&self, sequence: u64, record: &Logged) -> bool {
+        match self {
+            AsOf::Sequence(last) => sequence > *last,
+            AsOf::Time(time) => record.timestamp.is_some_and(|timestamp| timestamp > *time),
+        }
+    }
+}
+
+/// A record as it went into the engine, after the transforms.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Logged {
+    tx_type: TxType,
+    client: Option<u16>,
+    tx: u32,
+    amount: Option<Decimal>,
+    fee: Option<Decimal>,
+    memo: Option<String>,
+    evidence: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+    position: u64,
+    line: u64,
+    tags: Vec<String>,
+}
+
+impl Logged {
+    pub fn of(record: &Record) -> Self {
+        Logged {
+            tx_type: record.tx_type.clone(),
+            client: record.client,
+            tx: record.tx,
+            amount: record.amount,
+            fee: record.fee,
+            memo: record.memo.clone(),
+            evidence: record.evidence.clone(),
+            timestamp: record.timestamp,
+            position: record.position,
+            line: record.line,
+            tags: record.tags.clone(),
+        }
+    }
+
+    pub fn into_record(self) -> Record {
+        Record {
+            tx_type: self.tx_type,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+            fee: self.fee,
+            memo: self.memo,
+            evidence: self.evidence,
+            timestamp: self.timestamp,
+            position: self.position,
+            line: self.line,
+            sequence: 0,
+            next_position: None,
+            tags: self.tags,
+        }
+    }
+}
+
+/// Appends entries to the log at `path`, each MessagePack encoded behind its length as a 4 byte
+/// big-endian integer, and synced to disk before the call returns.
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    buffer: Vec<u8>,
+    /// whether every entry stays, for `rebuild` and `query`
+    keep: bool,
+}
+
+impl Wal {
+    /// Opens the log, creating it if missing. A last entry cut short by a crash is dropped.
+    pub fn open(path: &Path) -> Result<Self, TxError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // the log is appended to, the entries already in it are replayed
+            .truncate(false)
+            .open(path)?;
+        let complete = complete_length(&mut file)?;
+        if complete < file.metadata()?.len() {
+            warn!(
+                "dropping the incomplete last entry of the WAL {}",
+                path.display()
+            );
+            file.set_len(complete)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok(Wal {
+            path: path.to_path_buf(),
+            file,
+            buffer: Vec::new(),
+            keep: false,
+        })
+    }
+
+    /// Keeps every entry instead of emptying the log once the state is persisted, so the log
+    /// covers the state from the start.
+    pub fn keep(mut self) -> Self {
+        self.keep = true;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Empties the log, once the state it leads to is persisted where the next run
+    /// continues from, unless it is kept.
+    pub fn truncate(&mut self) -> Result<(), TxError> {
+        if self.keep {
+            return Ok(());
+        }
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+
+    pub fn append(&mut self, entry: &WalEntry) -> Result<(), TxError> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&[0; 4]);
+        state_file::encode(&mut self.buffer, entry)?;
+        let length = u32::try_from(self.buffer.len() - 4).map_err(TxError::other)?;
+        self.buffer[..4].copy_from_slice(&length.to_be_bytes());
+        self.file.write_all(&self.buffer)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Bytes of the log up to the end of its last complete entry.
+fn complete_length(file: &mut File) -> io::Result<u64> {
+    let total = file.metadata()?.len();
+    let mut reader = BufReader::new(&mut *file);
+    let mut complete = 0;
+    let mut prefix = [0u8; 4];
+    while complete + 4 <= total {
+        reader.read_exact(&mut prefix)?;
+        let end = complete + 4 + u64::from(u32::from_be_bytes(prefix));
+        if end > total {
+            break;
+        }
+        reader.seek_relative(end as i64 - complete as i64 - 4)?;
+        complete = end;
+    }
+    Ok(complete)
+}
+
+/// Reads the entries of the log at `path` in order. A last entry cut short ends the log.
+pub fn read(path: &Path) -> Result<WalReader, TxError> {
+    Ok(WalReader {
+        reader: BufReader::new(File::open(path)?),
+        buffer: Vec::new(),
+    })
+}
+
+pub struct WalReader {
+    reader: BufReader<File>,
+    buffer: Vec<u8>,
+}
+
+impl WalReader {
+    fn next_entry(&mut self) -> Result<Option<WalEntry>, TxError> {
+        let mut prefix = [0u8; 4];
+        match self.reader.read_exact(&mut prefix) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        self.buffer.resize(u32::from_be_bytes(prefix) as usize, 0);
+        match self.reader.read_exact(&mut self.buffer) {
+            Ok(()) => Ok(Some(state_file::decode(self.buffer.as_slice())?)),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!("the WAL ends with an incomplete entry, ignoring it");
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Iterator for WalReader {
+    type Item = Result<WalEntry, TxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+/// The logged balances that differ from the accounts in `state`, as messages. Accounts that
+/// are not in `client_map` are not compared.
+pub fn diverged(state: &State, balances: &[Balance]) -> Vec<String> {
+    balances
+        .iter()
+        .filter_map(|logged| {
+            let info = state.client_map.get(&logged.client)?;
+            let now = Balance::of(logged.client, info);
+            (&now != logged).then(|| {
+                format!(
+                    "client {}: logged available {} held {} total {} locked {}, \
+                     now available {} held {} total {} locked {}",
+                    logged.client,
+                    logged.available,
+                    logged.held,
+                    logged.total,
+                    logged.locked,
+                    now.available,
+                    now.held,
+                    now.total,
+                    now.locked
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "msgpack"))]
+mod tests {
+    use super::*;
+    use crate::pipeline::PipelineBuilder;
+    use std::{env, fs, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("csvtest-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn records(input: &'static str) -> impl Iterator<Item = Result<Record, TxError>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize()
+            .map(|record| record.map_err(TxError::from))
+    }
+
+    fn balances(state: &State) -> Vec<Balance> {
+        let mut balances: Vec<Balance> = state
+            .client_map
+            .iter()
+            .map(|(client, info)| Balance::of(*client, info))
+            .collect();
+        balances.sort_by_key(|balance| balance.client);
+        balances
+    }
+
+    const LOGGED: &str = "type,client,tx,amount
+        deposit,1,1,10
+        deposit,2,2,20
+        dispute,1,1,";
+    const AFTER: &str = "type,client,tx,amount
+        withdrawal,2,3,5
+        chargeback,1,1,";
+
+    fn effect(sequence: u64) -> WalEntry {
+        WalEntry::Effect {
+            sequence,
+            balances: Vec::new(),
+        }
+    }
+
+    fn sequences(path: &Path) -> Vec<u64> {
+        read(path)
+            .unwrap()
+            .map(|entry| match entry.unwrap() {
+                WalEntry::Record { sequence, .. } | WalEntry::Effect { sequence, .. } => sequence,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn entry_cut_short_is_dropped() {
+        let path = env::temp_dir().join(format!("csvtest-wal-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(&effect(1)).unwrap();
+        wal.append(&effect(2)).unwrap();
+        drop(wal);
+        // a crash in the middle of writing the second entry
+        let length = fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(length - 2)
+            .unwrap();
+        assert_eq!(sequences(&path), [1]);
+
+        // the next entry goes where the cut one started
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(&effect(3)).unwrap();
+        drop(wal);
+        assert_eq!(sequences(&path), [1, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_run_cut_short_ends_as_a_run_in_one_go() {
+        let path = temp_path("wal-crash");
+        let in_one_go = PipelineBuilder::new()
+            .source(records(LOGGED).chain(records(AFTER)))
+            .build()
+            .run()
+            .unwrap();
+
+        let mut crashed = PipelineBuilder::new()
+            .source(records(""))
+            .wal(Wal::open(&path).unwrap())
+            .build();
+        for record in records(LOGGED) {
+            crashed.process(record.unwrap()).unwrap();
+        }
+        // gone with its state, before finishing
+        drop(crashed);
+
+        let restarted = PipelineBuilder::new()
+            .source(records(AFTER))
+            .wal(Wal::open(&path).unwrap())
+            .build()
+            .run()
+            .unwrap();
+        assert_eq!(restarted.sequence, in_one_go.sequence);
+        assert_eq!(balances(&restarted), balances(&in_one_go));
+        assert!(restarted.client_map[&1].locked);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replays_up_to_a_record_or_a_time() {
+        let path = temp_path("wal-as-of");
+        PipelineBuilder::new()
+            .source(records(
+                "type,client,tx,amount,timestamp
+                deposit,1,1,10,2022-03-01T10:00:00Z
+                dispute,1,1,,
+                chargeback,1,1,,2022-03-02T10:00:00Z
+                deposit,2,2,5,",
+            ))
+            .wal(Wal::open(&path).unwrap())
+            .build()
+            .run()
+            .unwrap();
+        let as_of = |as_of| {
+            PipelineBuilder::new()
+                .source(records(""))
+                .replay(path.clone(), Some(as_of))
+                .build()
+                .run()
+                .unwrap()
+        };
+
+        // the balance before the chargeback
+        let before = as_of(AsOf::Sequence(2));
+        assert_eq!(before.sequence, 2);
+        assert_eq!(before.client_map[&1].held_funds, Decimal::from(10));
+        assert!(!before.client_map[&1].locked);
+        // the dispute without a timestamp goes with the deposit before it
+        let time = "2022-03-01T12:00:00Z".parse().unwrap();
+        assert_eq!(as_of(AsOf::Time(time)).sequence, 2);
+        let after = as_of(AsOf::Sequence(4));
+        assert!(after.client_map[&1].locked);
+        assert_eq!(after.client_map[&2].total_funds, Decimal::from(5));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn the_log_is_emptied_once_the_store_has_its_records() {
+        use crate::sled_store::SledStore;
+
+        let path = temp_path("wal-store");
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let run = |input| {
+            PipelineBuilder::new()
+                .source(records(input))
+                .store(SledStore::of(db.clone()).unwrap(), 1)
+                .wal(Wal::open(&path).unwrap())
+                .build()
+                .run()
+                .unwrap()
+        };
+        run(LOGGED);
+        assert_eq!(sequences(&path), [] as [u64; 0]);
+        // the next run takes the first records from the store, not from the log again
+        let second = run(AFTER);
+        assert_eq!(second.sequence, 5);
+        assert_eq!(second.client_map[&2].available_funds, Decimal::from(15));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use crate::Config;
+#[cfg(feature = "watch")]
+use crate::{
+    is_stdin, loaded_state, open_inputs, persisted, pipeline::State, pipeline_for, save_state,
+    simulate, write_atomic, Record, TxError,
+};
+#[cfg(feature = "watch")]
+use glob::Pattern;
+#[cfg(feature = "watch")]
+use log::{error, info};
+#[cfg(feature = "watch")]
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+#[cfg(feature = "watch")]
+use std::{
+    collections::BTreeMap,
+    fs, iter,
+    path::Path,
+    slice,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+use std::{error::Error, path::PathBuf};
+
+/// A file is picked up once it has not changed for this long, so files still being
+/// written are left alone.
+#[cfg(feature = "watch")]
+const SETTLE: Duration = Duration::from_secs(2);
+
+/// Directory watched by `--watch`.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "watch"), allow(dead_code))]
+pub struct WatchOptions {
+    pub dir: PathBuf,
+    /// glob the file names have to match
+    pub pattern: String,
+}
+
+impl WatchOptions {
+    pub const DEFAULT_PATTERN: &'static str = "*.csv";
+}
+
+/// Applies every file of the directory, those already there first and then the new ones in
+/// the order they settle, on top of the state left by the input and the files before it.
+/// Runs until the process is stopped.
+///
+/// The changes a file made, as with `simulate`, are written to `<name>.report.json`. A file
+/// that fails gets `<name>.failed` holding the error instead. Files with either are not
+/// applied again, also by a later watch of the directory.
+///
+/// The state is kept in `--state-store` or `--db` and every record logged to `--wal` as in a
+/// batch run, and written to `--save-state` after every file. A file that fails leaves the
+/// state as it was, unless it is kept in a store or the WAL: like a failed batch run it then
+/// keeps the records applied before the failure.
+#[cfg(feature = "watch")]
+pub fn watch(options: &WatchOptions, config: &Config) -> Result<(), Box<dyn Error>> {
+    let pattern = Pattern::new(&options.pattern)?;
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // watching before listing, so no file is missed in between
+    watcher.watch(&options.dir, RecursiveMode::NonRecursive)?;
+
+    let started = start(config)?;
+    save_state(config, &started)?;
+    let mut state = Some(started);
+    let mut applied: Vec<PathBuf> = Vec::new();
+    let mut existing = Vec::new();
+    for entry in fs::read_dir(&options.dir)? {
+        let path = entry?.path();
+        if matches(&path, &pattern) {
+            existing.push(path);
+        }
+    }
+    existing.sort();
+    for path in existing {
+        apply_new(&path, &mut state, &mut applied, config)?;
+    }
+
+    let mut pending: BTreeMap<PathBuf, Instant> = BTreeMap::new();
+    info!("watching {} for {}", options.dir.display(), options.pattern);
+    loop {
+        match events.recv_timeout(SETTLE) {
+            Ok(Ok(event)) => {
+                for path in changed_files(event, &pattern) {
+                    pending.insert(path, Instant::now());
+                }
+            }
+            Ok(Err(err)) => error!("watching {} failed: {}", options.dir.display(), err),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(From::from("the watcher stopped")),
+        }
+
+        let settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= SETTLE)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            pending.remove(&path);
+            apply_new(&path, &mut state, &mut applied, config)?;
+        }
+    }
+}
+
+#[cfg(not(feature = "watch"))]
+pub fn watch(_options: &WatchOptions, _config: &Config) -> Result<(), Box<dyn Error>> {
+    Err(From::from("--watch needs the watch feature"))
+}
+
+/// The files matching `pattern` that an event created or wrote to.
+#[cfg(feature = "watch")]
+fn changed_files(event: Event, pattern: &Pattern) -> impl Iterator<Item = PathBuf> + '_ {
+    let changed = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_));
+    event
+        .paths
+        .into_iter()
+        .filter(move |path| changed && matches(path, pattern))
+}
+
+#[cfg(feature = "watch")]
+fn matches(path: &Path, pattern: &Pattern) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| pattern.matches(name))
+}
+
+/// The state the input leaves, applied through the state store and the WAL like a batch run
+/// but with nothing written for it. Stdin is not read, the state is the stored, loaded or
+/// logged one then.
+#[cfg(feature = "watch")]
+fn start(config: &Config) -> Result<State, Box<dyn Error>> {
+    let pipeline = pipeline_for(config)?.state(loaded_state(config)?);
+    let pipeline = if is_stdin(config.input()) {
+        pipeline.source(iter::empty::<Result<Record, TxError>>())
+    } else {
+        pipeline.source(open_inputs(config, &config.inputs)?)
+    };
+    Ok(persisted(pipeline, config)?.build().run()?)
+}
+
+/// Applies `path` unless it is gone, or was applied or failed before.
+#[cfg(feature = "watch")]
+fn apply_new(
+    path: &Path,
+    state: &mut Option<State>,
+    applied: &mut Vec<PathBuf>,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let report = with_suffix(path, ".report.json");
+    let failed = with_suffix(path, ".failed");
+    // gone again, or written once more after being applied
+    if !path.is_file() || report.exists() || failed.exists() {
+        return Ok(());
+    }
+    let before = state.take().ok_or("watch state lost")?;
+    let after = match apply(path, &report, before, config) {
+        Ok(after) => {
+            info!("applied {}", path.display());
+            applied.push(path.to_path_buf());
+            after
+        }
+        Err(err) => {
+            error!("applying {} failed: {}", path.display(), err);
+            fs::write(&failed, format!("{}\n", err))?;
+            // the pipeline took the state with it
+            recover(applied, config)?
+        }
+    };
+    save_state(config, &after)?;
+    *state = Some(after);
+    Ok(())
+}
+
+#[cfg(feature = "watch")]
+fn apply(
+    path: &Path,
+    report: &Path,
+    state: State,
+    config: &Config,
+) -> Result<State, Box<dyn Error>> {
+    let mut after = None;
+    write_atomic(report, |out| {
+        let before = simulate::balances(&state);
+        let pipeline = pipeline_for(config)?.source(open_inputs(config, &[path.to_path_buf()])?);
+        // a state store replaces the state with its own, it holds the same accounts
+        let pipeline = match config.state_store {
+            Some(_) => pipeline,
+            None => pipeline.state(state),
+        };
+        let mut pipeline = persisted(pipeline, config)?;
+        let events = pipeline.subscribe();
+        let report = simulate::Simulation {
+            out,
+            before,
+            events,
+        };
+        after = Some(pipeline.sink(report).build().run()?);
+        Ok(())
+    })?;
+    after.ok_or_else(|| From::from("no state after applying the file"))
+}
+
+/// The state after a file failed. Without a state store or WAL it is rebuilt from the input
+/// and the files applied before, otherwise it is read back from them.
+#[cfg(feature = "watch")]
+fn recover(applied: &[PathBuf], config: &Config) -> Result<State, Box<dyn Error>> {
+    if config.state_store.is_some() || config.wal.is_some() {
+        let pipeline = pipeline_for(config)?
+            .state(loaded_state(config)?)
+            .source(iter::empty::<Result<Record, TxError>>());
+        return Ok(persisted(pipeline, config)?.build().run()?);
+    }
+    let mut state = start(config)?;
+    for path in applied {
+        state = pipeline_for(config)?
+            .source(open_inputs(config, slice::from_ref(path))?)
+            .state(state)
+            .build()
+            .run()?;
+    }
+    Ok(state)
+}
+
+#[cfg(feature = "watch")]
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(all(test, feature = "watch"))]
+mod tests {
+    use super::*;
+    use crate::{
+        policy::{Policy, Refused},
+        StateStoreKind,
+    };
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn a_failed_file_leaves_the_state_as_it_was() {
+        let dir = std::env::temp_dir().join(format!("watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = ["a.csv", "b.csv", "c.csv"].map(|name| dir.join(name));
+        fs::write(&files[0], "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+        fs::write(&files[1], "type,client,tx,amount\nwithdrawal,1,2,50\n").unwrap();
+        fs::write(&files[2], "type,client,tx,amount\nwithdrawal,1,3,4\n").unwrap();
+        let config = Config {
+            policy: Policy {
+                refused: Refused::Abort,
+                ..Policy::default()
+            },
+            ..Config::default()
+        };
+        let mut state = Some(start(&config).unwrap());
+        let mut applied = Vec::new();
+        for path in files.iter().chain(&files) {
+            apply_new(path, &mut state, &mut applied, &config).unwrap();
+        }
+        let failed = fs::read_to_string(with_suffix(&files[1], ".failed")).unwrap();
+        let reported = files
+            .iter()
+            .map(|path| with_suffix(path, ".report.json").exists())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+        // every file is applied once, the second one failed on its withdrawal
+        assert_eq!(applied, [files[0].clone(), files[2].clone()]);
+        assert_eq!(reported, [true, false, true]);
+        assert!(failed.contains("tx 2"), "{}", failed);
+        let account = &state.unwrap().client_map[&1];
+        assert_eq!(account.available_funds, Decimal::from(6));
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn a_restarted_watch_continues_from_the_state_store() {
+        let dir = std::env::temp_dir().join(format!("watch-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.csv");
+        fs::write(&file, "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+        let config = Config {
+            state_store: Some(StateStoreKind::Sled {
+                path: dir.join("state"),
+            }),
+            state_cache: 10,
+            ..Config::default()
+        };
+        let mut state = Some(start(&config).unwrap());
+        apply_new(&file, &mut state, &mut Vec::new(), &config).unwrap();
+        drop(state);
+        let restarted = start(&config).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(restarted.client_map[&1].available_funds, Decimal::from(10));
+    }
+}
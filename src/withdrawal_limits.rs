@@ -0,0 +1,229 @@
+use crate::{transactions::TxKind, ClientInfo, Record, TxType};
+use chrono::{DateTime, Duration, Utc};
+use log::error;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// Length of the rolling window the limits apply to.
+fn window() -> Duration {
+    Duration::hours(24)
+}
+
+/// Most withdrawals, and most funds withdrawn, per client in any 24 hours. No limit when
+/// not set.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct WithdrawalLimits {
+    pub count: Option<u32>,
+    pub amount: Option<Decimal>,
+}
+
+impl WithdrawalLimits {
+    pub fn is_set(&self) -> bool {
+        self.count.is_some() || self.amount.is_some()
+    }
+
+    /// Whether the withdrawal stays within the limits of its client in the 24 hours up to
+    /// `now`, the time of the run. Anything else, and anything while the run has no time yet,
+    /// passes.
+    pub fn admits(
+        &self,
+        record: &Record,
+        client_map: &HashMap<u16, ClientInfo>,
+        now: Option<DateTime<Utc>>,
+    ) -> bool {
+        if record.tx_type != TxType::Withdrawal || !self.is_set() {
+            return true;
+        }
+        let info = record.client.and_then(|client| client_map.get(&client));
+        let (now, info, amount) = match (now, info, record.amount) {
+            (Some(now), Some(info), Some(amount)) => (now, info, amount),
+            _ => return true,
+        };
+        let (count, withdrawn) = usage(info, now);
+        let over_count = self.count.is_some_and(|max| count >= max);
+        let over_amount = self
+            .amount
+            .is_some_and(|max| withdrawn.saturating_add(amount) > max);
+        if over_count || over_amount {
+            error!(
+                "withdrawal limit reached, {} withdrawals of {} in the last 24 hours, skipping tx {:?}",
+                count, withdrawn, record
+            );
+            return false;
+        }
+        true
+    }
+}
+
+/// Number and summed amount of the withdrawals kept by the account in the 24 hours up to
+/// `now`, `now` included. Withdrawals without a timestamp and voided ones don't count. The
+/// transactions are looked at newest first, and the lookup stops at the first one older
+/// than the window, so the records are expected in time order.
+pub fn usage(info: &ClientInfo, now: DateTime<Utc>) -> (u32, Decimal) {
+    let start = now - window();
+    let mut count = 0;
+    let mut amount = Decimal::ZERO;
+    for entry in info.transactions().iter().rev() {
+        let at = match entry.timestamp() {
+            Some(at) => at,
+            None => continue,
+        };
+        if at <= start {
+            break;
+        }
+        if at > now || entry.kind != TxKind::Withdrawal || entry.voided {
+            continue;
+        }
+        count += 1;
+        amount = amount.saturating_add(entry.amount.unwrap_or(Decimal::ZERO));
+    }
+    (count, amount)
+}
+
+/// Time of the newest deposit or withdrawal kept by any account, where the window of the
+/// usage columns of the balances ends. `None` without timestamps.
+pub fn latest(client_map: &HashMap<u16, ClientInfo>) -> Option<DateTime<Utc>> {
+    client_map
+        .values()
+        .filter_map(|info| {
+            info.transactions()
+                .iter()
+                .filter_map(|entry| entry.timestamp())
+                .max()
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{PipelineBuilder, State};
+
+    /// Runs `input` under `limits`, CSV with a timestamp column.
+    fn run(input: &str, limits: WithdrawalLimits) -> State {
+        let mut pipeline = PipelineBuilder::new().transform(limits).build();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        for record in reader.deserialize::<Record>() {
+            pipeline.process(record.unwrap()).unwrap();
+        }
+        pipeline.finish().unwrap()
+    }
+
+    fn kept(state: &State, client: u16) -> Vec<u32> {
+        let transactions = state.client_map[&client].transactions();
+        transactions.iter().map(|entry| entry.tx).collect()
+    }
+
+    #[test]
+    fn refuses_withdrawals_past_the_count_in_24_hours() {
+        let limits = WithdrawalLimits {
+            count: Some(2),
+            amount: None,
+        };
+        let state = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,100,2022-03-01T08:00:00Z
+            withdrawal,1,2,1,2022-03-01T09:00:00Z
+            withdrawal,1,3,1,2022-03-01T10:00:00Z
+            withdrawal,1,4,1,2022-03-02T08:00:00Z
+            withdrawal,1,5,1,2022-03-02T09:00:00Z",
+            limits,
+        );
+        // tx 2 left the window just as tx 5 came in
+        assert_eq!(kept(&state, 1), vec![1, 2, 3, 5]);
+        assert_eq!(state.client_map[&1].available_funds, Decimal::from(97));
+    }
+
+    #[test]
+    fn refuses_withdrawals_past_the_amount_in_24_hours() {
+        let limits = WithdrawalLimits {
+            count: None,
+            amount: Some(Decimal::from(50)),
+        };
+        let state = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,100,2022-03-01T08:00:00Z
+            withdrawal,1,2,30,2022-03-01T09:00:00Z
+            withdrawal,1,3,30,2022-03-01T10:00:00Z
+            withdrawal,1,4,20,2022-03-01T11:00:00Z
+            withdrawal,1,5,30,2022-03-02T09:30:00Z",
+            limits,
+        );
+        assert_eq!(kept(&state, 1), vec![1, 2, 4, 5]);
+        assert_eq!(state.client_map[&1].available_funds, Decimal::from(20));
+    }
+
+    #[test]
+    fn voided_and_untimed_withdrawals_do_not_count() {
+        let limits = WithdrawalLimits {
+            count: Some(1),
+            amount: None,
+        };
+        let state = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,100,
+            withdrawal,1,2,10,
+            withdrawal,1,3,10,2022-03-01T09:00:00Z
+            void,1,3,,2022-03-01T09:30:00Z
+            withdrawal,1,4,10,2022-03-01T10:00:00Z
+            withdrawal,1,5,10,2022-03-01T11:00:00Z",
+            limits,
+        );
+        assert_eq!(kept(&state, 1), vec![1, 2, 3, 4]);
+        assert!(state.client_map[&1].transaction(3).unwrap().voided);
+        assert_eq!(state.client_map[&1].available_funds, Decimal::from(80));
+    }
+
+    #[test]
+    fn nothing_is_limited_before_the_first_timestamp() {
+        let limits = WithdrawalLimits {
+            count: Some(1),
+            amount: Some(Decimal::ONE),
+        };
+        let state = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,100,
+            withdrawal,1,2,10,
+            withdrawal,1,3,10,",
+            limits,
+        );
+        assert_eq!(kept(&state, 1), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn records_without_a_timestamp_count_as_at_the_one_before() {
+        let limits = WithdrawalLimits {
+            count: Some(1),
+            amount: None,
+        };
+        let state = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,100,2022-03-01T08:00:00Z
+            withdrawal,1,2,10,2022-03-01T09:00:00Z
+            withdrawal,1,3,10,",
+            limits,
+        );
+        assert_eq!(kept(&state, 1), vec![1, 2]);
+    }
+
+    #[test]
+    fn usage_ends_at_the_newest_record() {
+        let state = run(
+            "type,client,tx,amount,timestamp
+            deposit,1,1,100,2022-03-01T08:00:00Z
+            withdrawal,1,2,10,2022-03-01T09:00:00Z
+            withdrawal,1,3,5,2022-03-02T08:00:00Z
+            deposit,2,4,1,2022-03-02T10:00:00Z",
+            WithdrawalLimits::default(),
+        );
+        let now = latest(&state.client_map).unwrap();
+        assert_eq!(
+            now,
+            crate::timestamp::parse("2022-03-02T10:00:00Z").unwrap()
+        );
+        assert_eq!(usage(&state.client_map[&1], now), (1, Decimal::from(5)));
+        assert_eq!(usage(&state.client_map[&2], now), (0, Decimal::ZERO));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{timestamp, Record, TxError, TxType};
+use calamine::{open_workbook_auto, DataType, Reader};
+use chrono::{DateTime, Utc};
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use std::{error::Error, path::Path};
+
+/// Columns of the transaction fields in the sheet, 0-based.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnLayout {
+    pub tx_type: usize,
+    pub client: usize,
+    pub tx: usize,
+    pub amount: usize,
+    pub memo: Option<usize>,
+    /// only found from a header row
+    pub timestamp: Option<usize>,
+    /// only found from a header row
+    pub fee: Option<usize>,
+    /// only found from a header row
+    pub evidence: Option<usize>,
+}
+
+impl ColumnLayout {
+    /// Parses spreadsheet column letters in the order `type,client,tx,amount[,memo]`, e.g. `B,C,A,E`.
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        let columns = value
+            .split(',')
+            .map(column_index)
+            .collect::<Result<Vec<usize>, _>>()?;
+        match columns[..] {
+            [tx_type, client, tx, amount] => Ok(ColumnLayout {
+                tx_type,
+                client,
+                tx,
+                amount,
+                memo: None,
+                timestamp: None,
+                fee: None,
+                evidence: None,
+            }),
+            [tx_type, client, tx, amount, memo] => Ok(ColumnLayout {
+                tx_type,
+                client,
+                tx,
+                amount,
+                memo: Some(memo),
+                timestamp: None,
+                fee: None,
+                evidence: None,
+            }),
+            _ => Err(From::from(
+                "xlsx columns expected as type,client,tx,amount[,memo]",
+            )),
+        }
+    }
+
+    /// Finds the columns by their names in a header row, like the CSV reader does.
+    fn from_header(row: &[DataType]) -> Result<Self, Box<dyn Error>> {
+        let find = |name: &str| {
+            row.iter()
+                .position(|cell| cell.get_string().map(str::trim) == Some(name))
+        };
+        let require =
+            |name: &str| find(name).ok_or_else(|| format!("xlsx header column {} not found", name));
+        Ok(ColumnLayout {
+            tx_type: require("type")?,
+            client: require("client")?,
+            tx: require("tx")?,
+            amount: require("amount")?,
+            memo: find("memo"),
+            timestamp: find("timestamp"),
+            fee: find("fee"),
+            evidence: find("evidence"),
+        })
+    }
+}
+
+/// Spreadsheet column letters to a 0-based index, `A` is 0 and `AA` is 26.
+fn column_index(letters: &str) -> Result<usize, Box<dyn Error>> {
+    let letters = letters.trim();
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(From::from(format!("invalid xlsx column: {}", letters)));
+    }
+    let index = letters
+        .to_ascii_uppercase()
+        .bytes()
+        .fold(0, |acc, b| acc * 26 + (b - b'A' + 1) as usize);
+    Ok(index - 1)
+}
+
+/// The rows of a sheet, each parsed on its own.
+type SheetRecords = Vec<Result<Record, TxError>>;
+
+/// Reads all transactions from `sheet` (the first sheet when not given). Without a
+/// column layout the first row is treated as a header naming the columns, with one
+/// every row is data.
+pub fn read_records(
+    path: &Path,
+    sheet: Option<&str>,
+    layout: Option<&ColumnLayout>,
+) -> Result<SheetRecords, Box<dyn Error>> {
+    let mut workbook = open_workbook_auto(path)?;
+    let sheet = match sheet {
+        Some(name) => name.to_string(),
+        None => workbook
+            .sheet_names()
+            .first()
+            .cloned()
+            .ok_or("xlsx workbook has no sheets")?,
+    };
+    let range = workbook
+        .worksheet_range(&sheet)
+        .ok_or_else(|| format!("xlsx sheet {} not found", sheet))??;
+
+    let mut rows = range.rows();
+    let layout = match layout {
+        Some(layout) => layout.clone(),
+        None => match rows.next() {
+            Some(header) => ColumnLayout::from_header(header)?,
+            None => return Ok(Vec::new()),
+        },
+    };
+    Ok(rows
+        .filter(|row| row.iter().any(|cell| !cell.is_empty()))
+        .map(|row| to_record(row, &layout))
+        .collect())
+}
+
+fn to_record(row: &[DataType], layout: &ColumnLayout) -> Result<Record, TxError> {
+    let cell = |index: usize| row.get(index).unwrap_or(&DataType::Empty);
+    let tx_type = text(cell(layout.tx_type))
+        .ok_or_else(|| TxError::malformed("xlsx row without a transaction type"))?;
+    let tx = number(cell(layout.tx))
+        .filter(|tx| *tx >= 0.0 && *tx <= u32::MAX as f64 && tx.fract() == 0.0)
+        .ok_or_else(|| {
+            TxError::malformed(format!(
+                "xlsx row with invalid tx id: {:?}",
+                cell(layout.tx)
+            ))
+        })?;
+    Ok(Record {
+        tx_type: TxType::from(tx_type),
+        // like the CSV reader, invalid optional values are treated as missing
+        client: number(cell(layout.client))
+            .filter(|c| *c >= 0.0 && *c <= u16::MAX as f64 && c.fract() == 0.0)
+            .map(|c| c as u16),
+        tx: tx as u32,
+        amount: number(cell(layout.amount)).and_then(Decimal::from_f64),
+        fee: layout
+            .fee
+            .and_then(|index| number(cell(index)))
+            .and_then(Decimal::from_f64),
+        memo: layout.memo.and_then(|index| text(cell(index))),
+        evidence: layout.evidence.and_then(|index| text(cell(index))),
+        timestamp: layout.timestamp.and_then(|index| datetime(cell(index))),
+        position: 0,
+        line: 0,
+        sequence: 0,
+        next_position: None,
+        tags: Vec::new(),
+    })
+}
+
+fn text(cell: &DataType) -> Option<String> {
+    match cell {
+        DataType::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        DataType::Int(i) => Some(i.to_string()),
+        DataType::Float(f) => Some(f.to_string()),
+        _ => None,
+    }
+}
+
+/// Days between the spreadsheet epoch, 1899-12-30, and the unix epoch.
+const UNIX_EPOCH_SERIAL: f64 = 25569.0;
+
+/// Date cells hold a spreadsheet serial date, text cells RFC 3339 or epoch millis like
+/// the CSV column, and number cells epoch millis.
+fn datetime(cell: &DataType) -> Option<DateTime<Utc>> {
+    match cell {
+        DataType::DateTime(serial) => {
+            let millis = ((serial - UNIX_EPOCH_SERIAL) * 86_400_000.0).round();
+            timestamp::from_millis(millis as i64)
+        }
+        DataType::String(s) => timestamp::parse(s),
+        DataType::Int(millis) => timestamp::from_millis(*millis),
+        DataType::Float(millis) if millis.fract() == 0.0 => timestamp::from_millis(*millis as i64),
+        _ => None,
+    }
+}
+
+fn number(cell: &DataType) -> Option<f64> {
+    match cell {
+        DataType::Int(i) => Some(*i as f64),
+        DataType::Float(f) => Some(*f),
+        DataType::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<DataType> {
+        ["tx", "type", "client", "amount", "timestamp"]
+            .iter()
+            .map(|name| DataType::String(name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_column_letters() {
+        let layout = ColumnLayout::parse("B, C,A,AA,e").unwrap();
+        assert_eq!(
+            (layout.tx_type, layout.client, layout.tx, layout.amount),
+            (1, 2, 0, 26)
+        );
+        assert_eq!(layout.memo, Some(4));
+        assert!(ColumnLayout::parse("B,C,A").is_err());
+        assert!(ColumnLayout::parse("B,C,A,1").is_err());
+    }
+
+    #[test]
+    fn finds_columns_from_the_header_row() {
+        let layout = ColumnLayout::from_header(&header()).unwrap();
+        assert_eq!(
+            (layout.tx_type, layout.client, layout.tx, layout.amount),
+            (1, 2, 0, 3)
+        );
+        assert_eq!((layout.memo, layout.timestamp), (None, Some(4)));
+        assert!(ColumnLayout::from_header(&header()[1..]).is_err());
+    }
+
+    #[test]
+    fn reads_a_row_like_the_csv_reader() {
+        let layout = ColumnLayout::from_header(&header()).unwrap();
+        let row = [
+            DataType::Float(7.0),
+            DataType::String(" deposit ".to_string()),
+            DataType::Int(3),
+            DataType::String("1.5".to_string()),
+            // 2022-03-01T12:00:00Z
+            DataType::DateTime(44621.5),
+        ];
+        let record = to_record(&row, &layout).unwrap();
+        assert_eq!(record.tx_type, TxType::Deposit);
+        assert_eq!(
+            (record.client, record.tx, record.amount),
+            (Some(3), 7, Some(1.5))
+        );
+        assert_eq!(record.timestamp, timestamp::parse("2022-03-01T12:00:00Z"));
+    }
+
+    #[test]
+    fn treats_invalid_optional_cells_as_missing() {
+        let layout = ColumnLayout::from_header(&header()).unwrap();
+        let row = [
+            DataType::Int(7),
+            DataType::String("dispute".to_string()),
+            DataType::Float(-1.0),
+            DataType::Empty,
+            DataType::String("yesterday".to_string()),
+        ];
+        let record = to_record(&row, &layout).unwrap();
+        assert_eq!(
+            (record.client, record.amount, record.timestamp),
+            (None, None, None)
+        );
+        let row = [
+            DataType::Float(7.5),
+            DataType::String("dispute".to_string()),
+        ];
+        assert!(to_record(&row, &layout).is_err());
+    }
+}